@@ -0,0 +1,122 @@
+# Signup form
+signup-title = Subscribe
+signup-name = Name
+signup-email = Email
+signup-keep-domain = Did you mean { $suggestion }? Tick to keep the address as typed.
+signup-trap = Leave this field empty
+signup-submit = Subscribe
+
+# After submitting the signup form
+check-inbox-title = Check your inbox
+check-inbox-sent = We have sent a confirmation link to { $email }.
+check-inbox-next = Click it to start receiving { $site }.
+
+# Confirmation link
+confirmed-title = You are in!
+confirmed-body = Your subscription to { $site } has been confirmed.
+confirmed-unsubscribe = Changed your mind? You can unsubscribe at any time.
+confirmed-unsubscribe-link = Unsubscribe
+invalid-link-title = This link is not valid
+invalid-link-body = Please make sure you copied the whole link from the email we sent you.
+expired-link-title = This link has expired
+expired-link-body = Confirmation links are only valid for a limited time. Sign up again to get a new one.
+expired-link-signup = Sign up again
+
+# Unsubscribing
+unsubscribe-title = Unsubscribe
+unsubscribe-question = Do you really want to stop receiving { $site }?
+unsubscribe-submit = Unsubscribe
+unsubscribed-title = You have been unsubscribed
+unsubscribed-body = You will not receive { $site } anymore. Sorry to see you go!
+
+# Embedded signup form
+embed-check-inbox = Check your inbox to confirm your subscription to { $site }.
+embed-error = Something went wrong. Please try again.
+
+# Archive
+archive-title = Archive
+archive-search = Search past issues
+archive-empty = No issue here yet.
+archive-feeds = Follow with
+archive-back = All issues
+archive-subscribe = Get the next issues in your inbox
+
+# Invites to help run the newsletter
+invite-title = Join { $site }
+invite-intro = You have been invited to help run { $site }.
+invite-role = Your role: { $role }
+invite-username = Username
+invite-password = Password
+invite-password-hint = Between 12 and 128 characters.
+invite-submit = Create my account
+invite-accepted-title = Your account is ready
+invite-accepted-body = Sign in to { $site } with your username and password.
+invite-invalid-title = This invitation is not valid
+invite-invalid-body = It may have been revoked, or used already. Ask for a new one.
+invite-expired-title = This invitation has expired
+invite-expired-body = Invitations are only valid for a limited time. Ask for a new one.
+invite-error-username = Pick a username of 1 to 64 characters.
+invite-error-username-taken = This username is taken.
+invite-error-password = Pick a password of 12 to 128 characters.
+role-owner = owner
+role-editor = editor
+role-publisher = publisher
+role-viewer = viewer
+
+# Forgotten passwords
+forgot-password-title = Forgot your password?
+forgot-password-intro = Enter the email address of your account: we will send you a link to pick a new password.
+forgot-password-email = Email address
+forgot-password-submit = Send me a link
+password-reset-title = Pick a new password
+password-reset-password = New password
+password-reset-submit = Change my password
+password-reset-sent-title = Check your inbox
+password-reset-sent-body = If an account has this email address, a link to pick a new password is on its way.
+password-reset-done-title = Your password has changed
+password-reset-done-body = Sign in to { $site } with your new password. You have been signed out everywhere else.
+password-reset-invalid-title = This link is not valid
+password-reset-invalid-body = It may have been used already. Ask for a new one.
+password-reset-expired-title = This link has expired
+password-reset-expired-body = Links to reset a password are only valid for a short time. Ask for a new one.
+
+# Confirmation email
+confirmation-email-subject = Welcome!
+confirmation-email-greeting = Hi { $name }, welcome to { $site }!
+confirmation-email-action = Confirm your subscription
+confirmation-email-ignore = If you did not sign up, you can safely ignore this email.
+
+# Confirmation reminder
+confirmation-reminder-email-subject = Please confirm your subscription
+confirmation-reminder-email-greeting = Hi { $name }, you signed up for { $site } but have not confirmed your subscription yet.
+confirmation-reminder-email-ignore = If you changed your mind, ignore this email: we will forget your address in a few days.
+
+# Newsletter issues
+newsletter-email-reason = You are receiving this because you subscribed to { $site }.
+newsletter-email-unsubscribe = Unsubscribe
+
+# Welcome series
+welcome-email-greeting = Hi { $name },
+welcome-email-subject = Welcome to { $site }
+welcome-email-body = Thanks for confirming your subscription to { $site }! Every issue lands in this inbox: reply to any of them to tell us what you would like to read about.
+welcome-tips-email-subject = Getting the most out of { $site }
+welcome-tips-email-body = Add our address to your contacts, so that { $site } never ends up in your spam folder.
+welcome-catch-up-email-subject = What you missed on { $site }
+welcome-catch-up-email-body = Looks like you have not had the time to open { $site } yet. The latest issues are waiting for you in your inbox.
+
+# Invite email
+invite-email-subject = Your invitation to { $site }
+invite-email-greeting = You have been invited to help run { $site } as { $role }.
+invite-email-action = Create your account
+invite-email-expiry = The link is valid for { $hours } hours. If you were not expecting this invitation, ignore this email.
+
+# Account locked email
+account-locked-email-subject = Your { $site } account is locked
+account-locked-email-body = Somebody failed to log in to your { $site } account too many times in a row, last from { $ip }. To keep your password from being guessed, nobody can log in to it for { $minutes } minutes.
+account-locked-email-advice = If it was not you, consider changing your password, and turning on two-factor authentication.
+
+# Password reset email
+password-reset-email-subject = Reset your { $site } password
+password-reset-email-intro = Somebody asked to reset the password of your { $site } account.
+password-reset-email-action = Pick a new password
+password-reset-email-expiry = The link is valid for { $minutes } minutes, and only once. If you did not ask for it, ignore this email: your password stays the same.
@@ -0,0 +1,122 @@
+# Форма підписки
+signup-title = Підписатися
+signup-name = Ім'я
+signup-email = Електронна пошта
+signup-keep-domain = Можливо, ви мали на увазі { $suggestion }? Позначте, щоб залишити адресу без змін.
+signup-trap = Залиште це поле порожнім
+signup-submit = Підписатися
+
+# Після надсилання форми
+check-inbox-title = Перевірте пошту
+check-inbox-sent = Ми надіслали посилання для підтвердження на { $email }.
+check-inbox-next = Перейдіть за ним, щоб почати отримувати { $site }.
+
+# Посилання для підтвердження
+confirmed-title = Готово!
+confirmed-body = Вашу підписку на { $site } підтверджено.
+confirmed-unsubscribe = Передумали? Ви можете відписатися будь-коли.
+confirmed-unsubscribe-link = Відписатися
+invalid-link-title = Посилання недійсне
+invalid-link-body = Переконайтеся, що ви скопіювали повне посилання з нашого листа.
+expired-link-title = Термін дії посилання минув
+expired-link-body = Посилання для підтвердження дійсні лише обмежений час. Підпишіться ще раз, щоб отримати нове.
+expired-link-signup = Підписатися ще раз
+
+# Відписка
+unsubscribe-title = Відписатися
+unsubscribe-question = Ви справді більше не хочете отримувати { $site }?
+unsubscribe-submit = Відписатися
+unsubscribed-title = Ви відписалися
+unsubscribed-body = Ви більше не отримуватимете { $site }. Шкода, що ви йдете!
+
+# Вбудована форма підписки
+embed-check-inbox = Перевірте пошту, щоб підтвердити підписку на { $site }.
+embed-error = Щось пішло не так. Спробуйте ще раз.
+
+# Архів
+archive-title = Архів
+archive-search = Пошук у минулих випусках
+archive-empty = Тут ще немає випусків.
+archive-feeds = Стежити через
+archive-back = Усі випуски
+archive-subscribe = Отримувати наступні випуски поштою
+
+# Запрошення допомагати з розсилкою
+invite-title = Приєднуйтеся до { $site }
+invite-intro = Вас запросили допомагати з { $site }.
+invite-role = Ваша роль: { $role }
+invite-username = Ім'я користувача
+invite-password = Пароль
+invite-password-hint = Від 12 до 128 символів.
+invite-submit = Створити обліковий запис
+invite-accepted-title = Ваш обліковий запис готовий
+invite-accepted-body = Входьте до { $site } зі своїм ім'ям користувача та паролем.
+invite-invalid-title = Це запрошення недійсне
+invite-invalid-body = Можливо, його відкликали або вже використали. Попросіть нове.
+invite-expired-title = Термін дії запрошення минув
+invite-expired-body = Запрошення дійсні лише обмежений час. Попросіть нове.
+invite-error-username = Оберіть ім'я користувача довжиною від 1 до 64 символів.
+invite-error-username-taken = Це ім'я користувача вже зайняте.
+invite-error-password = Оберіть пароль довжиною від 12 до 128 символів.
+role-owner = власник
+role-editor = редактор
+role-publisher = видавець
+role-viewer = спостерігач
+
+# Забуті паролі
+forgot-password-title = Забули пароль?
+forgot-password-intro = Введіть адресу електронної пошти свого облікового запису: ми надішлемо посилання, щоб обрати новий пароль.
+forgot-password-email = Адреса електронної пошти
+forgot-password-submit = Надіслати посилання
+password-reset-title = Оберіть новий пароль
+password-reset-password = Новий пароль
+password-reset-submit = Змінити пароль
+password-reset-sent-title = Перевірте пошту
+password-reset-sent-body = Якщо обліковий запис має цю адресу, посилання для вибору нового пароля вже в дорозі.
+password-reset-done-title = Ваш пароль змінено
+password-reset-done-body = Входьте до { $site } з новим паролем. На всіх інших пристроях ви вийшли з облікового запису.
+password-reset-invalid-title = Це посилання недійсне
+password-reset-invalid-body = Можливо, його вже використали. Попросіть нове.
+password-reset-expired-title = Термін дії посилання минув
+password-reset-expired-body = Посилання для скидання пароля дійсні лише недовго. Попросіть нове.
+
+# Лист із підтвердженням
+confirmation-email-subject = Ласкаво просимо!
+confirmation-email-greeting = Вітаємо, { $name }! Ласкаво просимо до { $site }!
+confirmation-email-action = Підтвердити підписку
+confirmation-email-ignore = Якщо ви не підписувалися, просто проігноруйте цей лист.
+
+# Нагадування про підтвердження
+confirmation-reminder-email-subject = Підтвердьте свою підписку
+confirmation-reminder-email-greeting = Вітаємо, { $name }! Ви підписалися на { $site }, але ще не підтвердили підписку.
+confirmation-reminder-email-ignore = Якщо ви передумали, просто проігноруйте цей лист: за кілька днів ми забудемо вашу адресу.
+
+# Випуски розсилки
+newsletter-email-reason = Ви отримали цей лист, бо підписалися на { $site }.
+newsletter-email-unsubscribe = Відписатися
+
+# Вітальна серія
+welcome-email-greeting = Вітаємо, { $name }!
+welcome-email-subject = Ласкаво просимо до { $site }
+welcome-email-body = Дякуємо, що підтвердили підписку на { $site }! Кожен випуск надходитиме на цю адресу: відповідайте на будь-який із них, щоб розповісти, про що хочете читати.
+welcome-tips-email-subject = Як отримати більше від { $site }
+welcome-tips-email-body = Додайте нашу адресу до контактів, щоб { $site } ніколи не потрапляв у спам.
+welcome-catch-up-email-subject = Що ви пропустили в { $site }
+welcome-catch-up-email-body = Схоже, ви ще не встигли відкрити { $site }. Останні випуски чекають на вас у поштовій скриньці.
+
+# Лист із запрошенням
+invite-email-subject = Запрошення до { $site }
+invite-email-greeting = Вас запросили допомагати з { $site } у ролі «{ $role }».
+invite-email-action = Створити обліковий запис
+invite-email-expiry = Посилання дійсне { $hours } год. Якщо ви не чекали на це запрошення, просто проігноруйте лист.
+
+# Лист про блокування облікового запису
+account-locked-email-subject = Ваш обліковий запис { $site } заблоковано
+account-locked-email-body = Хтось забагато разів поспіль не зміг увійти до вашого облікового запису { $site }, востаннє з { $ip }. Щоб ваш пароль не вгадали, увійти до нього не можна протягом { $minutes } хв.
+account-locked-email-advice = Якщо це були не ви, варто змінити пароль і ввімкнути двофакторну автентифікацію.
+
+# Лист для скидання пароля
+password-reset-email-subject = Скидання пароля до { $site }
+password-reset-email-intro = Хтось попросив скинути пароль вашого облікового запису { $site }.
+password-reset-email-action = Обрати новий пароль
+password-reset-email-expiry = Посилання дійсне { $minutes } хв і лише один раз. Якщо ви не просили про це, просто проігноруйте лист: ваш пароль не зміниться.
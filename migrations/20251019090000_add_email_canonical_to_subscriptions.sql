@@ -0,0 +1,19 @@
+-- Add migration script here
+BEGIN;
+
+ALTER TABLE subscriptions
+    ADD COLUMN email_canonical TEXT NULL;
+
+-- Backfill with the case-insensitive form of historical entries. Only the
+-- oldest row of a group of duplicates gets it: the others are left NULL,
+-- since they would violate the unique index below.
+UPDATE subscriptions
+SET email_canonical = lower(email)
+WHERE id IN (SELECT DISTINCT ON (lower(email)) id
+             FROM subscriptions
+             ORDER BY lower(email), subscribed_at);
+
+CREATE UNIQUE INDEX subscriptions_email_canonical_idx
+    ON subscriptions (email_canonical);
+
+COMMIT;
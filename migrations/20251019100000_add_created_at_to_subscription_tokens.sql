@@ -0,0 +1,4 @@
+-- Add migration script here
+-- Historical tokens are considered issued now, so they stay valid for a while.
+ALTER TABLE subscription_tokens
+    ADD COLUMN created_at timestamptz NOT NULL DEFAULT now();
@@ -0,0 +1,3 @@
+-- Add migration script here
+ALTER TABLE subscriptions
+    ADD COLUMN locale TEXT NOT NULL DEFAULT 'en';
@@ -0,0 +1,8 @@
+-- Templates edited at runtime, replacing the bundled ones with the same name.
+CREATE TABLE email_templates(
+    name TEXT NOT NULL,
+    PRIMARY KEY (name),
+    html_body TEXT NOT NULL,
+    text_body TEXT NOT NULL,
+    updated_at timestamptz NOT NULL DEFAULT now()
+);
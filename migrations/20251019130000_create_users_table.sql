@@ -0,0 +1,6 @@
+-- People allowed to publish newsletter issues.
+CREATE TABLE users(
+    user_id uuid PRIMARY KEY,
+    username TEXT NOT NULL UNIQUE,
+    password_hash TEXT NOT NULL
+);
@@ -0,0 +1,2 @@
+-- Custom attributes, e.g. `{"company": "Earthsea"}`, for merge fields.
+ALTER TABLE subscriptions ADD COLUMN attributes JSONB NOT NULL DEFAULT '{}'::jsonb;
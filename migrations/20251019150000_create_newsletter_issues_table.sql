@@ -0,0 +1,30 @@
+CREATE TABLE newsletter_issues(
+    newsletter_issue_id uuid NOT NULL,
+    PRIMARY KEY (newsletter_issue_id),
+    title TEXT NOT NULL,
+    -- Either `{"markdown": ...}` or `{"html": ..., "text": ...}`.
+    content JSONB NOT NULL,
+    status TEXT NOT NULL,
+    scheduled_for timestamptz,
+    created_by uuid REFERENCES users (user_id) ON DELETE SET NULL,
+    created_at timestamptz NOT NULL,
+    updated_at timestamptz NOT NULL,
+    sent_at timestamptz
+);
+-- The scheduler looks for due issues on every tick.
+CREATE INDEX newsletter_issues_due_idx
+    ON newsletter_issues (scheduled_for)
+    WHERE status = 'scheduled';
+
+-- One email to send, shared by every running instance.
+CREATE TABLE issue_delivery_queue(
+    newsletter_issue_id uuid NOT NULL
+        REFERENCES newsletter_issues (newsletter_issue_id) ON DELETE CASCADE,
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+    PRIMARY KEY (newsletter_issue_id, subscriber_id),
+    n_retries SMALLINT NOT NULL DEFAULT 0,
+    execute_after timestamptz NOT NULL DEFAULT now()
+);
+
+-- Where test issues are sent to.
+ALTER TABLE users ADD COLUMN email TEXT;
@@ -0,0 +1,19 @@
+-- Links go through a signed redirect and a pixel is added, unless turned off.
+ALTER TABLE newsletter_issues ADD COLUMN tracking BOOLEAN NOT NULL DEFAULT true;
+
+-- The opens and clicks of an issue, one row per request we believe came from
+-- a person.
+CREATE TABLE issue_events(
+    event_id uuid NOT NULL,
+    PRIMARY KEY (event_id),
+    newsletter_issue_id uuid NOT NULL
+        REFERENCES newsletter_issues (newsletter_issue_id) ON DELETE CASCADE,
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+    -- 'open' or 'click'.
+    kind TEXT NOT NULL,
+    -- The link that was clicked.
+    url TEXT,
+    user_agent TEXT,
+    occurred_at timestamptz NOT NULL
+);
+CREATE INDEX issue_events_issue_idx ON issue_events (newsletter_issue_id, kind);
@@ -0,0 +1,6 @@
+-- When subscribers changed status, for the growth report.
+ALTER TABLE subscriptions ADD COLUMN confirmed_at timestamptz;
+ALTER TABLE subscriptions ADD COLUMN unsubscribed_at timestamptz;
+-- The best we know for the subscribers confirmed so far. Past unsubscribes
+-- stay undated.
+UPDATE subscriptions SET confirmed_at = subscribed_at WHERE status = 'confirmed';
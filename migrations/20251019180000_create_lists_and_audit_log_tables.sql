@@ -0,0 +1,30 @@
+-- Subscribers can be on any number of lists.
+CREATE TABLE lists(
+    list_id uuid NOT NULL,
+    PRIMARY KEY (list_id),
+    slug TEXT NOT NULL UNIQUE,
+    name TEXT NOT NULL,
+    created_at timestamptz NOT NULL
+);
+CREATE TABLE list_subscriptions(
+    list_id uuid NOT NULL REFERENCES lists (list_id) ON DELETE CASCADE,
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+    PRIMARY KEY (list_id, subscriber_id),
+    added_at timestamptz NOT NULL
+);
+CREATE INDEX list_subscriptions_subscriber_idx ON list_subscriptions (subscriber_id);
+
+-- The admin listing pages through subscribers on this key.
+CREATE INDEX subscriptions_subscribed_at_id_idx ON subscriptions (subscribed_at, id);
+
+-- Who did what, and to whom. Kept when the user or the subscriber is gone.
+CREATE TABLE audit_log(
+    audit_log_id uuid NOT NULL,
+    PRIMARY KEY (audit_log_id),
+    user_id uuid REFERENCES users (user_id) ON DELETE SET NULL,
+    action TEXT NOT NULL,
+    subscriber_id uuid,
+    details JSONB NOT NULL DEFAULT '{}',
+    occurred_at timestamptz NOT NULL
+);
+CREATE INDEX audit_log_subscriber_idx ON audit_log (subscriber_id, occurred_at);
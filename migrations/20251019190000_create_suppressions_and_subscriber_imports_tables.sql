@@ -0,0 +1,24 @@
+-- Addresses that must never be added back, e.g. by an import.
+CREATE TABLE suppressions(
+    email_canonical TEXT NOT NULL,
+    PRIMARY KEY (email_canonical),
+    reason TEXT NOT NULL,
+    created_at timestamptz NOT NULL
+);
+
+-- CSV imports, with their progress while they run.
+CREATE TABLE subscriber_imports(
+    import_id uuid NOT NULL,
+    PRIMARY KEY (import_id),
+    user_id uuid REFERENCES users (user_id) ON DELETE SET NULL,
+    mode TEXT NOT NULL,
+    status TEXT NOT NULL,
+    processed INTEGER NOT NULL DEFAULT 0,
+    imported INTEGER NOT NULL DEFAULT 0,
+    duplicates INTEGER NOT NULL DEFAULT 0,
+    suppressed INTEGER NOT NULL DEFAULT 0,
+    invalid INTEGER NOT NULL DEFAULT 0,
+    errors JSONB NOT NULL DEFAULT '[]',
+    started_at timestamptz NOT NULL,
+    finished_at timestamptz
+);
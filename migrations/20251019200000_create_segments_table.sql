@@ -0,0 +1,18 @@
+-- Segments match subscribers on their attributes: `@>` uses this index.
+CREATE INDEX subscriptions_attributes_idx ON subscriptions USING GIN (attributes jsonb_path_ops);
+CREATE INDEX subscriptions_status_idx ON subscriptions (status);
+-- And on their recent opens and clicks.
+CREATE INDEX issue_events_subscriber_idx ON issue_events (subscriber_id, kind, occurred_at);
+
+-- Saved filters over subscribers, e.g. `attributes.plan = "pro" and opened within 30 days`.
+CREATE TABLE segments(
+    segment_id uuid NOT NULL,
+    PRIMARY KEY (segment_id),
+    name TEXT NOT NULL UNIQUE,
+    expression TEXT NOT NULL,
+    created_at timestamptz NOT NULL,
+    updated_at timestamptz NOT NULL
+);
+
+-- Issues go to the confirmed subscribers of their segment, or to all of them.
+ALTER TABLE newsletter_issues ADD COLUMN segment_id uuid REFERENCES segments (segment_id);
@@ -0,0 +1,28 @@
+-- Where each confirmed subscriber is in the welcome series.
+CREATE TABLE welcome_series_enrollments(
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+    PRIMARY KEY (subscriber_id),
+    -- The index of the next step in the configured series.
+    next_step INTEGER NOT NULL DEFAULT 0,
+    next_send_at timestamptz NOT NULL,
+    n_retries SMALLINT NOT NULL DEFAULT 0,
+    enrolled_at timestamptz NOT NULL,
+    completed_at timestamptz,
+    -- e.g. `unsubscribed` or `suppressed`.
+    stopped_reason TEXT,
+    stopped_at timestamptz
+);
+-- The scheduler only ever looks at the enrollments still running.
+CREATE INDEX welcome_series_enrollments_due_idx ON welcome_series_enrollments (next_send_at)
+    WHERE completed_at IS NULL AND stopped_at IS NULL;
+
+-- What became of each step: `sent`, `skipped` (its condition did not match)
+-- or `failed`.
+CREATE TABLE welcome_series_deliveries(
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+    step INTEGER NOT NULL,
+    PRIMARY KEY (subscriber_id, step),
+    template TEXT NOT NULL,
+    outcome TEXT NOT NULL,
+    occurred_at timestamptz NOT NULL
+);
@@ -0,0 +1,12 @@
+-- The one reminder each pending subscriber gets, with a fresh confirmation
+-- link. The primary key makes sure nobody is reminded twice.
+CREATE TABLE confirmation_reminders(
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+    PRIMARY KEY (subscriber_id),
+    -- `sent` or `failed`: failed reminders are not tried again.
+    outcome TEXT NOT NULL,
+    sent_at timestamptz NOT NULL
+);
+-- The scheduler looks for old pending subscribers.
+CREATE INDEX subscriptions_pending_idx ON subscriptions (subscribed_at)
+    WHERE status = 'pending_confirmation';
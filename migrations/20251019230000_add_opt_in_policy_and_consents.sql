@@ -0,0 +1,18 @@
+-- `single` lists confirm signups right away, `double` ones send a
+-- confirmation link. NULL follows the deployment setting.
+ALTER TABLE lists ADD COLUMN opt_in TEXT CHECK (opt_in IN ('single', 'double'));
+
+-- What a subscriber agreed to when they signed up, and how.
+CREATE TABLE subscriber_consents(
+    consent_id uuid NOT NULL,
+    PRIMARY KEY (consent_id),
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+    -- The list they signed up to, if any.
+    list_id uuid REFERENCES lists (list_id) ON DELETE SET NULL,
+    -- `single_opt_in` or `double_opt_in`.
+    method TEXT NOT NULL,
+    ip_address TEXT,
+    user_agent TEXT,
+    given_at timestamptz NOT NULL
+);
+CREATE INDEX subscriber_consents_subscriber_idx ON subscriber_consents (subscriber_id);
@@ -0,0 +1,41 @@
+-- Where to send events about subscribers and deliveries.
+CREATE TABLE webhook_endpoints(
+    endpoint_id uuid NOT NULL,
+    PRIMARY KEY (endpoint_id),
+    url TEXT NOT NULL,
+    -- Signs the requests, so the receiver knows they come from us.
+    secret TEXT NOT NULL,
+    -- The event types the endpoint wants. Empty for all of them.
+    events TEXT[] NOT NULL DEFAULT '{}',
+    created_at timestamptz NOT NULL
+);
+
+-- The outbox: events are written in the transaction that caused them, and
+-- delivered to every interested endpoint afterwards.
+CREATE TABLE webhook_events(
+    event_id uuid NOT NULL,
+    PRIMARY KEY (event_id),
+    event_type TEXT NOT NULL,
+    -- The JSON body sent to the endpoints.
+    payload JSONB NOT NULL,
+    occurred_at timestamptz NOT NULL
+);
+
+CREATE TABLE webhook_deliveries(
+    delivery_id uuid NOT NULL,
+    PRIMARY KEY (delivery_id),
+    event_id uuid NOT NULL REFERENCES webhook_events (event_id) ON DELETE CASCADE,
+    endpoint_id uuid NOT NULL REFERENCES webhook_endpoints (endpoint_id) ON DELETE CASCADE,
+    -- `pending`, `delivered` or `failed` once out of attempts.
+    status TEXT NOT NULL,
+    n_attempts SMALLINT NOT NULL DEFAULT 0,
+    next_attempt_at timestamptz NOT NULL,
+    -- What the endpoint answered last time, if it answered at all.
+    last_status_code SMALLINT,
+    last_error TEXT,
+    created_at timestamptz NOT NULL,
+    delivered_at timestamptz
+);
+CREATE INDEX webhook_deliveries_due_idx ON webhook_deliveries (next_attempt_at)
+    WHERE status = 'pending';
+CREATE INDEX webhook_deliveries_endpoint_idx ON webhook_deliveries (endpoint_id, created_at);
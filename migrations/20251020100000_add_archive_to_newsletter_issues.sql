@@ -0,0 +1,26 @@
+-- Sent issues show up in the public archive only if they opted in.
+ALTER TABLE newsletter_issues ADD COLUMN archived BOOLEAN NOT NULL DEFAULT false;
+
+-- Where an issue lives in the archive, e.g. `/archive/hello-world-1b2c3d4e`.
+ALTER TABLE newsletter_issues ADD COLUMN slug TEXT;
+UPDATE newsletter_issues
+SET slug = COALESCE(
+        NULLIF(trim(BOTH '-' FROM lower(regexp_replace(title, '[^A-Za-z0-9]+', '-', 'g'))), ''),
+        'issue'
+    ) || '-' || left(newsletter_issue_id::text, 8);
+ALTER TABLE newsletter_issues ALTER COLUMN slug SET NOT NULL;
+ALTER TABLE newsletter_issues ADD CONSTRAINT newsletter_issues_slug_key UNIQUE (slug);
+
+-- Full-text search over the archive.
+ALTER TABLE newsletter_issues ADD COLUMN search_vector tsvector
+    GENERATED ALWAYS AS (
+        setweight(to_tsvector('english', title), 'A')
+        || setweight(
+            to_tsvector('english', COALESCE(content ->> 'markdown', content ->> 'text', '')),
+            'B'
+        )
+    ) STORED;
+CREATE INDEX newsletter_issues_search_idx ON newsletter_issues USING GIN (search_vector);
+CREATE INDEX newsletter_issues_archive_idx
+    ON newsletter_issues (sent_at DESC)
+    WHERE archived AND status = 'sent';
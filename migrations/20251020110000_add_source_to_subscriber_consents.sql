@@ -0,0 +1,3 @@
+-- The site the signup form was embedded in, e.g. `https://blog.example.com`.
+-- NULL for signups on our own pages and through the API.
+ALTER TABLE subscriber_consents ADD COLUMN source TEXT;
@@ -0,0 +1,23 @@
+-- What each user is allowed to do: `owner`, `editor`, `publisher` or
+-- `viewer`. The users from before roles existed could do anything.
+ALTER TABLE users ADD COLUMN role TEXT NOT NULL DEFAULT 'owner'
+    CHECK (role IN ('owner', 'editor', 'publisher', 'viewer'));
+-- From now on, every user is given a role on purpose.
+ALTER TABLE users ALTER COLUMN role DROP DEFAULT;
+
+-- People invited to join as a user, by email.
+CREATE TABLE user_invites(
+    invite_id uuid NOT NULL,
+    PRIMARY KEY (invite_id),
+    email TEXT NOT NULL,
+    role TEXT NOT NULL
+        CHECK (role IN ('owner', 'editor', 'publisher', 'viewer')),
+    -- Only a hash: the token itself is in the signup link, and nowhere else.
+    token_hash TEXT NOT NULL UNIQUE,
+    invited_by uuid REFERENCES users (user_id) ON DELETE SET NULL,
+    created_at timestamptz NOT NULL,
+    expires_at timestamptz NOT NULL,
+    -- Set once the invite has been used to sign up.
+    accepted_at timestamptz,
+    user_id uuid REFERENCES users (user_id) ON DELETE SET NULL
+);
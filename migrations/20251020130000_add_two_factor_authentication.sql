@@ -0,0 +1,36 @@
+-- TOTP two-factor authentication. The secret is set when a user starts
+-- enrolling, and only enforced once they confirmed a first code.
+ALTER TABLE users ADD COLUMN totp_secret TEXT;
+ALTER TABLE users ADD COLUMN totp_enabled_at timestamptz;
+-- The time step of the last code used: a code works only once.
+ALTER TABLE users ADD COLUMN totp_last_used_step BIGINT;
+
+-- One-time codes to log in without the authenticator app, only as hashes.
+CREATE TABLE recovery_codes(
+    user_id uuid NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    code_hash TEXT NOT NULL,
+    PRIMARY KEY (user_id, code_hash),
+    used_at timestamptz
+);
+
+-- Users logged in with their password, and a second factor if they have one.
+CREATE TABLE user_sessions(
+    session_id uuid NOT NULL,
+    PRIMARY KEY (session_id),
+    user_id uuid NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    -- Only a hash: the token itself is sent with each request.
+    token_hash TEXT NOT NULL UNIQUE,
+    created_at timestamptz NOT NULL,
+    expires_at timestamptz NOT NULL
+);
+CREATE INDEX user_sessions_user_idx ON user_sessions (user_id);
+
+-- Users who gave the right password, and still owe us a second factor.
+CREATE TABLE login_challenges(
+    challenge_id uuid NOT NULL,
+    PRIMARY KEY (challenge_id),
+    user_id uuid NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    token_hash TEXT NOT NULL UNIQUE,
+    n_failed_attempts SMALLINT NOT NULL DEFAULT 0,
+    expires_at timestamptz NOT NULL
+);
@@ -0,0 +1,12 @@
+-- Failed logins, per username and per IP address, to slow down and lock out
+-- whoever guesses passwords. Usernames are counted whether or not a user has
+-- them: lockouts must not tell which ones exist.
+CREATE TABLE login_failures(
+    scope TEXT NOT NULL CHECK (scope IN ('username', 'ip')),
+    key TEXT NOT NULL,
+    PRIMARY KEY (scope, key),
+    n_failures INTEGER NOT NULL,
+    last_failure_at timestamptz NOT NULL,
+    -- Only ever set for usernames: addresses are slowed down, not locked.
+    locked_until timestamptz
+);
@@ -0,0 +1,12 @@
+-- Links sent by email to users who forgot their password.
+CREATE TABLE password_reset_tokens(
+    -- Only a hash: the token itself is in the link, and nowhere else.
+    token_hash TEXT NOT NULL,
+    PRIMARY KEY (token_hash),
+    user_id uuid NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    created_at timestamptz NOT NULL,
+    expires_at timestamptz NOT NULL,
+    -- Set once the link has been used: it only works once.
+    used_at timestamptz
+);
+CREATE INDEX password_reset_tokens_user_idx ON password_reset_tokens (user_id);
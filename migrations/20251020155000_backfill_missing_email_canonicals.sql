@@ -0,0 +1,17 @@
+-- The duplicates left without a canonical email by the first backfill: the
+-- next migration needs one for every subscriber.
+-- Their case-insensitive form when nobody has it yet, for the oldest of them...
+UPDATE subscriptions s
+SET email_canonical = lower(s.email)
+WHERE s.id IN (SELECT DISTINCT ON (lower(email)) id
+               FROM subscriptions
+               WHERE email_canonical IS NULL
+               ORDER BY lower(email), subscribed_at)
+    AND NOT EXISTS (SELECT 1 FROM subscriptions o WHERE o.email_canonical = lower(s.email));
+
+-- ...their id otherwise, which no email can collide with, until
+-- `zero2prod canonicalize-emails` merges them into the subscriber they
+-- duplicate.
+UPDATE subscriptions
+SET email_canonical = id::text
+WHERE email_canonical IS NULL;
@@ -0,0 +1,11 @@
+-- Every subscriber has a canonical email once `zero2prod canonicalize-emails`
+-- has run: refuse to go on before that.
+DO $$
+BEGIN
+    IF EXISTS (SELECT 1 FROM subscriptions WHERE email_canonical IS NULL) THEN
+        RAISE EXCEPTION 'Some subscribers have no canonical email: run `zero2prod canonicalize-emails` first.';
+    END IF;
+END
+$$;
+
+ALTER TABLE subscriptions ALTER COLUMN email_canonical SET NOT NULL;
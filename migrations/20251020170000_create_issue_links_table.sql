@@ -0,0 +1,10 @@
+-- The links the click redirect of an issue may send readers to, recorded as
+-- the issue is sent: they keep working whatever happens to the issue, its
+-- subscribers or the branding afterwards.
+CREATE TABLE issue_links(
+    newsletter_issue_id uuid NOT NULL
+        REFERENCES newsletter_issues (newsletter_issue_id) ON DELETE CASCADE,
+    -- The SHA-256 of the link, in hex: links can be longer than an index entry.
+    url_hash TEXT NOT NULL,
+    PRIMARY KEY (newsletter_issue_id, url_hash)
+);
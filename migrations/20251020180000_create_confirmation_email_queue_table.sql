@@ -0,0 +1,7 @@
+-- Subscribers to send a confirmation email to in the background, e.g. the
+-- ones of an import: the import does not wait for their emails to go out.
+CREATE TABLE confirmation_email_queue(
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+    PRIMARY KEY (subscriber_id),
+    enqueued_at timestamptz NOT NULL
+);
@@ -0,0 +1,9 @@
+-- Form tokens already used for a signup: each is good for one.
+CREATE TABLE used_form_tokens(
+    -- Only a hash: no need to keep the token itself.
+    token_hash TEXT NOT NULL,
+    PRIMARY KEY (token_hash),
+    -- Expired tokens are refused anyway: past this, the row can go.
+    expires_at timestamptz NOT NULL
+);
+CREATE INDEX used_form_tokens_expires_at_idx ON used_form_tokens (expires_at);
@@ -1,15 +1,36 @@
-﻿pub mod lib {
+pub mod lib {
+    pub mod archive;
+    pub mod audit;
+    pub mod authentication;
+    pub mod bot_protection;
+    pub mod canonical_emails;
     pub mod configurations;
+    pub mod confirmation_reminders;
+    pub mod cors;
     pub mod domain;
     pub mod email_client;
+    pub mod email_templates;
+    pub mod invites;
+    pub mod issue_delivery_worker;
+    pub mod issues;
+    pub mod localization;
+    pub mod login_throttling;
+    pub mod markdown;
+    pub mod pages;
+    pub mod password_resets;
+    pub mod personalization;
+    pub mod reports;
+    pub mod roles;
     pub mod routes;
+    pub mod segments;
+    pub mod sessions;
     pub mod startup;
+    pub mod subscriber_csv;
+    pub mod subscribers;
     pub mod telemetry;
-}
-
-use actix_web::{HttpRequest, Responder};
-
-async fn greet(req: HttpRequest) -> impl Responder {
-    let name = req.match_info().get("name").unwrap_or("World");
-    format!("Hello {name}!")
+    pub mod totp;
+    pub mod tracking;
+    pub mod two_factor;
+    pub mod webhooks;
+    pub mod welcome_series;
 }
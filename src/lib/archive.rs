@@ -0,0 +1,162 @@
+use crate::lib::issues::{NewsletterContent, SENT};
+use crate::lib::personalization::{MergeFieldError, PersonalizedContent, Recipient};
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// A sent issue that opted into the public archive.
+pub struct ArchivedIssue {
+    pub slug: String,
+    pub title: String,
+    pub sent_at: DateTime<Utc>,
+    pub content: NewsletterContent,
+}
+
+impl ArchivedIssue {
+    /// The HTML of the issue as anybody can read it: merge fields are left
+    /// blank, and there is no tracking nor unsubscribe link, which only exist
+    /// in the emails.
+    pub fn html(&self, link_color: &str) -> Result<String, MergeFieldError> {
+        let content = PersonalizedContent::new(&self.content.render(link_color))?;
+        let (html, _) = content.render(&Recipient::anonymous())?;
+        Ok(html)
+    }
+}
+
+/// Where the issue lives in the archive: its title, made URL-friendly, and
+/// the start of its id to keep it unique.
+///
+/// Must match the backfill of the migration that added slugs.
+pub fn issue_slug(title: &str, newsletter_issue_id: Uuid) -> String {
+    let mut slug = String::with_capacity(title.len());
+    for c in title.chars() {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    if slug.ends_with('-') {
+        slug.pop();
+    }
+    if slug.is_empty() {
+        slug.push_str("issue");
+    }
+    let id = newsletter_issue_id.simple().to_string();
+    format!("{}-{}", slug, &id[..8])
+}
+
+struct ArchivedIssueRow {
+    slug: String,
+    title: String,
+    sent_at: DateTime<Utc>,
+    content: serde_json::Value,
+}
+
+impl TryFrom<ArchivedIssueRow> for ArchivedIssue {
+    type Error = sqlx::Error;
+
+    fn try_from(row: ArchivedIssueRow) -> Result<Self, Self::Error> {
+        Ok(Self {
+            slug: row.slug,
+            title: row.title,
+            sent_at: row.sent_at,
+            // Only ever written from a `NewsletterContent`.
+            content: serde_json::from_value(row.content)
+                .map_err(|e| sqlx::Error::Decode(Box::new(e)))?,
+        })
+    }
+}
+
+/// The latest archived issues, or the ones best matching `search`, e.g.
+/// `rust -async` or `"error handling"`.
+#[tracing::instrument(name = "List archived issues", skip(pool))]
+pub async fn list_archived(
+    pool: &PgPool,
+    search: Option<&str>,
+    limit: i64,
+) -> Result<Vec<ArchivedIssue>, sqlx::Error> {
+    let rows = sqlx::query_as!(
+        ArchivedIssueRow,
+        r#"
+        SELECT slug, title, sent_at AS "sent_at!", content
+        FROM newsletter_issues
+        WHERE archived
+            AND status = $1
+            AND ($2::text IS NULL OR search_vector @@ websearch_to_tsquery('english', $2))
+        ORDER BY ts_rank(search_vector, websearch_to_tsquery('english', COALESCE($2, ''))) DESC,
+            sent_at DESC
+        LIMIT $3
+        "#,
+        SENT,
+        search,
+        limit
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    rows.into_iter().map(ArchivedIssue::try_from).collect()
+}
+
+#[tracing::instrument(name = "Get an archived issue", skip(pool))]
+pub async fn get_archived(pool: &PgPool, slug: &str) -> Result<Option<ArchivedIssue>, sqlx::Error> {
+    let row = sqlx::query_as!(
+        ArchivedIssueRow,
+        r#"
+        SELECT slug, title, sent_at AS "sent_at!", content
+        FROM newsletter_issues
+        WHERE slug = $1 AND archived AND status = $2
+        "#,
+        slug,
+        SENT
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    row.map(ArchivedIssue::try_from).transpose()
+}
+
+/// When the archive last changed. Sent issues cannot be edited, so it is when
+/// the latest one went out.
+#[tracing::instrument(name = "Get when the archive last changed", skip(pool))]
+pub async fn archive_updated_at(pool: &PgPool) -> Result<Option<DateTime<Utc>>, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"SELECT max(sent_at) FROM newsletter_issues WHERE archived AND status = $1"#,
+        SENT
+    )
+    .fetch_one(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id() -> Uuid {
+        Uuid::parse_str("1b2c3d4e-0000-4000-8000-000000000000").unwrap()
+    }
+
+    #[test]
+    fn slugs_keep_letters_and_digits() {
+        assert_eq!(
+            issue_slug("  Rust 2024: what's new?! ", id()),
+            "rust-2024-what-s-new-1b2c3d4e"
+        );
+    }
+
+    #[test]
+    fn titles_without_letters_still_get_a_slug() {
+        assert_eq!(issue_slug("Ціни — 2025", id()), "2025-1b2c3d4e");
+        assert_eq!(issue_slug("¡¿!?", id()), "issue-1b2c3d4e");
+    }
+}
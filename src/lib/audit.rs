@@ -0,0 +1,34 @@
+use sqlx::PgExecutor;
+use uuid::Uuid;
+
+/// Record that `user_id` did `action`, e.g. `subscriber.deleted`, to
+/// `subscriber_id`.
+///
+/// Run it in the same transaction as the action: no action goes unrecorded.
+#[tracing::instrument(name = "Record in the audit log", skip(executor, details))]
+pub async fn record_action<'e>(
+    executor: impl PgExecutor<'e>,
+    user_id: Option<Uuid>,
+    action: &str,
+    subscriber_id: Option<Uuid>,
+    details: serde_json::Value,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO audit_log (audit_log_id, user_id, action, subscriber_id, details, occurred_at)
+        VALUES ($1, $2, $3, $4, $5, now())
+        "#,
+        Uuid::new_v4(),
+        user_id,
+        action,
+        subscriber_id,
+        details
+    )
+    .execute(executor)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(())
+}
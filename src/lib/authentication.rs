@@ -0,0 +1,385 @@
+use crate::lib::configurations::{AdminUserSettings, InitialOwnerSettings};
+use crate::lib::login_throttling::attempt_login;
+use crate::lib::roles::{Permission, RequiredPermission, Role};
+use crate::lib::sessions::{bearer_token, find_session};
+use actix_web::dev::Payload;
+use actix_web::http::StatusCode;
+use actix_web::http::header::{AUTHORIZATION, HeaderMap, RETRY_AFTER, WWW_AUTHENTICATE};
+use actix_web::{FromRequest, HttpRequest, HttpResponse, ResponseError, web};
+use argon2::password_hash::SaltString;
+use argon2::password_hash::rand_core::OsRng;
+use argon2::{Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version};
+use base64::Engine;
+use secrecy::{ExposeSecret, SecretString};
+use sqlx::PgPool;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use uuid::Uuid;
+
+pub struct Credentials {
+    pub username: String,
+    pub password: SecretString,
+}
+
+#[derive(Debug)]
+pub enum AuthError {
+    InvalidCredentials,
+    /// Too many failed logins for the username or the IP address: try again
+    /// later, without the password being checked.
+    TooManyAttempts(std::time::Duration),
+    Unexpected(String),
+}
+
+impl std::fmt::Display for AuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AuthError::InvalidCredentials => f.write_str("Invalid credentials."),
+            AuthError::TooManyAttempts(_) => f.write_str("Too many failed logins."),
+            AuthError::Unexpected(e) => write!(f, "Failed to check the credentials: {}", e),
+        }
+    }
+}
+
+/// Extract the credentials of the `Authorization: Basic ...` header.
+pub fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, String> {
+    let header = headers
+        .get(AUTHORIZATION)
+        .ok_or("The 'Authorization' header is missing.")?
+        .to_str()
+        .map_err(|_| "The 'Authorization' header is not a valid UTF-8 string.")?;
+    let encoded = header
+        .strip_prefix("Basic ")
+        .ok_or("The authorization scheme is not 'Basic'.")?;
+    let decoded = base64::engine::general_purpose::STANDARD
+        .decode(encoded)
+        .map_err(|_| "Failed to base64-decode the 'Basic' credentials.")?;
+    let decoded = String::from_utf8(decoded).map_err(|_| "The credentials are not valid UTF-8.")?;
+
+    let (username, password) = decoded
+        .split_once(':')
+        .ok_or("The 'Basic' credentials must be `username:password`.")?;
+    Ok(Credentials {
+        username: username.to_owned(),
+        password: SecretString::from(password),
+    })
+}
+
+/// A user who proved who they are.
+#[derive(Debug)]
+pub struct AuthenticatedUser {
+    pub user_id: Uuid,
+    pub role: Role,
+    pub two_factor_enabled: bool,
+}
+
+/// Checked instead of the hash of a user when there is no such user, so that
+/// unknown usernames take as long to turn down as wrong passwords. Same
+/// parameters as the hashes of actual users.
+const DUMMY_PASSWORD_HASH: &str = "$argon2id$v=19$m=15000,t=2,p=1$\
+    gZiV/M1gPc22ElAH/Jh1Hw$CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno";
+
+/// The user the credentials belong to.
+#[tracing::instrument(name = "Validate credentials", skip(credentials, pool))]
+pub async fn validate_credentials(
+    credentials: Credentials,
+    pool: &PgPool,
+) -> Result<AuthenticatedUser, AuthError> {
+    let row = sqlx::query!(
+        r#"
+        SELECT user_id, password_hash, role, totp_enabled_at IS NOT NULL AS "two_factor_enabled!"
+        FROM users
+        WHERE username = $1
+        "#,
+        credentials.username,
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| AuthError::Unexpected(e.to_string()))?;
+
+    let password_hash = SecretString::from(
+        row.as_ref()
+            .map_or(DUMMY_PASSWORD_HASH, |row| row.password_hash.as_str()),
+    );
+    // Hashing is CPU-bound and slow on purpose: keep it off the async workers.
+    let current_span = tracing::Span::current();
+    tokio::task::spawn_blocking(move || {
+        current_span.in_scope(|| verify_password_hash(&password_hash, &credentials.password))
+    })
+    .await
+    .map_err(|e| AuthError::Unexpected(e.to_string()))??;
+
+    // Nobody knows a password matching the dummy hash, but still.
+    let row = row.ok_or(AuthError::InvalidCredentials)?;
+    let role = Role::parse(&row.role)
+        .ok_or_else(|| AuthError::Unexpected(format!("Unknown role: {}", row.role)))?;
+    Ok(AuthenticatedUser {
+        user_id: row.user_id,
+        role,
+        two_factor_enabled: row.two_factor_enabled,
+    })
+}
+
+#[tracing::instrument(name = "Verify password hash", skip(expected, candidate))]
+fn verify_password_hash(
+    expected: &SecretString,
+    candidate: &SecretString,
+) -> Result<(), AuthError> {
+    let expected = PasswordHash::new(expected.expose_secret())
+        .map_err(|e| AuthError::Unexpected(e.to_string()))?;
+    Argon2::default()
+        .verify_password(candidate.expose_secret().as_bytes(), &expected)
+        .map_err(|_| AuthError::InvalidCredentials)
+}
+
+/// The length, in characters, of the passwords users can pick.
+const PASSWORD_LENGTH: std::ops::RangeInclusive<usize> = 12..=128;
+
+pub fn is_acceptable_password(password: &SecretString) -> bool {
+    PASSWORD_LENGTH.contains(&password.expose_secret().chars().count())
+}
+
+/// Hash a new password.
+pub fn compute_password_hash(password: &SecretString) -> Result<SecretString, AuthError> {
+    let salt = SaltString::generate(&mut OsRng);
+    let password_hash = Argon2::new(
+        Algorithm::Argon2id,
+        Version::V0x13,
+        Params::new(15000, 2, 1, None).map_err(|e| AuthError::Unexpected(e.to_string()))?,
+    )
+    .hash_password(password.expose_secret().as_bytes(), &salt)
+    .map_err(|e| AuthError::Unexpected(e.to_string()))?
+    .to_string();
+    Ok(SecretString::from(password_hash))
+}
+
+/// Create the initial owner, unless there are users already: changing its
+/// password in the configuration afterwards changes nothing.
+///
+/// Returns whether it was created.
+#[tracing::instrument(
+    name = "Create the initial owner",
+    skip(pool, owner),
+    fields(username = %owner.username)
+)]
+pub async fn create_initial_owner(
+    pool: &PgPool,
+    owner: &InitialOwnerSettings,
+) -> Result<bool, AuthError> {
+    if !is_acceptable_password(&owner.password) {
+        return Err(AuthError::Unexpected(format!(
+            "The initial owner password must be {} to {} characters long.",
+            PASSWORD_LENGTH.start(),
+            PASSWORD_LENGTH.end()
+        )));
+    }
+    let password = owner.password.clone();
+    let password_hash = tokio::task::spawn_blocking(move || compute_password_hash(&password))
+        .await
+        .map_err(|e| AuthError::Unexpected(e.to_string()))??;
+    let created = sqlx::query!(
+        r#"
+        INSERT INTO users (user_id, username, password_hash, email, role)
+        SELECT $1, $2, $3, $4, $5
+        WHERE NOT EXISTS (SELECT 1 FROM users)
+        ON CONFLICT (username) DO NOTHING
+        "#,
+        Uuid::new_v4(),
+        owner.username,
+        password_hash.expose_secret(),
+        owner.email,
+        Role::Owner.as_str()
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| AuthError::Unexpected(e.to_string()))?;
+    Ok(created.rows_affected() > 0)
+}
+
+/// Why an admin request was turned down.
+#[derive(Debug)]
+pub enum AccessDenied {
+    /// No valid credentials: ask for them.
+    Unauthenticated,
+    /// Valid credentials, but the role of the user does not allow it.
+    Forbidden,
+    /// A password alone, for a user with two-factor authentication: log in
+    /// to get a session.
+    SessionRequired,
+    /// Two-factor authentication is mandatory for the role of the user, and
+    /// they have not turned it on yet.
+    TwoFactorRequired,
+    /// Too many failed logins: wait that long.
+    TooManyAttempts(std::time::Duration),
+    Unexpected,
+}
+
+impl std::fmt::Display for AccessDenied {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AccessDenied::Unauthenticated => f.write_str("Invalid credentials."),
+            AccessDenied::Forbidden => f.write_str("Your role does not allow this."),
+            AccessDenied::SessionRequired => f.write_str(
+                "Two-factor authentication is on: log in at /admin/sessions, \
+                 and send the session token as 'Authorization: Bearer ...'.",
+            ),
+            AccessDenied::TwoFactorRequired => f.write_str(
+                "Two-factor authentication is mandatory for your role: \
+                 turn it on at /admin/account/two-factor first.",
+            ),
+            AccessDenied::TooManyAttempts(_) => {
+                f.write_str("Too many failed logins: try again later.")
+            }
+            AccessDenied::Unexpected => f.write_str("Failed to check the credentials."),
+        }
+    }
+}
+
+impl ResponseError for AccessDenied {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AccessDenied::Unauthenticated | AccessDenied::SessionRequired => {
+                StatusCode::UNAUTHORIZED
+            }
+            AccessDenied::Forbidden | AccessDenied::TwoFactorRequired => StatusCode::FORBIDDEN,
+            AccessDenied::TooManyAttempts(_) => StatusCode::TOO_MANY_REQUESTS,
+            AccessDenied::Unexpected => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            AccessDenied::Unauthenticated => HttpResponse::Unauthorized()
+                .insert_header((WWW_AUTHENTICATE, r#"Basic realm="publish""#))
+                .finish(),
+            AccessDenied::SessionRequired => HttpResponse::Unauthorized()
+                .insert_header((WWW_AUTHENTICATE, "Bearer"))
+                .body(self.to_string()),
+            AccessDenied::Forbidden | AccessDenied::TwoFactorRequired => {
+                HttpResponse::Forbidden().body(self.to_string())
+            }
+            AccessDenied::TooManyAttempts(retry_after) => HttpResponse::TooManyRequests()
+                // Rounded up: retrying any earlier is refused again.
+                .insert_header((
+                    RETRY_AFTER,
+                    retry_after.as_millis().div_ceil(1000).to_string(),
+                ))
+                .body(self.to_string()),
+            AccessDenied::Unexpected => HttpResponse::InternalServerError().finish(),
+        }
+    }
+}
+
+/// The user behind an admin request, allowed to do what `P` stands for.
+///
+/// Every admin route takes one: the request is turned down unless it carries
+/// the token of a session, or `Authorization: Basic ...` credentials of a
+/// user without two-factor authentication, and the role of the user grants
+/// the permission. Passwords are checked like any login: failures slow the
+/// next attempts down.
+pub struct Authorized<P> {
+    pub user_id: Uuid,
+    pub role: Role,
+    permission: PhantomData<P>,
+}
+
+impl<P: RequiredPermission> FromRequest for Authorized<P> {
+    type Error = AccessDenied;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let session_token = bearer_token(req.headers()).map(str::to_owned);
+        let credentials = basic_authentication(req.headers());
+        let pool = req
+            .app_data::<web::Data<PgPool>>()
+            .cloned()
+            .expect("The connection pool is registered.");
+        let settings = req
+            .app_data::<web::Data<AdminUserSettings>>()
+            .cloned()
+            .expect("The admin user settings are registered.");
+        let req = req.clone();
+        Box::pin(async move {
+            let user = match session_token {
+                Some(token) => find_session(&pool, &token)
+                    .await
+                    .map_err(|_| AccessDenied::Unexpected)?
+                    .ok_or_else(|| {
+                        tracing::info!("Rejected a request with an unknown or expired session.");
+                        AccessDenied::Unauthenticated
+                    })?,
+                None => {
+                    let credentials = credentials.map_err(|e| {
+                        tracing::info!(error = %e, "Rejected a request without valid credentials.");
+                        AccessDenied::Unauthenticated
+                    })?;
+                    let user = attempt_login(&req, credentials)
+                        .await
+                        .map_err(|e| match e {
+                            AuthError::InvalidCredentials => AccessDenied::Unauthenticated,
+                            AuthError::TooManyAttempts(retry_after) => {
+                                AccessDenied::TooManyAttempts(retry_after)
+                            }
+                            AuthError::Unexpected(e) => {
+                                tracing::error!(error = %e, "Failed to authenticate a request.");
+                                AccessDenied::Unexpected
+                            }
+                        })?;
+                    if user.two_factor_enabled {
+                        tracing::info!(
+                            user_id = %user.user_id,
+                            "Refused a password alone for a user with two-factor authentication."
+                        );
+                        return Err(AccessDenied::SessionRequired);
+                    }
+                    user
+                }
+            };
+            let AuthenticatedUser {
+                user_id,
+                role,
+                two_factor_enabled,
+            } = user;
+            if !role.can(P::PERMISSION) {
+                tracing::info!(
+                    %user_id,
+                    role = role.as_str(),
+                    permission = ?P::PERMISSION,
+                    "Refused a request the role does not allow."
+                );
+                return Err(AccessDenied::Forbidden);
+            }
+            if P::PERMISSION != Permission::ManageOwnAccount
+                && !two_factor_enabled
+                && settings.requires_two_factor(role)
+            {
+                tracing::info!(
+                    %user_id,
+                    role = role.as_str(),
+                    "Refused a request until two-factor authentication is on."
+                );
+                return Err(AccessDenied::TwoFactorRequired);
+            }
+            Ok(Self {
+                user_id,
+                role,
+                permission: PhantomData,
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_dummy_hash_takes_as_long_to_check_as_actual_ones() {
+        let dummy = PasswordHash::new(DUMMY_PASSWORD_HASH).unwrap();
+        let actual = compute_password_hash(&SecretString::from("a very long password")).unwrap();
+        let actual = PasswordHash::new(actual.expose_secret()).unwrap();
+        assert_eq!(dummy.algorithm, actual.algorithm);
+        assert_eq!(dummy.version, actual.version);
+        assert_eq!(dummy.params, actual.params);
+    }
+}
@@ -0,0 +1,320 @@
+use crate::lib::configurations::BotProtectionSettings;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, SecretString};
+use sha2::{Digest, Sha256};
+use sqlx::{Postgres, Transaction};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// The anti-spam fields a signup form may carry along with the subscriber
+/// details.
+pub struct BotCheck<'a> {
+    /// A field hidden from humans with CSS: any value means a bot filled it.
+    pub honeypot: Option<&'a str>,
+    /// The signed timestamp handed out when the form was rendered.
+    pub form_token: Option<&'a str>,
+    /// The client-side proof-of-work solution for `form_token`.
+    pub pow_nonce: Option<&'a str>,
+}
+
+/// Why a submission has been flagged as automated.
+#[derive(Debug, PartialEq)]
+pub enum BotRejection {
+    Honeypot,
+    MissingFormToken,
+    InvalidFormToken,
+    TooFast,
+    Expired,
+    MissingProofOfWork,
+    InvalidProofOfWork,
+    Replayed,
+}
+
+impl std::fmt::Display for BotRejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let reason = match self {
+            BotRejection::Honeypot => "honeypot field was filled",
+            BotRejection::MissingFormToken => "form token is missing",
+            BotRejection::InvalidFormToken => "form token signature is invalid",
+            BotRejection::TooFast => "form was submitted too fast",
+            BotRejection::Expired => "form token has expired",
+            BotRejection::MissingProofOfWork => "proof-of-work nonce is missing",
+            BotRejection::InvalidProofOfWork => "proof-of-work nonce is invalid",
+            BotRejection::Replayed => "form token has already been used",
+        };
+        f.write_str(reason)
+    }
+}
+
+/// A freshly issued form token, together with the proof-of-work difficulty the
+/// client has to solve for it (if any).
+#[derive(serde::Serialize)]
+pub struct FormChallenge {
+    pub form_token: String,
+    pub pow_difficulty: Option<u8>,
+}
+
+/// Issues and verifies signed form-render timestamps and proof-of-work
+/// challenges. Everything is verified locally: no third-party service involved.
+pub struct BotProtection {
+    hmac_secret: SecretString,
+    min_submit_seconds: i64,
+    max_form_age_seconds: i64,
+    require_form_token: bool,
+    pow_difficulty: Option<u8>,
+    rejections: AtomicU64,
+}
+
+impl BotProtection {
+    pub fn new(settings: BotProtectionSettings) -> Self {
+        Self {
+            hmac_secret: settings.hmac_secret,
+            min_submit_seconds: settings.min_submit_seconds as i64,
+            max_form_age_seconds: settings.max_form_age_seconds as i64,
+            require_form_token: settings.require_form_token,
+            pow_difficulty: settings.pow_difficulty,
+            rejections: AtomicU64::new(0),
+        }
+    }
+
+    /// Sign the moment the form has been rendered, as `<timestamp>.<signature>`.
+    pub fn issue(&self, now: i64) -> FormChallenge {
+        FormChallenge {
+            form_token: format!("{}.{}", now, hex::encode(self.sign(now))),
+            pow_difficulty: self.pow_difficulty,
+        }
+    }
+
+    /// Check a submission received at `now` (seconds since the Unix epoch).
+    ///
+    /// The form token it carries, if any, still has to be spent once the
+    /// submission turns out to be valid.
+    pub fn verify(&self, check: &BotCheck, now: i64) -> Result<Option<FormToken>, BotRejection> {
+        if check.honeypot.is_some_and(|value| !value.is_empty()) {
+            return Err(BotRejection::Honeypot);
+        }
+
+        let form_token = match check.form_token.filter(|token| !token.is_empty()) {
+            Some(token) => token,
+            // Without a token there is nothing else we can check.
+            None if self.require_form_token || self.pow_difficulty.is_some() => {
+                return Err(BotRejection::MissingFormToken);
+            }
+            None => return Ok(None),
+        };
+        let rendered_at = self.verify_form_token(form_token)?;
+        let elapsed = now - rendered_at;
+        if elapsed < self.min_submit_seconds {
+            return Err(BotRejection::TooFast);
+        }
+        if elapsed > self.max_form_age_seconds {
+            return Err(BotRejection::Expired);
+        }
+
+        if let Some(difficulty) = self.pow_difficulty {
+            let nonce = check.pow_nonce.ok_or(BotRejection::MissingProofOfWork)?;
+            if leading_zero_bits(&pow_digest(form_token, nonce)) < u32::from(difficulty) {
+                return Err(BotRejection::InvalidProofOfWork);
+            }
+        }
+
+        Ok(Some(FormToken {
+            token_hash: hex::encode(Sha256::digest(form_token.as_bytes())),
+            expires_at: DateTime::from_timestamp(rendered_at + self.max_form_age_seconds, 0)
+                .ok_or(BotRejection::InvalidFormToken)?,
+        }))
+    }
+
+    /// Count a rejected submission, returning the total so far.
+    pub fn record_rejection(&self) -> u64 {
+        self.rejections.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    pub fn rejections(&self) -> u64 {
+        self.rejections.load(Ordering::Relaxed)
+    }
+
+    fn verify_form_token(&self, form_token: &str) -> Result<i64, BotRejection> {
+        let (timestamp, signature) = form_token
+            .split_once('.')
+            .ok_or(BotRejection::InvalidFormToken)?;
+        let timestamp = timestamp
+            .parse::<i64>()
+            .map_err(|_| BotRejection::InvalidFormToken)?;
+        let signature = hex::decode(signature).map_err(|_| BotRejection::InvalidFormToken)?;
+
+        // `verify_slice` compares in constant time.
+        self.mac(timestamp)
+            .verify_slice(&signature)
+            .map_err(|_| BotRejection::InvalidFormToken)?;
+        Ok(timestamp)
+    }
+
+    fn sign(&self, timestamp: i64) -> Vec<u8> {
+        self.mac(timestamp).finalize().into_bytes().to_vec()
+    }
+
+    fn mac(&self, timestamp: i64) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(self.hmac_secret.expose_secret().as_bytes())
+            .expect("HMAC can take a key of any size");
+        mac.update(b"form-token:");
+        mac.update(timestamp.to_string().as_bytes());
+        mac
+    }
+}
+
+/// A form token that passed every check: it is good for a single signup.
+#[derive(Debug)]
+pub struct FormToken {
+    token_hash: String,
+    expires_at: DateTime<Utc>,
+}
+
+impl FormToken {
+    /// Record that the token has been used, in the transaction saving the
+    /// signup: a submission rejected for its content can be fixed and sent
+    /// again with the same form. Returns whether it was still unused.
+    #[tracing::instrument(name = "Spend a form token", skip_all)]
+    pub async fn spend(
+        &self,
+        transaction: &mut Transaction<'_, Postgres>,
+    ) -> Result<bool, sqlx::Error> {
+        sqlx::query!("DELETE FROM used_form_tokens WHERE expires_at < now()")
+            .execute(&mut **transaction)
+            .await?;
+        let inserted = sqlx::query!(
+            r#"
+            INSERT INTO used_form_tokens (token_hash, expires_at)
+            VALUES ($1, $2)
+            ON CONFLICT (token_hash) DO NOTHING
+            "#,
+            self.token_hash,
+            self.expires_at
+        )
+        .execute(&mut **transaction)
+        .await?;
+        Ok(inserted.rows_affected() > 0)
+    }
+}
+
+/// The client has to find a `nonce` such that `SHA-256("<form_token>:<nonce>")`
+/// starts with at least `pow_difficulty` zero bits.
+pub fn pow_digest(form_token: &str, nonce: &str) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(form_token.as_bytes());
+    hasher.update(b":");
+    hasher.update(nonce.as_bytes());
+    hasher.finalize().into()
+}
+
+fn leading_zero_bits(digest: &[u8]) -> u32 {
+    let mut bits = 0;
+    for byte in digest {
+        if *byte == 0 {
+            bits += 8;
+        } else {
+            bits += byte.leading_zeros();
+            break;
+        }
+    }
+    bits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use claims::{assert_err_eq, assert_ok};
+
+    const NOW: i64 = 1_700_000_000;
+
+    fn bot_protection(pow_difficulty: Option<u8>) -> BotProtection {
+        BotProtection::new(BotProtectionSettings {
+            hmac_secret: SecretString::from("super-secret"),
+            min_submit_seconds: 3,
+            max_form_age_seconds: 3600,
+            require_form_token: true,
+            pow_difficulty,
+        })
+    }
+
+    fn check<'a>(form_token: &'a str, pow_nonce: Option<&'a str>) -> BotCheck<'a> {
+        BotCheck {
+            honeypot: None,
+            form_token: Some(form_token),
+            pow_nonce,
+        }
+    }
+
+    #[test]
+    fn a_filled_honeypot_is_rejected() {
+        let protection = bot_protection(None);
+        let token = protection.issue(NOW).form_token;
+        let check = BotCheck {
+            honeypot: Some("http://spam.example"),
+            ..check(&token, None)
+        };
+        assert_err_eq!(protection.verify(&check, NOW + 10), BotRejection::Honeypot);
+    }
+
+    #[test]
+    fn a_form_submitted_after_the_minimum_delay_is_accepted() {
+        let protection = bot_protection(None);
+        let token = protection.issue(NOW).form_token;
+        assert_ok!(protection.verify(&check(&token, None), NOW + 3));
+    }
+
+    #[test]
+    fn a_form_submitted_too_fast_is_rejected() {
+        let protection = bot_protection(None);
+        let token = protection.issue(NOW).form_token;
+        assert_err_eq!(
+            protection.verify(&check(&token, None), NOW + 1),
+            BotRejection::TooFast
+        );
+    }
+
+    #[test]
+    fn a_stale_form_is_rejected() {
+        let protection = bot_protection(None);
+        let token = protection.issue(NOW).form_token;
+        assert_err_eq!(
+            protection.verify(&check(&token, None), NOW + 3601),
+            BotRejection::Expired
+        );
+    }
+
+    #[test]
+    fn a_tampered_timestamp_is_rejected() {
+        let protection = bot_protection(None);
+        let token = protection.issue(NOW).form_token;
+        let (_, signature) = token.split_once('.').unwrap();
+        let forged = format!("{}.{}", NOW - 60, signature);
+        assert_err_eq!(
+            protection.verify(&check(&forged, None), NOW + 10),
+            BotRejection::InvalidFormToken
+        );
+    }
+
+    #[test]
+    fn a_valid_proof_of_work_is_accepted() {
+        let protection = bot_protection(Some(8));
+        let token = protection.issue(NOW).form_token;
+        let nonce = (0u64..)
+            .map(|n| n.to_string())
+            .find(|nonce| leading_zero_bits(&pow_digest(&token, nonce)) >= 8)
+            .unwrap();
+        assert_ok!(protection.verify(&check(&token, Some(&nonce)), NOW + 10));
+    }
+
+    #[test]
+    fn a_missing_proof_of_work_is_rejected() {
+        let protection = bot_protection(Some(8));
+        let token = protection.issue(NOW).form_token;
+        assert_err_eq!(
+            protection.verify(&check(&token, None), NOW + 10),
+            BotRejection::MissingProofOfWork
+        );
+    }
+}
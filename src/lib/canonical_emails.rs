@@ -0,0 +1,146 @@
+use crate::lib::audit::record_action;
+use crate::lib::domain::SubscriberEmail;
+use crate::lib::routes::CONFIRMED;
+use sqlx::{PgPool, Postgres, Transaction};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// What `canonicalize_subscriber_emails` changed.
+#[derive(Debug, Default, PartialEq)]
+pub struct CanonicalizationReport {
+    /// Subscribers whose canonical email was missing or out of date.
+    pub updated: usize,
+    /// Subscribers removed as duplicates of another one.
+    pub removed: usize,
+}
+
+struct Subscriber {
+    id: Uuid,
+    email: String,
+    email_canonical: Option<String>,
+    status: String,
+}
+
+/// Compute the canonical email of every subscriber, the way signups do.
+///
+/// Subscribers who turn out to share one are merged into a single one: the
+/// oldest confirmed subscriber if any, the oldest otherwise. The others are
+/// deleted, after adding their lists to the one kept, and the deletion is
+/// recorded in the audit log.
+#[tracing::instrument(name = "Canonicalize subscriber emails", skip(pool))]
+pub async fn canonicalize_subscriber_emails(
+    pool: &PgPool,
+    provider_aware: bool,
+) -> Result<CanonicalizationReport, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    // Signups wait until we are done, so none of them is missed.
+    sqlx::query!("LOCK TABLE subscriptions IN SHARE ROW EXCLUSIVE MODE")
+        .execute(&mut *transaction)
+        .await?;
+    let subscribers = sqlx::query_as!(
+        Subscriber,
+        r#"
+        SELECT id, email, email_canonical AS "email_canonical?", status
+        FROM subscriptions
+        ORDER BY subscribed_at, id
+        "#
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+
+    // Subscribers by canonical email, oldest first.
+    let mut groups: HashMap<String, Vec<Subscriber>> = HashMap::new();
+    for subscriber in subscribers {
+        let canonical = match SubscriberEmail::parse(&subscriber.email) {
+            Ok(email) => email.canonical(provider_aware),
+            Err(_) => {
+                // No signup can collide with it: any key unique to it does.
+                tracing::warn!(subscriber_id = %subscriber.id, "A subscriber has an invalid email.");
+                subscriber.email.trim().to_lowercase()
+            }
+        };
+        groups.entry(canonical).or_default().push(subscriber);
+    }
+
+    let mut report = CanonicalizationReport::default();
+    let mut updated_ids = Vec::new();
+    let mut updated_canonicals = Vec::new();
+    for (canonical, mut group) in groups {
+        let kept_index = group
+            .iter()
+            .position(|subscriber| subscriber.status == CONFIRMED)
+            .unwrap_or(0);
+        let kept = group.remove(kept_index);
+        for duplicate in group {
+            remove_duplicate(&mut transaction, &duplicate, kept.id).await?;
+            report.removed += 1;
+        }
+        if kept.email_canonical.as_deref() != Some(canonical.as_str()) {
+            updated_ids.push(kept.id);
+            updated_canonicals.push(canonical);
+        }
+    }
+
+    // Set aside first, under their id which no email can collide with: a
+    // subscriber may take the key another one had so far.
+    sqlx::query!(
+        r#"UPDATE subscriptions SET email_canonical = id::text WHERE id = ANY($1)"#,
+        &updated_ids
+    )
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions s SET email_canonical = t.email_canonical
+        FROM UNNEST($1::uuid[], $2::text[]) AS t(id, email_canonical)
+        WHERE s.id = t.id
+        "#,
+        &updated_ids,
+        &updated_canonicals
+    )
+    .execute(&mut *transaction)
+    .await?;
+    report.updated = updated_ids.len();
+
+    transaction.commit().await?;
+    Ok(report)
+}
+
+async fn remove_duplicate(
+    transaction: &mut Transaction<'_, Postgres>,
+    duplicate: &Subscriber,
+    kept_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO list_subscriptions (list_id, subscriber_id, added_at)
+        SELECT list_id, $2, added_at FROM list_subscriptions WHERE subscriber_id = $1
+        ON CONFLICT (list_id, subscriber_id) DO NOTHING
+        "#,
+        duplicate.id,
+        kept_id
+    )
+    .execute(&mut **transaction)
+    .await?;
+    sqlx::query!(
+        r#"DELETE FROM subscription_tokens WHERE subscriber_id = $1"#,
+        duplicate.id
+    )
+    .execute(&mut **transaction)
+    .await?;
+    sqlx::query!(r#"DELETE FROM subscriptions WHERE id = $1"#, duplicate.id)
+        .execute(&mut **transaction)
+        .await?;
+    record_action(
+        &mut **transaction,
+        None,
+        "subscriber.deleted_duplicate",
+        Some(duplicate.id),
+        serde_json::json!({
+            "email": duplicate.email,
+            "status": duplicate.status,
+            "kept_subscriber_id": kept_id,
+        }),
+    )
+    .await
+}
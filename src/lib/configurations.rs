@@ -1,4 +1,5 @@
 use crate::lib::domain::SubscriberEmail;
+use crate::lib::roles::{Permission, Role};
 use secrecy::{ExposeSecret, SecretString};
 use serde_aux::field_attributes::deserialize_number_from_string;
 use sqlx::ConnectOptions;
@@ -9,6 +10,228 @@ pub struct Setting {
     pub database: DatabaseSettings,
     pub application: ApplicationSettings,
     pub email_client: EmailClientSettings,
+    pub bot_protection: BotProtectionSettings,
+    pub subscriptions: SubscriptionSettings,
+    pub branding: BrandingSettings,
+    pub localization: LocalizationSettings,
+    pub email_templates: EmailTemplateSettings,
+    pub issue_delivery: IssueDeliverySettings,
+    pub confirmation_reminders: ConfirmationReminderSettings,
+    pub welcome_series: WelcomeSeriesSettings,
+    pub webhooks: WebhookSettings,
+    pub embed: EmbedSettings,
+    pub admin_users: AdminUserSettings,
+    pub login_throttling: LoginThrottlingSettings,
+}
+
+#[derive(Clone, serde::Deserialize)]
+pub struct IssueDeliverySettings {
+    /// How long the worker waits before looking for work again when there is
+    /// nothing to do.
+    pub poll_interval_milliseconds: u64,
+    /// Give up on an email after this many failed attempts.
+    pub max_retries: i16,
+}
+
+/// The people running the newsletter.
+#[derive(Clone, serde::Deserialize)]
+pub struct AdminUserSettings {
+    /// How long the signup link sent with an invite stays valid.
+    pub invite_ttl_hours: i64,
+    /// How long the link sent to users who forgot their password stays valid.
+    pub password_reset_ttl_minutes: i64,
+    /// How long users stay logged in.
+    pub session_ttl_hours: i64,
+    /// Make two-factor authentication mandatory for the users who can send
+    /// issues: publishers, and owners. Until they turn it on, they can only
+    /// reach their own account.
+    #[serde(default)]
+    pub two_factor_mandatory_for_publishers: bool,
+    /// The owner to create at startup while there are no users at all, to be
+    /// able to log in after a fresh deployment. Best set from the
+    /// environment, e.g. `APP_ADMIN_USERS__INITIAL_OWNER__PASSWORD`.
+    #[serde(default)]
+    pub initial_owner: Option<InitialOwnerSettings>,
+}
+
+#[derive(Clone, serde::Deserialize)]
+pub struct InitialOwnerSettings {
+    pub username: String,
+    pub password: SecretString,
+    pub email: Option<String>,
+}
+
+impl AdminUserSettings {
+    /// Whether users with `role` must turn two-factor authentication on.
+    pub fn requires_two_factor(&self, role: Role) -> bool {
+        self.two_factor_mandatory_for_publishers && role.can(Permission::SendIssues)
+    }
+}
+
+/// Slowing down whoever guesses passwords.
+#[derive(Clone, serde::Deserialize)]
+pub struct LoginThrottlingSettings {
+    /// Lock an account after this many failed logins in a row.
+    pub max_failures_per_account: i32,
+    /// How long a locked account stays locked. Failures older than this are
+    /// forgotten too.
+    pub lockout_minutes: i32,
+    /// How long to wait after a first failed login, per account and per IP
+    /// address. The wait doubles with each failure after that.
+    pub base_delay_milliseconds: i64,
+    pub max_delay_seconds: i64,
+}
+
+/// The signup form other sites can embed.
+#[derive(Clone, serde::Deserialize)]
+pub struct EmbedSettings {
+    /// The sites allowed to embed it, e.g. `https://blog.example.com`. Browsers
+    /// on any other site are refused cross-origin requests.
+    #[serde(default)]
+    pub allowed_origins: Vec<String>,
+}
+
+/// Events sent to the endpoints registered by admins.
+#[derive(Clone, serde::Deserialize)]
+pub struct WebhookSettings {
+    pub poll_interval_milliseconds: u64,
+    /// Give up on a delivery after this many failed attempts.
+    pub max_attempts: i16,
+    /// How long an endpoint has to answer.
+    pub timeout_milliseconds: u64,
+}
+
+/// The nudge sent to subscribers who did not click their confirmation link.
+#[derive(Clone, serde::Deserialize)]
+pub struct ConfirmationReminderSettings {
+    pub poll_interval_milliseconds: u64,
+    /// How long after signing up a pending subscriber gets the reminder.
+    pub remind_after_hours: i32,
+    /// How long after the reminder a subscriber who still did not confirm
+    /// is deleted.
+    pub delete_after_hours: i32,
+}
+
+/// Emails sent to every subscriber after they confirm, one step at a time.
+#[derive(Clone, serde::Deserialize)]
+pub struct WelcomeSeriesSettings {
+    pub poll_interval_milliseconds: u64,
+    /// Give up on a step after this many failed attempts, and move on.
+    pub max_retries: i16,
+    #[serde(default)]
+    pub steps: Vec<WelcomeStepSettings>,
+}
+
+#[derive(Clone, serde::Deserialize)]
+pub struct WelcomeStepSettings {
+    /// How long after confirming the subscriber gets the step.
+    pub delay_days: u32,
+    /// The email template, rendered with `name`, `email` and
+    /// `unsubscribe_link`.
+    pub template: String,
+    /// The translation key of the subject, given the `site` name.
+    pub subject: String,
+    /// A segment expression the subscriber has to match to get the step.
+    /// The others skip it.
+    #[serde(default)]
+    pub condition: Option<String>,
+}
+
+#[derive(Clone, serde::Deserialize)]
+pub struct EmailTemplateSettings {
+    /// Holds a `<name>.html` and a `<name>.txt` file per template.
+    pub directory: String,
+    /// Let the templates stored in the `email_templates` table replace the
+    /// ones from the directory, so they can be edited without a redeploy.
+    pub load_from_database: bool,
+}
+
+#[derive(Clone, serde::Deserialize)]
+pub struct LocalizationSettings {
+    /// Holds one folder of Fluent `.ftl` catalogs per locale.
+    pub directory: String,
+    /// Used when we don't speak the subscriber's language and for messages
+    /// missing from their catalog.
+    pub default_locale: String,
+}
+
+/// How the public pages look.
+#[derive(Clone, serde::Deserialize)]
+pub struct BrandingSettings {
+    pub site_name: String,
+    pub tagline: String,
+    /// Any CSS color.
+    pub accent_color: String,
+    pub logo_url: Option<String>,
+    pub footer: String,
+}
+
+#[derive(Clone, serde::Deserialize)]
+pub struct SubscriptionSettings {
+    /// Fold provider specific aliases (e.g. Gmail dots and `+tags`) when
+    /// looking for duplicate subscribers.
+    pub provider_aware_canonicalization: bool,
+    pub email_domains: EmailDomainSettings,
+    /// How long a confirmation link stays valid.
+    pub confirmation_token_ttl_hours: i64,
+    /// The subscriber attributes the signup form can set. The others it
+    /// sends are ignored.
+    #[serde(default)]
+    pub signup_attributes: Vec<String>,
+    /// Lists can override it.
+    #[serde(default)]
+    pub opt_in: OptIn,
+    /// Start the welcome series right away for single opt-in signups, in
+    /// place of the confirmation email.
+    #[serde(default)]
+    pub single_opt_in_welcome_email: bool,
+}
+
+/// Whether signups have to click a confirmation link.
+#[derive(Clone, Copy, Debug, Default, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OptIn {
+    /// Signups are confirmed right away.
+    Single,
+    /// Signups go through the confirmation email.
+    #[default]
+    Double,
+}
+
+impl TryFrom<&str> for OptIn {
+    type Error = String;
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "single" => Ok(Self::Single),
+            "double" => Ok(Self::Double),
+            other => Err(format!(
+                "{} is not a supported opt-in policy. Use either `single` or `double`.",
+                other
+            )),
+        }
+    }
+}
+
+#[derive(Clone, serde::Deserialize)]
+pub struct EmailDomainSettings {
+    /// Reject the bundled list of throwaway mailbox providers.
+    pub reject_disposable: bool,
+    #[serde(default)]
+    pub extra_disposable: Vec<String>,
+    /// Ask "did you mean ...?" for domains a typo away from a popular one.
+    pub suggest_typos: bool,
+}
+
+#[derive(Clone, serde::Deserialize)]
+pub struct BotProtectionSettings {
+    pub hmac_secret: SecretString,
+    /// Forms submitted sooner than this after being rendered are from bots.
+    pub min_submit_seconds: u64,
+    pub max_form_age_seconds: u64,
+    /// Reject submissions that do not carry a signed form token.
+    pub require_form_token: bool,
+    /// When set, the client has to solve a proof-of-work challenge.
+    pub pow_difficulty: Option<u8>,
 }
 
 #[derive(Clone, serde::Deserialize)]
@@ -30,7 +253,8 @@ pub struct ApplicationSettings {
     #[serde(deserialize_with = "deserialize_number_from_string")]
     pub port: u16,
     pub host: String,
-	pub base_url: String
+    pub base_url: String,
+    pub hmac_secret: SecretString,
 }
 
 #[derive(Clone, serde::Deserialize)]
@@ -60,7 +284,7 @@ impl DatabaseSettings {
         PgConnectOptions::new()
             .host(&self.host)
             .username(&self.username)
-            .password(&self.password.expose_secret())
+            .password(self.password.expose_secret())
             .port(self.port)
             .ssl_mode(ssl_mode)
     }
@@ -86,6 +310,21 @@ pub fn get_configuration() -> Result<Setting, config::ConfigError> {
                 .separator("__"),
         )
         .build()?;
+    if let Environment::Production = environment {
+        // Anybody can read the secrets committed for development: whatever
+        // they sign could be forged.
+        let committed = config::Config::builder()
+            .add_source(config::File::from(conf_dir.join("base.yaml")))
+            .build()?;
+        for key in ["application.hmac_secret", "bot_protection.hmac_secret"] {
+            if settings.get_string(key)? == committed.get_string(key)? {
+                return Err(config::ConfigError::Message(format!(
+                    "`{}` is still the committed development value: set it in the environment.",
+                    key
+                )));
+            }
+        }
+    }
     settings.try_deserialize()
 }
 
@@ -0,0 +1,297 @@
+use crate::lib::configurations::{ConfirmationReminderSettings, Setting};
+use crate::lib::email_client::EmailClient;
+use crate::lib::email_templates::EmailTemplates;
+use crate::lib::issue_delivery_worker::ExecutionOutcome;
+use crate::lib::issues::{Subscriber, get_subscriber};
+use crate::lib::localization::Translations;
+use crate::lib::routes::{
+    PENDING_CONFIRMATION, confirmation_link, generate_subscription_token, store_token,
+};
+use crate::lib::startup::get_connection_pool;
+use minijinja::{Value, context};
+use sqlx::PgPool;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{Span, field::display};
+
+pub const REMINDER_SENT: &str = "sent";
+pub const REMINDER_FAILED: &str = "failed";
+
+/// Reminds pending subscribers to confirm, once, and forgets the ones who
+/// never do. Also sends the confirmation emails queued by imports.
+///
+/// Subscribers are picked with `FOR UPDATE SKIP LOCKED` and their reminder is
+/// recorded in the same transaction, so it goes out once even with several
+/// instances running.
+pub struct ConfirmationReminders {
+    pool: PgPool,
+    email_client: EmailClient,
+    email_templates: EmailTemplates,
+    translations: Arc<Translations>,
+    base_url: String,
+    settings: ConfirmationReminderSettings,
+}
+
+impl ConfirmationReminders {
+    pub async fn build(configuration: &Setting) -> Result<Self, String> {
+        let pool = get_connection_pool(configuration);
+        let translations = Arc::new(Translations::load(&configuration.localization)?);
+        let email_templates = EmailTemplates::load(
+            &configuration.email_templates,
+            &pool,
+            Arc::clone(&translations),
+            &configuration.branding.site_name,
+        )
+        .await?;
+
+        Ok(Self {
+            email_client: configuration.email_client.clone().try_into()?,
+            pool,
+            email_templates,
+            translations,
+            base_url: configuration.application.base_url.clone(),
+            settings: configuration.confirmation_reminders.clone(),
+        })
+    }
+
+    /// Send their confirmation email to one subscriber queued for it, if any.
+    ///
+    /// An email that fails to go out is not tried again: the reminder is their
+    /// next chance.
+    #[tracing::instrument(
+        name = "Send a queued confirmation email",
+        skip_all,
+        fields(subscriber_id = tracing::field::Empty),
+        err
+    )]
+    pub async fn try_send_queued_confirmation(
+        &self,
+    ) -> Result<ExecutionOutcome, Box<dyn std::error::Error + Send + Sync>> {
+        let mut transaction = self.pool.begin().await?;
+        let subscriber_id = sqlx::query_scalar!(
+            r#"
+            DELETE FROM confirmation_email_queue
+            WHERE subscriber_id = (
+                SELECT subscriber_id FROM confirmation_email_queue
+                ORDER BY enqueued_at
+                FOR UPDATE SKIP LOCKED
+                LIMIT 1
+            )
+            RETURNING subscriber_id
+            "#
+        )
+        .fetch_optional(&mut *transaction)
+        .await?;
+        let Some(subscriber_id) = subscriber_id else {
+            return Ok(ExecutionOutcome::EmptyQueue);
+        };
+        Span::current().record("subscriber_id", display(subscriber_id));
+
+        match get_subscriber(&self.pool, subscriber_id).await? {
+            Some(Ok(subscriber)) if subscriber.status == PENDING_CONFIRMATION => {
+                let subscription_token = generate_subscription_token();
+                store_token(&mut transaction, subscriber_id, &subscription_token).await?;
+                if let Err(e) = self
+                    .send(
+                        &subscriber,
+                        &subscription_token,
+                        "confirmation",
+                        "confirmation-email-subject",
+                    )
+                    .await
+                {
+                    tracing::error!(error = %e, "Failed to send a confirmation email.");
+                }
+            }
+            Some(Err(e)) => {
+                tracing::error!(error = %e, "Cannot confirm a subscriber with an invalid email.");
+            }
+            // Confirmed, or deleted, in the meantime.
+            Some(Ok(_)) | None => {}
+        }
+        transaction.commit().await?;
+        Ok(ExecutionOutcome::TaskCompleted)
+    }
+
+    /// Send a fresh confirmation link to one pending subscriber who signed up
+    /// long enough ago and was never reminded, if any.
+    ///
+    /// A reminder that fails to go out is recorded as such and not tried
+    /// again.
+    #[tracing::instrument(
+        name = "Remind a pending subscriber to confirm",
+        skip_all,
+        fields(subscriber_id = tracing::field::Empty),
+        err
+    )]
+    pub async fn try_send_reminder(
+        &self,
+    ) -> Result<ExecutionOutcome, Box<dyn std::error::Error + Send + Sync>> {
+        let mut transaction = self.pool.begin().await?;
+        let subscriber_id = sqlx::query_scalar!(
+            r#"
+            SELECT s.id
+            FROM subscriptions s
+            WHERE s.status = $1
+                AND s.subscribed_at <= now() - make_interval(hours => $2)
+                AND NOT EXISTS (
+                    SELECT 1 FROM confirmation_reminders r WHERE r.subscriber_id = s.id
+                )
+                AND NOT EXISTS (
+                    SELECT 1 FROM suppressions
+                    WHERE email_canonical = s.email_canonical
+                )
+            ORDER BY s.subscribed_at
+            FOR UPDATE SKIP LOCKED
+            LIMIT 1
+            "#,
+            PENDING_CONFIRMATION,
+            self.settings.remind_after_hours
+        )
+        .fetch_optional(&mut *transaction)
+        .await?;
+        let Some(subscriber_id) = subscriber_id else {
+            return Ok(ExecutionOutcome::EmptyQueue);
+        };
+        Span::current().record("subscriber_id", display(subscriber_id));
+
+        let subscription_token = generate_subscription_token();
+        store_token(&mut transaction, subscriber_id, &subscription_token).await?;
+        let outcome = match get_subscriber(&self.pool, subscriber_id).await? {
+            Some(Ok(subscriber)) => match self
+                .send(
+                    &subscriber,
+                    &subscription_token,
+                    "confirmation_reminder",
+                    "confirmation-reminder-email-subject",
+                )
+                .await
+            {
+                Ok(()) => REMINDER_SENT,
+                Err(e) => {
+                    tracing::error!(error = %e, "Failed to send a confirmation reminder.");
+                    REMINDER_FAILED
+                }
+            },
+            Some(Err(e)) => {
+                tracing::error!(error = %e, "Cannot remind a subscriber with an invalid email.");
+                REMINDER_FAILED
+            }
+            // Deleted since we picked them.
+            None => return Ok(ExecutionOutcome::TaskCompleted),
+        };
+        sqlx::query!(
+            r#"
+            INSERT INTO confirmation_reminders (subscriber_id, outcome, sent_at)
+            VALUES ($1, $2, now())
+            "#,
+            subscriber_id,
+            outcome
+        )
+        .execute(&mut *transaction)
+        .await?;
+        transaction.commit().await?;
+        Ok(ExecutionOutcome::TaskCompleted)
+    }
+
+    /// Send the `template` email, with a confirmation link, to `subscriber`.
+    async fn send(
+        &self,
+        subscriber: &Subscriber,
+        subscription_token: &str,
+        template: &str,
+        subject: &str,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let t = self.translations.translator(&subscriber.locale);
+        let email = self.email_templates.render(
+            template,
+            &subscriber.locale,
+            context! {
+                name => subscriber.recipient.name,
+                // Built by us out of URL-safe characters: no need to escape it.
+                confirmation_link => Value::from_safe_string(
+                    confirmation_link(&self.base_url, subscription_token)
+                ),
+            },
+        )?;
+
+        self.email_client
+            .send_email(&subscriber.email, &t.get(subject), &email.html, &email.text)
+            .await?;
+        Ok(())
+    }
+
+    /// Delete the subscribers still pending long after their reminder.
+    /// Returns how many were deleted.
+    #[tracing::instrument(name = "Delete unconfirmed subscribers", skip(self))]
+    pub async fn delete_unconfirmed(&self) -> Result<u64, sqlx::Error> {
+        // Their tokens go first: they reference them. Subscribers who were
+        // never reminded, e.g. because their address is suppressed, go as
+        // late as if they had been.
+        let deleted = sqlx::query!(
+            r#"
+            WITH expired AS (
+                SELECT s.id
+                FROM subscriptions s
+                LEFT JOIN confirmation_reminders r ON r.subscriber_id = s.id
+                WHERE s.status = $1
+                    AND COALESCE(r.sent_at, s.subscribed_at + make_interval(hours => $2))
+                        <= now() - make_interval(hours => $3)
+                FOR UPDATE OF s SKIP LOCKED
+            ),
+            tokens AS (
+                DELETE FROM subscription_tokens
+                WHERE subscriber_id IN (SELECT id FROM expired)
+            )
+            DELETE FROM subscriptions
+            WHERE id IN (SELECT id FROM expired)
+            "#,
+            PENDING_CONFIRMATION,
+            self.settings.remind_after_hours,
+            self.settings.delete_after_hours
+        )
+        .execute(&self.pool)
+        .await?
+        .rows_affected();
+        if deleted > 0 {
+            tracing::info!(deleted, "Deleted subscribers who never confirmed.");
+        }
+        Ok(deleted)
+    }
+}
+
+/// The in-process scheduler of the queued confirmation emails, the reminders
+/// and their clean-up, until the application stops.
+pub async fn run_confirmation_reminders_until_stopped(
+    configuration: Setting,
+) -> Result<(), std::io::Error> {
+    let reminders = ConfirmationReminders::build(&configuration)
+        .await
+        .map_err(std::io::Error::other)?;
+    let poll_interval = Duration::from_millis(
+        configuration
+            .confirmation_reminders
+            .poll_interval_milliseconds,
+    );
+
+    // The clean-up scans the pending subscribers: once per poll interval is
+    // plenty, not before every email.
+    let mut deleted_unconfirmed_at: Option<Instant> = None;
+    loop {
+        if deleted_unconfirmed_at.is_none_or(|at| at.elapsed() >= poll_interval) {
+            deleted_unconfirmed_at = Some(Instant::now());
+            if reminders.delete_unconfirmed().await.is_err() {
+                tokio::time::sleep(poll_interval).await;
+                continue;
+            }
+        }
+        let outcome = match reminders.try_send_queued_confirmation().await {
+            Ok(ExecutionOutcome::EmptyQueue) => reminders.try_send_reminder().await,
+            outcome => outcome,
+        };
+        match outcome {
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+            Ok(ExecutionOutcome::EmptyQueue) | Err(_) => tokio::time::sleep(poll_interval).await,
+        }
+    }
+}
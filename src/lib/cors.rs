@@ -0,0 +1,185 @@
+use crate::lib::configurations::EmbedSettings;
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::http::{Method, StatusCode};
+use actix_web::middleware::Next;
+use actix_web::{Error, HttpMessage, HttpResponse, web};
+use std::collections::HashSet;
+
+/// How long browsers may cache the answer to a preflight request.
+const PREFLIGHT_MAX_AGE_SECONDS: u32 = 86400;
+
+/// The origins browsers may call us from: ours, and the sites embedding the
+/// signup form.
+pub struct AllowedOrigins {
+    own: String,
+    embedding: HashSet<String>,
+}
+
+/// The site, other than ours, a request was sent from. Only ever set for
+/// allowed origins.
+#[derive(Clone)]
+pub struct EmbeddingOrigin(pub String);
+
+impl AllowedOrigins {
+    pub fn new(base_url: &str, settings: &EmbedSettings) -> Result<Self, String> {
+        let embedding = settings
+            .allowed_origins
+            .iter()
+            .map(|origin| {
+                normalize(origin).ok_or_else(|| format!("{} is not a valid origin.", origin))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            own: normalize(base_url)
+                .ok_or_else(|| format!("{} is not a valid base URL.", base_url))?,
+            embedding,
+        })
+    }
+
+    fn classify(&self, origin: &str) -> Origin {
+        match normalize(origin) {
+            Some(origin) if origin == self.own => Origin::Own,
+            Some(origin) if self.embedding.contains(&origin) => Origin::Embedding(origin),
+            // Including `null`, sent by sandboxed frames and the like.
+            _ => Origin::Refused,
+        }
+    }
+}
+
+enum Origin {
+    Own,
+    Embedding(String),
+    Refused,
+}
+
+/// `https://Blog.example.com:443/` and `https://blog.example.com` are the
+/// same origin.
+fn normalize(origin: &str) -> Option<String> {
+    let origin = reqwest::Url::parse(origin).ok()?.origin();
+    origin.is_tuple().then(|| origin.ascii_serialization())
+}
+
+/// Apply the CORS policy to requests sent by browsers from another site.
+///
+/// Allowed origins get the `Access-Control-Allow-*` headers, and their
+/// requests are tagged with an `EmbeddingOrigin`. Anything but reads from
+/// other origins is refused: plain HTML forms can post across sites without
+/// asking the browser first.
+pub async fn cors<B: MessageBody + 'static>(
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<EitherBody<B>>, Error> {
+    let Some(origin) = req
+        .headers()
+        .get(header::ORIGIN)
+        .and_then(|origin| origin.to_str().ok())
+        .map(str::to_owned)
+    else {
+        return Ok(next.call(req).await?.map_into_left_body());
+    };
+    let allowed_origins = req
+        .app_data::<web::Data<AllowedOrigins>>()
+        .expect("The allowed origins are registered.");
+    let preflight = req.method() == Method::OPTIONS
+        && req
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD);
+
+    let origin = match allowed_origins.classify(&origin) {
+        Origin::Own => return Ok(next.call(req).await?.map_into_left_body()),
+        Origin::Embedding(origin) => origin,
+        Origin::Refused if !preflight && is_safe(req.method()) => {
+            return Ok(next.call(req).await?.map_into_left_body());
+        }
+        Origin::Refused => {
+            tracing::info!(%origin, "Refused a cross-origin request.");
+            let response = HttpResponse::build(StatusCode::FORBIDDEN)
+                .body("This origin is not allowed to embed the signup form.");
+            return Ok(req.into_response(response).map_into_right_body());
+        }
+    };
+    let allow_origin = HeaderValue::from_str(&origin).expect("A serialized origin.");
+
+    if preflight {
+        let mut response = HttpResponse::NoContent();
+        response
+            .insert_header((header::ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin))
+            .insert_header((header::ACCESS_CONTROL_ALLOW_METHODS, "GET, POST"))
+            .insert_header((header::ACCESS_CONTROL_MAX_AGE, PREFLIGHT_MAX_AGE_SECONDS))
+            .insert_header((header::VARY, "Origin"));
+        if let Some(headers) = req
+            .headers()
+            .get(header::ACCESS_CONTROL_REQUEST_HEADERS)
+            .cloned()
+        {
+            response.insert_header((header::ACCESS_CONTROL_ALLOW_HEADERS, headers));
+        }
+        return Ok(req.into_response(response.finish()).map_into_right_body());
+    }
+
+    req.extensions_mut().insert(EmbeddingOrigin(origin));
+    let mut response = next.call(req).await?;
+    let headers = response.headers_mut();
+    headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+    headers.append(header::VARY, HeaderValue::from_static("Origin"));
+    Ok(response.map_into_left_body())
+}
+
+fn is_safe(method: &Method) -> bool {
+    matches!(*method, Method::GET | Method::HEAD)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allowed_origins() -> AllowedOrigins {
+        AllowedOrigins::new(
+            "https://newsletter.example.com/",
+            &EmbedSettings {
+                allowed_origins: vec!["https://Blog.Example.com:443".into()],
+            },
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn origins_are_compared_once_normalized() {
+        let origins = allowed_origins();
+        assert!(matches!(
+            origins.classify("https://newsletter.example.com"),
+            Origin::Own
+        ));
+        assert!(matches!(
+            origins.classify("https://blog.example.com"),
+            Origin::Embedding(origin) if origin == "https://blog.example.com"
+        ));
+    }
+
+    #[test]
+    fn other_origins_are_refused() {
+        let origins = allowed_origins();
+        for origin in [
+            "http://blog.example.com",
+            "https://blog.example.com:8443",
+            "https://evil.example.com",
+            "null",
+        ] {
+            assert!(
+                matches!(origins.classify(origin), Origin::Refused),
+                "{}",
+                origin
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_allowed_origins_are_reported() {
+        let settings = EmbedSettings {
+            allowed_origins: vec!["blog.example.com".into()],
+        };
+        assert!(AllowedOrigins::new("https://newsletter.example.com", &settings).is_err());
+    }
+}
@@ -1,7 +1,11 @@
-﻿mod new_subscriber;
+﻿mod email_domain_policy;
+mod new_subscriber;
+mod subscriber_attributes;
 mod subscriber_email;
 mod subscriber_name;
 
+pub use email_domain_policy::{EmailDomainError, EmailDomainPolicy};
 pub use new_subscriber::NewSubscriber;
+pub use subscriber_attributes::SubscriberAttributes;
 pub use subscriber_email::SubscriberEmail;
 pub use subscriber_name::SubscriberName;
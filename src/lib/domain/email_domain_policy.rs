@@ -0,0 +1,208 @@
+use crate::lib::configurations::EmailDomainSettings;
+use crate::lib::domain::SubscriberEmail;
+use std::collections::HashSet;
+
+/// Bundled list of throwaway mailbox providers.
+const DISPOSABLE_DOMAINS: &str = include_str!("disposable_domains.txt");
+
+/// Providers most of our subscribers use: a domain a single typo away from one
+/// of them is most likely a mistake.
+const POPULAR_DOMAINS: &[&str] = &[
+    "gmail.com",
+    "googlemail.com",
+    "yahoo.com",
+    "yahoo.co.uk",
+    "hotmail.com",
+    "hotmail.co.uk",
+    "outlook.com",
+    "live.com",
+    "icloud.com",
+    "me.com",
+    "aol.com",
+    "mail.com",
+    "gmx.com",
+    "gmx.de",
+    "web.de",
+    "proton.me",
+    "protonmail.com",
+    "fastmail.com",
+    "ukr.net",
+    "yandex.com",
+];
+
+/// Short domains are too close to too many legitimate ones to guess a typo.
+const MIN_SUGGESTION_LENGTH: usize = 8;
+
+#[derive(Debug, PartialEq)]
+pub enum EmailDomainError {
+    Disposable { domain: String },
+    PossibleTypo { suggestion: String },
+}
+
+impl std::fmt::Display for EmailDomainError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EmailDomainError::Disposable { domain } => {
+                write!(f, "{} is a disposable email provider.", domain)
+            }
+            EmailDomainError::PossibleTypo { suggestion } => {
+                write!(f, "Did you mean {}?", suggestion)
+            }
+        }
+    }
+}
+
+impl std::error::Error for EmailDomainError {}
+
+/// Which email domains we are happy to send our newsletter to.
+pub struct EmailDomainPolicy {
+    reject_disposable: bool,
+    disposable_domains: HashSet<String>,
+    suggest_typos: bool,
+}
+
+impl EmailDomainPolicy {
+    pub fn new(settings: &EmailDomainSettings) -> Self {
+        let disposable_domains = DISPOSABLE_DOMAINS
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(str::to_owned)
+            .chain(
+                settings
+                    .extra_disposable
+                    .iter()
+                    .map(|domain| domain.trim().to_lowercase()),
+            )
+            .collect();
+
+        Self {
+            reject_disposable: settings.reject_disposable,
+            disposable_domains,
+            suggest_typos: settings.suggest_typos,
+        }
+    }
+
+    pub fn check(&self, email: &SubscriberEmail) -> Result<(), EmailDomainError> {
+        let domain = email.domain();
+
+        if self.reject_disposable && self.disposable_domains.contains(domain) {
+            return Err(EmailDomainError::Disposable {
+                domain: domain.into(),
+            });
+        }
+
+        if self.suggest_typos && !POPULAR_DOMAINS.contains(&domain) {
+            let suggestion = POPULAR_DOMAINS
+                .iter()
+                .filter(|popular| popular.len() >= MIN_SUGGESTION_LENGTH)
+                .find(|popular| edit_distance(domain, popular) == 1);
+            if let Some(popular) = suggestion {
+                return Err(EmailDomainError::PossibleTypo {
+                    suggestion: format!("{}@{}", email.local_part(), popular),
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Optimal string alignment distance: like Levenshtein, but a transposition of
+/// two adjacent characters (`gmial` -> `gmail`) counts as a single edit.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a = a.as_bytes();
+    let b = b.as_bytes();
+    let mut rows = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in rows.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in rows[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            let mut distance = (rows[i - 1][j] + 1)
+                .min(rows[i][j - 1] + 1)
+                .min(rows[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(rows[i - 2][j - 2] + 1);
+            }
+            rows[i][j] = distance;
+        }
+    }
+
+    rows[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use claims::{assert_err_eq, assert_ok};
+
+    fn policy() -> EmailDomainPolicy {
+        EmailDomainPolicy::new(&EmailDomainSettings {
+            reject_disposable: true,
+            extra_disposable: vec!["Throwaway.example".into()],
+            suggest_typos: true,
+        })
+    }
+
+    fn email(email: &str) -> SubscriberEmail {
+        SubscriberEmail::parse(email).unwrap()
+    }
+
+    #[test]
+    fn bundled_disposable_domains_are_rejected() {
+        assert_err_eq!(
+            policy().check(&email("ursula@mailinator.com")),
+            EmailDomainError::Disposable {
+                domain: "mailinator.com".into()
+            }
+        );
+    }
+
+    #[test]
+    fn configured_disposable_domains_are_rejected() {
+        assert_err_eq!(
+            policy().check(&email("ursula@throwaway.example")),
+            EmailDomainError::Disposable {
+                domain: "throwaway.example".into()
+            }
+        );
+    }
+
+    #[test]
+    fn a_transposed_popular_domain_gets_a_suggestion() {
+        assert_err_eq!(
+            policy().check(&email("ursula@gmial.com")),
+            EmailDomainError::PossibleTypo {
+                suggestion: "ursula@gmail.com".into()
+            }
+        );
+    }
+
+    #[test]
+    fn a_popular_domain_is_accepted() {
+        assert_ok!(policy().check(&email("ursula@gmail.com")));
+        assert_ok!(policy().check(&email("ursula@mail.com")));
+    }
+
+    #[test]
+    fn an_unrelated_domain_is_accepted() {
+        assert_ok!(policy().check(&email("ursula@earthsea.org")));
+    }
+
+    #[test]
+    fn checks_can_be_turned_off() {
+        let policy = EmailDomainPolicy::new(&EmailDomainSettings {
+            reject_disposable: false,
+            extra_disposable: vec![],
+            suggest_typos: false,
+        });
+        assert_ok!(policy.check(&email("ursula@mailinator.com")));
+        assert_ok!(policy.check(&email("ursula@gmial.com")));
+    }
+}
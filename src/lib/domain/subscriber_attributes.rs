@@ -0,0 +1,140 @@
+use serde_json::{Map, Value};
+
+/// A subscriber can have at most this many attributes.
+const MAX_ATTRIBUTES: usize = 50;
+const MAX_KEY_LENGTH: usize = 64;
+const MAX_STRING_LENGTH: usize = 500;
+
+/// Custom attributes of a subscriber, e.g. `{"plan": "pro", "seats": 5}`.
+///
+/// Values are strings, numbers, booleans or `null`: segments compare them,
+/// and merge fields print them.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SubscriberAttributes(Map<String, Value>);
+
+impl SubscriberAttributes {
+    pub fn parse(value: Value) -> Result<SubscriberAttributes, String> {
+        let Value::Object(attributes) = value else {
+            return Err("Attributes must be a JSON object.".into());
+        };
+        if attributes.len() > MAX_ATTRIBUTES {
+            return Err(format!(
+                "There are more than {} attributes.",
+                MAX_ATTRIBUTES
+            ));
+        }
+        for (key, value) in &attributes {
+            if !Self::is_valid_key(key) {
+                return Err(format!(
+                    "`{}` is not a valid attribute name: use up to {} lowercase letters, digits and \
+                     underscores, starting with a letter.",
+                    key, MAX_KEY_LENGTH
+                ));
+            }
+            match value {
+                Value::Array(_) | Value::Object(_) => {
+                    return Err(format!(
+                        "The `{}` attribute must be a string, a number, a boolean or null.",
+                        key
+                    ));
+                }
+                Value::String(s) if s.chars().count() > MAX_STRING_LENGTH => {
+                    return Err(format!(
+                        "The `{}` attribute is longer than {} characters.",
+                        key, MAX_STRING_LENGTH
+                    ));
+                }
+                _ => {}
+            }
+        }
+        Ok(Self(attributes))
+    }
+
+    pub fn is_valid_key(key: &str) -> bool {
+        key.len() <= MAX_KEY_LENGTH
+            && key.starts_with(|c: char| c.is_ascii_lowercase())
+            && key
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Add `other` to these, replacing the attributes they share.
+    pub fn merge(&mut self, other: SubscriberAttributes) {
+        self.0.extend(other.0);
+    }
+
+    pub fn into_json(self) -> Value {
+        Value::Object(self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use claims::{assert_err, assert_ok};
+    use serde_json::json;
+
+    #[test]
+    fn scalar_attributes_are_valid() {
+        assert_ok!(SubscriberAttributes::parse(json!({
+            "plan": "pro",
+            "seats": 5,
+            "beta": true,
+            "referrer": null,
+            "signup_source_2": "footer"
+        })));
+    }
+
+    #[test]
+    fn only_objects_are_attributes() {
+        assert_err!(SubscriberAttributes::parse(json!(["pro"])));
+        assert_err!(SubscriberAttributes::parse(json!("pro")));
+    }
+
+    #[test]
+    fn nested_values_are_rejected() {
+        assert_err!(SubscriberAttributes::parse(
+            json!({"plan": {"name": "pro"}})
+        ));
+        assert_err!(SubscriberAttributes::parse(json!({"tags": ["a", "b"]})));
+    }
+
+    #[test]
+    fn invalid_keys_are_rejected() {
+        for key in ["", "Plan", "2fa", "plan-name", "plan.name", &"a".repeat(65)] {
+            assert_err!(
+                SubscriberAttributes::parse(json!({ key: "pro" })),
+                "{} is valid",
+                key
+            );
+        }
+    }
+
+    #[test]
+    fn long_strings_are_rejected() {
+        assert_err!(SubscriberAttributes::parse(json!({"bio": "a".repeat(501)})));
+    }
+
+    #[test]
+    fn too_many_attributes_are_rejected() {
+        let attributes: Map<String, Value> = (0..51)
+            .map(|i| (format!("a{}", i), Value::from(i)))
+            .collect();
+
+        assert_err!(SubscriberAttributes::parse(Value::Object(attributes)));
+    }
+
+    #[test]
+    fn merged_attributes_replace_the_ones_they_share() {
+        let mut attributes =
+            SubscriberAttributes::parse(json!({"plan": "free", "seats": 1})).unwrap();
+
+        attributes.merge(SubscriberAttributes::parse(json!({"plan": "pro"})).unwrap());
+
+        assert_eq!(attributes.into_json(), json!({"plan": "pro", "seats": 1}));
+    }
+}
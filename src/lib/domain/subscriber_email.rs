@@ -3,13 +3,94 @@
 #[derive(Debug, Clone)]
 pub struct SubscriberEmail(String);
 
+/// Mailbox providers known to deliver several spellings of an address to the
+/// same inbox.
+struct ProviderRule {
+    domains: &'static [&'static str],
+    /// Some providers serve the same mailboxes under several domains.
+    canonical_domain: Option<&'static str>,
+    /// `u.r.s.u.l.a` and `ursula` are the same mailbox.
+    ignore_dots: bool,
+    /// `ursula+news` and `ursula` are the same mailbox.
+    plus_tags: bool,
+}
+
+const PROVIDER_RULES: &[ProviderRule] = &[
+    ProviderRule {
+        domains: &["gmail.com", "googlemail.com"],
+        canonical_domain: Some("gmail.com"),
+        ignore_dots: true,
+        plus_tags: true,
+    },
+    ProviderRule {
+        domains: &["outlook.com", "hotmail.com", "live.com"],
+        canonical_domain: None,
+        ignore_dots: false,
+        plus_tags: true,
+    },
+    ProviderRule {
+        domains: &["fastmail.com", "icloud.com", "proton.me", "protonmail.com"],
+        canonical_domain: None,
+        ignore_dots: false,
+        plus_tags: true,
+    },
+];
+
 impl SubscriberEmail {
+    /// The domain part is lowercased and IDN domains are converted to
+    /// punycode. The local part is stored exactly as typed, since it is
+    /// case-sensitive according to the RFC.
     pub fn parse(email: &str) -> Result<SubscriberEmail, String> {
-        if email.validate_email() {
-            Ok(Self(email.into()))
+        let invalid = || format!("{} is not a valid subscriber email", email);
+        if !email.validate_email() {
+            return Err(invalid());
+        }
+
+        // A valid email always contains an `@`.
+        let (local, domain) = email.rsplit_once('@').ok_or_else(invalid)?;
+        let domain = if domain.starts_with('[') {
+            // An IP address literal, nothing to normalise.
+            domain.to_owned()
         } else {
-            Err(format!("{} is not a valid subscriber email", email))
+            idna::domain_to_ascii(domain).map_err(|_| invalid())?
+        };
+        Ok(Self(format!("{}@{}", local, domain)))
+    }
+
+    pub fn local_part(&self) -> &str {
+        self.0.rsplit_once('@').map_or("", |(local, _)| local)
+    }
+
+    pub fn domain(&self) -> &str {
+        self.0.rsplit_once('@').map_or("", |(_, domain)| domain)
+    }
+
+    /// The form used to detect duplicate subscriptions. In practice mailboxes
+    /// are case-insensitive, so the whole address is lowercased. With
+    /// `provider_aware` the rules of well known providers are applied on top,
+    /// e.g. `U.rsula+news@googlemail.com` becomes `ursula@gmail.com`.
+    pub fn canonical(&self, provider_aware: bool) -> String {
+        let mut local = self.local_part().to_lowercase();
+        let mut domain = self.domain().to_lowercase();
+
+        let rule = PROVIDER_RULES
+            .iter()
+            .find(|rule| rule.domains.contains(&domain.as_str()));
+        if let Some(rule) = rule.filter(|_| provider_aware) {
+            if rule.plus_tags
+                && let Some((mailbox, _tag)) = local.split_once('+')
+            {
+                local = mailbox.to_owned();
+            }
+            if rule.ignore_dots {
+                local.retain(|c| c != '.');
+            }
+            if let Some(canonical_domain) = rule.canonical_domain {
+                domain = canonical_domain.to_owned();
+            }
         }
+
+        format!("{}@{}", local, domain)
     }
 }
 
@@ -44,6 +125,37 @@ mod tests {
         assert_err!(SubscriberEmail::parse(&email));
     }
 
+    #[test]
+    fn the_domain_is_lowercased() {
+        let email = SubscriberEmail::parse("Ursula@Example.COM").unwrap();
+        assert_eq!(email.as_ref(), "Ursula@example.com");
+    }
+
+    #[test]
+    fn idn_domains_are_converted_to_punycode() {
+        let email = SubscriberEmail::parse("ursula@Bücher.example").unwrap();
+        assert_eq!(email.as_ref(), "ursula@xn--bcher-kva.example");
+    }
+
+    #[test]
+    fn the_canonical_form_ignores_case() {
+        let email = SubscriberEmail::parse("Ursula@Example.com").unwrap();
+        assert_eq!(email.canonical(false), "ursula@example.com");
+    }
+
+    #[test]
+    fn gmail_dots_and_plus_tags_are_folded_when_provider_aware() {
+        let email = SubscriberEmail::parse("U.rsula+news@googlemail.com").unwrap();
+        assert_eq!(email.canonical(false), "u.rsula+news@googlemail.com");
+        assert_eq!(email.canonical(true), "ursula@gmail.com");
+    }
+
+    #[test]
+    fn dots_are_kept_for_providers_that_do_not_ignore_them() {
+        let email = SubscriberEmail::parse("u.rsula+news@outlook.com").unwrap();
+        assert_eq!(email.canonical(true), "u.rsula@outlook.com");
+    }
+
     #[derive(Debug, Clone)]
     struct ValidEmailFixture(pub String);
     impl quickcheck::Arbitrary for ValidEmailFixture {
@@ -0,0 +1,290 @@
+use crate::lib::configurations::EmailTemplateSettings;
+use crate::lib::localization::Translations;
+use fluent_bundle::FluentArgs;
+use minijinja::value::Kwargs;
+use minijinja::{Environment, Error, State, UndefinedBehavior, Value, context};
+use sqlx::PgPool;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Arc;
+
+/// The templates the application itself sends, with the variables each of
+/// them is rendered with.
+const REQUIRED_TEMPLATES: &[(&str, &[&str])] = &[
+    ("account_locked", &["lockout_minutes", "ip_address"]),
+    ("confirmation", &["name", "confirmation_link"]),
+    ("confirmation_reminder", &["name", "confirmation_link"]),
+    ("invite", &["role", "invite_link", "ttl_hours"]),
+    (
+        "newsletter",
+        &[
+            "name",
+            "email",
+            "title",
+            "content_html",
+            "content_text",
+            "unsubscribe_link",
+        ],
+    ),
+    ("password_reset", &["reset_link", "ttl_minutes"]),
+];
+
+/// The two bodies of an email.
+#[derive(Debug)]
+pub struct RenderedEmail {
+    pub html: String,
+    pub text: String,
+}
+
+/// The sources of a template: `<name>.html` and `<name>.txt`.
+#[derive(Default)]
+struct TemplateSource {
+    html: Option<String>,
+    text: Option<String>,
+}
+
+/// Named email templates, each with an HTML and a plain text variant.
+///
+/// Templates use the Jinja syntax. Variables are HTML-escaped in the HTML
+/// variant unless they are marked as safe, and `t("key", name=value)` looks up
+/// a translation in the locale the email is rendered in.
+pub struct EmailTemplates {
+    environment: Environment<'static>,
+}
+
+impl EmailTemplates {
+    /// Load the templates from the configured directory, then let the ones
+    /// stored in the database replace them if enabled.
+    ///
+    /// Every template is compiled and the ones we send are rendered once with
+    /// dummy values, so that a broken template stops the application from
+    /// starting instead of failing when an email goes out.
+    pub async fn load(
+        settings: &EmailTemplateSettings,
+        pool: &PgPool,
+        translations: Arc<Translations>,
+        site_name: &str,
+    ) -> Result<Self, String> {
+        let mut sources = read_directory(Path::new(&settings.directory))?;
+        if settings.load_from_database {
+            let rows = sqlx::query!(r#"SELECT name, html_body, text_body FROM email_templates"#)
+                .fetch_all(pool)
+                .await
+                .map_err(|e| format!("Failed to fetch the email templates: {}", e))?;
+            for row in rows {
+                sources.insert(
+                    row.name,
+                    TemplateSource {
+                        html: Some(row.html_body),
+                        text: Some(row.text_body),
+                    },
+                );
+            }
+        }
+        Self::compile(sources, translations, site_name)
+    }
+
+    fn compile(
+        sources: BTreeMap<String, TemplateSource>,
+        translations: Arc<Translations>,
+        site_name: &str,
+    ) -> Result<Self, String> {
+        let mut environment = Environment::new();
+        // A typo in a variable name must be an error, not an empty string.
+        environment.set_undefined_behavior(UndefinedBehavior::Strict);
+        environment.add_global("site_name", site_name.to_owned());
+        let default_locale = translations.default_locale().to_owned();
+        environment.add_function(
+            "t",
+            move |state: &State, key: &str, kwargs: Kwargs| -> Result<String, Error> {
+                let locale = state.lookup("locale").map(|locale| locale.to_string());
+                let mut args = FluentArgs::new();
+                for name in kwargs.args() {
+                    let value: Value = kwargs.get(name)?;
+                    args.set(name.to_owned(), value.to_string());
+                }
+                Ok(translations.translate(
+                    locale.as_deref().unwrap_or(&default_locale),
+                    key,
+                    Some(&args),
+                ))
+            },
+        );
+
+        for (name, source) in sources {
+            let (Some(html), Some(text)) = (source.html, source.text) else {
+                return Err(format!(
+                    "The `{}` email template needs both an HTML and a text variant.",
+                    name
+                ));
+            };
+            // The extension turns HTML escaping on for the HTML variant only.
+            for (file, body) in [
+                (format!("{}.html", name), html),
+                (format!("{}.txt", name), text),
+            ] {
+                environment
+                    .add_template_owned(file.clone(), body)
+                    .map_err(|e| format!("Invalid email template {}: {:#}", file, e))?;
+            }
+        }
+
+        let templates = Self { environment };
+        for (name, variables) in REQUIRED_TEMPLATES {
+            templates.check(name, variables)?;
+        }
+        Ok(templates)
+    }
+
+    /// Fail if the `name` template is missing, or does not render with dummy
+    /// values for `variables`.
+    pub fn check(&self, name: &str, variables: &[&str]) -> Result<(), String> {
+        let dummy = variables
+            .iter()
+            .map(|variable| (*variable, Value::from_safe_string(variable.to_string())))
+            .collect::<BTreeMap<_, _>>();
+        self.render(name, "", Value::from(dummy))
+            .map(|_| ())
+            .map_err(|e| format!("Invalid email template `{}`: {:#}", name, e))
+    }
+
+    /// Render both variants of the `name` template in `locale`.
+    pub fn render(
+        &self,
+        name: &str,
+        locale: &str,
+        variables: Value,
+    ) -> Result<RenderedEmail, Error> {
+        let variables = context! { locale => locale, ..variables };
+        let render = |extension: &str| {
+            self.environment
+                .get_template(&format!("{}.{}", name, extension))?
+                .render(&variables)
+        };
+        Ok(RenderedEmail {
+            html: render("html")?,
+            text: render("txt")?,
+        })
+    }
+}
+
+/// Pair up the `<name>.html` and `<name>.txt` files of the directory.
+fn read_directory(directory: &Path) -> Result<BTreeMap<String, TemplateSource>, String> {
+    let entries = std::fs::read_dir(directory)
+        .map_err(|e| format!("Failed to read {}: {}", directory.display(), e))?;
+
+    let mut sources = BTreeMap::<String, TemplateSource>::new();
+    for entry in entries {
+        let path = entry.map_err(|e| e.to_string())?.path();
+        let (Some(name), Some(extension)) = (
+            path.file_stem().and_then(|stem| stem.to_str()),
+            path.extension().and_then(|extension| extension.to_str()),
+        ) else {
+            continue;
+        };
+        let source = sources.entry(name.to_owned()).or_default();
+        let variant = match extension {
+            "html" => &mut source.html,
+            "txt" => &mut source.text,
+            _ => continue,
+        };
+        *variant = Some(
+            std::fs::read_to_string(&path)
+                .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?,
+        );
+    }
+    Ok(sources)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lib::configurations::LocalizationSettings;
+    use claims::assert_ok;
+
+    fn translations() -> Arc<Translations> {
+        let translations = Translations::load(&LocalizationSettings {
+            directory: "locales".into(),
+            default_locale: "en".into(),
+        })
+        .expect("Failed to load the bundled catalogs.");
+        Arc::new(translations)
+    }
+
+    fn bundled_sources() -> BTreeMap<String, TemplateSource> {
+        read_directory(Path::new("email_templates")).expect("Failed to read the templates.")
+    }
+
+    fn compile_with(name: &str, html: &str, text: &str) -> Result<EmailTemplates, String> {
+        let mut sources = bundled_sources();
+        sources.insert(
+            name.into(),
+            TemplateSource {
+                html: Some(html.into()),
+                text: Some(text.into()),
+            },
+        );
+        EmailTemplates::compile(sources, translations(), "Newsletter")
+    }
+
+    #[test]
+    fn the_bundled_templates_are_valid() {
+        assert_ok!(EmailTemplates::compile(
+            bundled_sources(),
+            translations(),
+            "Newsletter"
+        ));
+    }
+
+    #[test]
+    fn variables_are_escaped_in_the_html_variant_only() {
+        let templates = compile_with("hello", "<p>{{ name }}</p>", "{{ name }}").unwrap();
+
+        let email = templates
+            .render("hello", "en", context! { name => "<b>Ursula</b>" })
+            .unwrap();
+
+        assert_eq!(email.html, "<p>&lt;b&gt;Ursula&lt;&#x2f;b&gt;</p>");
+        assert_eq!(email.text, "<b>Ursula</b>");
+    }
+
+    #[test]
+    fn translations_are_looked_up_in_the_rendering_locale() {
+        let templates = compile_with("subject", "{{ t('confirmation-email-subject') }}", "")
+            .expect("Failed to compile the templates.");
+
+        let email = templates.render("subject", "uk", context! {}).unwrap();
+
+        assert_eq!(email.html, "Ласкаво просимо!");
+    }
+
+    #[test]
+    fn a_syntax_error_is_reported_when_loading() {
+        assert!(compile_with("broken", "{% if name %}", "").is_err());
+    }
+
+    #[test]
+    fn an_unknown_variable_in_a_required_template_is_reported_when_loading() {
+        assert!(
+            compile_with(
+                "confirmation",
+                "{{ confirmation_url }}",
+                "{{ confirmation_link }}"
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn a_template_without_a_text_variant_is_rejected() {
+        let mut sources = bundled_sources();
+        sources.insert(
+            "lonely".into(),
+            TemplateSource {
+                html: Some("<p>Hi!</p>".into()),
+                text: None,
+            },
+        );
+        assert!(EmailTemplates::compile(sources, translations(), "Newsletter").is_err());
+    }
+}
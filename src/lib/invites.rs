@@ -0,0 +1,153 @@
+use crate::lib::domain::SubscriberEmail;
+use crate::lib::email_client::EmailClient;
+use crate::lib::email_templates::EmailTemplates;
+use crate::lib::localization::Translator;
+use crate::lib::roles::Role;
+use chrono::{DateTime, Utc};
+use minijinja::{Value, context};
+use rand::Rng;
+use rand::distr::Alphanumeric;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// An invite, as listed to the owners.
+#[derive(serde::Serialize)]
+pub struct Invite {
+    pub invite_id: Uuid,
+    pub email: String,
+    pub role: Role,
+    pub invited_by: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// An invite found by the token of its signup link.
+pub struct PendingInvite {
+    pub invite_id: Uuid,
+    pub email: String,
+    pub role: Role,
+    pub expires_at: DateTime<Utc>,
+}
+
+pub fn generate_invite_token() -> String {
+    let mut rng = rand::rng();
+    std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(32)
+        .collect()
+}
+
+/// What is stored instead of the token: a leaked table lets nobody sign up.
+pub fn hash_invite_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// The link the invited person clicks to pick a username and a password.
+pub fn invite_link(base_url: &str, token: &str) -> String {
+    format!("{}/invites/accept?token={}", base_url, token)
+}
+
+/// The invites not used yet, expired or not.
+#[tracing::instrument(name = "List pending invites", skip(pool))]
+pub async fn list_pending_invites(pool: &PgPool) -> Result<Vec<Invite>, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT invite_id, email, role, invited_by, created_at, expires_at
+        FROM user_invites
+        WHERE accepted_at IS NULL
+        ORDER BY created_at DESC
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(rows
+        .into_iter()
+        .filter_map(|row| {
+            Some(Invite {
+                invite_id: row.invite_id,
+                email: row.email,
+                role: Role::parse(&row.role)?,
+                invited_by: row.invited_by,
+                created_at: row.created_at,
+                expires_at: row.expires_at,
+            })
+        })
+        .collect())
+}
+
+/// The invite behind `token`, unless it is unknown or already used.
+#[tracing::instrument(name = "Find an invite by token", skip(pool, token))]
+pub async fn find_pending_invite(
+    pool: &PgPool,
+    token: &str,
+) -> Result<Option<PendingInvite>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT invite_id, email, role, expires_at
+        FROM user_invites
+        WHERE token_hash = $1 AND accepted_at IS NULL
+        "#,
+        hash_invite_token(token)
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(row.and_then(|row| {
+        Some(PendingInvite {
+            invite_id: row.invite_id,
+            email: row.email,
+            role: Role::parse(&row.role)?,
+            expires_at: row.expires_at,
+        })
+    }))
+}
+
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(
+    name = "Send an invite email",
+    skip(email_client, email_templates, email, link, site_name, t),
+    fields(locale = %t.locale())
+)]
+pub async fn send_invite_email(
+    email_client: &EmailClient,
+    email_templates: &EmailTemplates,
+    email: &SubscriberEmail,
+    role: Role,
+    link: &str,
+    ttl_hours: i64,
+    site_name: &str,
+    t: Translator<'_>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let rendered = email_templates
+        .render(
+            "invite",
+            t.locale(),
+            context! {
+                role => role.as_str(),
+                // Built by us out of URL-safe characters: no need to escape it.
+                invite_link => Value::from_safe_string(link.to_owned()),
+                ttl_hours => ttl_hours,
+            },
+        )
+        .map_err(|e| {
+            tracing::error!(error = %e, "Failed to render the invite email.");
+            e
+        })?;
+    email_client
+        .send_email(
+            email,
+            &t.with("invite-email-subject", "site", site_name),
+            &rendered.html,
+            &rendered.text,
+        )
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,409 @@
+use crate::lib::configurations::{IssueDeliverySettings, Setting};
+use crate::lib::email_client::EmailClient;
+use crate::lib::email_templates::EmailTemplates;
+use crate::lib::issues::{SCHEDULED, SENDING, SENT, get_issue, get_subscriber, render_issue};
+use crate::lib::localization::Translations;
+use crate::lib::personalization::PersonalizedContent;
+use crate::lib::routes::{CONFIRMED, unsubscribe_link};
+use crate::lib::segments::{issue_segment, push_audience};
+use crate::lib::startup::get_connection_pool;
+use crate::lib::tracking::{BOUNCED, DELIVERED, IssueTracker, link_hash};
+use crate::lib::webhooks::{EMAIL_BOUNCED, publish_subscriber_event};
+use secrecy::SecretString;
+use sqlx::{PgPool, Postgres, QueryBuilder, Transaction};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{Span, field::display};
+use uuid::Uuid;
+
+pub enum ExecutionOutcome {
+    TaskCompleted,
+    EmptyQueue,
+}
+
+/// What became of a queued email.
+enum Delivery {
+    Sent,
+    /// The subscriber left, or was removed, since the issue was queued.
+    Skipped,
+}
+
+/// Why an email could not be delivered.
+enum DeliveryError {
+    /// Trying again might work, e.g. the email API is down.
+    Transient(String),
+    /// Trying again would fail the same way.
+    Permanent(String),
+}
+
+/// What every email of an issue shares: rendered, and its merge fields
+/// checked, once rather than for each subscriber.
+struct PreparedIssue {
+    title: String,
+    tracking: bool,
+    content: PersonalizedContent,
+}
+
+/// Delivers scheduled issues, outside of any request.
+///
+/// Every running instance has one: due issues are claimed and their emails
+/// picked with `FOR UPDATE SKIP LOCKED`, so an issue is started by a single
+/// instance and each email goes out once even with several replicas.
+pub struct IssueDelivery {
+    pool: PgPool,
+    email_client: EmailClient,
+    email_templates: EmailTemplates,
+    base_url: String,
+    hmac_secret: SecretString,
+    link_color: String,
+    settings: IssueDeliverySettings,
+    /// The issues being sent, prepared on their first email. They cannot be
+    /// edited anymore: each is kept until it is sent.
+    prepared_issues: Mutex<HashMap<Uuid, Arc<PreparedIssue>>>,
+}
+
+impl IssueDelivery {
+    pub async fn build(configuration: &Setting) -> Result<Self, String> {
+        let pool = get_connection_pool(configuration);
+        let translations = Arc::new(Translations::load(&configuration.localization)?);
+        let email_templates = EmailTemplates::load(
+            &configuration.email_templates,
+            &pool,
+            translations,
+            &configuration.branding.site_name,
+        )
+        .await?;
+
+        Ok(Self {
+            email_client: configuration.email_client.clone().try_into()?,
+            pool,
+            email_templates,
+            base_url: configuration.application.base_url.clone(),
+            hmac_secret: configuration.application.hmac_secret.clone(),
+            link_color: configuration.branding.accent_color.clone(),
+            settings: configuration.issue_delivery.clone(),
+            prepared_issues: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Queue an email for every confirmed subscriber of each issue whose time
+    /// has come. Returns the issues that started going out.
+    #[tracing::instrument(name = "Start due issues", skip(self))]
+    pub async fn start_due_issues(&self) -> Result<Vec<Uuid>, sqlx::Error> {
+        let mut transaction = self.pool.begin().await?;
+        // Issues claimed by another instance are locked: skip them.
+        let issues = sqlx::query_scalar!(
+            r#"
+            UPDATE newsletter_issues
+            SET status = $1, updated_at = now()
+            WHERE newsletter_issue_id IN (
+                SELECT newsletter_issue_id
+                FROM newsletter_issues
+                WHERE status = $2 AND scheduled_for <= now()
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING newsletter_issue_id
+            "#,
+            SENDING,
+            SCHEDULED
+        )
+        .fetch_all(&mut *transaction)
+        .await?;
+        for issue_id in &issues {
+            enqueue_delivery_tasks(&mut transaction, *issue_id).await?;
+        }
+        transaction.commit().await?;
+
+        for issue_id in &issues {
+            tracing::info!(%issue_id, "Started sending an issue.");
+            // Nobody to send it to.
+            self.mark_sent_if_done(*issue_id).await?;
+        }
+        Ok(issues)
+    }
+
+    /// Send one queued email, if any.
+    #[tracing::instrument(
+        name = "Deliver a queued email",
+        skip_all,
+        fields(newsletter_issue_id = tracing::field::Empty, subscriber_id = tracing::field::Empty),
+        err
+    )]
+    pub async fn try_execute_task(
+        &self,
+    ) -> Result<ExecutionOutcome, Box<dyn std::error::Error + Send + Sync>> {
+        let mut transaction = self.pool.begin().await?;
+        let task = sqlx::query!(
+            r#"
+            SELECT newsletter_issue_id, subscriber_id, n_retries
+            FROM issue_delivery_queue
+            WHERE execute_after <= now()
+            FOR UPDATE SKIP LOCKED
+            LIMIT 1
+            "#
+        )
+        .fetch_optional(&mut *transaction)
+        .await?;
+        let Some(task) = task else {
+            return Ok(ExecutionOutcome::EmptyQueue);
+        };
+        Span::current()
+            .record("newsletter_issue_id", display(task.newsletter_issue_id))
+            .record("subscriber_id", display(task.subscriber_id));
+
+        match self
+            .deliver(task.newsletter_issue_id, task.subscriber_id)
+            .await
+        {
+            Ok(Delivery::Sent) => {
+                record_delivery(
+                    &mut transaction,
+                    task.newsletter_issue_id,
+                    task.subscriber_id,
+                    DELIVERED,
+                )
+                .await?;
+            }
+            Ok(Delivery::Skipped) => {}
+            Err(DeliveryError::Transient(e)) if task.n_retries + 1 < self.settings.max_retries => {
+                tracing::warn!(error = %e, n_retries = task.n_retries, "Failed to deliver an email, will retry.");
+                retry_later(
+                    &mut transaction,
+                    task.newsletter_issue_id,
+                    task.subscriber_id,
+                )
+                .await?;
+                transaction.commit().await?;
+                return Ok(ExecutionOutcome::TaskCompleted);
+            }
+            Err(DeliveryError::Transient(e) | DeliveryError::Permanent(e)) => {
+                tracing::error!(error = %e, "Failed to deliver an email, giving up.");
+                record_delivery(
+                    &mut transaction,
+                    task.newsletter_issue_id,
+                    task.subscriber_id,
+                    BOUNCED,
+                )
+                .await?;
+                publish_subscriber_event(
+                    &mut transaction,
+                    EMAIL_BOUNCED,
+                    task.subscriber_id,
+                    serde_json::json!({ "newsletter_issue_id": task.newsletter_issue_id }),
+                )
+                .await?;
+            }
+        }
+        sqlx::query!(
+            r#"
+            DELETE FROM issue_delivery_queue
+            WHERE newsletter_issue_id = $1 AND subscriber_id = $2
+            "#,
+            task.newsletter_issue_id,
+            task.subscriber_id
+        )
+        .execute(&mut *transaction)
+        .await?;
+        transaction.commit().await?;
+
+        self.mark_sent_if_done(task.newsletter_issue_id).await?;
+        Ok(ExecutionOutcome::TaskCompleted)
+    }
+
+    async fn deliver(
+        &self,
+        issue_id: Uuid,
+        subscriber_id: Uuid,
+    ) -> Result<Delivery, DeliveryError> {
+        let transient = |e: sqlx::Error| DeliveryError::Transient(e.to_string());
+        let issue = self.prepared_issue(issue_id).await?;
+        let subscriber = match get_subscriber(&self.pool, subscriber_id)
+            .await
+            .map_err(transient)?
+        {
+            Some(Ok(subscriber)) if subscriber.status == CONFIRMED => subscriber,
+            Some(Ok(_)) | None => return Ok(Delivery::Skipped),
+            Some(Err(e)) => return Err(DeliveryError::Permanent(e)),
+        };
+
+        let tracker = issue
+            .tracking
+            .then(|| IssueTracker::new(&self.base_url, &self.hmac_secret, issue_id));
+        let email = render_issue(
+            &self.email_templates,
+            &issue.title,
+            &issue.content,
+            &subscriber,
+            unsubscribe_link(&self.base_url, &self.hmac_secret, subscriber.id),
+            tracker.as_ref(),
+        )
+        .map_err(|e| DeliveryError::Permanent(e.to_string()))?;
+        if let Some(tracker) = &tracker {
+            // Before the email goes out: its links must work once it is read.
+            let link_hashes: Vec<String> = tracker
+                .tracked_links(&email.html)
+                .iter()
+                .map(|url| link_hash(url))
+                .collect();
+            sqlx::query!(
+                r#"
+                INSERT INTO issue_links (newsletter_issue_id, url_hash)
+                SELECT $1, * FROM UNNEST($2::text[])
+                ON CONFLICT DO NOTHING
+                "#,
+                issue_id,
+                &link_hashes
+            )
+            .execute(&self.pool)
+            .await
+            .map_err(transient)?;
+        }
+
+        self.email_client
+            .send_email(&subscriber.email, &issue.title, &email.html, &email.text)
+            .await
+            .map(|()| Delivery::Sent)
+            .map_err(|e| DeliveryError::Transient(e.to_string()))
+    }
+
+    async fn prepared_issue(&self, issue_id: Uuid) -> Result<Arc<PreparedIssue>, DeliveryError> {
+        let prepared = self.prepared_issues().get(&issue_id).cloned();
+        if let Some(prepared) = prepared {
+            return Ok(prepared);
+        }
+
+        let issue = get_issue(&self.pool, issue_id)
+            .await
+            .map_err(|e| DeliveryError::Transient(e.to_string()))?
+            .ok_or_else(|| DeliveryError::Permanent("The issue was deleted.".into()))?;
+        let content = PersonalizedContent::new(&issue.content.render(&self.link_color))
+            .map_err(|e| DeliveryError::Permanent(e.to_string()))?;
+        let prepared = Arc::new(PreparedIssue {
+            title: issue.title,
+            tracking: issue.tracking,
+            content,
+        });
+        self.prepared_issues()
+            .insert(issue_id, Arc::clone(&prepared));
+        Ok(prepared)
+    }
+
+    fn prepared_issues(&self) -> std::sync::MutexGuard<'_, HashMap<Uuid, Arc<PreparedIssue>>> {
+        self.prepared_issues
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    async fn mark_sent_if_done(&self, issue_id: Uuid) -> Result<(), sqlx::Error> {
+        let marked = sqlx::query!(
+            r#"
+            UPDATE newsletter_issues
+            SET status = $1, sent_at = now(), updated_at = now()
+            WHERE newsletter_issue_id = $2
+                AND status = $3
+                AND NOT EXISTS (
+                    SELECT 1 FROM issue_delivery_queue WHERE newsletter_issue_id = $2
+                )
+            "#,
+            SENT,
+            issue_id,
+            SENDING
+        )
+        .execute(&self.pool)
+        .await?;
+        if marked.rows_affected() > 0 {
+            self.prepared_issues().remove(&issue_id);
+        }
+        Ok(())
+    }
+}
+
+/// Queue the issue for the confirmed subscribers in its segment, as it is
+/// when sending starts.
+#[tracing::instrument(skip(transaction))]
+async fn enqueue_delivery_tasks(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    let segment = issue_segment(&mut **transaction, newsletter_issue_id).await?;
+    let mut query =
+        QueryBuilder::new("INSERT INTO issue_delivery_queue (newsletter_issue_id, subscriber_id) ");
+    query
+        .push("SELECT ")
+        .push_bind(newsletter_issue_id)
+        .push(", s.id");
+    push_audience(&mut query, segment.as_ref());
+    query.build().execute(&mut **transaction).await?;
+    Ok(())
+}
+
+/// Record that an email went out, or never will.
+async fn record_delivery(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    subscriber_id: Uuid,
+    kind: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_events (event_id, newsletter_issue_id, subscriber_id, kind, occurred_at)
+        VALUES ($1, $2, $3, $4, now())
+        "#,
+        Uuid::new_v4(),
+        newsletter_issue_id,
+        subscriber_id,
+        kind
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+/// Back off exponentially: 2s, 4s, 8s...
+async fn retry_later(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET n_retries = n_retries + 1,
+            execute_after = now() + make_interval(secs => power(2, n_retries + 1))
+        WHERE newsletter_issue_id = $1 AND subscriber_id = $2
+        "#,
+        newsletter_issue_id,
+        subscriber_id
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+/// The in-process scheduler: start due issues and send their emails, until
+/// the application stops.
+pub async fn run_worker_until_stopped(configuration: Setting) -> Result<(), std::io::Error> {
+    let delivery = IssueDelivery::build(&configuration)
+        .await
+        .map_err(std::io::Error::other)?;
+    let poll_interval =
+        Duration::from_millis(configuration.issue_delivery.poll_interval_milliseconds);
+
+    // Due issues are looked for once per poll interval, not before every
+    // email: that is a scan of the issues.
+    let mut started_due_issues_at: Option<Instant> = None;
+    loop {
+        if started_due_issues_at.is_none_or(|at| at.elapsed() >= poll_interval) {
+            started_due_issues_at = Some(Instant::now());
+            if delivery.start_due_issues().await.is_err() {
+                tokio::time::sleep(poll_interval).await;
+                continue;
+            }
+        }
+        match delivery.try_execute_task().await {
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+            Ok(ExecutionOutcome::EmptyQueue) | Err(_) => tokio::time::sleep(poll_interval).await,
+        }
+    }
+}
@@ -0,0 +1,196 @@
+use crate::lib::domain::SubscriberEmail;
+use crate::lib::email_templates::{EmailTemplates, RenderedEmail};
+use crate::lib::markdown::{IssueContent, sanitize};
+use crate::lib::personalization::{PersonalizedContent, Recipient};
+use crate::lib::routes::CONFIRMED;
+use crate::lib::tracking::IssueTracker;
+use chrono::{DateTime, Utc};
+use minijinja::{Value, context};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Being written: can be edited, deleted and test-sent.
+pub const DRAFT: &str = "draft";
+/// Waiting for its `scheduled_for` time to be picked up for delivery.
+pub const SCHEDULED: &str = "scheduled";
+/// Queued for every confirmed subscriber, going out.
+pub const SENDING: &str = "sending";
+pub const SENT: &str = "sent";
+pub const CANCELLED: &str = "cancelled";
+
+/// Issues are written in Markdown, or as ready-made HTML and text bodies.
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
+#[serde(untagged)]
+pub enum NewsletterContent {
+    Markdown { markdown: String },
+    Rendered { html: String, text: String },
+}
+
+impl NewsletterContent {
+    pub fn render(&self, link_color: &str) -> IssueContent {
+        match self {
+            NewsletterContent::Markdown { markdown } => {
+                IssueContent::from_markdown(markdown, link_color)
+            }
+            // Sanitized like Markdown once personalized, and already now
+            // for whoever reads it before that.
+            NewsletterContent::Rendered { html, text } => IssueContent {
+                html: sanitize(html),
+                text: text.clone(),
+                warnings: vec![],
+            },
+        }
+    }
+}
+
+/// An issue, at any point of its lifecycle.
+#[derive(serde::Serialize)]
+pub struct NewsletterIssue {
+    pub newsletter_issue_id: Uuid,
+    pub title: String,
+    pub content: NewsletterContent,
+    pub status: String,
+    /// Whether opens and clicks are tracked.
+    pub tracking: bool,
+    /// Only the subscribers in this segment get the issue, if set.
+    pub segment_id: Option<Uuid>,
+    /// Whether the issue shows up in the public archive once sent.
+    pub archived: bool,
+    /// Where it does: `/archive/{slug}`.
+    pub slug: String,
+    pub scheduled_for: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub sent_at: Option<DateTime<Utc>>,
+}
+
+#[tracing::instrument(name = "Get a newsletter issue", skip(pool))]
+pub async fn get_issue(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<Option<NewsletterIssue>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT newsletter_issue_id, title, content, status, tracking, segment_id, archived,
+            slug, scheduled_for, created_at, updated_at, sent_at
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+
+    row.map(|row| {
+        Ok(NewsletterIssue {
+            newsletter_issue_id: row.newsletter_issue_id,
+            title: row.title,
+            // Only ever written from a `NewsletterContent`.
+            content: serde_json::from_value(row.content)
+                .map_err(|e| sqlx::Error::Decode(Box::new(e)))?,
+            status: row.status,
+            tracking: row.tracking,
+            segment_id: row.segment_id,
+            archived: row.archived,
+            slug: row.slug,
+            scheduled_for: row.scheduled_for,
+            created_at: row.created_at,
+            updated_at: row.updated_at,
+            sent_at: row.sent_at,
+        })
+    })
+    .transpose()
+}
+
+/// Somebody an issue is rendered for.
+pub struct Subscriber {
+    pub id: Uuid,
+    pub email: SubscriberEmail,
+    pub status: String,
+    pub locale: String,
+    pub recipient: Recipient,
+}
+
+impl Subscriber {
+    /// A made-up subscriber, to preview an issue with.
+    pub fn example(locale: &str) -> Self {
+        let recipient = Recipient::example();
+        Self {
+            id: Uuid::nil(),
+            email: SubscriberEmail::parse(&recipient.email).expect("A valid email."),
+            status: CONFIRMED.into(),
+            locale: locale.into(),
+            recipient,
+        }
+    }
+}
+
+/// The whole email for `subscriber`: their content in the newsletter template,
+/// with opens and clicks tracked if there is a `tracker`.
+pub fn render_issue(
+    email_templates: &EmailTemplates,
+    title: &str,
+    content: &PersonalizedContent,
+    subscriber: &Subscriber,
+    unsubscribe_link: String,
+    tracker: Option<&IssueTracker>,
+) -> Result<RenderedEmail, Box<dyn std::error::Error + Send + Sync>> {
+    let (mut content_html, content_text) = content.render(&subscriber.recipient)?;
+    if let Some(tracker) = tracker {
+        content_html = tracker.track(&content_html, subscriber.id);
+    }
+    let email = email_templates.render(
+        "newsletter",
+        &subscriber.locale,
+        context! {
+            name => subscriber.recipient.name,
+            email => subscriber.email.as_ref(),
+            title => title,
+            content_html => Value::from_safe_string(content_html),
+            content_text => content_text,
+            // Built by us out of URL-safe characters: no need to escape it.
+            unsubscribe_link => Value::from_safe_string(unsubscribe_link),
+        },
+    )?;
+    Ok(email)
+}
+
+#[tracing::instrument(name = "Get a subscriber", skip(pool))]
+pub async fn get_subscriber(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Option<Result<Subscriber, String>>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT id, email, name, status, locale, subscribed_at, attributes
+        FROM subscriptions
+        WHERE id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+
+    Ok(row.map(|row| {
+        Ok(Subscriber {
+            id: row.id,
+            email: SubscriberEmail::parse(&row.email)?,
+            status: row.status,
+            locale: row.locale,
+            recipient: Recipient {
+                name: row.name,
+                email: row.email,
+                subscribed_at: row.subscribed_at,
+                attributes: row.attributes,
+            },
+        })
+    }))
+}
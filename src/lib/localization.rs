@@ -0,0 +1,266 @@
+use crate::lib::configurations::LocalizationSettings;
+use actix_web::HttpRequest;
+use actix_web::http::header::ACCEPT_LANGUAGE;
+use fluent_bundle::concurrent::FluentBundle;
+use fluent_bundle::{FluentArgs, FluentResource};
+use std::collections::HashMap;
+use std::path::Path;
+use unic_langid::LanguageIdentifier;
+
+/// Every translation catalog found in the localization directory, one
+/// `<locale>/*.ftl` folder per locale.
+pub struct Translations {
+    bundles: HashMap<String, FluentBundle<FluentResource>>,
+    default_locale: String,
+}
+
+impl Translations {
+    /// Load all the catalogs at startup: a syntax error in a `.ftl` file is
+    /// reported straight away rather than when the message is needed.
+    pub fn load(settings: &LocalizationSettings) -> Result<Self, String> {
+        let directory = Path::new(&settings.directory);
+        let entries = std::fs::read_dir(directory)
+            .map_err(|e| format!("Failed to read {}: {}", directory.display(), e))?;
+
+        let mut bundles = HashMap::new();
+        for entry in entries {
+            let path = entry.map_err(|e| e.to_string())?.path();
+            if !path.is_dir() {
+                continue;
+            }
+            let locale = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .ok_or_else(|| format!("{} is not a valid locale", path.display()))?
+                .to_lowercase();
+            bundles.insert(locale.clone(), load_bundle(&locale, &path)?);
+        }
+
+        if !bundles.contains_key(&settings.default_locale) {
+            return Err(format!(
+                "There is no catalog for the default locale `{}`.",
+                settings.default_locale
+            ));
+        }
+        Ok(Self {
+            bundles,
+            default_locale: settings.default_locale.clone(),
+        })
+    }
+
+    pub fn default_locale(&self) -> &str {
+        &self.default_locale
+    }
+
+    /// Pick the locale to talk to a subscriber in: the one they explicitly
+    /// asked for if we support it, otherwise the best match from their
+    /// `Accept-Language` header, otherwise the default one.
+    pub fn negotiate(&self, explicit: Option<&str>, accept_language: Option<&str>) -> String {
+        let mut requested = explicit.map(|l| (l, 2.0)).into_iter().collect::<Vec<_>>();
+        requested.extend(
+            accept_language
+                .unwrap_or_default()
+                .split(',')
+                .filter_map(parse_language_range),
+        );
+        // A stable sort keeps the header order for equal weights.
+        requested.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+
+        requested
+            .into_iter()
+            .find_map(|(tag, _)| self.supported(tag))
+            .unwrap_or_else(|| self.default_locale.clone())
+    }
+
+    /// `uk-UA` is served by the `uk` catalog if there is no `uk-ua` one.
+    fn supported(&self, tag: &str) -> Option<String> {
+        let tag = tag.trim().to_lowercase();
+        let language = tag.split(['-', '_']).next().unwrap_or_default();
+        [tag.as_str(), language]
+            .into_iter()
+            .find(|candidate| self.bundles.contains_key(*candidate))
+            .map(str::to_owned)
+    }
+
+    /// Translate `key` into `locale`, falling back to the default locale and
+    /// then to the key itself if the message is missing.
+    pub fn translate(&self, locale: &str, key: &str, args: Option<&FluentArgs>) -> String {
+        [locale, self.default_locale.as_str()]
+            .into_iter()
+            .filter_map(|locale| self.bundles.get(locale))
+            .find_map(|bundle| {
+                let pattern = bundle.get_message(key)?.value()?;
+                let mut errors = vec![];
+                let value = bundle.format_pattern(pattern, args, &mut errors);
+                if !errors.is_empty() {
+                    tracing::warn!(key, ?errors, "Failed to format a translation.");
+                }
+                Some(value.into_owned())
+            })
+            .unwrap_or_else(|| {
+                tracing::warn!(key, locale, "Missing translation.");
+                key.to_owned()
+            })
+    }
+
+    pub fn translator<'a>(&'a self, locale: &'a str) -> Translator<'a> {
+        Translator {
+            translations: self,
+            locale,
+        }
+    }
+}
+
+/// Translations bound to a locale, for templates to use.
+#[derive(Clone, Copy)]
+pub struct Translator<'a> {
+    translations: &'a Translations,
+    locale: &'a str,
+}
+
+impl Translator<'_> {
+    pub fn locale(&self) -> &str {
+        self.locale
+    }
+
+    pub fn get(&self, key: &str) -> String {
+        self.translations.translate(self.locale, key, None)
+    }
+
+    /// Translate a message with a single `{ $name }` variable.
+    pub fn with(&self, key: &str, name: &str, value: &str) -> String {
+        let mut args = FluentArgs::new();
+        args.set(name.to_owned(), value.to_owned());
+        self.translations.translate(self.locale, key, Some(&args))
+    }
+}
+
+/// The raw `Accept-Language` header of a request, if any.
+pub fn accept_language(req: &HttpRequest) -> Option<&str> {
+    req.headers()
+        .get(ACCEPT_LANGUAGE)
+        .and_then(|value| value.to_str().ok())
+}
+
+fn load_bundle(locale: &str, path: &Path) -> Result<FluentBundle<FluentResource>, String> {
+    let language: LanguageIdentifier = locale
+        .parse()
+        .map_err(|e| format!("{} is not a valid locale: {}", locale, e))?;
+    let mut bundle = FluentBundle::new_concurrent(vec![language]);
+    // Unicode isolation marks end up as garbage in plain-text emails.
+    bundle.set_use_isolating(false);
+
+    let mut files = std::fs::read_dir(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "ftl"))
+        .collect::<Vec<_>>();
+    files.sort();
+
+    for file in files {
+        let source = std::fs::read_to_string(&file)
+            .map_err(|e| format!("Failed to read {}: {}", file.display(), e))?;
+        let resource = FluentResource::try_new(source)
+            .map_err(|(_, errors)| format!("Invalid catalog {}: {:?}", file.display(), errors))?;
+        bundle
+            .add_resource(resource)
+            .map_err(|errors| format!("Invalid catalog {}: {:?}", file.display(), errors))?;
+    }
+    Ok(bundle)
+}
+
+/// Parse one entry of an `Accept-Language` header, e.g. `uk;q=0.9`.
+fn parse_language_range(range: &str) -> Option<(&str, f32)> {
+    let mut parts = range.split(';');
+    let tag = parts.next()?.trim();
+    if tag.is_empty() || tag == "*" {
+        return None;
+    }
+    let weight = parts
+        .find_map(|param| param.trim().strip_prefix("q="))
+        .map_or(Some(1.0), |q| q.trim().parse::<f32>().ok())?;
+    Some((tag, weight))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn translations() -> Translations {
+        Translations::load(&LocalizationSettings {
+            directory: "locales".into(),
+            default_locale: "en".into(),
+        })
+        .expect("Failed to load the bundled catalogs.")
+    }
+
+    #[test]
+    fn the_best_weighted_supported_language_is_picked() {
+        let translations = translations();
+        let locale = translations.negotiate(None, Some("fr-CH, fr;q=0.9, uk;q=0.8, en;q=0.7"));
+        assert_eq!(locale, "uk");
+    }
+
+    #[test]
+    fn regional_variants_fall_back_to_the_language() {
+        assert_eq!(translations().negotiate(None, Some("uk-UA")), "uk");
+    }
+
+    #[test]
+    fn an_explicit_choice_wins_over_the_header() {
+        assert_eq!(translations().negotiate(Some("uk"), Some("en")), "uk");
+    }
+
+    #[test]
+    fn unsupported_languages_fall_back_to_the_default_locale() {
+        assert_eq!(translations().negotiate(Some("xx"), Some("fr, de")), "en");
+        assert_eq!(translations().negotiate(None, None), "en");
+    }
+
+    #[test]
+    fn missing_keys_fall_back_to_the_default_locale_then_to_the_key() {
+        let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        for (locale, catalog) in [("en", "hello = Hello\nbye = Bye"), ("uk", "hello = Привіт")]
+        {
+            std::fs::create_dir_all(directory.join(locale)).unwrap();
+            std::fs::write(directory.join(locale).join("main.ftl"), catalog).unwrap();
+        }
+        let translations = Translations::load(&LocalizationSettings {
+            directory: directory.to_string_lossy().into(),
+            default_locale: "en".into(),
+        })
+        .unwrap();
+
+        assert_eq!(translations.translate("uk", "hello", None), "Привіт");
+        assert_eq!(translations.translate("uk", "bye", None), "Bye");
+        assert_eq!(translations.translate("uk", "nope", None), "nope");
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn every_english_key_is_translated_in_every_catalog() {
+        let translations = translations();
+        let mut keys = vec![];
+        for entry in std::fs::read_dir("locales/en").unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_none_or(|ext| ext != "ftl") {
+                continue;
+            }
+            let resource = FluentResource::try_new(std::fs::read_to_string(path).unwrap())
+                .expect("The bundled catalogs are valid.");
+            keys.extend(resource.entries().filter_map(|entry| match entry {
+                fluent_syntax::ast::Entry::Message(message) => Some(message.id.name.to_owned()),
+                _ => None,
+            }));
+        }
+        assert!(!keys.is_empty());
+
+        for (locale, bundle) in &translations.bundles {
+            let missing = keys
+                .iter()
+                .filter(|key| !bundle.has_message(key))
+                .collect::<Vec<_>>();
+            assert!(missing.is_empty(), "{} is missing {:?}", locale, missing);
+        }
+    }
+}
@@ -0,0 +1,267 @@
+use crate::lib::audit::record_action;
+use crate::lib::authentication::{AuthError, AuthenticatedUser, Credentials, validate_credentials};
+use crate::lib::configurations::{BrandingSettings, LoginThrottlingSettings};
+use crate::lib::domain::SubscriberEmail;
+use crate::lib::email_client::EmailClient;
+use crate::lib::email_templates::EmailTemplates;
+use crate::lib::localization::Translations;
+use actix_web::{HttpRequest, web};
+use chrono::{DateTime, Utc};
+use minijinja::context;
+use sqlx::PgPool;
+use std::time::Duration;
+
+const USERNAME_SCOPE: &str = "username";
+const IP_SCOPE: &str = "ip";
+
+/// Check the password of a login, unless there were too many failed logins
+/// for the username or from the IP address lately.
+///
+/// Each failure makes the next attempt wait longer, and enough of them in a
+/// row lock the username for a while: its user, if there is one, is told by
+/// email. A successful login starts the username over.
+#[tracing::instrument(
+    name = "Attempt a login",
+    skip(req, credentials),
+    fields(username = %credentials.username)
+)]
+pub async fn attempt_login(
+    req: &HttpRequest,
+    credentials: Credentials,
+) -> Result<AuthenticatedUser, AuthError> {
+    let pool = app_data::<PgPool>(req);
+    let settings = app_data::<LoginThrottlingSettings>(req);
+    let username = credentials.username.clone();
+    // The peer, not `X-Forwarded-For`: whoever sends the header picks it.
+    let ip_address = req
+        .peer_addr()
+        .map(|addr| addr.ip().to_string())
+        .unwrap_or_default();
+
+    let retry_after = retry_after(&pool, &settings, &username, &ip_address)
+        .await
+        .map_err(|e| AuthError::Unexpected(e.to_string()))?;
+    if let Some(retry_after) = retry_after {
+        tracing::info!(?retry_after, "Refused a login until the wait is over.");
+        return Err(AuthError::TooManyAttempts(retry_after));
+    }
+
+    match validate_credentials(credentials, &pool).await {
+        Ok(user) => {
+            sqlx::query!(
+                r#"DELETE FROM login_failures WHERE scope = $1 AND key = $2"#,
+                USERNAME_SCOPE,
+                username
+            )
+            .execute(pool.get_ref())
+            .await
+            .map_err(|e| AuthError::Unexpected(e.to_string()))?;
+            Ok(user)
+        }
+        Err(AuthError::InvalidCredentials) => {
+            let locked_until = record_failure(&pool, &settings, &username, &ip_address)
+                .await
+                .map_err(|e| AuthError::Unexpected(e.to_string()))?;
+            if let Some(locked_until) = locked_until {
+                notify_lockout(req, &pool, &username, &ip_address, locked_until).await;
+            }
+            Err(AuthError::InvalidCredentials)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+fn app_data<T: 'static>(req: &HttpRequest) -> web::Data<T> {
+    req.app_data::<web::Data<T>>()
+        .cloned()
+        .unwrap_or_else(|| panic!("{} is registered.", std::any::type_name::<T>()))
+}
+
+/// How long to wait after `n_failures` failed logins in a row.
+fn delay_after(settings: &LoginThrottlingSettings, n_failures: i32) -> chrono::Duration {
+    let doublings = n_failures.saturating_sub(1).clamp(0, 30) as u32;
+    let delay = settings
+        .base_delay_milliseconds
+        .saturating_mul(1 << doublings);
+    chrono::Duration::milliseconds(delay).min(chrono::Duration::seconds(settings.max_delay_seconds))
+}
+
+/// How long before the username or the IP address may try again, if at all.
+async fn retry_after(
+    pool: &PgPool,
+    settings: &LoginThrottlingSettings,
+    username: &str,
+    ip_address: &str,
+) -> Result<Option<Duration>, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT n_failures, last_failure_at, locked_until FROM login_failures
+        WHERE (scope = $1 AND key = $2) OR (scope = $3 AND key = $4)
+        "#,
+        USERNAME_SCOPE,
+        username,
+        IP_SCOPE,
+        ip_address
+    )
+    .fetch_all(pool)
+    .await?;
+    let now = Utc::now();
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            let until = row
+                .locked_until
+                .unwrap_or(row.last_failure_at + delay_after(settings, row.n_failures));
+            until - now
+        })
+        .max()
+        .and_then(|wait| wait.to_std().ok())
+        .filter(|wait| !wait.is_zero()))
+}
+
+/// Count a failed login, and lock the username if it failed too many times.
+/// Returns until when, if it just got locked.
+async fn record_failure(
+    pool: &PgPool,
+    settings: &LoginThrottlingSettings,
+    username: &str,
+    ip_address: &str,
+) -> Result<Option<DateTime<Utc>>, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    for (scope, key) in [(USERNAME_SCOPE, username), (IP_SCOPE, ip_address)] {
+        // Failures from before the last lockout, or too old, are forgotten.
+        sqlx::query!(
+            r#"
+            INSERT INTO login_failures (scope, key, n_failures, last_failure_at)
+            VALUES ($1, $2, 1, now())
+            ON CONFLICT (scope, key) DO UPDATE SET
+                n_failures = CASE
+                    WHEN login_failures.locked_until IS NOT NULL
+                        OR login_failures.last_failure_at < now() - make_interval(mins => $3)
+                    THEN 1
+                    ELSE login_failures.n_failures + 1
+                END,
+                last_failure_at = now(),
+                locked_until = NULL
+            "#,
+            scope,
+            key,
+            settings.lockout_minutes
+        )
+        .execute(&mut *transaction)
+        .await?;
+    }
+    let locked_until = sqlx::query_scalar!(
+        r#"
+        UPDATE login_failures SET locked_until = now() + make_interval(mins => $3)
+        WHERE scope = $1 AND key = $2 AND n_failures >= $4
+        RETURNING locked_until AS "locked_until!"
+        "#,
+        USERNAME_SCOPE,
+        username,
+        settings.lockout_minutes,
+        settings.max_failures_per_account
+    )
+    .fetch_optional(&mut *transaction)
+    .await?;
+    transaction.commit().await?;
+    Ok(locked_until)
+}
+
+/// Record the lockout of a user, and tell them by email. Usernames nobody
+/// has are locked all the same, without anybody to tell.
+async fn notify_lockout(
+    req: &HttpRequest,
+    pool: &PgPool,
+    username: &str,
+    ip_address: &str,
+    locked_until: DateTime<Utc>,
+) {
+    tracing::warn!(%locked_until, "Locked a username after too many failed logins.");
+    let user = match sqlx::query!(
+        r#"SELECT user_id, email FROM users WHERE username = $1"#,
+        username
+    )
+    .fetch_optional(pool)
+    .await
+    {
+        Ok(Some(user)) => user,
+        Ok(None) => return,
+        Err(e) => {
+            tracing::error!("Failed to execute query: {:?}", e);
+            return;
+        }
+    };
+    if let Err(e) = record_action(
+        pool,
+        Some(user.user_id),
+        "user.locked_out",
+        None,
+        serde_json::json!({
+            "ip_address": ip_address,
+            "locked_until": locked_until,
+        }),
+    )
+    .await
+    {
+        tracing::error!("Failed to execute query: {:?}", e);
+    }
+
+    let Some(email) = user
+        .email
+        .and_then(|email| SubscriberEmail::parse(&email).ok())
+    else {
+        return;
+    };
+    let email_client = app_data::<EmailClient>(req);
+    let email_templates = app_data::<EmailTemplates>(req);
+    let translations = app_data::<Translations>(req);
+    let branding = app_data::<BrandingSettings>(req);
+    let settings = app_data::<LoginThrottlingSettings>(req);
+    let t = translations.translator(translations.default_locale());
+    let rendered = match email_templates.render(
+        "account_locked",
+        t.locale(),
+        context! {
+            lockout_minutes => settings.lockout_minutes,
+            ip_address => ip_address,
+        },
+    ) {
+        Ok(rendered) => rendered,
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to render the account locked email.");
+            return;
+        }
+    };
+    if let Err(e) = email_client
+        .send_email(
+            &email,
+            &t.with("account-locked-email-subject", "site", &branding.site_name),
+            &rendered.html,
+            &rendered.text,
+        )
+        .await
+    {
+        tracing::error!(error = %e, "Failed to send the account locked email.");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_delay_doubles_with_each_failure_up_to_the_maximum() {
+        let settings = LoginThrottlingSettings {
+            max_failures_per_account: 5,
+            lockout_minutes: 15,
+            base_delay_milliseconds: 500,
+            max_delay_seconds: 60,
+        };
+        let delays: Vec<_> = [1, 2, 3, 4, 8, 1000]
+            .into_iter()
+            .map(|n| delay_after(&settings, n).num_milliseconds())
+            .collect();
+        assert_eq!(delays, vec![500, 1000, 2000, 4000, 60000, 60000]);
+    }
+}
@@ -0,0 +1,513 @@
+use pulldown_cmark::{Event, HeadingLevel, Options, Parser, Tag, TagEnd};
+use std::collections::HashSet;
+use std::sync::LazyLock;
+
+/// Merge fields (`{{ name }}`) are swapped for `\u{E000}<n>\u{E001}` while the
+/// Markdown is rendered, so that it leaves them alone.
+const PLACEHOLDER_START: char = '\u{E000}';
+const PLACEHOLDER_END: char = '\u{E001}';
+
+/// Styles inlined into the HTML of an issue: most email clients ignore
+/// `<style>` blocks.
+const INLINE_STYLES: &[(&str, &str)] = &[
+    (
+        "h1",
+        "font-size: 26px; line-height: 1.25; margin: 24px 0 12px;",
+    ),
+    (
+        "h2",
+        "font-size: 21px; line-height: 1.25; margin: 20px 0 10px;",
+    ),
+    (
+        "h3",
+        "font-size: 17px; line-height: 1.25; margin: 16px 0 8px;",
+    ),
+    ("p", "margin: 0 0 16px; line-height: 1.5;"),
+    ("ul", "margin: 0 0 16px; padding-left: 24px;"),
+    ("ol", "margin: 0 0 16px; padding-left: 24px;"),
+    ("li", "margin: 0 0 4px;"),
+    (
+        "blockquote",
+        "margin: 0 0 16px; padding-left: 12px; border-left: 3px solid #ddd; color: #555;",
+    ),
+    (
+        "pre",
+        "margin: 0 0 16px; padding: 12px; background: #f6f6f6; overflow-x: auto;",
+    ),
+    (
+        "code",
+        "font-family: Menlo, Consolas, monospace; font-size: 90%;",
+    ),
+    ("img", "max-width: 100%; height: auto;"),
+    ("table", "border-collapse: collapse; margin: 0 0 16px;"),
+    (
+        "th",
+        "border: 1px solid #ddd; padding: 6px 10px; text-align: left;",
+    ),
+    ("td", "border: 1px solid #ddd; padding: 6px 10px;"),
+    (
+        "hr",
+        "border: none; border-top: 1px solid #ddd; margin: 24px 0;",
+    ),
+];
+
+/// Cleans the HTML of an issue, keeping the styles `INLINE_STYLES` and links
+/// use.
+static SANITIZER: LazyLock<ammonia::Builder<'static>> = LazyLock::new(|| {
+    let mut properties = HashSet::from(["color"]);
+    for (_, style) in INLINE_STYLES {
+        properties.extend(
+            style
+                .split(';')
+                .filter_map(|declaration| Some(declaration.split_once(':')?.0.trim())),
+        );
+    }
+    let mut sanitizer = ammonia::Builder::default();
+    sanitizer
+        .add_generic_attributes(["style"])
+        .filter_style_properties(properties);
+    sanitizer
+});
+
+/// Something in the Markdown that is likely to look broken in an inbox.
+#[derive(Debug, PartialEq, serde::Serialize)]
+#[serde(tag = "code", rename_all = "snake_case")]
+pub enum MarkdownWarning {
+    /// Relative links have no page to be relative to in an email.
+    RelativeLink {
+        url: String,
+    },
+    RelativeImage {
+        src: String,
+    },
+    /// Images are often blocked: the alt text is all some readers get.
+    ImageWithoutAlt {
+        src: String,
+    },
+}
+
+impl std::fmt::Display for MarkdownWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MarkdownWarning::RelativeLink { url } => {
+                write!(f, "The link to `{}` is relative: use a full URL.", url)
+            }
+            MarkdownWarning::RelativeImage { src } => {
+                write!(f, "The image `{}` is relative: use a full URL.", src)
+            }
+            MarkdownWarning::ImageWithoutAlt { src } => {
+                write!(f, "The image `{}` has no alt text.", src)
+            }
+        }
+    }
+}
+
+/// The content of an issue, ready to be sent.
+#[derive(Debug, serde::Serialize)]
+pub struct IssueContent {
+    /// With the styles inlined. Only sanitized once the merge fields are filled
+    /// in, with `sanitize`: they can output anything.
+    pub html: String,
+    pub text: String,
+    pub warnings: Vec<MarkdownWarning>,
+}
+
+impl IssueContent {
+    /// Render an issue written in Markdown, with links in `link_color`.
+    ///
+    /// Merge fields are copied as is to both the HTML and the text.
+    pub fn from_markdown(markdown: &str, link_color: &str) -> Self {
+        let (markdown, merge_fields) = protect_merge_fields(markdown);
+        let options = Options::ENABLE_TABLES
+            | Options::ENABLE_STRIKETHROUGH
+            | Options::ENABLE_TASKLISTS
+            | Options::ENABLE_SMART_PUNCTUATION;
+        let events = Parser::new_ext(&markdown, options).collect::<Vec<_>>();
+
+        let mut html = String::new();
+        pulldown_cmark::html::push_html(&mut html, events.iter().cloned());
+        // Raw HTML is allowed in Markdown: keep the harmless part of it.
+        let html = ammonia::clean(&html);
+        let link_style = format!("color: {};", link_color);
+
+        Self {
+            html: restore_merge_fields(&inline_styles(&html, &link_style), &merge_fields),
+            text: restore_merge_fields(&TextRenderer::default().render(&events), &merge_fields),
+            warnings: warnings(&events),
+        }
+    }
+}
+
+/// Remove anything that could run or hide in the HTML of an issue, keeping
+/// the styles `IssueContent::from_markdown` inlines.
+pub fn sanitize(html: &str) -> String {
+    SANITIZER.clean(html).to_string()
+}
+
+fn protect_merge_fields(markdown: &str) -> (String, Vec<&str>) {
+    let mut protected = String::with_capacity(markdown.len());
+    let mut merge_fields = vec![];
+    let mut rest = markdown;
+    while let Some(start) = rest.find("{{")
+        && let Some(length) = rest[start..].find("}}")
+    {
+        let end = start + length + 2;
+        protected.push_str(&rest[..start]);
+        protected.push_str(&format!(
+            "{}{}{}",
+            PLACEHOLDER_START,
+            merge_fields.len(),
+            PLACEHOLDER_END
+        ));
+        merge_fields.push(&rest[start..end]);
+        rest = &rest[end..];
+    }
+    protected.push_str(rest);
+    (protected, merge_fields)
+}
+
+fn restore_merge_fields(rendered: &str, merge_fields: &[&str]) -> String {
+    merge_fields
+        .iter()
+        .enumerate()
+        .fold(rendered.to_owned(), |rendered, (i, merge_field)| {
+            let placeholder = format!("{}{}{}", PLACEHOLDER_START, i, PLACEHOLDER_END);
+            // Link destinations are percent-encoded.
+            let encoded = placeholder
+                .bytes()
+                .map(|byte| match byte {
+                    b'0'..=b'9' => char::from(byte).to_string(),
+                    _ => format!("%{:02X}", byte),
+                })
+                .collect::<String>();
+            rendered
+                .replace(&placeholder, merge_field)
+                .replace(&encoded, merge_field)
+        })
+}
+
+fn warnings(events: &[Event]) -> Vec<MarkdownWarning> {
+    let mut warnings = vec![];
+    for (i, event) in events.iter().enumerate() {
+        match event {
+            Event::Start(Tag::Link { dest_url, .. }) if is_relative(dest_url) => {
+                warnings.push(MarkdownWarning::RelativeLink {
+                    url: dest_url.to_string(),
+                });
+            }
+            Event::Start(Tag::Image { dest_url, .. }) => {
+                let src = dest_url.to_string();
+                if is_relative(&src) {
+                    warnings.push(MarkdownWarning::RelativeImage { src: src.clone() });
+                }
+                let has_alt = events[i + 1..]
+                    .iter()
+                    .take_while(|event| !matches!(event, Event::End(TagEnd::Image)))
+                    .any(|event| matches!(event, Event::Text(text) if !text.trim().is_empty()));
+                if !has_alt {
+                    warnings.push(MarkdownWarning::ImageWithoutAlt { src });
+                }
+            }
+            _ => {}
+        }
+    }
+    warnings
+}
+
+/// In-page anchors are fine, anything without a scheme is not. Links starting
+/// with a merge field are only known when sending.
+fn is_relative(url: &str) -> bool {
+    !url.starts_with(['#', PLACEHOLDER_START]) && reqwest::Url::parse(url).is_err()
+}
+
+/// Add a `style` attribute to the tags we have a style for.
+///
+/// Only works on sanitized HTML: the sanitizer drops every `style` attribute
+/// and escapes every `<` that does not open a tag.
+fn inline_styles(html: &str, link_style: &str) -> String {
+    let mut styled = String::with_capacity(html.len() * 2);
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        let (before, tag) = rest.split_at(start + 1);
+        styled.push_str(before);
+        let name_length = tag
+            .find(|c: char| !c.is_ascii_alphanumeric())
+            .unwrap_or(tag.len());
+        let name = &tag[..name_length];
+        let style = match name {
+            "a" => Some(link_style),
+            _ => INLINE_STYLES
+                .iter()
+                .find(|(tag, _)| *tag == name)
+                .map(|(_, style)| *style),
+        };
+        styled.push_str(name);
+        if let Some(style) = style {
+            styled.push_str(&format!(r#" style="{}""#, style));
+        }
+        rest = &tag[name_length..];
+    }
+    styled.push_str(rest);
+    styled
+}
+
+/// Turns Markdown events into text that reads well as is.
+#[derive(Default)]
+struct TextRenderer {
+    text: String,
+    /// The next item number of each list we are in, `None` for bullets.
+    lists: Vec<Option<u64>>,
+    /// Where the text of the links we are in starts, with their target.
+    links: Vec<(usize, String)>,
+    heading_start: usize,
+    quote_depth: usize,
+    in_code_block: bool,
+}
+
+impl TextRenderer {
+    fn render(mut self, events: &[Event]) -> String {
+        for event in events {
+            match event {
+                Event::Start(tag) => self.start(tag),
+                Event::End(tag) => self.end(tag),
+                Event::Text(text) => self.push_text(text),
+                Event::Code(code) => self.push_text(code),
+                Event::SoftBreak => self.push_text(" "),
+                Event::HardBreak => self.new_line(),
+                Event::Rule => {
+                    self.push_text("----------");
+                    self.end_block();
+                }
+                Event::TaskListMarker(checked) => {
+                    self.push_text(if *checked { "[x] " } else { "[ ] " })
+                }
+                // Raw HTML and the rest have no plain text equivalent.
+                _ => {}
+            }
+        }
+        format!("{}\n", self.text.trim_end())
+    }
+
+    fn start(&mut self, tag: &Tag) {
+        match tag {
+            Tag::Heading { .. } => self.heading_start = self.text.len(),
+            Tag::BlockQuote(_) => self.quote_depth += 1,
+            Tag::CodeBlock(_) => self.in_code_block = true,
+            Tag::List(first) => {
+                if !self.lists.is_empty() {
+                    self.new_line();
+                }
+                self.lists.push(*first);
+            }
+            Tag::Item => {
+                let depth = self.lists.len().saturating_sub(1);
+                let marker = match self.lists.last_mut() {
+                    Some(Some(number)) => {
+                        *number += 1;
+                        format!("{}. ", *number - 1)
+                    }
+                    _ => "- ".to_owned(),
+                };
+                self.push_text(&format!("{}{}", "   ".repeat(depth), marker));
+            }
+            Tag::Link { dest_url, .. } => self.links.push((self.text.len(), dest_url.to_string())),
+            // The alt text stands in for the image.
+            Tag::Image { .. } => self.push_text("["),
+            _ => {}
+        }
+    }
+
+    fn end(&mut self, tag: &TagEnd) {
+        match tag {
+            TagEnd::Paragraph if self.lists.is_empty() => self.end_block(),
+            TagEnd::Paragraph => self.new_line(),
+            TagEnd::Heading(level) => {
+                let length = self.text[self.heading_start..].chars().count();
+                let underline = if *level == HeadingLevel::H1 { "=" } else { "-" };
+                self.new_line();
+                self.push_text(&underline.repeat(length));
+                self.end_block();
+            }
+            TagEnd::BlockQuote(_) => {
+                self.quote_depth -= 1;
+                self.end_block();
+            }
+            TagEnd::CodeBlock => {
+                self.in_code_block = false;
+                self.end_block();
+            }
+            TagEnd::List(_) => {
+                self.lists.pop();
+                if self.lists.is_empty() {
+                    self.end_block();
+                }
+            }
+            TagEnd::Item => self.new_line(),
+            TagEnd::Link => {
+                if let Some((start, url)) = self.links.pop()
+                    && self.text[start..] != url
+                {
+                    self.push_text(&format!(" ({})", url));
+                }
+            }
+            TagEnd::Image => self.push_text("]"),
+            TagEnd::TableHead | TagEnd::TableRow => self.new_line(),
+            TagEnd::TableCell => self.push_text(" | "),
+            TagEnd::Table => self.end_block(),
+            _ => {}
+        }
+    }
+
+    fn push_text(&mut self, text: &str) {
+        for (i, line) in text.split('\n').enumerate() {
+            if i > 0 {
+                self.new_line();
+            }
+            if line.is_empty() {
+                continue;
+            }
+            if self.at_line_start() {
+                self.text.push_str(&"> ".repeat(self.quote_depth));
+                if self.in_code_block {
+                    self.text.push_str("    ");
+                }
+            }
+            self.text.push_str(line);
+        }
+    }
+
+    fn at_line_start(&self) -> bool {
+        self.text.is_empty() || self.text.ends_with('\n')
+    }
+
+    fn new_line(&mut self) {
+        self.text.truncate(self.text.trim_end_matches(' ').len());
+        if !self.at_line_start() {
+            self.text.push('\n');
+        }
+    }
+
+    /// Leave an empty line after a paragraph, a heading, a list...
+    fn end_block(&mut self) {
+        self.new_line();
+        if !self.text.is_empty() && !self.text.ends_with("\n\n") {
+            self.text.push('\n');
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(markdown: &str) -> IssueContent {
+        IssueContent::from_markdown(markdown, "#c4421a")
+    }
+
+    #[test]
+    fn styles_are_inlined_into_the_html() {
+        let content = render("# Hello\n\nSome [link](https://example.com).");
+        assert!(content.html.contains(
+            r#"<h1 style="font-size: 26px; line-height: 1.25; margin: 24px 0 12px;">Hello</h1>"#
+        ));
+        assert!(
+            content
+                .html
+                .contains(r#"<a style="color: #c4421a;" href="https://example.com""#)
+        );
+    }
+
+    #[test]
+    fn dangerous_html_is_removed() {
+        let content = render("Hi <script>alert(1)</script><img src=x onerror=alert(1)>");
+        assert!(!content.html.contains("<script"));
+        assert!(!content.html.contains("onerror"));
+    }
+
+    #[test]
+    fn sanitizing_keeps_the_inlined_styles() {
+        let content = render("# Hello\n\nSome [link](https://example.com).");
+
+        let html = sanitize(&content.html);
+
+        assert!(
+            html.contains(r#"<h1 style="font-size:26px;line-height:1.25;margin:24px 0 12px">"#)
+        );
+        assert!(html.contains(r#"<a style="color:#c4421a" href="https://example.com""#));
+    }
+
+    #[test]
+    fn sanitizing_removes_scripts_and_unexpected_styles() {
+        let html = sanitize(
+            r#"<p style="color: red; position: fixed;" onclick="alert(1)">Hi</p><script>alert(1)</script>"#,
+        );
+        assert_eq!(html, r#"<p style="color:red">Hi</p>"#);
+    }
+
+    #[test]
+    fn the_text_version_keeps_the_structure() {
+        let content = render(
+            "# Title\n\nA paragraph with a [link](https://example.com).\n\n\
+             - one\n- two\n\n1. first\n2. second\n\n> quoted\n\n```\nlet x = 1;\n```\n",
+        );
+        assert_eq!(
+            content.text,
+            "Title\n=====\n\n\
+             A paragraph with a link (https://example.com).\n\n\
+             - one\n- two\n\n\
+             1. first\n2. second\n\n\
+             > quoted\n\n\
+             \x20   let x = 1;\n"
+        );
+    }
+
+    #[test]
+    fn bare_links_are_not_repeated_in_the_text_version() {
+        let content = render("<https://example.com>");
+        assert_eq!(content.text, "https://example.com\n");
+    }
+
+    #[test]
+    fn relative_links_and_images_are_reported() {
+        let content = render("[about](/about) [top](#top) ![logo](logo.png)");
+        assert_eq!(
+            content.warnings,
+            vec![
+                MarkdownWarning::RelativeLink {
+                    url: "/about".into()
+                },
+                MarkdownWarning::RelativeImage {
+                    src: "logo.png".into()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn merge_fields_are_left_untouched() {
+        let content =
+            render("Hi *{{ name | default(\"there\") }}*, [unsubscribe]({{ unsubscribe_link }}).");
+        assert!(
+            content
+                .html
+                .contains(r#"<em>{{ name | default("there") }}</em>"#)
+        );
+        assert!(content.html.contains(r#"href="{{ unsubscribe_link }}""#));
+        assert_eq!(
+            content.text,
+            "Hi {{ name | default(\"there\") }}, unsubscribe ({{ unsubscribe_link }}).\n"
+        );
+        assert_eq!(content.warnings, vec![]);
+    }
+
+    #[test]
+    fn images_without_alt_text_are_reported() {
+        let content = render("![](https://example.com/a.png) ![A cat](https://example.com/b.png)");
+        assert_eq!(
+            content.warnings,
+            vec![MarkdownWarning::ImageWithoutAlt {
+                src: "https://example.com/a.png".into()
+            }]
+        );
+    }
+}
@@ -0,0 +1,230 @@
+use crate::lib::archive::ArchivedIssue;
+use crate::lib::bot_protection::FormChallenge;
+use crate::lib::configurations::BrandingSettings;
+use crate::lib::localization::Translator;
+use crate::lib::reports::{GrowthDay, IssueStats};
+use actix_web::HttpResponse;
+use actix_web::http::StatusCode;
+use actix_web::http::header::ContentType;
+use askama::Template;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+#[derive(Template)]
+#[template(path = "signup.html")]
+pub struct SignupPage<'a> {
+    pub branding: &'a BrandingSettings,
+    pub t: Translator<'a>,
+    pub challenge: FormChallenge,
+    /// Values to pre-fill the form with when it is shown again after an error.
+    pub name: &'a str,
+    pub email: &'a str,
+    pub errors: Vec<String>,
+    pub suggestion: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "check_inbox.html")]
+pub struct CheckInboxPage<'a> {
+    pub branding: &'a BrandingSettings,
+    pub t: Translator<'a>,
+    pub email: &'a str,
+}
+
+#[derive(Template)]
+#[template(path = "confirmed.html")]
+pub struct ConfirmedPage<'a> {
+    pub branding: &'a BrandingSettings,
+    pub t: Translator<'a>,
+    pub unsubscribe_link: String,
+}
+
+#[derive(Template)]
+#[template(path = "invalid_token.html")]
+pub struct InvalidTokenPage<'a> {
+    pub branding: &'a BrandingSettings,
+    pub t: Translator<'a>,
+    pub expired: bool,
+}
+
+#[derive(Template)]
+#[template(path = "unsubscribe.html")]
+pub struct UnsubscribePage<'a> {
+    pub branding: &'a BrandingSettings,
+    pub t: Translator<'a>,
+    pub subscriber_id: Uuid,
+    pub token: &'a str,
+}
+
+#[derive(Template)]
+#[template(path = "unsubscribed.html")]
+pub struct UnsubscribedPage<'a> {
+    pub branding: &'a BrandingSettings,
+    pub t: Translator<'a>,
+}
+
+/// For the people running the newsletter, in the default locale.
+#[derive(Template)]
+#[template(path = "reports.html")]
+pub struct ReportsPage<'a> {
+    pub branding: &'a BrandingSettings,
+    pub t: Translator<'a>,
+    pub issues: Vec<IssueStats>,
+    pub growth: Vec<GrowthDay>,
+}
+
+/// The script other sites include to embed the signup form. Its settings and
+/// texts are a JSON object: no escaping needed.
+#[derive(Template)]
+#[template(path = "signup_widget.js", escape = "none")]
+pub struct SignupWidget {
+    pub config: String,
+}
+
+/// Where somebody invited to help run the newsletter signs up.
+#[derive(Template)]
+#[template(path = "invite.html")]
+pub struct InvitePage<'a> {
+    pub branding: &'a BrandingSettings,
+    pub t: Translator<'a>,
+    pub token: &'a str,
+    /// The role of the invite, translated.
+    pub role_name: String,
+    /// The value to pre-fill the form with when it is shown again after an
+    /// error.
+    pub username: &'a str,
+    pub errors: Vec<String>,
+}
+
+pub enum InviteOutcome {
+    Accepted,
+    Invalid,
+    Expired,
+}
+
+impl InviteOutcome {
+    pub fn title_key(&self) -> &'static str {
+        match self {
+            InviteOutcome::Accepted => "invite-accepted-title",
+            InviteOutcome::Invalid => "invite-invalid-title",
+            InviteOutcome::Expired => "invite-expired-title",
+        }
+    }
+}
+
+#[derive(Template)]
+#[template(path = "invite_outcome.html")]
+pub struct InviteOutcomePage<'a> {
+    pub branding: &'a BrandingSettings,
+    pub t: Translator<'a>,
+    pub outcome: InviteOutcome,
+}
+
+/// The form to ask for a link to reset a forgotten password.
+#[derive(Template)]
+#[template(path = "forgot_password.html")]
+pub struct ForgotPasswordPage<'a> {
+    pub branding: &'a BrandingSettings,
+    pub t: Translator<'a>,
+}
+
+#[derive(Template)]
+#[template(path = "password_reset.html")]
+pub struct PasswordResetPage<'a> {
+    pub branding: &'a BrandingSettings,
+    pub t: Translator<'a>,
+    pub token: &'a str,
+    pub errors: Vec<String>,
+}
+
+pub enum PasswordResetOutcome {
+    /// Shown whether or not a user has the email: it must not tell.
+    EmailSent,
+    Done,
+    Invalid,
+    Expired,
+}
+
+impl PasswordResetOutcome {
+    pub fn title_key(&self) -> &'static str {
+        match self {
+            PasswordResetOutcome::EmailSent => "password-reset-sent-title",
+            PasswordResetOutcome::Done => "password-reset-done-title",
+            PasswordResetOutcome::Invalid => "password-reset-invalid-title",
+            PasswordResetOutcome::Expired => "password-reset-expired-title",
+        }
+    }
+}
+
+#[derive(Template)]
+#[template(path = "password_reset_outcome.html")]
+pub struct PasswordResetOutcomePage<'a> {
+    pub branding: &'a BrandingSettings,
+    pub t: Translator<'a>,
+    pub outcome: PasswordResetOutcome,
+}
+
+/// The sent issues that opted into the archive, or the ones matching
+/// `search`.
+#[derive(Template)]
+#[template(path = "archive.html")]
+pub struct ArchivePage<'a> {
+    pub branding: &'a BrandingSettings,
+    pub t: Translator<'a>,
+    pub issues: Vec<ArchivedIssue>,
+    pub search: &'a str,
+}
+
+#[derive(Template)]
+#[template(path = "archived_issue.html")]
+pub struct ArchivedIssuePage<'a> {
+    pub branding: &'a BrandingSettings,
+    pub t: Translator<'a>,
+    pub title: &'a str,
+    pub sent_at: DateTime<Utc>,
+    pub content_html: String,
+}
+
+/// An archived issue, in a feed.
+pub struct FeedEntry {
+    pub title: String,
+    pub link: String,
+    pub published: DateTime<Utc>,
+    pub content_html: String,
+}
+
+/// The archive as RSS 2.0. Like every `.xml` template, values are escaped.
+#[derive(Template)]
+#[template(path = "rss.xml")]
+pub struct RssFeed<'a> {
+    pub branding: &'a BrandingSettings,
+    pub archive_url: &'a str,
+    pub updated_at: DateTime<Utc>,
+    pub entries: Vec<FeedEntry>,
+}
+
+/// The archive as an Atom feed.
+#[derive(Template)]
+#[template(path = "atom.xml")]
+pub struct AtomFeed<'a> {
+    pub branding: &'a BrandingSettings,
+    pub archive_url: &'a str,
+    pub updated_at: DateTime<Utc>,
+    pub entries: Vec<FeedEntry>,
+}
+
+/// Render `page` as the body of a response with the given status code.
+///
+/// Templates live in `templates/` and are checked at compile time: every value
+/// is HTML-escaped unless explicitly marked as safe.
+pub fn render(page: &impl Template, status: StatusCode) -> HttpResponse {
+    match page.render() {
+        Ok(body) => HttpResponse::build(status)
+            .insert_header(ContentType::html())
+            .body(body),
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to render a page.");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
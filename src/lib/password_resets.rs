@@ -0,0 +1,151 @@
+use crate::lib::domain::SubscriberEmail;
+use crate::lib::email_client::EmailClient;
+use crate::lib::email_templates::EmailTemplates;
+use crate::lib::localization::Translator;
+use chrono::{DateTime, Duration, Utc};
+use minijinja::{Value, context};
+use rand::Rng;
+use rand::distr::Alphanumeric;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// At most one reset email per user in this many seconds, however often the
+/// form is sent.
+const MIN_SECONDS_BETWEEN_EMAILS: i64 = 60;
+
+/// A reset token found by its link.
+pub struct PendingReset {
+    pub user_id: Uuid,
+    pub expires_at: DateTime<Utc>,
+}
+
+pub fn generate_reset_token() -> String {
+    let mut rng = rand::rng();
+    std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(32)
+        .collect()
+}
+
+/// What is stored instead of the token: a leaked table resets no password.
+pub fn hash_reset_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// The link users click to pick a new password.
+pub fn reset_link(base_url: &str, token: &str) -> String {
+    format!("{}/password-reset/confirm?token={}", base_url, token)
+}
+
+/// Store a new token for each user with `email` who was not sent another
+/// one just now. Returns their emails and tokens, to send.
+#[tracing::instrument(name = "Create password reset tokens", skip(pool, email))]
+pub async fn create_reset_tokens(
+    pool: &PgPool,
+    email: &str,
+    ttl_minutes: i64,
+) -> Result<Vec<(String, String)>, sqlx::Error> {
+    let now = Utc::now();
+    let users = sqlx::query!(
+        r#"
+        SELECT u.user_id, u.email AS "email!" FROM users u
+        WHERE lower(u.email) = lower($1)
+            AND NOT EXISTS (
+                SELECT 1 FROM password_reset_tokens t
+                WHERE t.user_id = u.user_id AND t.created_at > $2
+            )
+        "#,
+        email.trim(),
+        now - Duration::seconds(MIN_SECONDS_BETWEEN_EMAILS)
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    let mut tokens = Vec::with_capacity(users.len());
+    for user in users {
+        let token = generate_reset_token();
+        sqlx::query!(
+            r#"
+            INSERT INTO password_reset_tokens (token_hash, user_id, created_at, expires_at)
+            VALUES ($1, $2, $3, $4)
+            "#,
+            hash_reset_token(&token),
+            user.user_id,
+            now,
+            now + Duration::minutes(ttl_minutes)
+        )
+        .execute(pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to execute query: {:?}", e);
+            e
+        })?;
+        tokens.push((user.email, token));
+    }
+    Ok(tokens)
+}
+
+/// The reset behind `token`, unless it is unknown or already used.
+#[tracing::instrument(name = "Find a password reset by token", skip(pool, token))]
+pub async fn find_pending_reset(
+    pool: &PgPool,
+    token: &str,
+) -> Result<Option<PendingReset>, sqlx::Error> {
+    sqlx::query_as!(
+        PendingReset,
+        r#"
+        SELECT user_id, expires_at FROM password_reset_tokens
+        WHERE token_hash = $1 AND used_at IS NULL
+        "#,
+        hash_reset_token(token)
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })
+}
+
+#[tracing::instrument(
+    name = "Send a password reset email",
+    skip(email_client, email_templates, email, link, site_name, t),
+    fields(locale = %t.locale())
+)]
+pub async fn send_reset_email(
+    email_client: &EmailClient,
+    email_templates: &EmailTemplates,
+    email: &SubscriberEmail,
+    link: &str,
+    ttl_minutes: i64,
+    site_name: &str,
+    t: Translator<'_>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let rendered = email_templates
+        .render(
+            "password_reset",
+            t.locale(),
+            context! {
+                // Built by us out of URL-safe characters: no need to escape it.
+                reset_link => Value::from_safe_string(link.to_owned()),
+                ttl_minutes => ttl_minutes,
+            },
+        )
+        .map_err(|e| {
+            tracing::error!(error = %e, "Failed to render the password reset email.");
+            e
+        })?;
+    email_client
+        .send_email(
+            email,
+            &t.with("password-reset-email-subject", "site", site_name),
+            &rendered.html,
+            &rendered.text,
+        )
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,199 @@
+use crate::lib::markdown::{IssueContent, sanitize};
+use chrono::{DateTime, Utc};
+use minijinja::{Environment, UndefinedBehavior, context};
+
+/// The merge fields an issue can use.
+const MERGE_FIELDS: &[&str] = &["name", "email", "subscribed_at", "attributes"];
+
+/// Who an issue is being rendered for.
+pub struct Recipient {
+    pub name: String,
+    pub email: String,
+    pub subscribed_at: DateTime<Utc>,
+    /// Custom attributes, as a JSON object.
+    pub attributes: serde_json::Value,
+}
+
+impl Recipient {
+    /// A made-up subscriber, to preview an issue with.
+    pub fn example() -> Self {
+        Self {
+            name: "Ursula Le Guin".into(),
+            email: "ursula_le_guin@example.com".into(),
+            subscribed_at: Utc::now(),
+            attributes: serde_json::json!({}),
+        }
+    }
+
+    /// Nobody in particular, e.g. a reader of the archive: every merge field
+    /// is blank, and attributes use their fallback.
+    pub fn anonymous() -> Self {
+        Self {
+            name: String::new(),
+            email: String::new(),
+            subscribed_at: Utc::now(),
+            attributes: serde_json::json!({}),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct MergeFieldError(String);
+
+impl std::fmt::Display for MergeFieldError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for MergeFieldError {}
+
+/// The content of an issue, with merge fields filled in for each recipient.
+///
+/// Merge fields use the template syntax: `{{ name }}`, `{{ email }}`,
+/// `{{ subscribed_at }}` and `{{ attributes.company }}`. Missing attributes
+/// render as nothing, and any field can fall back to a value with
+/// `{{ attributes.city | default("your city") }}`.
+pub struct PersonalizedContent {
+    environment: Environment<'static>,
+}
+
+impl PersonalizedContent {
+    /// Check the merge fields once, before rendering the content for anyone.
+    pub fn new(content: &IssueContent) -> Result<Self, MergeFieldError> {
+        let mut environment = Environment::new();
+        // Missing custom attributes are expected: not every subscriber has
+        // them all.
+        environment.set_undefined_behavior(UndefinedBehavior::Chainable);
+        environment.set_keep_trailing_newline(true);
+        for (name, source) in [
+            ("content.html", &content.html),
+            ("content.txt", &content.text),
+        ] {
+            environment
+                .add_template_owned(name, source.clone())
+                .map_err(|e| MergeFieldError(format!("Invalid merge field: {}", e)))?;
+            let template = environment.get_template(name).expect("Just added.");
+            let mut unknown = template
+                .undeclared_variables(false)
+                .into_iter()
+                .filter(|field| !MERGE_FIELDS.contains(&field.as_str()))
+                .collect::<Vec<_>>();
+            unknown.sort();
+            if !unknown.is_empty() {
+                return Err(MergeFieldError(format!(
+                    "Unknown merge fields: {}. Use one of: {}.",
+                    unknown.join(", "),
+                    MERGE_FIELDS.join(", ")
+                )));
+            }
+        }
+
+        let content = Self { environment };
+        // Catch the errors only rendering can find, e.g. an unknown filter.
+        content.render(&Recipient::example())?;
+        Ok(content)
+    }
+
+    /// The HTML and text content for `recipient`. The HTML is sanitized: merge
+    /// fields may output markup, e.g. with the `safe` filter.
+    pub fn render(&self, recipient: &Recipient) -> Result<(String, String), MergeFieldError> {
+        let variables = context! {
+            name => recipient.name,
+            email => recipient.email,
+            subscribed_at => recipient.subscribed_at.format("%Y-%m-%d").to_string(),
+            attributes => recipient.attributes,
+        };
+        let render = |name: &str| {
+            self.environment
+                .get_template(name)
+                .and_then(|template| template.render(&variables))
+                .map_err(|e| MergeFieldError(format!("Failed to fill in the merge fields: {}", e)))
+        };
+        Ok((sanitize(&render("content.html")?), render("content.txt")?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn content(markdown: &str) -> Result<PersonalizedContent, MergeFieldError> {
+        PersonalizedContent::new(&IssueContent::from_markdown(markdown, "#000"))
+    }
+
+    fn recipient() -> Recipient {
+        Recipient {
+            name: "Le Guin".into(),
+            email: "ursula@example.com".into(),
+            subscribed_at: Utc.with_ymd_and_hms(2025, 10, 19, 12, 0, 0).unwrap(),
+            attributes: serde_json::json!({ "company": "Earthsea & co" }),
+        }
+    }
+
+    #[test]
+    fn merge_fields_are_filled_in() {
+        let content = content(
+            "Hi {{ name }} ({{ email }}), from {{ attributes.company }} since {{ subscribed_at }}.",
+        )
+        .unwrap();
+
+        let (html, text) = content.render(&recipient()).unwrap();
+
+        assert!(
+            html.contains(
+                "Hi Le Guin (ursula@example.com), from Earthsea &amp; co since 2025-10-19."
+            )
+        );
+        assert_eq!(
+            text,
+            "Hi Le Guin (ursula@example.com), from Earthsea & co since 2025-10-19.\n"
+        );
+    }
+
+    #[test]
+    fn missing_attributes_use_the_fallback() {
+        let content =
+            content(r#"Greetings from {{ attributes.city | default("your city") }}!"#).unwrap();
+
+        let (_, text) = content.render(&recipient()).unwrap();
+
+        assert_eq!(text, "Greetings from your city!\n");
+    }
+
+    #[test]
+    fn values_are_escaped_in_the_html() {
+        let content = content("Hi {{ name }}").unwrap();
+        let recipient = Recipient {
+            name: "<script>".into(),
+            ..recipient()
+        };
+
+        let (html, _) = content.render(&recipient).unwrap();
+
+        assert!(html.contains("Hi &lt;script&gt;"));
+    }
+
+    #[test]
+    fn markup_output_by_merge_fields_is_sanitized() {
+        let content = content(r#"Hi {{ "<script>alert(1)</script>" | safe }}{{ name }}"#).unwrap();
+
+        let (html, _) = content.render(&recipient()).unwrap();
+
+        assert!(!html.contains("<script"));
+        assert!(html.contains("Hi Le Guin"));
+    }
+
+    #[test]
+    fn unknown_merge_fields_are_rejected() {
+        let error = content("Hi {{ nmae }}").err().unwrap();
+        assert!(error.to_string().contains("nmae"));
+    }
+
+    #[test]
+    fn invalid_merge_fields_are_rejected() {
+        assert!(content("Hi {{ name | shout }}").is_err());
+        assert!(content("Hi {{ name ) }}").is_err());
+    }
+}
@@ -0,0 +1,212 @@
+use crate::lib::issues::{SENDING, SENT};
+use crate::lib::tracking::{BOUNCED, CLICK, DELIVERED, OPEN};
+use chrono::{DateTime, NaiveDate, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// How an issue did once it went out.
+///
+/// An unsubscribe is attributed to the last issue delivered to the subscriber
+/// before they left.
+#[derive(serde::Serialize)]
+pub struct IssueStats {
+    pub newsletter_issue_id: Uuid,
+    pub title: String,
+    pub status: String,
+    pub sent_at: Option<DateTime<Utc>>,
+    pub delivered: i64,
+    pub bounced: i64,
+    pub unique_opens: i64,
+    pub unique_clicks: i64,
+    pub unsubscribes: i64,
+}
+
+#[derive(serde::Serialize)]
+pub struct LinkStats {
+    pub url: String,
+    pub clicks: i64,
+    pub unique_clicks: i64,
+}
+
+/// The subscribers who joined, confirmed and left on one day, in UTC.
+#[derive(serde::Serialize)]
+pub struct GrowthDay {
+    pub day: NaiveDate,
+    pub new: i64,
+    pub confirmed: i64,
+    pub unsubscribed: i64,
+}
+
+/// The stats of every issue that went out, the latest first.
+#[tracing::instrument(name = "Get issue stats", skip(pool))]
+pub async fn issue_stats(
+    pool: &PgPool,
+    newsletter_issue_id: Option<Uuid>,
+) -> Result<Vec<IssueStats>, sqlx::Error> {
+    sqlx::query_as!(
+        IssueStats,
+        r#"
+        SELECT
+            i.newsletter_issue_id,
+            i.title,
+            i.status,
+            i.sent_at,
+            count(*) FILTER (WHERE e.kind = $1) AS "delivered!",
+            count(*) FILTER (WHERE e.kind = $2) AS "bounced!",
+            count(DISTINCT e.subscriber_id) FILTER (WHERE e.kind = $3) AS "unique_opens!",
+            count(DISTINCT e.subscriber_id) FILTER (WHERE e.kind = $4) AS "unique_clicks!",
+            (
+                SELECT count(*)
+                FROM subscriptions s
+                WHERE s.unsubscribed_at IS NOT NULL
+                    AND (
+                        SELECT d.newsletter_issue_id
+                        FROM issue_events d
+                        WHERE d.subscriber_id = s.id
+                            AND d.kind = $1
+                            AND d.occurred_at <= s.unsubscribed_at
+                        ORDER BY d.occurred_at DESC
+                        LIMIT 1
+                    ) = i.newsletter_issue_id
+            ) AS "unsubscribes!"
+        FROM newsletter_issues i
+        LEFT JOIN issue_events e ON e.newsletter_issue_id = i.newsletter_issue_id
+        WHERE i.status IN ($5, $6)
+            AND ($7::uuid IS NULL OR i.newsletter_issue_id = $7)
+        GROUP BY i.newsletter_issue_id
+        ORDER BY i.sent_at DESC NULLS FIRST, i.created_at DESC
+        "#,
+        DELIVERED,
+        BOUNCED,
+        OPEN,
+        CLICK,
+        SENDING,
+        SENT,
+        newsletter_issue_id
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })
+}
+
+/// The most clicked links of an issue.
+#[tracing::instrument(name = "Get top links", skip(pool))]
+pub async fn top_links(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+    limit: i64,
+) -> Result<Vec<LinkStats>, sqlx::Error> {
+    sqlx::query_as!(
+        LinkStats,
+        r#"
+        SELECT
+            url AS "url!",
+            count(*) AS "clicks!",
+            count(DISTINCT subscriber_id) AS "unique_clicks!"
+        FROM issue_events
+        WHERE newsletter_issue_id = $1 AND kind = $2 AND url IS NOT NULL
+        GROUP BY url
+        ORDER BY 3 DESC, 2 DESC, url
+        LIMIT $3
+        "#,
+        newsletter_issue_id,
+        CLICK,
+        limit
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })
+}
+
+/// One row per day from `from` to `to`, both included, days without any
+/// change too.
+#[tracing::instrument(name = "Get subscriber growth", skip(pool))]
+pub async fn growth(
+    pool: &PgPool,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Result<Vec<GrowthDay>, sqlx::Error> {
+    sqlx::query_as!(
+        GrowthDay,
+        r#"
+        WITH days AS (
+            SELECT generate_series($1::date, $2::date, interval '1 day')::date AS day
+        )
+        SELECT
+            days.day AS "day!",
+            (
+                SELECT count(*) FROM subscriptions
+                WHERE (subscribed_at AT TIME ZONE 'UTC')::date = days.day
+            ) AS "new!",
+            (
+                SELECT count(*) FROM subscriptions
+                WHERE (confirmed_at AT TIME ZONE 'UTC')::date = days.day
+            ) AS "confirmed!",
+            (
+                SELECT count(*) FROM subscriptions
+                WHERE (unsubscribed_at AT TIME ZONE 'UTC')::date = days.day
+            ) AS "unsubscribed!"
+        FROM days
+        ORDER BY days.day
+        "#,
+        from,
+        to
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })
+}
+
+/// `rows` as CSV, with a header row named after their fields.
+pub fn to_csv<T: serde::Serialize>(rows: &[T]) -> Result<String, csv::Error> {
+    let mut writer = csv::Writer::from_writer(vec![]);
+    for row in rows {
+        writer.serialize(row)?;
+    }
+    let bytes = writer.into_inner().map_err(|e| e.into_error())?;
+    Ok(String::from_utf8(bytes).expect("Serialized from strings."))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_has_a_header_and_quotes_when_needed() {
+        let rows = vec![
+            LinkStats {
+                url: "https://example.com/?a=1,2".into(),
+                clicks: 3,
+                unique_clicks: 2,
+            },
+            LinkStats {
+                url: "https://example.com/\"quoted\"".into(),
+                clicks: 1,
+                unique_clicks: 1,
+            },
+        ];
+
+        let csv = to_csv(&rows).unwrap();
+
+        assert_eq!(
+            csv,
+            "url,clicks,unique_clicks\n\
+             \"https://example.com/?a=1,2\",3,2\n\
+             \"https://example.com/\"\"quoted\"\"\",1,1\n"
+        );
+    }
+
+    #[test]
+    fn empty_reports_have_no_rows() {
+        assert_eq!(to_csv::<LinkStats>(&[]).unwrap(), "");
+    }
+}
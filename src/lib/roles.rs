@@ -0,0 +1,151 @@
+/// What a user is allowed to do.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Everything, including managing users, subscribers and webhooks.
+    Owner,
+    /// Drafts issues, for a publisher to schedule.
+    Editor,
+    /// Whatever an editor does, and schedules or sends issues.
+    Publisher,
+    /// Reads the analytics, and nothing else.
+    Viewer,
+}
+
+/// Something only some roles are allowed to do.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Permission {
+    ViewReports,
+    /// Draft, edit and test issues, and manage the segments they target.
+    EditIssues,
+    /// Schedule an issue, or send it now: approve it to go out.
+    SendIssues,
+    ManageSubscribers,
+    ManageWebhooks,
+    ManageUsers,
+    /// Set up the two-factor authentication of one's own account. Every
+    /// role can, even when two-factor authentication is mandatory and not
+    /// set up yet.
+    ManageOwnAccount,
+}
+
+impl Role {
+    pub fn can(self, permission: Permission) -> bool {
+        match self {
+            Role::Owner => true,
+            Role::Publisher => matches!(
+                permission,
+                Permission::ManageOwnAccount
+                    | Permission::ViewReports
+                    | Permission::EditIssues
+                    | Permission::SendIssues
+            ),
+            Role::Editor => matches!(
+                permission,
+                Permission::ManageOwnAccount | Permission::ViewReports | Permission::EditIssues
+            ),
+            Role::Viewer => matches!(
+                permission,
+                Permission::ManageOwnAccount | Permission::ViewReports
+            ),
+        }
+    }
+
+    /// As stored in the database.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::Owner => "owner",
+            Role::Editor => "editor",
+            Role::Publisher => "publisher",
+            Role::Viewer => "viewer",
+        }
+    }
+
+    pub fn parse(role: &str) -> Option<Self> {
+        match role {
+            "owner" => Some(Role::Owner),
+            "editor" => Some(Role::Editor),
+            "publisher" => Some(Role::Publisher),
+            "viewer" => Some(Role::Viewer),
+            _ => None,
+        }
+    }
+}
+
+/// The permission a route requires, as a type: `Authorized<EditIssues>`
+/// only extracts for users who can edit issues.
+pub trait RequiredPermission {
+    const PERMISSION: Permission;
+}
+
+pub struct ViewReports;
+pub struct EditIssues;
+pub struct SendIssues;
+pub struct ManageSubscribers;
+pub struct ManageWebhooks;
+pub struct ManageUsers;
+pub struct ManageOwnAccount;
+
+impl RequiredPermission for ViewReports {
+    const PERMISSION: Permission = Permission::ViewReports;
+}
+
+impl RequiredPermission for EditIssues {
+    const PERMISSION: Permission = Permission::EditIssues;
+}
+
+impl RequiredPermission for SendIssues {
+    const PERMISSION: Permission = Permission::SendIssues;
+}
+
+impl RequiredPermission for ManageSubscribers {
+    const PERMISSION: Permission = Permission::ManageSubscribers;
+}
+
+impl RequiredPermission for ManageWebhooks {
+    const PERMISSION: Permission = Permission::ManageWebhooks;
+}
+
+impl RequiredPermission for ManageUsers {
+    const PERMISSION: Permission = Permission::ManageUsers;
+}
+
+impl RequiredPermission for ManageOwnAccount {
+    const PERMISSION: Permission = Permission::ManageOwnAccount;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roles_are_stored_by_name() {
+        for role in [Role::Owner, Role::Editor, Role::Publisher, Role::Viewer] {
+            assert_eq!(Role::parse(role.as_str()), Some(role));
+        }
+        assert_eq!(Role::parse("admin"), None);
+    }
+
+    #[test]
+    fn only_publishers_and_owners_send() {
+        assert!(Role::Owner.can(Permission::SendIssues));
+        assert!(Role::Publisher.can(Permission::SendIssues));
+        assert!(!Role::Editor.can(Permission::SendIssues));
+        assert!(!Role::Viewer.can(Permission::SendIssues));
+    }
+
+    #[test]
+    fn viewers_only_read_the_reports() {
+        assert!(Role::Viewer.can(Permission::ViewReports));
+        assert!(Role::Viewer.can(Permission::ManageOwnAccount));
+        for permission in [
+            Permission::EditIssues,
+            Permission::SendIssues,
+            Permission::ManageSubscribers,
+            Permission::ManageWebhooks,
+            Permission::ManageUsers,
+        ] {
+            assert!(!Role::Viewer.can(permission), "{:?}", permission);
+        }
+    }
+}
@@ -1,7 +1,42 @@
-﻿mod health_check;
+﻿mod admin_subscribers;
+mod admin_users;
+mod archive;
+mod embed;
+mod health_check;
+mod home;
+mod invites;
+mod newsletter_issues;
+mod newsletters;
+mod password_reset;
+mod reports;
+mod segments;
+mod sessions;
+mod subscriber_csv;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_payload;
+mod tracking;
+mod two_factor;
+mod unsubscribe;
+mod webhooks;
 
+pub use admin_subscribers::*;
+pub use admin_users::*;
+pub use archive::*;
+pub use embed::*;
 pub use health_check::*;
+pub use home::*;
+pub use invites::*;
+pub use newsletter_issues::*;
+pub use newsletters::*;
+pub use password_reset::*;
+pub use reports::*;
+pub use segments::*;
+pub use sessions::*;
+pub use subscriber_csv::*;
 pub use subscriptions::*;
 pub use subscriptions_confirm::*;
+pub use tracking::*;
+pub use two_factor::*;
+pub use unsubscribe::*;
+pub use webhooks::*;
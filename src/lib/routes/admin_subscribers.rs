@@ -0,0 +1,476 @@
+use crate::lib::audit::record_action;
+use crate::lib::authentication::Authorized;
+use crate::lib::configurations::SubscriptionSettings;
+use crate::lib::domain::{SubscriberAttributes, SubscriberEmail, SubscriberName};
+use crate::lib::roles::ManageSubscribers;
+use crate::lib::routes::{CONFIRMED, PENDING_CONFIRMATION, UNSUBSCRIBED, mark_as_unsubscribed};
+use crate::lib::subscribers::{
+    Cursor, SubscriberFilters, SubscriberRecord, get_subscriber_record, list_subscribers,
+};
+use crate::lib::webhooks::{SUBSCRIBER_CONFIRMED, publish_subscriber_event};
+use crate::lib::welcome_series::{STOPPED_SUPPRESSED, enroll};
+use actix_web::{HttpResponse, web};
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 500;
+
+#[derive(serde::Deserialize)]
+pub struct PageParameters {
+    /// The `next_cursor` of the previous page.
+    cursor: Option<String>,
+    limit: Option<i64>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct RenameBody {
+    name: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct SuppressionBody {
+    email: String,
+    reason: String,
+}
+
+#[derive(serde::Serialize)]
+struct SubscriberPage {
+    subscribers: Vec<SubscriberRecord>,
+    /// `None` on the last page.
+    next_cursor: Option<String>,
+}
+
+/// Search subscribers, one page at a time.
+#[tracing::instrument(name = "List subscribers for an admin", skip(_user, page, pool))]
+pub async fn admin_list_subscribers(
+    _user: Authorized<ManageSubscribers>,
+    filters: web::Query<SubscriberFilters>,
+    page: web::Query<PageParameters>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let limit = page.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return HttpResponse::BadRequest()
+            .body(format!("`limit` must be between 1 and {}.", MAX_PAGE_SIZE));
+    }
+    let after = match page.cursor.as_deref().map(Cursor::decode) {
+        Some(Some(cursor)) => Some(cursor),
+        Some(None) => return HttpResponse::BadRequest().body("Invalid cursor."),
+        None => None,
+    };
+
+    // One more than asked for, to know whether there is a next page.
+    let mut subscribers = match list_subscribers(&pool, &filters, after.as_ref(), limit + 1).await {
+        Ok(subscribers) => subscribers,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let next_cursor = if subscribers.len() as i64 > limit {
+        subscribers.truncate(limit as usize);
+        subscribers.last().map(|last| {
+            Cursor {
+                subscribed_at: last.subscribed_at,
+                id: last.id,
+            }
+            .encode()
+        })
+    } else {
+        None
+    };
+    HttpResponse::Ok().json(SubscriberPage {
+        subscribers,
+        next_cursor,
+    })
+}
+
+#[tracing::instrument(name = "Show a subscriber to an admin", skip(user, pool))]
+pub async fn admin_get_subscriber(
+    user: Authorized<ManageSubscribers>,
+    path: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let user_id = user.user_id;
+
+    let subscriber_id = path.into_inner();
+    let outcome = async {
+        let mut transaction = pool.begin().await?;
+        let Some(subscriber) = get_subscriber_record(&mut *transaction, subscriber_id).await?
+        else {
+            return Ok(None);
+        };
+        record_action(
+            &mut *transaction,
+            Some(user_id),
+            "subscriber.viewed",
+            Some(subscriber_id),
+            serde_json::json!({}),
+        )
+        .await?;
+        transaction.commit().await?;
+        Ok::<_, sqlx::Error>(Some(subscriber))
+    }
+    .await;
+    match outcome {
+        Ok(Some(subscriber)) => HttpResponse::Ok().json(subscriber),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[tracing::instrument(name = "Rename a subscriber", skip(user, body, pool))]
+pub async fn admin_rename_subscriber(
+    user: Authorized<ManageSubscribers>,
+    path: web::Path<Uuid>,
+    body: web::Json<RenameBody>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let user_id = user.user_id;
+    let name = match SubscriberName::parse(&body.name) {
+        Ok(name) => name,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+
+    let subscriber_id = path.into_inner();
+    let outcome = async {
+        let mut transaction = pool.begin().await?;
+        let Some(previous) = sqlx::query_scalar!(
+            r#"SELECT name FROM subscriptions WHERE id = $1 FOR UPDATE"#,
+            subscriber_id
+        )
+        .fetch_optional(&mut *transaction)
+        .await?
+        else {
+            return Ok(None);
+        };
+        sqlx::query!(
+            r#"UPDATE subscriptions SET name = $1 WHERE id = $2"#,
+            name.as_ref(),
+            subscriber_id
+        )
+        .execute(&mut *transaction)
+        .await?;
+        record_action(
+            &mut *transaction,
+            Some(user_id),
+            "subscriber.renamed",
+            Some(subscriber_id),
+            serde_json::json!({ "from": previous, "to": name.as_ref() }),
+        )
+        .await?;
+        let subscriber = get_subscriber_record(&mut *transaction, subscriber_id).await?;
+        transaction.commit().await?;
+        Ok::<_, sqlx::Error>(subscriber)
+    }
+    .await;
+    match outcome {
+        Ok(Some(subscriber)) => HttpResponse::Ok().json(subscriber),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// Set the attributes in the body, and remove the ones set to `null`: the
+/// others stay as they are.
+#[tracing::instrument(name = "Update the attributes of a subscriber", skip(user, body, pool))]
+pub async fn admin_update_subscriber_attributes(
+    user: Authorized<ManageSubscribers>,
+    path: web::Path<Uuid>,
+    body: web::Json<serde_json::Value>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let user_id = user.user_id;
+    let serde_json::Value::Object(patch) = body.into_inner() else {
+        return HttpResponse::BadRequest().body("Attributes must be a JSON object.");
+    };
+
+    let subscriber_id = path.into_inner();
+    let outcome = async {
+        let mut transaction = pool.begin().await?;
+        let Some(previous) = sqlx::query_scalar!(
+            r#"SELECT attributes FROM subscriptions WHERE id = $1 FOR UPDATE"#,
+            subscriber_id
+        )
+        .fetch_optional(&mut *transaction)
+        .await?
+        else {
+            return Ok(None);
+        };
+        let serde_json::Value::Object(mut attributes) = previous else {
+            return Err(sqlx::Error::Decode("Attributes are not an object.".into()));
+        };
+        for (key, value) in &patch {
+            if value.is_null() {
+                attributes.remove(key);
+            } else {
+                attributes.insert(key.clone(), value.clone());
+            }
+        }
+        let attributes = match SubscriberAttributes::parse(attributes.into()) {
+            Ok(attributes) => attributes,
+            Err(e) => return Ok(Some(Err(e))),
+        };
+        sqlx::query!(
+            r#"UPDATE subscriptions SET attributes = $1 WHERE id = $2"#,
+            attributes.into_json(),
+            subscriber_id
+        )
+        .execute(&mut *transaction)
+        .await?;
+        record_action(
+            &mut *transaction,
+            Some(user_id),
+            "subscriber.attributes_updated",
+            Some(subscriber_id),
+            serde_json::Value::Object(patch.clone()),
+        )
+        .await?;
+        let subscriber = get_subscriber_record(&mut *transaction, subscriber_id).await?;
+        transaction.commit().await?;
+        Ok::<_, sqlx::Error>(subscriber.map(Ok))
+    }
+    .await;
+    match outcome {
+        Ok(Some(Ok(subscriber))) => HttpResponse::Ok().json(subscriber),
+        Ok(Some(Err(e))) => HttpResponse::BadRequest().body(e),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// Confirm a subscriber who could not click their link, e.g. because it
+/// never arrived. Somebody who unsubscribed is not brought back.
+#[tracing::instrument(name = "Force the confirmation of a subscriber", skip(user, pool))]
+pub async fn admin_confirm_subscriber(
+    user: Authorized<ManageSubscribers>,
+    path: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let user_id = user.user_id;
+
+    let subscriber_id = path.into_inner();
+    let outcome = async {
+        let mut transaction = pool.begin().await?;
+        let status = sqlx::query_scalar!(
+            r#"SELECT status FROM subscriptions WHERE id = $1 FOR UPDATE"#,
+            subscriber_id
+        )
+        .fetch_optional(&mut *transaction)
+        .await?;
+        if status.as_deref() != Some(PENDING_CONFIRMATION) {
+            return Ok(status);
+        }
+        sqlx::query!(
+            r#"UPDATE subscriptions SET status = $1, confirmed_at = now() WHERE id = $2"#,
+            CONFIRMED,
+            subscriber_id
+        )
+        .execute(&mut *transaction)
+        .await?;
+        enroll(&mut *transaction, subscriber_id).await?;
+        publish_subscriber_event(
+            &mut transaction,
+            SUBSCRIBER_CONFIRMED,
+            subscriber_id,
+            serde_json::json!({}),
+        )
+        .await?;
+        // Their pending links have no use anymore.
+        sqlx::query!(
+            r#"DELETE FROM subscription_tokens WHERE subscriber_id = $1"#,
+            subscriber_id
+        )
+        .execute(&mut *transaction)
+        .await?;
+        record_action(
+            &mut *transaction,
+            Some(user_id),
+            "subscriber.confirmed",
+            Some(subscriber_id),
+            serde_json::json!({}),
+        )
+        .await?;
+        transaction.commit().await?;
+        Ok::<_, sqlx::Error>(Some(CONFIRMED.to_owned()))
+    }
+    .await;
+    match outcome {
+        Ok(Some(status)) if status == CONFIRMED => subscriber_response(&pool, subscriber_id).await,
+        Ok(Some(status)) => HttpResponse::Conflict().body(format!("The subscriber is {}.", status)),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[tracing::instrument(name = "Unsubscribe a subscriber", skip(user, pool))]
+pub async fn admin_unsubscribe_subscriber(
+    user: Authorized<ManageSubscribers>,
+    path: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let user_id = user.user_id;
+
+    let subscriber_id = path.into_inner();
+    let outcome = async {
+        let mut transaction = pool.begin().await?;
+        let Some(status) = sqlx::query_scalar!(
+            r#"SELECT status FROM subscriptions WHERE id = $1 FOR UPDATE"#,
+            subscriber_id
+        )
+        .fetch_optional(&mut *transaction)
+        .await?
+        else {
+            return Ok(false);
+        };
+        if status != UNSUBSCRIBED {
+            mark_as_unsubscribed(&mut transaction, subscriber_id).await?;
+            record_action(
+                &mut *transaction,
+                Some(user_id),
+                "subscriber.unsubscribed",
+                Some(subscriber_id),
+                serde_json::json!({ "from": status }),
+            )
+            .await?;
+        }
+        transaction.commit().await?;
+        Ok::<_, sqlx::Error>(true)
+    }
+    .await;
+    match outcome {
+        Ok(true) => subscriber_response(&pool, subscriber_id).await,
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// Forget a subscriber altogether. The audit log keeps who they were, and
+/// their address is suppressed.
+#[tracing::instrument(name = "Delete a subscriber", skip(user, pool))]
+pub async fn admin_delete_subscriber(
+    user: Authorized<ManageSubscribers>,
+    path: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let user_id = user.user_id;
+
+    let subscriber_id = path.into_inner();
+    let outcome = async {
+        let mut transaction = pool.begin().await?;
+        sqlx::query!(
+            r#"DELETE FROM subscription_tokens WHERE subscriber_id = $1"#,
+            subscriber_id
+        )
+        .execute(&mut *transaction)
+        .await?;
+        let Some(deleted) = sqlx::query!(
+            r#"
+            DELETE FROM subscriptions WHERE id = $1
+            RETURNING email, email_canonical, name, status
+            "#,
+            subscriber_id
+        )
+        .fetch_optional(&mut *transaction)
+        .await?
+        else {
+            return Ok(false);
+        };
+        // So that an import does not bring them back.
+        suppress(&mut *transaction, &deleted.email_canonical, "deleted").await?;
+        record_action(
+            &mut *transaction,
+            Some(user_id),
+            "subscriber.deleted",
+            Some(subscriber_id),
+            serde_json::json!({
+                "email": deleted.email,
+                "name": deleted.name,
+                "status": deleted.status,
+            }),
+        )
+        .await?;
+        transaction.commit().await?;
+        Ok::<_, sqlx::Error>(true)
+    }
+    .await;
+    match outcome {
+        Ok(true) => HttpResponse::NoContent().finish(),
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// Make sure an address is never imported, e.g. one that bounced on the
+/// platform the subscribers come from.
+#[tracing::instrument(name = "Suppress an email address", skip(user, body, pool, settings))]
+pub async fn admin_suppress_email(
+    user: Authorized<ManageSubscribers>,
+    body: web::Json<SuppressionBody>,
+    pool: web::Data<PgPool>,
+    settings: web::Data<SubscriptionSettings>,
+) -> HttpResponse {
+    let user_id = user.user_id;
+    let email = match SubscriberEmail::parse(&body.email) {
+        Ok(email) => email,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+
+    let canonical_email = email.canonical(settings.provider_aware_canonicalization);
+    let outcome = async {
+        let mut transaction = pool.begin().await?;
+        suppress(&mut *transaction, &canonical_email, &body.reason).await?;
+        record_action(
+            &mut *transaction,
+            Some(user_id),
+            "email.suppressed",
+            None,
+            serde_json::json!({ "email": email.as_ref(), "reason": body.reason }),
+        )
+        .await?;
+        transaction.commit().await
+    }
+    .await;
+    match outcome {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+async fn suppress<'e>(
+    executor: impl PgExecutor<'e>,
+    canonical_email: &str,
+    reason: &str,
+) -> Result<(), sqlx::Error> {
+    // The welcome series of the subscriber stops along, in the same statement.
+    sqlx::query!(
+        r#"
+        WITH stopped AS (
+            UPDATE welcome_series_enrollments e SET stopped_at = now(), stopped_reason = $3
+            FROM subscriptions s
+            WHERE s.id = e.subscriber_id
+                AND s.email_canonical = $1
+                AND e.completed_at IS NULL AND e.stopped_at IS NULL
+        )
+        INSERT INTO suppressions (email_canonical, reason, created_at)
+        VALUES ($1, $2, now())
+        ON CONFLICT (email_canonical) DO NOTHING
+        "#,
+        canonical_email,
+        reason,
+        STOPPED_SUPPRESSED
+    )
+    .execute(executor)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(())
+}
+
+async fn subscriber_response(pool: &PgPool, subscriber_id: Uuid) -> HttpResponse {
+    match get_subscriber_record(pool, subscriber_id).await {
+        Ok(Some(subscriber)) => HttpResponse::Ok().json(subscriber),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
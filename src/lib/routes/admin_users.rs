@@ -0,0 +1,365 @@
+use crate::lib::audit::record_action;
+use crate::lib::authentication::Authorized;
+use crate::lib::configurations::{AdminUserSettings, BrandingSettings};
+use crate::lib::domain::SubscriberEmail;
+use crate::lib::email_client::EmailClient;
+use crate::lib::email_templates::EmailTemplates;
+use crate::lib::invites::{
+    Invite, generate_invite_token, hash_invite_token, invite_link, list_pending_invites,
+    send_invite_email,
+};
+use crate::lib::localization::Translations;
+use crate::lib::roles::{ManageUsers, Role};
+use crate::lib::startup::ApplicationBaseUrl;
+use actix_web::{HttpResponse, web};
+use chrono::{Duration, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct InviteBody {
+    email: String,
+    role: Role,
+}
+
+#[derive(serde::Deserialize)]
+pub struct RoleBody {
+    role: Role,
+}
+
+#[derive(serde::Serialize)]
+struct AdminUser {
+    user_id: Uuid,
+    username: String,
+    email: Option<String>,
+    role: Role,
+}
+
+/// What changing or removing a user came to.
+enum UserChange {
+    Done,
+    NotFound,
+    /// It would leave nobody able to manage the users.
+    LastOwner,
+}
+
+/// Email an expiring signup link to somebody, who joins with `role`.
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(
+    name = "Invite a user",
+    skip(
+        user,
+        body,
+        pool,
+        email_client,
+        email_templates,
+        base_url,
+        branding,
+        translations,
+        settings
+    ),
+    fields(role = body.role.as_str())
+)]
+pub async fn invite_user(
+    user: Authorized<ManageUsers>,
+    body: web::Json<InviteBody>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    email_templates: web::Data<EmailTemplates>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    branding: web::Data<BrandingSettings>,
+    translations: web::Data<Translations>,
+    settings: web::Data<AdminUserSettings>,
+) -> HttpResponse {
+    let user_id = user.user_id;
+    let email = match SubscriberEmail::parse(&body.email) {
+        Ok(email) => email,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+
+    let now = Utc::now();
+    let token = generate_invite_token();
+    let invite = Invite {
+        invite_id: Uuid::new_v4(),
+        email: email.as_ref().to_owned(),
+        role: body.role,
+        invited_by: Some(user_id),
+        created_at: now,
+        expires_at: now + Duration::hours(settings.invite_ttl_hours),
+    };
+    let mut transaction = match pool.begin().await {
+        Ok(transaction) => transaction,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let stored = async {
+        let is_user = sqlx::query_scalar!(
+            r#"SELECT EXISTS (SELECT 1 FROM users WHERE lower(email) = lower($1)) AS "exists!""#,
+            invite.email
+        )
+        .fetch_one(&mut *transaction)
+        .await?;
+        if is_user {
+            return Ok(false);
+        }
+        sqlx::query!(
+            r#"
+            INSERT INTO user_invites
+                (invite_id, email, role, token_hash, invited_by, created_at, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            "#,
+            invite.invite_id,
+            invite.email,
+            invite.role.as_str(),
+            hash_invite_token(&token),
+            invite.invited_by,
+            invite.created_at,
+            invite.expires_at
+        )
+        .execute(&mut *transaction)
+        .await?;
+        record_action(
+            &mut *transaction,
+            Some(user_id),
+            "user.invited",
+            None,
+            serde_json::json!({
+                "invite_id": invite.invite_id,
+                "email": invite.email,
+                "role": invite.role,
+            }),
+        )
+        .await?;
+        Ok::<_, sqlx::Error>(true)
+    }
+    .await;
+    match stored {
+        Ok(true) => {}
+        Ok(false) => {
+            return HttpResponse::Conflict().body("Somebody with this email is already a user.");
+        }
+        Err(e) => {
+            tracing::error!("Failed to execute query: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    }
+
+    // Only keep the invite if its link made it out.
+    if send_invite_email(
+        &email_client,
+        &email_templates,
+        &email,
+        invite.role,
+        &invite_link(&base_url.0, &token),
+        settings.invite_ttl_hours,
+        &branding.site_name,
+        translations.translator(translations.default_locale()),
+    )
+    .await
+    .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+    if transaction.commit().await.is_err() {
+        return HttpResponse::InternalServerError().finish();
+    }
+    HttpResponse::Created().json(invite)
+}
+
+#[tracing::instrument(name = "List pending invites for an admin", skip(_user, pool))]
+pub async fn list_invites(_user: Authorized<ManageUsers>, pool: web::Data<PgPool>) -> HttpResponse {
+    match list_pending_invites(&pool).await {
+        Ok(invites) => HttpResponse::Ok().json(invites),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// Its signup link stops working.
+#[tracing::instrument(name = "Revoke an invite", skip(user, pool))]
+pub async fn revoke_invite(
+    user: Authorized<ManageUsers>,
+    path: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let invite_id = path.into_inner();
+    let outcome = async {
+        let mut transaction = pool.begin().await?;
+        let Some(email) = sqlx::query_scalar!(
+            r#"
+            DELETE FROM user_invites WHERE invite_id = $1 AND accepted_at IS NULL
+            RETURNING email
+            "#,
+            invite_id
+        )
+        .fetch_optional(&mut *transaction)
+        .await?
+        else {
+            return Ok(false);
+        };
+        record_action(
+            &mut *transaction,
+            Some(user.user_id),
+            "user.invite_revoked",
+            None,
+            serde_json::json!({ "invite_id": invite_id, "email": email }),
+        )
+        .await?;
+        transaction.commit().await?;
+        Ok::<_, sqlx::Error>(true)
+    }
+    .await;
+    match outcome {
+        Ok(true) => HttpResponse::NoContent().finish(),
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            tracing::error!("Failed to execute query: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[tracing::instrument(name = "List users for an admin", skip(_user, pool))]
+pub async fn list_users(_user: Authorized<ManageUsers>, pool: web::Data<PgPool>) -> HttpResponse {
+    let rows =
+        sqlx::query!(r#"SELECT user_id, username, email, role FROM users ORDER BY username"#)
+            .fetch_all(pool.get_ref())
+            .await;
+    match rows {
+        Ok(rows) => HttpResponse::Ok().json(
+            rows.into_iter()
+                .filter_map(|row| {
+                    Some(AdminUser {
+                        user_id: row.user_id,
+                        username: row.username,
+                        email: row.email,
+                        role: Role::parse(&row.role)?,
+                    })
+                })
+                .collect::<Vec<_>>(),
+        ),
+        Err(e) => {
+            tracing::error!("Failed to execute query: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// There is always an owner left: the last one cannot be demoted.
+#[tracing::instrument(name = "Change the role of a user", skip(user, body, pool), fields(role = body.role.as_str()))]
+pub async fn change_role(
+    user: Authorized<ManageUsers>,
+    path: web::Path<Uuid>,
+    body: web::Json<RoleBody>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let target_id = path.into_inner();
+    let role = body.role;
+    let outcome = async {
+        let mut transaction = pool.begin().await?;
+        let Some(from) = lock_user(&mut transaction, target_id).await? else {
+            return Ok(UserChange::NotFound);
+        };
+        if from == Role::Owner && role != Role::Owner && is_last_owner(&mut transaction).await? {
+            return Ok(UserChange::LastOwner);
+        }
+        sqlx::query!(
+            r#"UPDATE users SET role = $1 WHERE user_id = $2"#,
+            role.as_str(),
+            target_id
+        )
+        .execute(&mut *transaction)
+        .await?;
+        record_action(
+            &mut *transaction,
+            Some(user.user_id),
+            "user.role_changed",
+            None,
+            serde_json::json!({ "user_id": target_id, "from": from, "to": role }),
+        )
+        .await?;
+        transaction.commit().await?;
+        Ok::<_, sqlx::Error>(UserChange::Done)
+    }
+    .await;
+    user_change_response(outcome)
+}
+
+/// Their issues, imports and audit log entries stay, without an author.
+#[tracing::instrument(name = "Remove a user", skip(user, pool))]
+pub async fn delete_user(
+    user: Authorized<ManageUsers>,
+    path: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let target_id = path.into_inner();
+    let outcome = async {
+        let mut transaction = pool.begin().await?;
+        let Some(role) = lock_user(&mut transaction, target_id).await? else {
+            return Ok(UserChange::NotFound);
+        };
+        if role == Role::Owner && is_last_owner(&mut transaction).await? {
+            return Ok(UserChange::LastOwner);
+        }
+        let username = sqlx::query_scalar!(
+            r#"DELETE FROM users WHERE user_id = $1 RETURNING username"#,
+            target_id
+        )
+        .fetch_one(&mut *transaction)
+        .await?;
+        record_action(
+            &mut *transaction,
+            Some(user.user_id),
+            "user.removed",
+            None,
+            serde_json::json!({ "user_id": target_id, "username": username, "role": role }),
+        )
+        .await?;
+        transaction.commit().await?;
+        Ok::<_, sqlx::Error>(UserChange::Done)
+    }
+    .await;
+    user_change_response(outcome)
+}
+
+/// The current role of a user. The owners are locked along with them, so
+/// that two owners cannot demote each other at the same time.
+async fn lock_user(
+    transaction: &mut Transaction<'_, Postgres>,
+    user_id: Uuid,
+) -> Result<Option<Role>, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT user_id, role FROM users WHERE user_id = $1 OR role = 'owner'
+        ORDER BY user_id
+        FOR UPDATE
+        "#,
+        user_id
+    )
+    .fetch_all(&mut **transaction)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .find(|row| row.user_id == user_id)
+        .and_then(|row| Role::parse(&row.role)))
+}
+
+async fn is_last_owner(transaction: &mut Transaction<'_, Postgres>) -> Result<bool, sqlx::Error> {
+    let owners =
+        sqlx::query_scalar!(r#"SELECT count(*) AS "count!" FROM users WHERE role = 'owner'"#)
+            .fetch_one(&mut **transaction)
+            .await?;
+    Ok(owners <= 1)
+}
+
+fn user_change_response(outcome: Result<UserChange, sqlx::Error>) -> HttpResponse {
+    match outcome {
+        Ok(UserChange::Done) => HttpResponse::NoContent().finish(),
+        Ok(UserChange::NotFound) => HttpResponse::NotFound().finish(),
+        Ok(UserChange::LastOwner) => {
+            HttpResponse::Conflict().body("There must be at least one owner left.")
+        }
+        Err(e) => {
+            tracing::error!("Failed to execute query: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
@@ -0,0 +1,255 @@
+use crate::lib::archive::{archive_updated_at, get_archived, list_archived};
+use crate::lib::configurations::BrandingSettings;
+use crate::lib::localization::{Translations, accept_language};
+use crate::lib::pages::{ArchivePage, ArchivedIssuePage, AtomFeed, FeedEntry, RssFeed};
+use crate::lib::personalization::MergeFieldError;
+use crate::lib::startup::ApplicationBaseUrl;
+use actix_web::http::header::{
+    self, CacheControl, CacheDirective, ContentType, ETag, EntityTag, Header, HttpDate,
+    IfModifiedSince, IfNoneMatch, LastModified,
+};
+use actix_web::{HttpRequest, HttpResponse, web};
+use askama::Template;
+use chrono::{DateTime, SubsecRound, Utc};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use std::time::SystemTime;
+
+/// How many issues the archive page lists.
+const ARCHIVE_LENGTH: i64 = 100;
+/// How many of the latest issues the feeds carry.
+const FEED_LENGTH: i64 = 20;
+/// What pages showing an issue may load: the issue is written by a user, so
+/// no scripts, nor anything else but images and the inline styles it is
+/// rendered with, should it get past the sanitizer.
+pub(crate) const ISSUE_CONTENT_SECURITY_POLICY: &str = "default-src 'none'; img-src * data:; \
+    style-src 'unsafe-inline'; form-action 'self'; base-uri 'none'; frame-ancestors 'self'";
+
+#[derive(serde::Deserialize)]
+pub struct ArchiveParameters {
+    /// A search, e.g. `rust -async` or `"error handling"`.
+    #[serde(default)]
+    q: String,
+    /// Overrides the `Accept-Language` header.
+    lang: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct LocaleParameters {
+    /// Overrides the `Accept-Language` header.
+    pub lang: Option<String>,
+}
+
+/// The list of archived issues, or the ones matching the search.
+#[tracing::instrument(
+    name = "Show the archive",
+    skip(req, parameters, pool, branding, translations)
+)]
+pub async fn archive_page(
+    req: HttpRequest,
+    parameters: web::Query<ArchiveParameters>,
+    pool: web::Data<PgPool>,
+    branding: web::Data<BrandingSettings>,
+    translations: web::Data<Translations>,
+) -> HttpResponse {
+    let search = parameters.q.trim();
+    let (issues, updated_at) = match (
+        list_archived(
+            &pool,
+            Some(search).filter(|q| !q.is_empty()),
+            ARCHIVE_LENGTH,
+        )
+        .await,
+        archive_updated_at(&pool).await,
+    ) {
+        (Ok(issues), Ok(updated_at)) => (issues, updated_at),
+        _ => return HttpResponse::InternalServerError().finish(),
+    };
+    let locale = translations.negotiate(parameters.lang.as_deref(), accept_language(&req));
+    let page = ArchivePage {
+        branding: &branding,
+        t: translations.translator(&locale),
+        issues,
+        search,
+    };
+    cached(&req, page.render(), ContentType::html(), updated_at)
+}
+
+/// An archived issue, as anybody can read it.
+#[tracing::instrument(
+    name = "Show an archived issue",
+    skip(req, parameters, pool, branding, translations)
+)]
+pub async fn archived_issue(
+    req: HttpRequest,
+    path: web::Path<String>,
+    parameters: web::Query<LocaleParameters>,
+    pool: web::Data<PgPool>,
+    branding: web::Data<BrandingSettings>,
+    translations: web::Data<Translations>,
+) -> HttpResponse {
+    let issue = match get_archived(&pool, &path).await {
+        Ok(Some(issue)) => issue,
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let content_html = match issue.html(&branding.accent_color) {
+        Ok(html) => html,
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to render an archived issue.");
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let locale = translations.negotiate(parameters.lang.as_deref(), accept_language(&req));
+    let page = ArchivedIssuePage {
+        branding: &branding,
+        t: translations.translator(&locale),
+        title: &issue.title,
+        sent_at: issue.sent_at,
+        content_html,
+    };
+    cached(
+        &req,
+        page.render(),
+        ContentType::html(),
+        Some(issue.sent_at),
+    )
+}
+
+/// The latest archived issues, as RSS 2.0.
+#[tracing::instrument(name = "Serve the RSS feed", skip_all)]
+pub async fn rss_feed(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    branding: web::Data<BrandingSettings>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> HttpResponse {
+    let archive_url = format!("{}/archive", base_url.0);
+    let (entries, updated_at) = match feed_entries(&pool, &archive_url, &branding).await {
+        Ok(feed) => feed,
+        Err(response) => return response,
+    };
+    let feed = RssFeed {
+        branding: &branding,
+        archive_url: &archive_url,
+        updated_at: updated_at.unwrap_or_else(Utc::now),
+        entries,
+    };
+    let content_type = ContentType("application/rss+xml; charset=utf-8".parse().unwrap());
+    cached(&req, feed.render(), content_type, updated_at)
+}
+
+/// The latest archived issues, as an Atom feed.
+#[tracing::instrument(name = "Serve the Atom feed", skip_all)]
+pub async fn atom_feed(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    branding: web::Data<BrandingSettings>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> HttpResponse {
+    let archive_url = format!("{}/archive", base_url.0);
+    let (entries, updated_at) = match feed_entries(&pool, &archive_url, &branding).await {
+        Ok(feed) => feed,
+        Err(response) => return response,
+    };
+    let feed = AtomFeed {
+        branding: &branding,
+        archive_url: &archive_url,
+        updated_at: updated_at.unwrap_or_else(Utc::now),
+        entries,
+    };
+    let content_type = ContentType("application/atom+xml; charset=utf-8".parse().unwrap());
+    cached(&req, feed.render(), content_type, updated_at)
+}
+
+/// The entries of a feed, with when the archive last changed.
+async fn feed_entries(
+    pool: &PgPool,
+    archive_url: &str,
+    branding: &BrandingSettings,
+) -> Result<(Vec<FeedEntry>, Option<DateTime<Utc>>), HttpResponse> {
+    let (issues, updated_at) = match (
+        list_archived(pool, None, FEED_LENGTH).await,
+        archive_updated_at(pool).await,
+    ) {
+        (Ok(issues), Ok(updated_at)) => (issues, updated_at),
+        _ => return Err(HttpResponse::InternalServerError().finish()),
+    };
+    let entries = issues
+        .into_iter()
+        .map(|issue| {
+            Ok(FeedEntry {
+                content_html: issue.html(&branding.accent_color)?,
+                link: format!("{}/{}", archive_url, issue.slug),
+                title: issue.title,
+                published: issue.sent_at,
+            })
+        })
+        .collect::<Result<Vec<_>, MergeFieldError>>()
+        .map_err(|e| {
+            tracing::error!(error = %e, "Failed to render an archived issue.");
+            HttpResponse::InternalServerError().finish()
+        })?;
+    Ok((entries, updated_at))
+}
+
+/// Respond with `body`, or with `304 Not Modified` if the client already has
+/// it: by its `ETag`, a hash of the body, or else by `Last-Modified`.
+fn cached(
+    req: &HttpRequest,
+    body: Result<String, askama::Error>,
+    content_type: ContentType,
+    last_modified: Option<DateTime<Utc>>,
+) -> HttpResponse {
+    let body = match body {
+        Ok(body) => body,
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to render a page.");
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let etag = EntityTag::new_strong(hex::encode(Sha256::digest(body.as_bytes())));
+    // HTTP dates have no fractions of a second: drop them to compare.
+    let last_modified =
+        last_modified.map(|at| HttpDate::from(SystemTime::from(at.trunc_subsecs(0))));
+
+    // `If-None-Match` wins over `If-Modified-Since` when both are sent.
+    let not_modified = if req.headers().contains_key(header::IF_NONE_MATCH) {
+        match IfNoneMatch::parse(req) {
+            Ok(IfNoneMatch::Any) => true,
+            Ok(IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(&etag)),
+            Err(_) => false,
+        }
+    } else {
+        match (IfModifiedSince::parse(req), last_modified) {
+            (Ok(IfModifiedSince(since)), Some(last_modified)) => last_modified <= since,
+            _ => false,
+        }
+    };
+
+    let mut response = if not_modified {
+        HttpResponse::NotModified()
+    } else {
+        HttpResponse::Ok()
+    };
+    response
+        .insert_header(ETag(etag))
+        // Cheap to check again with the validators: always do.
+        .insert_header(CacheControl(vec![
+            CacheDirective::Public,
+            CacheDirective::NoCache,
+        ]))
+        .insert_header((header::VARY, "Accept-Language"))
+        .insert_header((
+            header::CONTENT_SECURITY_POLICY,
+            ISSUE_CONTENT_SECURITY_POLICY,
+        ));
+    if let Some(last_modified) = last_modified {
+        response.insert_header(LastModified(last_modified));
+    }
+    if not_modified {
+        response.finish()
+    } else {
+        response.insert_header(content_type).body(body)
+    }
+}
@@ -0,0 +1,56 @@
+use crate::lib::configurations::BrandingSettings;
+use crate::lib::localization::{Translations, accept_language};
+use crate::lib::pages::SignupWidget;
+use crate::lib::routes::LocaleParameters;
+use crate::lib::startup::ApplicationBaseUrl;
+use actix_web::http::header::{self, CacheControl, CacheDirective};
+use actix_web::{HttpRequest, HttpResponse, web};
+use askama::Template;
+
+/// How long browsers and proxies may keep the script.
+const WIDGET_MAX_AGE_SECONDS: u32 = 3600;
+
+/// The script embedding the signup form, in the language of the visitor.
+#[tracing::instrument(name = "Serve the signup widget", skip_all)]
+pub async fn signup_widget(
+    req: HttpRequest,
+    parameters: web::Query<LocaleParameters>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    branding: web::Data<BrandingSettings>,
+    translations: web::Data<Translations>,
+) -> HttpResponse {
+    let locale = translations.negotiate(parameters.lang.as_deref(), accept_language(&req));
+    let t = translations.translator(&locale);
+    let config = serde_json::json!({
+        "base_url": base_url.0,
+        "locale": locale,
+        "strings": {
+            "name": t.get("signup-name"),
+            "email": t.get("signup-email"),
+            "trap": t.get("signup-trap"),
+            "submit": t.get("signup-submit"),
+            "check_inbox": t.with("embed-check-inbox", "site", &branding.site_name),
+            "confirmed": t.with("confirmed-body", "site", &branding.site_name),
+            "error": t.get("embed-error"),
+            // Filled in by the script with the suggestion of the server.
+            "keep_domain": t.with("signup-keep-domain", "suggestion", "{suggestion}"),
+        },
+    });
+    let widget = SignupWidget {
+        config: config.to_string(),
+    };
+    match widget.render() {
+        Ok(script) => HttpResponse::Ok()
+            .content_type("application/javascript; charset=utf-8")
+            .insert_header(CacheControl(vec![
+                CacheDirective::Public,
+                CacheDirective::MaxAge(WIDGET_MAX_AGE_SECONDS),
+            ]))
+            .insert_header((header::VARY, "Accept-Language"))
+            .body(script),
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to render the signup widget.");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
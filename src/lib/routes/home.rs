@@ -0,0 +1,34 @@
+use crate::lib::bot_protection::BotProtection;
+use crate::lib::configurations::BrandingSettings;
+use crate::lib::localization::{Translations, accept_language};
+use crate::lib::pages::{SignupPage, render};
+use actix_web::http::StatusCode;
+use actix_web::{HttpRequest, HttpResponse, web};
+use chrono::Utc;
+
+#[derive(serde::Deserialize)]
+pub struct HomeParameters {
+    /// Overrides the `Accept-Language` header.
+    lang: Option<String>,
+}
+
+/// The signup form.
+pub async fn home(
+    req: HttpRequest,
+    parameters: web::Query<HomeParameters>,
+    bot_protection: web::Data<BotProtection>,
+    branding: web::Data<BrandingSettings>,
+    translations: web::Data<Translations>,
+) -> HttpResponse {
+    let locale = translations.negotiate(parameters.lang.as_deref(), accept_language(&req));
+    let page = SignupPage {
+        branding: &branding,
+        t: translations.translator(&locale),
+        challenge: bot_protection.issue(Utc::now().timestamp()),
+        name: "",
+        email: "",
+        errors: vec![],
+        suggestion: None,
+    };
+    render(&page, StatusCode::OK)
+}
@@ -0,0 +1,228 @@
+use crate::lib::audit::record_action;
+use crate::lib::authentication::{AuthError, compute_password_hash, is_acceptable_password};
+use crate::lib::configurations::BrandingSettings;
+use crate::lib::invites::{PendingInvite, find_pending_invite};
+use crate::lib::localization::{Translations, Translator, accept_language};
+use crate::lib::pages::{InviteOutcome, InviteOutcomePage, InvitePage, render};
+use actix_web::http::StatusCode;
+use actix_web::{HttpRequest, HttpResponse, web};
+use chrono::Utc;
+use secrecy::{ExposeSecret, SecretString};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// The longest username users can pick, in characters.
+const MAX_USERNAME_LENGTH: usize = 64;
+
+#[derive(serde::Deserialize)]
+pub struct InviteParameters {
+    token: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct AcceptInviteForm {
+    token: String,
+    username: String,
+    password: SecretString,
+}
+
+/// What signing up with an invite came to.
+enum Signup {
+    Done,
+    /// Somebody used the invite in the meantime.
+    AlreadyAccepted,
+    UsernameTaken,
+}
+
+/// The form to pick a username and a password, if the invite is still valid.
+#[tracing::instrument(
+    name = "Show the invite signup form",
+    skip(req, parameters, pool, branding, translations)
+)]
+pub async fn invite_form(
+    req: HttpRequest,
+    parameters: web::Query<InviteParameters>,
+    pool: web::Data<PgPool>,
+    branding: web::Data<BrandingSettings>,
+    translations: web::Data<Translations>,
+) -> HttpResponse {
+    let locale = translations.negotiate(None, accept_language(&req));
+    let t = translations.translator(&locale);
+    let invite = match valid_invite(&pool, &parameters.token, &branding, t).await {
+        Ok(invite) => invite,
+        Err(response) => return response,
+    };
+    let page = InvitePage {
+        branding: &branding,
+        t,
+        token: &parameters.token,
+        role_name: role_name(t, &invite),
+        username: "",
+        errors: vec![],
+    };
+    render(&page, StatusCode::OK)
+}
+
+/// Create the user the invite is for, with its role.
+#[tracing::instrument(
+    name = "Accept an invite",
+    skip(req, form, pool, branding, translations),
+    fields(username = %form.username)
+)]
+pub async fn accept_invite(
+    req: HttpRequest,
+    form: web::Form<AcceptInviteForm>,
+    pool: web::Data<PgPool>,
+    branding: web::Data<BrandingSettings>,
+    translations: web::Data<Translations>,
+) -> HttpResponse {
+    let locale = translations.negotiate(None, accept_language(&req));
+    let t = translations.translator(&locale);
+    let invite = match valid_invite(&pool, &form.token, &branding, t).await {
+        Ok(invite) => invite,
+        Err(response) => return response,
+    };
+    let form_again = |errors: Vec<String>| {
+        let page = InvitePage {
+            branding: &branding,
+            t,
+            token: &form.token,
+            role_name: role_name(t, &invite),
+            username: &form.username,
+            errors,
+        };
+        render(&page, StatusCode::BAD_REQUEST)
+    };
+
+    let username = form.username.trim();
+    let mut errors = vec![];
+    if username.is_empty() || username.chars().count() > MAX_USERNAME_LENGTH {
+        errors.push(t.get("invite-error-username"));
+    }
+    if !is_acceptable_password(&form.password) {
+        errors.push(t.get("invite-error-password"));
+    }
+    if !errors.is_empty() {
+        return form_again(errors);
+    }
+
+    // Hashing is CPU-bound and slow on purpose: keep it off the async workers.
+    let password = form.password.clone();
+    let password_hash = match tokio::task::spawn_blocking(move || compute_password_hash(&password))
+        .await
+        .map_err(|e| AuthError::Unexpected(e.to_string()))
+        .and_then(|hashed| hashed)
+    {
+        Ok(password_hash) => password_hash,
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to hash a password.");
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let user_id = Uuid::new_v4();
+    let outcome = async {
+        let mut transaction = pool.begin().await?;
+        let accepted = sqlx::query!(
+            r#"
+            UPDATE user_invites SET accepted_at = now()
+            WHERE invite_id = $1 AND accepted_at IS NULL
+            "#,
+            invite.invite_id
+        )
+        .execute(&mut *transaction)
+        .await?;
+        if accepted.rows_affected() == 0 {
+            return Ok(Signup::AlreadyAccepted);
+        }
+        let inserted = sqlx::query!(
+            r#"
+            INSERT INTO users (user_id, username, password_hash, email, role)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (username) DO NOTHING
+            "#,
+            user_id,
+            username,
+            password_hash.expose_secret(),
+            invite.email,
+            invite.role.as_str()
+        )
+        .execute(&mut *transaction)
+        .await?;
+        if inserted.rows_affected() == 0 {
+            return Ok(Signup::UsernameTaken);
+        }
+        sqlx::query!(
+            r#"UPDATE user_invites SET user_id = $1 WHERE invite_id = $2"#,
+            user_id,
+            invite.invite_id
+        )
+        .execute(&mut *transaction)
+        .await?;
+        record_action(
+            &mut *transaction,
+            Some(user_id),
+            "user.joined",
+            None,
+            serde_json::json!({
+                "invite_id": invite.invite_id,
+                "username": username,
+                "role": invite.role,
+            }),
+        )
+        .await?;
+        transaction.commit().await?;
+        Ok::<_, sqlx::Error>(Signup::Done)
+    }
+    .await;
+    match outcome {
+        Ok(Signup::Done) => outcome_page(&branding, t, InviteOutcome::Accepted),
+        Ok(Signup::AlreadyAccepted) => outcome_page(&branding, t, InviteOutcome::Invalid),
+        Ok(Signup::UsernameTaken) => form_again(vec![t.get("invite-error-username-taken")]),
+        Err(e) => {
+            tracing::error!("Failed to execute query: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// The invite behind `token`, or the page explaining why it cannot be used.
+async fn valid_invite(
+    pool: &PgPool,
+    token: &str,
+    branding: &BrandingSettings,
+    t: Translator<'_>,
+) -> Result<PendingInvite, HttpResponse> {
+    match find_pending_invite(pool, token).await {
+        Ok(Some(invite)) if invite.expires_at < Utc::now() => {
+            Err(outcome_page(branding, t, InviteOutcome::Expired))
+        }
+        Ok(Some(invite)) => Ok(invite),
+        Ok(None) => Err(outcome_page(branding, t, InviteOutcome::Invalid)),
+        Err(_) => Err(HttpResponse::InternalServerError().finish()),
+    }
+}
+
+fn outcome_page(
+    branding: &BrandingSettings,
+    t: Translator<'_>,
+    outcome: InviteOutcome,
+) -> HttpResponse {
+    let status = match outcome {
+        InviteOutcome::Accepted => StatusCode::OK,
+        InviteOutcome::Invalid => StatusCode::UNAUTHORIZED,
+        InviteOutcome::Expired => StatusCode::GONE,
+    };
+    render(
+        &InviteOutcomePage {
+            branding,
+            t,
+            outcome,
+        },
+        status,
+    )
+}
+
+fn role_name(t: Translator<'_>, invite: &PendingInvite) -> String {
+    t.get(&format!("role-{}", invite.role.as_str()))
+}
@@ -0,0 +1,568 @@
+use crate::lib::archive::issue_slug;
+use crate::lib::authentication::Authorized;
+use crate::lib::configurations::BrandingSettings;
+use crate::lib::domain::SubscriberEmail;
+use crate::lib::email_client::EmailClient;
+use crate::lib::email_templates::EmailTemplates;
+use crate::lib::issues::{
+    CANCELLED, DRAFT, NewsletterContent, NewsletterIssue, SCHEDULED, SENDING, Subscriber,
+    get_issue, get_subscriber, render_issue,
+};
+use crate::lib::localization::Translations;
+use crate::lib::markdown::{IssueContent, MarkdownWarning};
+use crate::lib::personalization::PersonalizedContent;
+use crate::lib::roles::{EditIssues, Permission, SendIssues};
+use crate::lib::routes::{
+    ISSUE_CONTENT_SECURITY_POLICY, PreviewParameters, find_segment, unsubscribe_link,
+};
+use crate::lib::startup::{ApplicationBaseUrl, HmacSecret};
+use actix_web::http::header::{self, ContentType};
+use actix_web::{HttpResponse, web};
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct IssueBody {
+    title: String,
+    content: NewsletterContent,
+    /// Track opens and clicks, unless turned off.
+    #[serde(default = "enabled")]
+    tracking: bool,
+    /// Send the issue to this segment only.
+    #[serde(default)]
+    segment_id: Option<Uuid>,
+    /// Publish the issue in the public archive once it is sent.
+    #[serde(default)]
+    archived: bool,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+#[derive(serde::Deserialize)]
+pub struct ScheduleBody {
+    /// When to start sending the issue, e.g. `2025-10-20T08:00:00Z`.
+    send_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+struct SavedIssue<'a> {
+    #[serde(flatten)]
+    issue: NewsletterIssue,
+    warnings: &'a [MarkdownWarning],
+}
+
+#[derive(serde::Serialize)]
+struct IssueSummary {
+    newsletter_issue_id: Uuid,
+    title: String,
+    status: String,
+    tracking: bool,
+    scheduled_for: Option<DateTime<Utc>>,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+    sent_at: Option<DateTime<Utc>>,
+}
+
+#[tracing::instrument(
+    name = "Create a newsletter issue",
+    skip(user, body, pool, branding),
+    fields(title = %body.title, user_id = tracing::field::Empty)
+)]
+pub async fn create_issue(
+    user: Authorized<EditIssues>,
+    body: web::Json<IssueBody>,
+    pool: web::Data<PgPool>,
+    branding: web::Data<BrandingSettings>,
+) -> HttpResponse {
+    let user_id = user.user_id;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    let IssueBody {
+        title,
+        content,
+        tracking,
+        segment_id,
+        archived,
+    } = body.into_inner();
+    let rendered = match validate_issue(&title, &content, &branding.accent_color) {
+        Ok(rendered) => rendered,
+        Err(response) => return response,
+    };
+    if let Err(response) = find_segment(&pool, segment_id).await {
+        return response;
+    }
+    let issue = NewIssue {
+        title: &title,
+        content: &content,
+        tracking,
+        segment_id,
+        archived,
+    };
+    match insert_issue(&pool, user_id, &issue, None).await {
+        Ok(newsletter_issue_id) => {
+            saved_issue(&pool, newsletter_issue_id, &rendered, HttpResponse::Created).await
+        }
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// What a new issue is made of.
+pub(crate) struct NewIssue<'a> {
+    pub(crate) title: &'a str,
+    pub(crate) content: &'a NewsletterContent,
+    pub(crate) tracking: bool,
+    pub(crate) segment_id: Option<Uuid>,
+    pub(crate) archived: bool,
+}
+
+/// Save a new issue: a draft, or scheduled for `scheduled_for` if set.
+pub(crate) async fn insert_issue(
+    pool: &PgPool,
+    user_id: Uuid,
+    issue: &NewIssue<'_>,
+    scheduled_for: Option<DateTime<Utc>>,
+) -> Result<Uuid, sqlx::Error> {
+    let newsletter_issue_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues (
+            newsletter_issue_id, title, content, status, tracking, segment_id, created_by,
+            archived, slug, scheduled_for, created_at, updated_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, now(), now())
+        "#,
+        newsletter_issue_id,
+        issue.title,
+        serde_json::to_value(issue.content).expect("Serializable content."),
+        if scheduled_for.is_some() {
+            SCHEDULED
+        } else {
+            DRAFT
+        },
+        issue.tracking,
+        issue.segment_id,
+        user_id,
+        issue.archived,
+        issue_slug(issue.title, newsletter_issue_id),
+        scheduled_for
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(newsletter_issue_id)
+}
+
+#[tracing::instrument(name = "List newsletter issues", skip(_user, pool))]
+pub async fn list_issues(_user: Authorized<EditIssues>, pool: web::Data<PgPool>) -> HttpResponse {
+    match sqlx::query_as!(
+        IssueSummary,
+        r#"
+        SELECT newsletter_issue_id, title, status, tracking, scheduled_for, created_at,
+            updated_at, sent_at
+        FROM newsletter_issues
+        ORDER BY created_at DESC
+        "#
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    {
+        Ok(issues) => HttpResponse::Ok().json(issues),
+        Err(e) => {
+            tracing::error!("Failed to execute query: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[tracing::instrument(name = "Get a newsletter issue", skip(_user, pool))]
+pub async fn get_newsletter_issue(
+    _user: Authorized<EditIssues>,
+    path: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    match get_issue(&pool, path.into_inner()).await {
+        Ok(Some(issue)) => HttpResponse::Ok().json(issue),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// Drafts can still be edited, and so can scheduled issues by users who can
+/// send them: scheduling is what approves an issue to go out.
+#[tracing::instrument(name = "Update a newsletter issue", skip(user, body, pool, branding))]
+pub async fn update_issue(
+    user: Authorized<EditIssues>,
+    path: web::Path<Uuid>,
+    body: web::Json<IssueBody>,
+    pool: web::Data<PgPool>,
+    branding: web::Data<BrandingSettings>,
+) -> HttpResponse {
+    let newsletter_issue_id = path.into_inner();
+    let IssueBody {
+        title,
+        content,
+        tracking,
+        segment_id,
+        archived,
+    } = body.into_inner();
+    let rendered = match validate_issue(&title, &content, &branding.accent_color) {
+        Ok(rendered) => rendered,
+        Err(response) => return response,
+    };
+    if let Err(response) = find_segment(&pool, segment_id).await {
+        return response;
+    }
+    let updated = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET title = $1, content = $2, tracking = $3, segment_id = $7, archived = $8,
+            slug = $9, updated_at = now()
+        WHERE newsletter_issue_id = $4 AND status IN ($5, $6)
+        "#,
+        title,
+        serde_json::to_value(&content).expect("Serializable content."),
+        tracking,
+        newsletter_issue_id,
+        DRAFT,
+        if user.role.can(Permission::SendIssues) {
+            SCHEDULED
+        } else {
+            DRAFT
+        },
+        segment_id,
+        archived,
+        issue_slug(&title, newsletter_issue_id)
+    )
+    .execute(pool.get_ref())
+    .await;
+    match updated {
+        Ok(result) if result.rows_affected() == 1 => {
+            saved_issue(&pool, newsletter_issue_id, &rendered, HttpResponse::Ok).await
+        }
+        Ok(_) => not_found_or_conflict(&pool, newsletter_issue_id).await,
+        Err(e) => {
+            tracing::error!("Failed to execute query: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Only drafts can be deleted: anything else has been, or is being, sent.
+#[tracing::instrument(name = "Delete a newsletter issue", skip(_user, pool))]
+pub async fn delete_issue(
+    _user: Authorized<EditIssues>,
+    path: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let newsletter_issue_id = path.into_inner();
+    let deleted = sqlx::query!(
+        r#"DELETE FROM newsletter_issues WHERE newsletter_issue_id = $1 AND status = $2"#,
+        newsletter_issue_id,
+        DRAFT
+    )
+    .execute(pool.get_ref())
+    .await;
+    match deleted {
+        Ok(result) if result.rows_affected() == 1 => HttpResponse::NoContent().finish(),
+        Ok(_) => not_found_or_conflict(&pool, newsletter_issue_id).await,
+        Err(e) => {
+            tracing::error!("Failed to execute query: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Send a draft at `send_at`, or move a scheduled issue to another time.
+/// A time in the past sends it right away. Either way the issue is approved
+/// to go out, so only users who can send issues may schedule them.
+#[tracing::instrument(name = "Schedule a newsletter issue", skip(_user, body, pool), fields(send_at = %body.send_at))]
+pub async fn schedule_issue(
+    _user: Authorized<SendIssues>,
+    path: web::Path<Uuid>,
+    body: web::Json<ScheduleBody>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let newsletter_issue_id = path.into_inner();
+    let scheduled = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET status = $1, scheduled_for = $2, updated_at = now()
+        WHERE newsletter_issue_id = $3 AND status IN ($4, $1)
+        "#,
+        SCHEDULED,
+        body.send_at,
+        newsletter_issue_id,
+        DRAFT
+    )
+    .execute(pool.get_ref())
+    .await;
+    match scheduled {
+        Ok(result) if result.rows_affected() == 1 => {
+            match get_issue(&pool, newsletter_issue_id).await {
+                Ok(Some(issue)) => HttpResponse::Ok().json(issue),
+                Ok(None) => HttpResponse::NotFound().finish(),
+                Err(_) => HttpResponse::InternalServerError().finish(),
+            }
+        }
+        Ok(_) => not_found_or_conflict(&pool, newsletter_issue_id).await,
+        Err(e) => {
+            tracing::error!("Failed to execute query: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Stop an issue from going out. The emails already sent stay sent.
+#[tracing::instrument(name = "Cancel a newsletter issue", skip(_user, pool))]
+pub async fn cancel_issue(
+    _user: Authorized<EditIssues>,
+    path: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let newsletter_issue_id = path.into_inner();
+    let cancelled = async {
+        let mut transaction = pool.begin().await?;
+        let result = sqlx::query!(
+            r#"
+            UPDATE newsletter_issues
+            SET status = $1, updated_at = now()
+            WHERE newsletter_issue_id = $2 AND status IN ($3, $4, $5)
+            "#,
+            CANCELLED,
+            newsletter_issue_id,
+            DRAFT,
+            SCHEDULED,
+            SENDING
+        )
+        .execute(&mut *transaction)
+        .await?;
+        sqlx::query!(
+            r#"DELETE FROM issue_delivery_queue WHERE newsletter_issue_id = $1"#,
+            newsletter_issue_id
+        )
+        .execute(&mut *transaction)
+        .await?;
+        transaction.commit().await?;
+        Ok::<_, sqlx::Error>(result.rows_affected() == 1)
+    }
+    .await;
+    match cancelled {
+        Ok(true) => match get_issue(&pool, newsletter_issue_id).await {
+            Ok(Some(issue)) => HttpResponse::Ok().json(issue),
+            Ok(None) => HttpResponse::NotFound().finish(),
+            Err(_) => HttpResponse::InternalServerError().finish(),
+        },
+        Ok(false) => not_found_or_conflict(&pool, newsletter_issue_id).await,
+        Err(e) => {
+            tracing::error!("Failed to execute query: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// The HTML email, as a page to open in the browser.
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(
+    name = "Preview a stored newsletter issue",
+    skip(
+        _user,
+        parameters,
+        pool,
+        email_templates,
+        base_url,
+        hmac_secret,
+        branding,
+        translations
+    )
+)]
+pub async fn preview_issue(
+    _user: Authorized<EditIssues>,
+    path: web::Path<Uuid>,
+    parameters: web::Query<PreviewParameters>,
+    pool: web::Data<PgPool>,
+    email_templates: web::Data<EmailTemplates>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
+    branding: web::Data<BrandingSettings>,
+    translations: web::Data<Translations>,
+) -> HttpResponse {
+    let issue = match get_issue(&pool, path.into_inner()).await {
+        Ok(Some(issue)) => issue,
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let subscriber = match parameters.subscriber_id {
+        Some(subscriber_id) => match get_subscriber(&pool, subscriber_id).await {
+            Ok(Some(Ok(subscriber))) => subscriber,
+            Ok(Some(Err(e))) => return HttpResponse::UnprocessableEntity().body(e),
+            Ok(None) => return HttpResponse::NotFound().finish(),
+            Err(_) => return HttpResponse::InternalServerError().finish(),
+        },
+        None => Subscriber::example(translations.default_locale()),
+    };
+
+    let personalized = match PersonalizedContent::new(&issue.content.render(&branding.accent_color))
+    {
+        Ok(personalized) => personalized,
+        Err(e) => return HttpResponse::UnprocessableEntity().body(e.to_string()),
+    };
+    let unsubscribe_link = unsubscribe_link(&base_url.0, &hmac_secret.0, subscriber.id);
+    match render_issue(
+        &email_templates,
+        &issue.title,
+        &personalized,
+        &subscriber,
+        unsubscribe_link,
+        None,
+    ) {
+        Ok(email) => HttpResponse::Ok()
+            .content_type(ContentType::html())
+            .insert_header((
+                header::CONTENT_SECURITY_POLICY,
+                ISSUE_CONTENT_SECURITY_POLICY,
+            ))
+            .body(email.html),
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to render the issue.");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Send the issue to the user, rendered for a made-up subscriber. Only to
+/// their own email: issues are not for sending anywhere from the list's domain.
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(
+    name = "Send a test newsletter issue",
+    skip(
+        user,
+        pool,
+        email_client,
+        email_templates,
+        base_url,
+        hmac_secret,
+        branding,
+        translations
+    ),
+    fields(user_id = tracing::field::Empty)
+)]
+pub async fn send_test_issue(
+    user: Authorized<EditIssues>,
+    path: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    email_templates: web::Data<EmailTemplates>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
+    branding: web::Data<BrandingSettings>,
+    translations: web::Data<Translations>,
+) -> HttpResponse {
+    let user_id = user.user_id;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    let issue = match get_issue(&pool, path.into_inner()).await {
+        Ok(Some(issue)) => issue,
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let email = match sqlx::query_scalar!(r#"SELECT email FROM users WHERE user_id = $1"#, user_id)
+        .fetch_one(pool.get_ref())
+        .await
+    {
+        Ok(Some(email)) => email,
+        Ok(None) => {
+            return HttpResponse::BadRequest()
+                .body("You have no email address to send the test to.");
+        }
+        Err(e) => {
+            tracing::error!("Failed to execute query: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let email = match SubscriberEmail::parse(&email) {
+        Ok(email) => email,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+
+    let mut subscriber = Subscriber::example(translations.default_locale());
+    subscriber.recipient.email = email.as_ref().to_owned();
+    subscriber.email = email;
+    let personalized = match PersonalizedContent::new(&issue.content.render(&branding.accent_color))
+    {
+        Ok(personalized) => personalized,
+        Err(e) => return HttpResponse::UnprocessableEntity().body(e.to_string()),
+    };
+    let unsubscribe_link = unsubscribe_link(&base_url.0, &hmac_secret.0, subscriber.id);
+    let rendered = match render_issue(
+        &email_templates,
+        &issue.title,
+        &personalized,
+        &subscriber,
+        unsubscribe_link,
+        None,
+    ) {
+        Ok(rendered) => rendered,
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to render the issue.");
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let subject = format!("[Test] {}", issue.title);
+    match email_client
+        .send_email(&subscriber.email, &subject, &rendered.html, &rendered.text)
+        .await
+    {
+        Ok(()) => HttpResponse::Accepted().finish(),
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to send the test issue.");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// The rendered content of a valid issue, or the response to send back.
+pub(crate) fn validate_issue(
+    title: &str,
+    content: &NewsletterContent,
+    link_color: &str,
+) -> Result<IssueContent, HttpResponse> {
+    if title.trim().is_empty() {
+        return Err(HttpResponse::BadRequest().body("The title of the issue is empty."));
+    }
+    let content = content.render(link_color);
+    PersonalizedContent::new(&content)
+        .map_err(|e| HttpResponse::BadRequest().body(e.to_string()))?;
+    Ok(content)
+}
+
+pub(crate) async fn saved_issue(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+    content: &IssueContent,
+    response: fn() -> actix_web::HttpResponseBuilder,
+) -> HttpResponse {
+    match get_issue(pool, newsletter_issue_id).await {
+        Ok(Some(issue)) => response().json(SavedIssue {
+            issue,
+            warnings: &content.warnings,
+        }),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// Why a guarded update of an issue did not happen: it is gone, or it is not
+/// in a state that allows it.
+async fn not_found_or_conflict(pool: &PgPool, newsletter_issue_id: Uuid) -> HttpResponse {
+    match get_issue(pool, newsletter_issue_id).await {
+        Ok(Some(issue)) => HttpResponse::Conflict().body(format!("The issue is {}.", issue.status)),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
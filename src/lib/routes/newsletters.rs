@@ -0,0 +1,157 @@
+use crate::lib::authentication::Authorized;
+use crate::lib::configurations::BrandingSettings;
+use crate::lib::email_templates::EmailTemplates;
+use crate::lib::issues::{NewsletterContent, Subscriber, get_subscriber, render_issue};
+use crate::lib::localization::Translations;
+use crate::lib::markdown::MarkdownWarning;
+use crate::lib::personalization::PersonalizedContent;
+use crate::lib::roles::{EditIssues, SendIssues};
+use crate::lib::routes::{
+    NewIssue, find_segment, insert_issue, saved_issue, unsubscribe_link, validate_issue,
+};
+use crate::lib::startup::{ApplicationBaseUrl, HmacSecret};
+use actix_web::{HttpResponse, web};
+use chrono::Utc;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct PreviewParameters {
+    /// Render the issue for this subscriber rather than for a made-up one.
+    pub(crate) subscriber_id: Option<Uuid>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct NewsletterBody {
+    title: String,
+    content: NewsletterContent,
+    /// Send the issue to this segment only.
+    #[serde(default)]
+    segment_id: Option<Uuid>,
+}
+
+#[derive(serde::Serialize)]
+struct IssuePreview<'a> {
+    subject: &'a str,
+    html: String,
+    text: String,
+    warnings: &'a [MarkdownWarning],
+}
+
+/// Render an issue the way a subscriber would get it, without sending it.
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(
+    name = "Preview a newsletter issue",
+    skip(
+        _user,
+        parameters,
+        body,
+        pool,
+        email_templates,
+        base_url,
+        hmac_secret,
+        branding,
+        translations
+    )
+)]
+pub async fn preview_newsletter(
+    _user: Authorized<EditIssues>,
+    parameters: web::Query<PreviewParameters>,
+    body: web::Json<NewsletterBody>,
+    pool: web::Data<PgPool>,
+    email_templates: web::Data<EmailTemplates>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
+    branding: web::Data<BrandingSettings>,
+    translations: web::Data<Translations>,
+) -> HttpResponse {
+    let NewsletterBody { title, content, .. } = body.into_inner();
+    let content = content.render(&branding.accent_color);
+    let personalized = match PersonalizedContent::new(&content) {
+        Ok(personalized) => personalized,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    let subscriber = match parameters.subscriber_id {
+        Some(subscriber_id) => match get_subscriber(&pool, subscriber_id).await {
+            Ok(Some(Ok(subscriber))) => subscriber,
+            Ok(Some(Err(e))) => return HttpResponse::UnprocessableEntity().body(e),
+            Ok(None) => return HttpResponse::NotFound().finish(),
+            Err(_) => return HttpResponse::InternalServerError().finish(),
+        },
+        None => Subscriber::example(translations.default_locale()),
+    };
+
+    let unsubscribe_link = unsubscribe_link(&base_url.0, &hmac_secret.0, subscriber.id);
+    match render_issue(
+        &email_templates,
+        &title,
+        &personalized,
+        &subscriber,
+        unsubscribe_link,
+        None,
+    ) {
+        Ok(email) => HttpResponse::Ok().json(IssuePreview {
+            subject: &title,
+            html: email.html,
+            text: email.text,
+            warnings: &content.warnings,
+        }),
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to render the issue.");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Send an issue to every confirmed subscriber, or to a segment, right away:
+/// a shortcut for creating an issue and scheduling it now. It goes out in the
+/// background, like any other issue.
+#[tracing::instrument(
+    name = "Publish a newsletter issue",
+    skip(user, body, pool, branding),
+    fields(title = %body.title, user_id = tracing::field::Empty)
+)]
+pub async fn publish_newsletter(
+    user: Authorized<SendIssues>,
+    body: web::Json<NewsletterBody>,
+    pool: web::Data<PgPool>,
+    branding: web::Data<BrandingSettings>,
+) -> HttpResponse {
+    let user_id = user.user_id;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    let NewsletterBody {
+        title,
+        content,
+        segment_id,
+    } = body.into_inner();
+    let rendered = match validate_issue(&title, &content, &branding.accent_color) {
+        Ok(rendered) => rendered,
+        Err(response) => return response,
+    };
+    for warning in &rendered.warnings {
+        tracing::info!(%warning, "The issue may not render well.");
+    }
+    if let Err(response) = find_segment(&pool, segment_id).await {
+        return response;
+    }
+    let issue = NewIssue {
+        title: &title,
+        content: &content,
+        tracking: true,
+        segment_id,
+        archived: false,
+    };
+    match insert_issue(&pool, user_id, &issue, Some(Utc::now())).await {
+        Ok(newsletter_issue_id) => {
+            saved_issue(
+                &pool,
+                newsletter_issue_id,
+                &rendered,
+                HttpResponse::Accepted,
+            )
+            .await
+        }
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
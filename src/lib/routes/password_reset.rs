@@ -0,0 +1,306 @@
+use crate::lib::audit::record_action;
+use crate::lib::authentication::{AuthError, compute_password_hash, is_acceptable_password};
+use crate::lib::configurations::{AdminUserSettings, BrandingSettings};
+use crate::lib::domain::SubscriberEmail;
+use crate::lib::email_client::EmailClient;
+use crate::lib::email_templates::EmailTemplates;
+use crate::lib::localization::{Translations, Translator, accept_language};
+use crate::lib::pages::{
+    ForgotPasswordPage, PasswordResetOutcome, PasswordResetOutcomePage, PasswordResetPage, render,
+};
+use crate::lib::password_resets::{
+    PendingReset, create_reset_tokens, find_pending_reset, hash_reset_token, reset_link,
+    send_reset_email,
+};
+use crate::lib::startup::ApplicationBaseUrl;
+use actix_web::http::StatusCode;
+use actix_web::{HttpRequest, HttpResponse, web};
+use chrono::Utc;
+use secrecy::{ExposeSecret, SecretString};
+use sqlx::PgPool;
+use tracing::Instrument;
+
+#[derive(serde::Deserialize)]
+pub struct ForgotPasswordForm {
+    email: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct PasswordResetParameters {
+    token: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct PasswordResetForm {
+    token: String,
+    password: SecretString,
+}
+
+/// The form to ask for a link to reset a forgotten password.
+#[tracing::instrument(
+    name = "Show the forgot password form",
+    skip(req, branding, translations)
+)]
+pub async fn forgot_password_form(
+    req: HttpRequest,
+    branding: web::Data<BrandingSettings>,
+    translations: web::Data<Translations>,
+) -> HttpResponse {
+    let locale = translations.negotiate(None, accept_language(&req));
+    let t = translations.translator(&locale);
+    render(
+        &ForgotPasswordPage {
+            branding: &branding,
+            t,
+        },
+        StatusCode::OK,
+    )
+}
+
+/// Email a link to reset their password to the users with this email. The
+/// page is the same whether there are any or not: it must not tell which
+/// emails belong to users, nor take longer when it does. The emails are sent
+/// after answering.
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(
+    name = "Request a password reset",
+    skip(
+        req,
+        form,
+        pool,
+        email_client,
+        email_templates,
+        base_url,
+        branding,
+        translations,
+        settings
+    )
+)]
+pub async fn request_password_reset(
+    req: HttpRequest,
+    form: web::Form<ForgotPasswordForm>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    email_templates: web::Data<EmailTemplates>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    branding: web::Data<BrandingSettings>,
+    translations: web::Data<Translations>,
+    settings: web::Data<AdminUserSettings>,
+) -> HttpResponse {
+    let locale = translations.negotiate(None, accept_language(&req));
+    let t = translations.translator(&locale);
+    let tokens =
+        match create_reset_tokens(&pool, &form.email, settings.password_reset_ttl_minutes).await {
+            Ok(tokens) => tokens,
+            Err(_) => return HttpResponse::InternalServerError().finish(),
+        };
+    if !tokens.is_empty() {
+        let translations = web::Data::clone(&translations);
+        let branding = web::Data::clone(&branding);
+        let settings = web::Data::clone(&settings);
+        let locale = locale.clone();
+        tokio::spawn(
+            async move {
+                let t = translations.translator(&locale);
+                for (email, token) in tokens {
+                    let Ok(email) = SubscriberEmail::parse(&email) else {
+                        tracing::warn!("A user has an invalid email, could not send a reset link.");
+                        continue;
+                    };
+                    // Failing to send is only logged: nobody is waiting for it.
+                    let _ = send_reset_email(
+                        &email_client,
+                        &email_templates,
+                        &email,
+                        &reset_link(&base_url.0, &token),
+                        settings.password_reset_ttl_minutes,
+                        &branding.site_name,
+                        t,
+                    )
+                    .await;
+                }
+            }
+            .instrument(tracing::Span::current()),
+        );
+    }
+    outcome_page(&branding, t, PasswordResetOutcome::EmailSent)
+}
+
+/// The form to pick a new password, if the link is still valid.
+#[tracing::instrument(
+    name = "Show the password reset form",
+    skip(req, parameters, pool, branding, translations)
+)]
+pub async fn password_reset_form(
+    req: HttpRequest,
+    parameters: web::Query<PasswordResetParameters>,
+    pool: web::Data<PgPool>,
+    branding: web::Data<BrandingSettings>,
+    translations: web::Data<Translations>,
+) -> HttpResponse {
+    let locale = translations.negotiate(None, accept_language(&req));
+    let t = translations.translator(&locale);
+    if let Err(response) = valid_reset(&pool, &parameters.token, &branding, t).await {
+        return response;
+    }
+    let page = PasswordResetPage {
+        branding: &branding,
+        t,
+        token: &parameters.token,
+        errors: vec![],
+    };
+    render(&page, StatusCode::OK)
+}
+
+/// Set the new password, and log the user out everywhere: whoever knew the
+/// old one must not stay logged in.
+#[tracing::instrument(
+    name = "Reset a password",
+    skip(req, form, pool, branding, translations)
+)]
+pub async fn reset_password(
+    req: HttpRequest,
+    form: web::Form<PasswordResetForm>,
+    pool: web::Data<PgPool>,
+    branding: web::Data<BrandingSettings>,
+    translations: web::Data<Translations>,
+) -> HttpResponse {
+    let locale = translations.negotiate(None, accept_language(&req));
+    let t = translations.translator(&locale);
+    let reset = match valid_reset(&pool, &form.token, &branding, t).await {
+        Ok(reset) => reset,
+        Err(response) => return response,
+    };
+    if !is_acceptable_password(&form.password) {
+        let page = PasswordResetPage {
+            branding: &branding,
+            t,
+            token: &form.token,
+            errors: vec![t.get("invite-error-password")],
+        };
+        return render(&page, StatusCode::BAD_REQUEST);
+    }
+
+    // Hashing is CPU-bound and slow on purpose: keep it off the async workers.
+    let password = form.password.clone();
+    let password_hash = match tokio::task::spawn_blocking(move || compute_password_hash(&password))
+        .await
+        .map_err(|e| AuthError::Unexpected(e.to_string()))
+        .and_then(|hashed| hashed)
+    {
+        Ok(password_hash) => password_hash,
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to hash a password.");
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let outcome = async {
+        let mut transaction = pool.begin().await?;
+        let used = sqlx::query!(
+            r#"
+            UPDATE password_reset_tokens SET used_at = now()
+            WHERE token_hash = $1 AND used_at IS NULL AND expires_at > now()
+            "#,
+            hash_reset_token(&form.token)
+        )
+        .execute(&mut *transaction)
+        .await?;
+        if used.rows_affected() == 0 {
+            // Somebody used the link in the meantime.
+            return Ok(false);
+        }
+        let username = sqlx::query_scalar!(
+            r#"UPDATE users SET password_hash = $1 WHERE user_id = $2 RETURNING username"#,
+            password_hash.expose_secret(),
+            reset.user_id
+        )
+        .fetch_one(&mut *transaction)
+        .await?;
+        // The other links sent for the old password, its sessions, and the
+        // failed logins that may have locked it are all done with.
+        sqlx::query!(
+            r#"
+            UPDATE password_reset_tokens SET used_at = now()
+            WHERE user_id = $1 AND used_at IS NULL
+            "#,
+            reset.user_id
+        )
+        .execute(&mut *transaction)
+        .await?;
+        sqlx::query!(
+            r#"DELETE FROM user_sessions WHERE user_id = $1"#,
+            reset.user_id
+        )
+        .execute(&mut *transaction)
+        .await?;
+        sqlx::query!(
+            r#"DELETE FROM login_challenges WHERE user_id = $1"#,
+            reset.user_id
+        )
+        .execute(&mut *transaction)
+        .await?;
+        sqlx::query!(
+            r#"DELETE FROM login_failures WHERE scope = 'username' AND key = $1"#,
+            username
+        )
+        .execute(&mut *transaction)
+        .await?;
+        record_action(
+            &mut *transaction,
+            Some(reset.user_id),
+            "user.password_reset",
+            None,
+            serde_json::json!({}),
+        )
+        .await?;
+        transaction.commit().await?;
+        Ok::<_, sqlx::Error>(true)
+    }
+    .await;
+    match outcome {
+        Ok(true) => outcome_page(&branding, t, PasswordResetOutcome::Done),
+        Ok(false) => outcome_page(&branding, t, PasswordResetOutcome::Invalid),
+        Err(e) => {
+            tracing::error!("Failed to execute query: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// The reset behind `token`, or the page explaining why it cannot be used.
+async fn valid_reset(
+    pool: &PgPool,
+    token: &str,
+    branding: &BrandingSettings,
+    t: Translator<'_>,
+) -> Result<PendingReset, HttpResponse> {
+    match find_pending_reset(pool, token).await {
+        Ok(Some(reset)) if reset.expires_at < Utc::now() => {
+            Err(outcome_page(branding, t, PasswordResetOutcome::Expired))
+        }
+        Ok(Some(reset)) => Ok(reset),
+        Ok(None) => Err(outcome_page(branding, t, PasswordResetOutcome::Invalid)),
+        Err(_) => Err(HttpResponse::InternalServerError().finish()),
+    }
+}
+
+fn outcome_page(
+    branding: &BrandingSettings,
+    t: Translator<'_>,
+    outcome: PasswordResetOutcome,
+) -> HttpResponse {
+    let status = match outcome {
+        PasswordResetOutcome::EmailSent | PasswordResetOutcome::Done => StatusCode::OK,
+        PasswordResetOutcome::Invalid => StatusCode::UNAUTHORIZED,
+        PasswordResetOutcome::Expired => StatusCode::GONE,
+    };
+    render(
+        &PasswordResetOutcomePage {
+            branding,
+            t,
+            outcome,
+        },
+        status,
+    )
+}
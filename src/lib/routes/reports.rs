@@ -0,0 +1,177 @@
+use crate::lib::authentication::Authorized;
+use crate::lib::configurations::BrandingSettings;
+use crate::lib::localization::Translations;
+use crate::lib::pages::{ReportsPage, render};
+use crate::lib::reports::{IssueStats, LinkStats, growth, issue_stats, to_csv, top_links};
+use crate::lib::roles::ViewReports;
+use actix_web::http::StatusCode;
+use actix_web::http::header::CONTENT_DISPOSITION;
+use actix_web::{HttpResponse, web};
+use chrono::{Days, NaiveDate, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// The growth report covers this many days unless told otherwise.
+const DEFAULT_GROWTH_DAYS: u64 = 30;
+/// And at most this many.
+const MAX_GROWTH_DAYS: u64 = 366;
+const TOP_LINKS: i64 = 10;
+
+#[derive(Clone, Copy, Default, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportFormat {
+    #[default]
+    Json,
+    Csv,
+}
+
+#[derive(serde::Deserialize)]
+pub struct ReportParameters {
+    #[serde(default)]
+    format: ReportFormat,
+}
+
+#[derive(serde::Deserialize)]
+pub struct GrowthParameters {
+    /// The first day, `2025-10-01`: 30 days before `to` by default.
+    from: Option<NaiveDate>,
+    /// The last day: today, in UTC, by default.
+    to: Option<NaiveDate>,
+    #[serde(default)]
+    format: ReportFormat,
+}
+
+#[derive(serde::Serialize)]
+struct IssueReport {
+    #[serde(flatten)]
+    stats: IssueStats,
+    top_links: Vec<LinkStats>,
+}
+
+/// The reports, as a page for the browser.
+#[tracing::instrument(name = "Show the reports", skip(_user, pool, branding, translations))]
+pub async fn reports_page(
+    _user: Authorized<ViewReports>,
+    pool: web::Data<PgPool>,
+    branding: web::Data<BrandingSettings>,
+    translations: web::Data<Translations>,
+) -> HttpResponse {
+    let (from, to) = match growth_range(None, None) {
+        Ok(range) => range,
+        Err(response) => return response,
+    };
+    let (issues, growth) = match (
+        issue_stats(&pool, None).await,
+        growth(&pool, from, to).await,
+    ) {
+        (Ok(issues), Ok(growth)) => (issues, growth),
+        _ => return HttpResponse::InternalServerError().finish(),
+    };
+    let page = ReportsPage {
+        branding: &branding,
+        t: translations.translator(translations.default_locale()),
+        issues,
+        growth,
+    };
+    render(&page, StatusCode::OK)
+}
+
+/// How every issue that went out did.
+#[tracing::instrument(name = "Report on issues", skip(_user, parameters, pool))]
+pub async fn issues_report(
+    _user: Authorized<ViewReports>,
+    parameters: web::Query<ReportParameters>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    match issue_stats(&pool, None).await {
+        Ok(issues) => match parameters.format {
+            ReportFormat::Json => HttpResponse::Ok().json(issues),
+            ReportFormat::Csv => csv_response(&issues, "issues"),
+        },
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// How one issue did, with its most clicked links. As CSV, only the links.
+#[tracing::instrument(name = "Report on an issue", skip(_user, parameters, pool))]
+pub async fn issue_report(
+    _user: Authorized<ViewReports>,
+    path: web::Path<Uuid>,
+    parameters: web::Query<ReportParameters>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let newsletter_issue_id = path.into_inner();
+    let stats = match issue_stats(&pool, Some(newsletter_issue_id)).await {
+        Ok(mut stats) => match stats.pop() {
+            Some(stats) => stats,
+            // Not sent, or not at all.
+            None => return HttpResponse::NotFound().finish(),
+        },
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let top_links = match top_links(&pool, newsletter_issue_id, TOP_LINKS).await {
+        Ok(top_links) => top_links,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    match parameters.format {
+        ReportFormat::Json => HttpResponse::Ok().json(IssueReport { stats, top_links }),
+        ReportFormat::Csv => {
+            csv_response(&top_links, &format!("issue-{}-links", newsletter_issue_id))
+        }
+    }
+}
+
+/// New, confirmed and unsubscribed subscribers per day.
+#[tracing::instrument(name = "Report on subscriber growth", skip(_user, parameters, pool))]
+pub async fn growth_report(
+    _user: Authorized<ViewReports>,
+    parameters: web::Query<GrowthParameters>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let (from, to) = match growth_range(parameters.from, parameters.to) {
+        Ok(range) => range,
+        Err(response) => return response,
+    };
+    match growth(&pool, from, to).await {
+        Ok(days) => match parameters.format {
+            ReportFormat::Json => HttpResponse::Ok().json(days),
+            ReportFormat::Csv => csv_response(&days, "growth"),
+        },
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+fn growth_range(
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+) -> Result<(NaiveDate, NaiveDate), HttpResponse> {
+    let to = to.unwrap_or_else(|| Utc::now().date_naive());
+    let from = from.unwrap_or(to - Days::new(DEFAULT_GROWTH_DAYS - 1));
+    if from > to {
+        return Err(HttpResponse::BadRequest().body("`from` is after `to`."));
+    }
+    if (to - from).num_days() >= MAX_GROWTH_DAYS as i64 {
+        return Err(HttpResponse::BadRequest().body(format!(
+            "The report covers at most {} days.",
+            MAX_GROWTH_DAYS
+        )));
+    }
+    Ok((from, to))
+}
+
+/// `rows` as a CSV file to download.
+fn csv_response<T: serde::Serialize>(rows: &[T], name: &str) -> HttpResponse {
+    match to_csv(rows) {
+        Ok(csv) => HttpResponse::Ok()
+            .content_type("text/csv; charset=utf-8")
+            .insert_header((
+                CONTENT_DISPOSITION,
+                format!(r#"attachment; filename="{}.csv""#, name),
+            ))
+            .body(csv),
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to write a report as CSV.");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
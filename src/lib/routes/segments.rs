@@ -0,0 +1,250 @@
+use crate::lib::audit::record_action;
+use crate::lib::authentication::Authorized;
+use crate::lib::roles::EditIssues;
+use crate::lib::segments::{Expression, Segment, count_audience, get_segment, list_segments};
+use actix_web::{HttpResponse, web};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+const MAX_NAME_LENGTH: usize = 100;
+
+#[derive(serde::Deserialize)]
+pub struct SegmentBody {
+    name: String,
+    expression: String,
+}
+
+impl SegmentBody {
+    fn validate(&self) -> Result<Expression, String> {
+        let name = self.name.trim();
+        if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+            return Err(format!(
+                "The name of a segment must have between 1 and {} characters.",
+                MAX_NAME_LENGTH
+            ));
+        }
+        Expression::parse(&self.expression).map_err(|e| e.to_string())
+    }
+}
+
+#[derive(serde::Serialize)]
+struct SegmentWithAudience {
+    #[serde(flatten)]
+    segment: Segment,
+    /// How many confirmed subscribers an issue sent now would reach.
+    audience: i64,
+}
+
+#[tracing::instrument(name = "Create a segment", skip(user, body, pool), fields(name = %body.name))]
+pub async fn create_segment(
+    user: Authorized<EditIssues>,
+    body: web::Json<SegmentBody>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let user_id = user.user_id;
+    if let Err(e) = body.validate() {
+        return HttpResponse::BadRequest().body(e);
+    }
+
+    let segment_id = Uuid::new_v4();
+    let outcome = async {
+        let mut transaction = pool.begin().await?;
+        let inserted = sqlx::query!(
+            r#"
+            INSERT INTO segments (segment_id, name, expression, created_at, updated_at)
+            VALUES ($1, $2, $3, now(), now())
+            ON CONFLICT (name) DO NOTHING
+            "#,
+            segment_id,
+            body.name.trim(),
+            body.expression
+        )
+        .execute(&mut *transaction)
+        .await?;
+        if inserted.rows_affected() == 0 {
+            return Ok(false);
+        }
+        record_action(
+            &mut *transaction,
+            Some(user_id),
+            "segment.created",
+            None,
+            serde_json::json!({ "segment_id": segment_id, "expression": body.expression }),
+        )
+        .await?;
+        transaction.commit().await?;
+        Ok::<_, sqlx::Error>(true)
+    }
+    .await;
+    match outcome {
+        Ok(true) => segment_response(&pool, segment_id, HttpResponse::Created).await,
+        Ok(false) => HttpResponse::Conflict().body("There is already a segment with this name."),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[tracing::instrument(name = "List segments for an admin", skip(_user, pool))]
+pub async fn admin_list_segments(
+    _user: Authorized<EditIssues>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    match list_segments(&pool).await {
+        Ok(segments) => HttpResponse::Ok().json(segments),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// A segment, with the size of its audience.
+#[tracing::instrument(name = "Show a segment to an admin", skip(_user, pool))]
+pub async fn admin_get_segment(
+    _user: Authorized<EditIssues>,
+    path: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    segment_response(&pool, path.into_inner(), HttpResponse::Ok).await
+}
+
+/// Issues already sent to the segment are not affected.
+#[tracing::instrument(name = "Update a segment", skip(user, body, pool))]
+pub async fn update_segment(
+    user: Authorized<EditIssues>,
+    path: web::Path<Uuid>,
+    body: web::Json<SegmentBody>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let user_id = user.user_id;
+    if let Err(e) = body.validate() {
+        return HttpResponse::BadRequest().body(e);
+    }
+
+    let segment_id = path.into_inner();
+    let outcome = async {
+        let mut transaction = pool.begin().await?;
+        let name_taken = sqlx::query_scalar!(
+            r#"SELECT EXISTS (SELECT 1 FROM segments WHERE name = $1 AND segment_id <> $2) AS "taken!""#,
+            body.name.trim(),
+            segment_id
+        )
+        .fetch_one(&mut *transaction)
+        .await?;
+        if name_taken {
+            return Ok(Err(
+                HttpResponse::Conflict().body("There is already a segment with this name.")
+            ));
+        }
+        let updated = sqlx::query!(
+            r#"
+            UPDATE segments SET name = $1, expression = $2, updated_at = now()
+            WHERE segment_id = $3
+            "#,
+            body.name.trim(),
+            body.expression,
+            segment_id
+        )
+        .execute(&mut *transaction)
+        .await?;
+        if updated.rows_affected() == 0 {
+            return Ok(Err(HttpResponse::NotFound().finish()));
+        }
+        record_action(
+            &mut *transaction,
+            Some(user_id),
+            "segment.updated",
+            None,
+            serde_json::json!({ "segment_id": segment_id, "expression": body.expression }),
+        )
+        .await?;
+        transaction.commit().await?;
+        Ok::<_, sqlx::Error>(Ok(()))
+    }
+    .await;
+    match outcome {
+        Ok(Ok(())) => segment_response(&pool, segment_id, HttpResponse::Ok).await,
+        Ok(Err(response)) => response,
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// Segments that issues were written for are kept.
+#[tracing::instrument(name = "Delete a segment", skip(user, pool))]
+pub async fn delete_segment(
+    user: Authorized<EditIssues>,
+    path: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let user_id = user.user_id;
+
+    let segment_id = path.into_inner();
+    let outcome = async {
+        let mut transaction = pool.begin().await?;
+        let Some(segment) = get_segment(&mut *transaction, segment_id).await? else {
+            return Ok(Err(HttpResponse::NotFound().finish()));
+        };
+        let in_use = sqlx::query_scalar!(
+            r#"SELECT EXISTS (SELECT 1 FROM newsletter_issues WHERE segment_id = $1) AS "in_use!""#,
+            segment_id
+        )
+        .fetch_one(&mut *transaction)
+        .await?;
+        if in_use {
+            return Ok(Err(
+                HttpResponse::Conflict().body("Issues are written for this segment.")
+            ));
+        }
+        sqlx::query!(r#"DELETE FROM segments WHERE segment_id = $1"#, segment_id)
+            .execute(&mut *transaction)
+            .await?;
+        record_action(
+            &mut *transaction,
+            Some(user_id),
+            "segment.deleted",
+            None,
+            serde_json::json!({ "segment_id": segment_id, "name": segment.name }),
+        )
+        .await?;
+        transaction.commit().await?;
+        Ok::<_, sqlx::Error>(Ok(()))
+    }
+    .await;
+    match outcome {
+        Ok(Ok(())) => HttpResponse::NoContent().finish(),
+        Ok(Err(response)) => response,
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+async fn segment_response(
+    pool: &PgPool,
+    segment_id: Uuid,
+    response: fn() -> actix_web::HttpResponseBuilder,
+) -> HttpResponse {
+    let segment = match get_segment(pool, segment_id).await {
+        Ok(Some(segment)) => segment,
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let audience = match segment.parsed() {
+        Ok(expression) => count_audience(pool, &expression).await,
+        Err(e) => Err(e),
+    };
+    match audience {
+        Ok(audience) => response().json(SegmentWithAudience { segment, audience }),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// The segment an issue is written for, or the response to send if there is
+/// no such segment.
+pub(crate) async fn find_segment(
+    pool: &PgPool,
+    segment_id: Option<Uuid>,
+) -> Result<Option<Segment>, HttpResponse> {
+    let Some(segment_id) = segment_id else {
+        return Ok(None);
+    };
+    match get_segment(pool, segment_id).await {
+        Ok(Some(segment)) => Ok(Some(segment)),
+        Ok(None) => Err(HttpResponse::BadRequest().body("There is no such segment.")),
+        Err(_) => Err(HttpResponse::InternalServerError().finish()),
+    }
+}
@@ -0,0 +1,162 @@
+use crate::lib::audit::record_action;
+use crate::lib::authentication::{AccessDenied, AuthError, Credentials};
+use crate::lib::configurations::AdminUserSettings;
+use crate::lib::login_throttling::attempt_login;
+use crate::lib::sessions::{
+    MAX_CHALLENGE_ATTEMPTS, bearer_token, create_challenge, create_session, delete_session,
+    find_challenge,
+};
+use crate::lib::two_factor::{SecondFactor, verify_second_factor};
+use actix_web::{HttpRequest, HttpResponse, ResponseError, web};
+use secrecy::SecretString;
+use sqlx::PgPool;
+
+#[derive(serde::Deserialize)]
+pub struct LoginBody {
+    username: String,
+    password: SecretString,
+}
+
+#[derive(serde::Deserialize)]
+pub struct SecondFactorBody {
+    challenge: String,
+    code: String,
+}
+
+/// Log in with a username and a password. Users without two-factor
+/// authentication get a session right away; the others get a challenge to
+/// answer with a code at `/admin/sessions/two-factor`.
+#[tracing::instrument(
+    name = "Log in",
+    skip(req, body, pool, settings),
+    fields(username = %body.username)
+)]
+pub async fn log_in(
+    req: HttpRequest,
+    body: web::Json<LoginBody>,
+    pool: web::Data<PgPool>,
+    settings: web::Data<AdminUserSettings>,
+) -> HttpResponse {
+    let LoginBody { username, password } = body.into_inner();
+    let user = match attempt_login(&req, Credentials { username, password }).await {
+        Ok(user) => user,
+        Err(AuthError::InvalidCredentials) => return HttpResponse::Unauthorized().finish(),
+        Err(AuthError::TooManyAttempts(retry_after)) => {
+            return AccessDenied::TooManyAttempts(retry_after).error_response();
+        }
+        Err(AuthError::Unexpected(e)) => {
+            tracing::error!(error = %e, "Failed to check the credentials.");
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    if user.two_factor_enabled {
+        return match create_challenge(&pool, user.user_id).await {
+            Ok(challenge) => HttpResponse::Ok().json(serde_json::json!({
+                "two_factor_challenge": challenge.token,
+                "expires_at": challenge.expires_at,
+            })),
+            Err(_) => HttpResponse::InternalServerError().finish(),
+        };
+    }
+    match create_session(&**pool, user.user_id, settings.session_ttl_hours).await {
+        Ok(session) => HttpResponse::Created().json(session),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// Finish logging in with a code from the authenticator app, or a recovery
+/// code. A challenge only survives a few wrong codes.
+#[tracing::instrument(
+    name = "Verify the second factor of a login",
+    skip(body, pool, settings)
+)]
+pub async fn verify_login(
+    body: web::Json<SecondFactorBody>,
+    pool: web::Data<PgPool>,
+    settings: web::Data<AdminUserSettings>,
+) -> HttpResponse {
+    let outcome = async {
+        let mut transaction = pool.begin().await?;
+        let Some(challenge) = find_challenge(&mut *transaction, &body.challenge).await? else {
+            return Ok(None);
+        };
+        let factor = verify_second_factor(&mut transaction, challenge.user_id, &body.code).await?;
+        let session = match factor {
+            Some(factor) => {
+                sqlx::query!(
+                    r#"DELETE FROM login_challenges WHERE challenge_id = $1"#,
+                    challenge.challenge_id
+                )
+                .execute(&mut *transaction)
+                .await?;
+                if factor == SecondFactor::RecoveryCode {
+                    record_action(
+                        &mut *transaction,
+                        Some(challenge.user_id),
+                        "user.recovery_code_used",
+                        None,
+                        serde_json::json!({}),
+                    )
+                    .await?;
+                }
+                Some(
+                    create_session(
+                        &mut *transaction,
+                        challenge.user_id,
+                        settings.session_ttl_hours,
+                    )
+                    .await?,
+                )
+            }
+            None if challenge.n_failed_attempts + 1 >= MAX_CHALLENGE_ATTEMPTS => {
+                tracing::info!(
+                    user_id = %challenge.user_id,
+                    "Too many wrong codes, dropped a login challenge."
+                );
+                sqlx::query!(
+                    r#"DELETE FROM login_challenges WHERE challenge_id = $1"#,
+                    challenge.challenge_id
+                )
+                .execute(&mut *transaction)
+                .await?;
+                None
+            }
+            None => {
+                sqlx::query!(
+                    r#"
+                    UPDATE login_challenges SET n_failed_attempts = n_failed_attempts + 1
+                    WHERE challenge_id = $1
+                    "#,
+                    challenge.challenge_id
+                )
+                .execute(&mut *transaction)
+                .await?;
+                None
+            }
+        };
+        transaction.commit().await?;
+        Ok::<_, sqlx::Error>(session)
+    }
+    .await;
+    match outcome {
+        Ok(Some(session)) => HttpResponse::Created().json(session),
+        Ok(None) => HttpResponse::Unauthorized().finish(),
+        Err(e) => {
+            tracing::error!("Failed to execute query: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// End the session the request was sent with.
+#[tracing::instrument(name = "Log out", skip(req, pool))]
+pub async fn log_out(req: HttpRequest, pool: web::Data<PgPool>) -> HttpResponse {
+    let Some(token) = bearer_token(req.headers()) else {
+        return HttpResponse::Unauthorized().finish();
+    };
+    match delete_session(&pool, token).await {
+        Ok(true) => HttpResponse::NoContent().finish(),
+        Ok(false) => HttpResponse::Unauthorized().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
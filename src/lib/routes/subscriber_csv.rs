@@ -0,0 +1,203 @@
+use crate::lib::audit::record_action;
+use crate::lib::authentication::Authorized;
+use crate::lib::configurations::SubscriptionSettings;
+use crate::lib::localization::Translations;
+use crate::lib::roles::ManageSubscribers;
+use crate::lib::subscriber_csv::{
+    ExportRow, IMPORT_COMPLETED, IMPORT_FAILED, ImportMode, SubscriberImporter, UnfinishedImport,
+    export_chunk, get_imports, start_import,
+};
+use crate::lib::subscribers::{Cursor, SubscriberFilters, list_subscribers};
+use actix_web::http::header::CONTENT_DISPOSITION;
+use actix_web::web::Bytes;
+use actix_web::{HttpResponse, web};
+use futures_util::StreamExt;
+use sqlx::PgPool;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// The export reads subscribers from the database this many at a time.
+const EXPORT_PAGE_SIZE: i64 = 1000;
+
+#[derive(Debug, serde::Deserialize)]
+pub struct ImportParameters {
+    mode: ImportMode,
+}
+
+/// Import subscribers from a CSV file with `email` and `name` columns.
+///
+/// The file is read as it is uploaded. Its progress can be followed with
+/// `GET /admin/subscribers/imports` meanwhile. Confirmation emails are sent
+/// in the background, once the rows are in.
+#[tracing::instrument(
+    name = "Import subscribers",
+    skip(user, payload, pool, settings, translations)
+)]
+pub async fn import_subscribers(
+    user: Authorized<ManageSubscribers>,
+    parameters: web::Query<ImportParameters>,
+    mut payload: web::Payload,
+    pool: web::Data<PgPool>,
+    settings: web::Data<SubscriptionSettings>,
+    translations: web::Data<Translations>,
+) -> HttpResponse {
+    let user_id = user.user_id;
+
+    let mode = parameters.mode;
+    let import_id = match start_import(&pool, user_id, mode).await {
+        Ok(import_id) => import_id,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let _unfinished = UnfinishedImport::new(pool.get_ref().clone(), import_id);
+    let mut importer = SubscriberImporter::new(
+        import_id,
+        mode,
+        translations.default_locale(),
+        settings.provider_aware_canonicalization,
+    );
+
+    while let Some(chunk) = payload.next().await {
+        let fed = match chunk {
+            Ok(chunk) => importer.feed(&chunk),
+            Err(e) => Err(format!("Failed to read the file: {}", e)),
+        };
+        if let Err(e) = fed {
+            return failed(&pool, &importer, e).await;
+        }
+        if importer.batch_is_full() && importer.flush(&pool).await.is_err() {
+            return HttpResponse::InternalServerError().finish();
+        }
+    }
+    if let Err(e) = importer.finish() {
+        return failed(&pool, &importer, e).await;
+    }
+    if importer.flush(&pool).await.is_err()
+        || importer.complete(&pool, IMPORT_COMPLETED).await.is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    match get_imports(&pool, Some(import_id)).await {
+        Ok(mut imports) if !imports.is_empty() => {
+            let import = imports.remove(0);
+            let audited = record_action(
+                pool.get_ref(),
+                Some(user_id),
+                "subscribers.imported",
+                None,
+                serde_json::json!({
+                    "import_id": import_id,
+                    "mode": mode.as_str(),
+                    "imported": import.imported,
+                }),
+            )
+            .await;
+            match audited {
+                Ok(()) => HttpResponse::Ok().json(import),
+                Err(_) => HttpResponse::InternalServerError().finish(),
+            }
+        }
+        _ => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// The file could not be read: the rows imported so far stay.
+async fn failed(pool: &PgPool, importer: &SubscriberImporter, error: String) -> HttpResponse {
+    tracing::warn!(%error, "Gave up on an import.");
+    match importer.complete(pool, IMPORT_FAILED).await {
+        Ok(()) => HttpResponse::BadRequest().body(error),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// Every import, the latest first.
+#[tracing::instrument(name = "List imports", skip(_user, pool))]
+pub async fn list_imports(
+    _user: Authorized<ManageSubscribers>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    match get_imports(&pool, None).await {
+        Ok(imports) => HttpResponse::Ok().json(imports),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[tracing::instrument(name = "Get an import", skip(_user, pool))]
+pub async fn get_import(
+    _user: Authorized<ManageSubscribers>,
+    path: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    match get_imports(&pool, Some(path.into_inner())).await {
+        Ok(mut imports) => match imports.pop() {
+            Some(import) => HttpResponse::Ok().json(import),
+            None => HttpResponse::NotFound().finish(),
+        },
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// The subscribers matching the same filters as the listing, as a CSV file
+/// written as it is read from the database.
+#[tracing::instrument(name = "Export subscribers", skip(user, pool))]
+pub async fn export_subscribers(
+    user: Authorized<ManageSubscribers>,
+    filters: web::Query<SubscriberFilters>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let user_id = user.user_id;
+    let filters = filters.into_inner();
+    if record_action(
+        pool.get_ref(),
+        Some(user_id),
+        "subscribers.exported",
+        None,
+        serde_json::json!({ "filters": &filters }),
+    )
+    .await
+    .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    // `None` once the last page is out.
+    let first_page = Some((None::<Cursor>, true));
+    let pool = pool.into_inner();
+    let filters = Arc::new(filters);
+    let chunks = futures_util::stream::unfold(first_page, move |page| {
+        let pool = Arc::clone(&pool);
+        let filters = Arc::clone(&filters);
+        async move {
+            let (after, first) = page?;
+            let subscribers =
+                match list_subscribers(&pool, &filters, after.as_ref(), EXPORT_PAGE_SIZE).await {
+                    Ok(subscribers) => subscribers,
+                    Err(e) => {
+                        return Some((Err(actix_web::error::ErrorInternalServerError(e)), None));
+                    }
+                };
+            let next = (subscribers.len() as i64 == EXPORT_PAGE_SIZE)
+                .then(|| subscribers.last())
+                .flatten()
+                .map(|last| {
+                    let cursor = Cursor {
+                        subscribed_at: last.subscribed_at,
+                        id: last.id,
+                    };
+                    (Some(cursor), false)
+                });
+            let rows = subscribers.into_iter().map(ExportRow::from).collect();
+            let chunk = export_chunk(rows, first)
+                .map(Bytes::from)
+                .map_err(actix_web::error::ErrorInternalServerError);
+            Some((chunk, next))
+        }
+    });
+    HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .insert_header((
+            CONTENT_DISPOSITION,
+            r#"attachment; filename="subscribers.csv""#,
+        ))
+        .streaming(chunks)
+}
@@ -1,104 +1,514 @@
-﻿use crate::lib::domain::{NewSubscriber, SubscriberEmail, SubscriberName};
+use crate::lib::bot_protection::{BotCheck, BotProtection, BotRejection};
+use crate::lib::configurations::{BrandingSettings, OptIn, SubscriptionSettings};
+use crate::lib::cors::EmbeddingOrigin;
+use crate::lib::domain::{
+    EmailDomainError, EmailDomainPolicy, NewSubscriber, SubscriberAttributes, SubscriberEmail,
+    SubscriberName,
+};
 use crate::lib::email_client::EmailClient;
-use crate::lib::startup::ApplicationBaseUrl;
-use actix_web::{HttpResponse, web};
+use crate::lib::email_templates::EmailTemplates;
+use crate::lib::localization::{Translations, Translator, accept_language};
+use crate::lib::pages::{CheckInboxPage, ConfirmedPage, SignupPage, render};
+use crate::lib::routes::subscriptions_payload::{FieldError, NegotiatedPayload};
+use crate::lib::routes::{CONFIRMED, unsubscribe_link};
+use crate::lib::startup::{ApplicationBaseUrl, HmacSecret};
+use crate::lib::webhooks::{SUBSCRIBER_CONFIRMED, SUBSCRIBER_SUBSCRIBED, publish_subscriber_event};
+use crate::lib::welcome_series::enroll;
+use actix_web::http::StatusCode;
+use actix_web::http::header::USER_AGENT;
+use actix_web::{HttpMessage, HttpRequest, HttpResponse, web};
 use chrono::Utc;
-use sqlx::PgPool;
+use minijinja::{Value, context};
+use rand::Rng;
+use rand::distr::Alphanumeric;
+use serde_aux::field_attributes::deserialize_bool_from_anything;
+use sqlx::{PgPool, Postgres, Transaction};
+use std::collections::HashMap;
+use uuid::Uuid;
 
-#[derive(serde::Deserialize)]
+/// The status of a subscriber who still has to click the confirmation link.
+pub const PENDING_CONFIRMATION: &str = "pending_confirmation";
+
+#[derive(Clone, serde::Deserialize)]
 pub struct FormData {
     name: String,
     email: String,
+    /// Honeypot: hidden from humans, so only bots fill it in.
+    #[serde(default)]
+    website: Option<String>,
+    #[serde(default)]
+    form_token: Option<String>,
+    #[serde(default)]
+    pow_nonce: Option<String>,
+    /// Set when the subscriber insists on a domain we suggested a fix for.
+    /// Forms send it as a string: `flatten` leaves it to us to convert.
+    #[serde(default, deserialize_with = "deserialize_bool_from_anything")]
+    keep_email_domain: bool,
+    /// Overrides the `Accept-Language` header.
+    #[serde(default)]
+    locale: Option<String>,
+    /// The slug of the list to sign up to.
+    #[serde(default)]
+    list: Option<String>,
+    /// Hidden `attributes.<name>` form fields, or an `attributes` JSON object.
+    #[serde(flatten)]
+    extra: HashMap<String, serde_json::Value>,
+}
+
+impl FormData {
+    fn bot_check(&self) -> BotCheck<'_> {
+        BotCheck {
+            honeypot: self.website.as_deref(),
+            form_token: self.form_token.as_deref(),
+            pow_nonce: self.pow_nonce.as_deref(),
+        }
+    }
+
+    /// The attributes the signup form may set, out of the ones it sent.
+    fn attributes(&self, allowed: &[String]) -> Result<SubscriberAttributes, String> {
+        let mut attributes = serde_json::Map::new();
+        if let Some(serde_json::Value::Object(object)) = self.extra.get("attributes") {
+            attributes.extend(object.clone());
+        }
+        for (field, value) in &self.extra {
+            if let Some(key) = field.strip_prefix("attributes.") {
+                attributes.insert(key.to_owned(), value.clone());
+            }
+        }
+        attributes.retain(|key, _| {
+            let is_allowed = allowed.contains(key);
+            if !is_allowed {
+                tracing::info!(attribute = %key, "Ignored an attribute the signup form cannot set.");
+            }
+            is_allowed
+        });
+        SubscriberAttributes::parse(serde_json::Value::Object(attributes))
+    }
+}
+
+/// The list a signup is for.
+struct SignupList {
+    list_id: Uuid,
+    /// `None` follows the deployment setting.
+    opt_in: Option<String>,
+}
+
+/// How a subscriber agreed to receive emails.
+pub struct Consent<'a> {
+    pub list_id: Option<Uuid>,
+    pub opt_in: OptIn,
+    /// The site the signup form was embedded in, if any.
+    pub source: Option<&'a str>,
+    pub ip_address: Option<&'a str>,
+    pub user_agent: Option<&'a str>,
 }
 
 impl TryFrom<FormData> for NewSubscriber {
-    type Error = String;
+    type Error = Vec<FieldError>;
     fn try_from(form: FormData) -> Result<Self, Self::Error> {
-        let name = SubscriberName::parse(&form.name)?;
-        let email = SubscriberEmail::parse(&form.email)?;
-        Ok(NewSubscriber { email, name })
+        // Report all the invalid fields at once.
+        match (
+            SubscriberName::parse(&form.name),
+            SubscriberEmail::parse(&form.email),
+        ) {
+            (Ok(name), Ok(email)) => Ok(NewSubscriber {
+                email,
+                name,
+                attributes: SubscriberAttributes::default(),
+            }),
+            (name, email) => Err([
+                name.err().map(|e| FieldError::new("name", "invalid", e)),
+                email.err().map(|e| FieldError::new("email", "invalid", e)),
+            ]
+            .into_iter()
+            .flatten()
+            .collect()),
+        }
     }
 }
 
+impl From<EmailDomainError> for FieldError {
+    fn from(e: EmailDomainError) -> Self {
+        let message = e.to_string();
+        match e {
+            EmailDomainError::Disposable { .. } => {
+                FieldError::new("email", "disposable_domain", message)
+            }
+            EmailDomainError::PossibleTypo { suggestion } => FieldError {
+                suggestion: Some(suggestion),
+                ..FieldError::new("email", "possible_typo", message)
+            },
+        }
+    }
+}
+
+/// Form and JSON clients share the endpoint: the `Content-Type` of the request
+/// decides how the body is parsed and how we answer.
+#[allow(clippy::too_many_arguments)]
 #[tracing::instrument(name = "Adding a new subscriber",
-	skip(form, pool, email_client, base_url),
+	skip(req, payload, pool, email_client, base_url, hmac_secret, bot_protection, settings, email_domain_policy, branding, translations, email_templates),
 	fields(
-		subscriber_email = %form.email,
-		subscriber_name = %form.name))]
+		subscriber_email = %payload.data.email,
+		subscriber_name = %payload.data.name))]
 pub async fn subscribe(
-    form: web::Form<FormData>,
+    req: HttpRequest,
+    payload: NegotiatedPayload<FormData>,
     pool: web::Data<PgPool>,
     email_client: web::Data<EmailClient>,
     base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
+    bot_protection: web::Data<BotProtection>,
+    settings: web::Data<SubscriptionSettings>,
+    email_domain_policy: web::Data<EmailDomainPolicy>,
+    branding: web::Data<BrandingSettings>,
+    translations: web::Data<Translations>,
+    email_templates: web::Data<EmailTemplates>,
 ) -> HttpResponse {
+    let NegotiatedPayload { format, data: form } = payload;
+    let locale = translations.negotiate(form.locale.as_deref(), accept_language(&req));
+    let t = translations.translator(&locale);
+    let check_inbox = |email: &str| {
+        let page = CheckInboxPage {
+            branding: &branding,
+            t,
+            email,
+        };
+        render(&page, StatusCode::OK)
+    };
+    let confirmed = |subscriber_id: Uuid| {
+        let page = ConfirmedPage {
+            branding: &branding,
+            t,
+            unsubscribe_link: unsubscribe_link(&base_url.0, &hmac_secret.0, subscriber_id),
+        };
+        render(&page, StatusCode::OK)
+    };
+    let signup_again = |form: &FormData, errors: Vec<FieldError>| {
+        let suggestion = errors.iter().find_map(|e| e.suggestion.clone());
+        let page = SignupPage {
+            branding: &branding,
+            t,
+            challenge: bot_protection.issue(Utc::now().timestamp()),
+            name: &form.name,
+            email: &form.email,
+            errors: errors.into_iter().map(|e| e.message).collect(),
+            suggestion,
+        };
+        render(&page, StatusCode::BAD_REQUEST)
+    };
+
+    let list = match &form.list {
+        Some(slug) => match get_signup_list(&pool, slug).await {
+            Ok(Some(list)) => Some(list),
+            Ok(None) => {
+                let errors = vec![FieldError::new(
+                    "list",
+                    "unknown",
+                    "There is no such list.".into(),
+                )];
+                return format.rejected(errors, |e| signup_again(&form, e));
+            }
+            Err(_) => return HttpResponse::InternalServerError().finish(),
+        },
+        None => None,
+    };
+    let opt_in = list
+        .as_ref()
+        .and_then(|list| list.opt_in.as_deref())
+        .and_then(|opt_in| OptIn::try_from(opt_in).ok())
+        .unwrap_or(settings.opt_in);
+    // The same response whether the subscriber is new or not, so the form
+    // can't be used to find out who is on the list.
+    let accepted = |subscriber_id: Uuid, email: &str| match opt_in {
+        OptIn::Single => format.accepted(subscriber_id, CONFIRMED, || confirmed(subscriber_id)),
+        OptIn::Double => {
+            format.accepted(subscriber_id, PENDING_CONFIRMATION, || check_inbox(email))
+        }
+    };
+
+    // Bots get the same response as humans, so they can't learn what gave
+    // them away.
+    let caught_bot = |reason: BotRejection| {
+        let total_rejections = bot_protection.record_rejection();
+        tracing::warn!(%reason, total_rejections, "Rejected a suspected bot signup.");
+        accepted(Uuid::new_v4(), &form.email)
+    };
+    let form_token = match bot_protection.verify(&form.bot_check(), Utc::now().timestamp()) {
+        Ok(form_token) => form_token,
+        Err(reason) => return caught_bot(reason),
+    };
+
     // Parse subscriber.
-    let new_subscriber = match form.0.try_into() {
+    let mut new_subscriber: NewSubscriber = match form.clone().try_into() {
         Ok(form) => form,
-        Err(_) => return HttpResponse::BadRequest().finish(),
+        Err(errors) => return format.rejected(errors, |e| signup_again(&form, e)),
     };
+    match form.attributes(&settings.signup_attributes) {
+        Ok(attributes) => new_subscriber.attributes = attributes,
+        Err(e) => {
+            let errors = vec![FieldError::new("attributes", "invalid", e)];
+            return format.rejected(errors, |e| signup_again(&form, e));
+        }
+    }
+    match email_domain_policy.check(&new_subscriber.email) {
+        Ok(()) => {}
+        Err(EmailDomainError::PossibleTypo { .. }) if form.keep_email_domain => {}
+        Err(e) => {
+            tracing::info!(error = %e, "Refused the subscriber email domain.");
+            return format.rejected(vec![e.into()], |e| signup_again(&form, e));
+        }
+    }
 
-    if insert_subscriber(&new_subscriber, &pool).await.is_err() {
+    let canonical_email = new_subscriber
+        .email
+        .canonical(settings.provider_aware_canonicalization);
+    let mut transaction = match pool.begin().await {
+        Ok(transaction) => transaction,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    if let Some(form_token) = &form_token {
+        match form_token.spend(&mut transaction).await {
+            Ok(true) => {}
+            Ok(false) => return caught_bot(BotRejection::Replayed),
+            Err(_) => return HttpResponse::InternalServerError().finish(),
+        }
+    }
+    let subscriber_id = match insert_subscriber(
+        &new_subscriber,
+        &canonical_email,
+        &locale,
+        opt_in,
+        &mut transaction,
+    )
+    .await
+    {
+        Ok(Some(subscriber_id)) => subscriber_id,
+        Ok(None) => {
+            tracing::info!("The subscriber is already on the list.");
+            // The form token is spent all the same.
+            if transaction.commit().await.is_err() {
+                return HttpResponse::InternalServerError().finish();
+            }
+            return accepted(Uuid::new_v4(), new_subscriber.email.as_ref());
+        }
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let ip_address = req
+        .connection_info()
+        .realip_remote_addr()
+        .map(str::to_owned);
+    let source = req
+        .extensions()
+        .get::<EmbeddingOrigin>()
+        .map(|origin| origin.0.clone());
+    let consent = Consent {
+        list_id: list.as_ref().map(|list| list.list_id),
+        opt_in,
+        source: source.as_deref(),
+        ip_address: ip_address.as_deref(),
+        user_agent: req
+            .headers()
+            .get(USER_AGENT)
+            .and_then(|user_agent| user_agent.to_str().ok()),
+    };
+    if record_consent(&mut transaction, subscriber_id, &consent)
+        .await
+        .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+    let details = serde_json::json!({ "list": form.list, "source": source });
+    if publish_subscriber_event(
+        &mut transaction,
+        SUBSCRIBER_SUBSCRIBED,
+        subscriber_id,
+        details.clone(),
+    )
+    .await
+    .is_err()
+    {
         return HttpResponse::InternalServerError().finish();
     }
-    if send_confirmation_email(email_client, new_subscriber, &base_url.0)
+    if opt_in == OptIn::Single {
+        if publish_subscriber_event(
+            &mut transaction,
+            SUBSCRIBER_CONFIRMED,
+            subscriber_id,
+            details,
+        )
+        .await
+        .is_err()
+        {
+            return HttpResponse::InternalServerError().finish();
+        }
+        if settings.single_opt_in_welcome_email
+            && enroll(&mut *transaction, subscriber_id).await.is_err()
+        {
+            return HttpResponse::InternalServerError().finish();
+        }
+        if transaction.commit().await.is_err() {
+            return HttpResponse::InternalServerError().finish();
+        }
+        return accepted(subscriber_id, new_subscriber.email.as_ref());
+    }
+
+    let subscription_token = generate_subscription_token();
+    if store_token(&mut transaction, subscriber_id, &subscription_token)
         .await
         .is_err()
     {
         return HttpResponse::InternalServerError().finish();
     }
+    if transaction.commit().await.is_err() {
+        return HttpResponse::InternalServerError().finish();
+    }
+    if send_confirmation_email(
+        &email_client,
+        &email_templates,
+        &new_subscriber,
+        &base_url.0,
+        &subscription_token,
+        t,
+    )
+    .await
+    .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    accepted(subscriber_id, new_subscriber.email.as_ref())
+}
 
-    HttpResponse::Ok().finish()
+/// Hand out a signed form-render timestamp (and proof-of-work challenge) for
+/// the signup form to submit along with the subscriber details.
+pub async fn form_token(bot_protection: web::Data<BotProtection>) -> HttpResponse {
+    HttpResponse::Ok().json(bot_protection.issue(Utc::now().timestamp()))
+}
+
+/// Generate a random 25-characters-long case-sensitive subscription token.
+pub fn generate_subscription_token() -> String {
+    let mut rng = rand::rng();
+    std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(25)
+        .collect()
+}
+
+/// The link a pending subscriber clicks to confirm their subscription.
+pub fn confirmation_link(base_url: &str, subscription_token: &str) -> String {
+    format!(
+        "{}/subscriptions/confirm?subscription_token={}",
+        base_url, subscription_token
+    )
 }
 
 #[tracing::instrument(
     name = "Send a confirmation email to a new subscriber",
-    skip(email_client, new_subscriber, base_url)
+    skip(email_client, email_templates, new_subscriber, base_url, subscription_token, t),
+    fields(locale = %t.locale())
 )]
 pub async fn send_confirmation_email(
-    email_client: web::Data<EmailClient>,
-    new_subscriber: NewSubscriber,
+    email_client: &EmailClient,
+    email_templates: &EmailTemplates,
+    new_subscriber: &NewSubscriber,
     base_url: &str,
-) -> Result<(), reqwest::Error> {
-    let confirmation_link = format!(
-        "{}/subscriptions/confirm?{}",
-        base_url, "subscription_token=mytoken"
-    );
-    let html_body = format!(
-        r#"Welcome to our newsletter!<br />
-		Click <a href="{}">here</a> to confirm your subscriptions"#,
-        confirmation_link
-    );
-    let plain_body = format!(
-        r#"Welcome to our newsletter!
-		Visit {} to confirm your subscriptions"#,
-        confirmation_link
-    );
+    subscription_token: &str,
+    t: Translator<'_>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let email = email_templates
+        .render(
+            "confirmation",
+            t.locale(),
+            context! {
+                name => new_subscriber.name.as_ref(),
+                // Built by us out of URL-safe characters: no need to escape it.
+                confirmation_link => Value::from_safe_string(
+                    confirmation_link(base_url, subscription_token)
+                ),
+            },
+        )
+        .map_err(|e| {
+            tracing::error!(error = %e, "Failed to render the confirmation email.");
+            e
+        })?;
 
     email_client
-        .send_email(new_subscriber.email, "Welcome!", &html_body, &plain_body)
-        .await
+        .send_email(
+            &new_subscriber.email,
+            &t.get("confirmation-email-subject"),
+            &email.html,
+            &email.text,
+        )
+        .await?;
+    Ok(())
 }
 
+/// Returns `None` if somebody with the same canonical email has already
+/// subscribed. Single opt-in subscribers are confirmed straight away.
 #[tracing::instrument(
     name = "Saving new subscriber details in the database",
-    skip(new_subscriber, pool)
+    skip(new_subscriber, canonical_email, transaction)
 )]
 pub async fn insert_subscriber(
     new_subscriber: &NewSubscriber,
-    pool: &PgPool,
-) -> Result<(), sqlx::Error> {
-    sqlx::query!(
+    canonical_email: &str,
+    locale: &str,
+    opt_in: OptIn,
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let now = Utc::now();
+    let (status, confirmed_at) = match opt_in {
+        OptIn::Single => (CONFIRMED, Some(now)),
+        OptIn::Double => (PENDING_CONFIRMATION, None),
+    };
+    let subscriber_id = sqlx::query_scalar!(
         r#"
-        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
-        VALUES ($1, $2, $3, $4, $5)
+        INSERT INTO subscriptions
+            (id, email, email_canonical, name, subscribed_at, status, locale, attributes,
+                confirmed_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+        ON CONFLICT (email_canonical) DO NOTHING
+        RETURNING id
         "#,
-        uuid::Uuid::new_v4(),
+        Uuid::new_v4(),
         new_subscriber.email.as_ref(),
+        canonical_email,
         new_subscriber.name.as_ref(),
-        Utc::now(),
-        "pending_confirmation"
+        now,
+        status,
+        locale,
+        new_subscriber.attributes.clone().into_json(),
+        confirmed_at
+    )
+    .fetch_optional(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+
+    Ok(subscriber_id)
+}
+
+#[tracing::instrument(
+    name = "Store subscription token in the database",
+    skip(subscription_token, transaction)
+)]
+pub async fn store_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    subscription_token: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO subscription_tokens (subscription_token, subscriber_id, created_at)
+        VALUES ($1, $2, $3)
+        "#,
+        subscription_token,
+        subscriber_id,
+        Utc::now()
     )
-    .execute(pool)
+    .execute(&mut **transaction)
     .await
     .map_err(|e| {
         tracing::error!("Failed to execute query: {:?}", e);
@@ -107,3 +517,74 @@ pub async fn insert_subscriber(
 
     Ok(())
 }
+
+#[tracing::instrument(name = "Get the list of a signup", skip(pool))]
+async fn get_signup_list(pool: &PgPool, slug: &str) -> Result<Option<SignupList>, sqlx::Error> {
+    sqlx::query_as!(
+        SignupList,
+        r#"SELECT list_id, opt_in FROM lists WHERE slug = $1"#,
+        slug
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })
+}
+
+/// Keep a record of the signup, and add the subscriber to its list if any.
+#[tracing::instrument(
+    name = "Record the consent of a subscriber",
+    skip(transaction, consent)
+)]
+pub async fn record_consent(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    consent: &Consent<'_>,
+) -> Result<(), sqlx::Error> {
+    let method = match consent.opt_in {
+        OptIn::Single => "single_opt_in",
+        OptIn::Double => "double_opt_in",
+    };
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriber_consents
+            (consent_id, subscriber_id, list_id, method, ip_address, user_agent, source,
+                given_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, now())
+        "#,
+        Uuid::new_v4(),
+        subscriber_id,
+        consent.list_id,
+        method,
+        consent.ip_address,
+        consent.user_agent,
+        consent.source
+    )
+    .execute(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    if let Some(list_id) = consent.list_id {
+        sqlx::query!(
+            r#"
+            INSERT INTO list_subscriptions (list_id, subscriber_id, added_at)
+            VALUES ($1, $2, now())
+            ON CONFLICT DO NOTHING
+            "#,
+            list_id,
+            subscriber_id
+        )
+        .execute(&mut **transaction)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to execute query: {:?}", e);
+            e
+        })?;
+    }
+
+    Ok(())
+}
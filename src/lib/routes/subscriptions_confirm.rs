@@ -1,13 +1,154 @@
-﻿use actix_web::{HttpResponse, web};
+use crate::lib::configurations::{BrandingSettings, SubscriptionSettings};
+use crate::lib::localization::{Translations, accept_language};
+use crate::lib::pages::{ConfirmedPage, InvalidTokenPage, render};
+use crate::lib::routes::{PENDING_CONFIRMATION, unsubscribe_link};
+use crate::lib::startup::{ApplicationBaseUrl, HmacSecret};
+use crate::lib::webhooks::{SUBSCRIBER_CONFIRMED, publish_subscriber_event};
+use crate::lib::welcome_series::enroll;
+use actix_web::http::StatusCode;
+use actix_web::{HttpRequest, HttpResponse, web};
+use chrono::{DateTime, Duration, Utc};
 use serde::Deserialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// The status of a subscriber who clicked the confirmation link.
+pub const CONFIRMED: &str = "confirmed";
 
-#[allow(unused)]
 #[derive(Deserialize)]
 pub struct Parameters {
     subscription_token: String,
 }
 
-#[tracing::instrument(name = "Confirm a pending subscriber", skip(_parameters))]
-pub async fn confirm(_parameters: web::Query<Parameters>) -> HttpResponse {
-    HttpResponse::Ok().finish()
+struct StoredToken {
+    subscriber_id: Uuid,
+    created_at: DateTime<Utc>,
+    /// The locale the subscriber signed up in.
+    locale: String,
+}
+
+#[tracing::instrument(
+    name = "Confirm a pending subscriber",
+    skip(
+        req,
+        parameters,
+        pool,
+        settings,
+        branding,
+        base_url,
+        hmac_secret,
+        translations
+    )
+)]
+#[allow(clippy::too_many_arguments)]
+pub async fn confirm(
+    req: HttpRequest,
+    parameters: web::Query<Parameters>,
+    pool: web::Data<PgPool>,
+    settings: web::Data<SubscriptionSettings>,
+    branding: web::Data<BrandingSettings>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
+    translations: web::Data<Translations>,
+) -> HttpResponse {
+    let token = match get_stored_token(&pool, &parameters.subscription_token).await {
+        Ok(token) => token,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let token = match token {
+        Some(token) => token,
+        None => {
+            let locale = translations.negotiate(None, accept_language(&req));
+            let page = InvalidTokenPage {
+                branding: &branding,
+                t: translations.translator(&locale),
+                expired: false,
+            };
+            return render(&page, StatusCode::UNAUTHORIZED);
+        }
+    };
+    let t = translations.translator(&token.locale);
+    let ttl = Duration::hours(settings.confirmation_token_ttl_hours);
+    if token.created_at + ttl < Utc::now() {
+        let page = InvalidTokenPage {
+            branding: &branding,
+            t,
+            expired: true,
+        };
+        return render(&page, StatusCode::GONE);
+    }
+
+    if confirm_subscriber(&pool, token.subscriber_id)
+        .await
+        .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    let page = ConfirmedPage {
+        branding: &branding,
+        t,
+        unsubscribe_link: unsubscribe_link(&base_url.0, &hmac_secret.0, token.subscriber_id),
+    };
+    render(&page, StatusCode::OK)
+}
+
+/// Clicking an old link again must not bring back somebody who unsubscribed
+/// in the meantime. Subscribers who just confirmed start the welcome series.
+#[tracing::instrument(name = "Mark subscriber as confirmed", skip(pool))]
+pub async fn confirm_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<(), sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    let confirmed = sqlx::query!(
+        r#"
+        UPDATE subscriptions SET status = $1, confirmed_at = now()
+        WHERE id = $2 AND status = $3
+        "#,
+        CONFIRMED,
+        subscriber_id,
+        PENDING_CONFIRMATION
+    )
+    .execute(&mut *transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    if confirmed.rows_affected() == 1 {
+        enroll(&mut *transaction, subscriber_id).await?;
+        publish_subscriber_event(
+            &mut transaction,
+            SUBSCRIBER_CONFIRMED,
+            subscriber_id,
+            serde_json::json!({}),
+        )
+        .await?;
+    }
+    transaction.commit().await?;
+
+    Ok(())
+}
+
+#[tracing::instrument(name = "Get subscriber_id from token", skip(subscription_token, pool))]
+async fn get_stored_token(
+    pool: &PgPool,
+    subscription_token: &str,
+) -> Result<Option<StoredToken>, sqlx::Error> {
+    let token = sqlx::query_as!(
+        StoredToken,
+        r#"
+        SELECT t.subscriber_id, t.created_at, s.locale
+        FROM subscription_tokens t
+        JOIN subscriptions s ON s.id = t.subscriber_id
+        WHERE t.subscription_token = $1
+        "#,
+        subscription_token
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+
+    Ok(token)
 }
@@ -0,0 +1,160 @@
+use actix_web::dev::Payload;
+use actix_web::http::header::ContentType;
+use actix_web::{FromRequest, HttpMessage, HttpRequest, HttpResponse, ResponseError, web};
+use serde::de::DeserializeOwned;
+use std::future::Future;
+use std::pin::Pin;
+use uuid::Uuid;
+
+/// How the client talks to us: responses mirror the request `Content-Type`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PayloadFormat {
+    Form,
+    Json,
+}
+
+impl PayloadFormat {
+    fn of(req: &HttpRequest) -> Self {
+        let content_type = req.content_type();
+        if content_type == "application/json" || content_type.ends_with("+json") {
+            PayloadFormat::Json
+        } else {
+            PayloadFormat::Form
+        }
+    }
+
+    /// The subscription has been taken into account. Form clients get `page`.
+    pub fn accepted(
+        &self,
+        subscriber_id: Uuid,
+        status: &str,
+        page: impl FnOnce() -> HttpResponse,
+    ) -> HttpResponse {
+        match self {
+            PayloadFormat::Form => page(),
+            PayloadFormat::Json => HttpResponse::Ok().json(SubscriptionAccepted {
+                subscriber_id,
+                status,
+            }),
+        }
+    }
+
+    /// The subscription details did not pass validation. Form clients get
+    /// `page`, to show the form again along with the errors.
+    pub fn rejected(
+        &self,
+        errors: Vec<FieldError>,
+        page: impl FnOnce(Vec<FieldError>) -> HttpResponse,
+    ) -> HttpResponse {
+        match self {
+            PayloadFormat::Form => page(errors),
+            PayloadFormat::Json => HttpResponse::BadRequest().json(ValidationErrors { errors }),
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+struct SubscriptionAccepted<'a> {
+    subscriber_id: Uuid,
+    status: &'a str,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct ValidationErrors {
+    errors: Vec<FieldError>,
+}
+
+/// A single validation failure, reported to JSON clients as-is.
+#[derive(Debug, serde::Serialize)]
+pub struct FieldError {
+    /// The offending field, if the failure can be pinned on one.
+    pub field: Option<&'static str>,
+    /// A machine-readable error code, e.g. `invalid` or `possible_typo`.
+    pub code: &'static str,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub suggestion: Option<String>,
+}
+
+impl FieldError {
+    pub fn new(field: &'static str, code: &'static str, message: String) -> Self {
+        Self {
+            field: Some(field),
+            code,
+            message,
+            suggestion: None,
+        }
+    }
+}
+
+/// A request body that could not be deserialized at all.
+#[derive(Debug)]
+pub struct MalformedPayload {
+    format: PayloadFormat,
+    message: String,
+}
+
+impl std::fmt::Display for MalformedPayload {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl ResponseError for MalformedPayload {
+    fn error_response(&self) -> HttpResponse {
+        match self.format {
+            // Keep the framework's plain 400 for form clients.
+            PayloadFormat::Form => HttpResponse::BadRequest()
+                .insert_header(ContentType::plaintext())
+                .body(self.message.clone()),
+            PayloadFormat::Json => HttpResponse::BadRequest().json(ValidationErrors {
+                errors: vec![FieldError {
+                    field: None,
+                    code: "malformed_body",
+                    message: self.message.clone(),
+                    suggestion: None,
+                }],
+            }),
+        }
+    }
+}
+
+/// The body of a request, either `application/x-www-form-urlencoded` or
+/// `application/json` depending on its `Content-Type`.
+pub struct NegotiatedPayload<T> {
+    pub format: PayloadFormat,
+    pub data: T,
+}
+
+impl<T> FromRequest for NegotiatedPayload<T>
+where
+    T: DeserializeOwned + 'static,
+{
+    type Error = MalformedPayload;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let format = PayloadFormat::of(req);
+        let malformed = move |e: actix_web::Error| MalformedPayload {
+            format,
+            message: e.to_string(),
+        };
+
+        match format {
+            PayloadFormat::Form => {
+                let form = web::Form::<T>::from_request(req, payload);
+                Box::pin(async move {
+                    let data = form.await.map_err(malformed)?.into_inner();
+                    Ok(Self { format, data })
+                })
+            }
+            PayloadFormat::Json => {
+                let json = web::Json::<T>::from_request(req, payload);
+                Box::pin(async move {
+                    let data = json.await.map_err(malformed)?.into_inner();
+                    Ok(Self { format, data })
+                })
+            }
+        }
+    }
+}
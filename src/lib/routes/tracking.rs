@@ -0,0 +1,119 @@
+use crate::lib::startup::HmacSecret;
+use crate::lib::tracking::{CLICK, OPEN, PIXEL, TrackingToken, is_automated, link_hash};
+use actix_web::http::header::{CACHE_CONTROL, LOCATION, USER_AGENT};
+use actix_web::{HttpRequest, HttpResponse, web};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// The pixel at the end of a tracked issue.
+#[tracing::instrument(name = "Track an open", skip(req, path, pool, hmac_secret))]
+pub async fn track_open(
+    req: HttpRequest,
+    path: web::Path<String>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> HttpResponse {
+    let Some(token) = TrackingToken::decode(&path, OPEN, &hmac_secret.0) else {
+        return HttpResponse::NotFound().finish();
+    };
+    if !is_automated(&req) {
+        // Whatever happens, the image is shown.
+        let _ = record_event(&pool, &req, &token, OPEN).await;
+    }
+
+    HttpResponse::Ok()
+        .content_type("image/gif")
+        .insert_header((CACHE_CONTROL, "no-store"))
+        .body(PIXEL)
+}
+
+/// Where the links of a tracked issue point to.
+///
+/// The token is signed, and its link must also be one the issue was sent
+/// with: even with the secret, this cannot be used to send anybody anywhere
+/// else.
+#[tracing::instrument(name = "Track a click", skip(req, path, pool, hmac_secret))]
+pub async fn track_click(
+    req: HttpRequest,
+    path: web::Path<String>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> HttpResponse {
+    let Some(token) = TrackingToken::decode(&path, CLICK, &hmac_secret.0) else {
+        return HttpResponse::NotFound().finish();
+    };
+    let url = token.url.as_deref().expect("Click tokens have a link.");
+    match is_in_issue(&pool, &token, url).await {
+        Ok(true) => {}
+        Ok(false) => {
+            tracing::warn!(%url, "Refused to redirect to a link that is not in the issue.");
+            return HttpResponse::NotFound().finish();
+        }
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    }
+    if !is_automated(&req) {
+        // Whatever happens, the reader gets where they want to go.
+        let _ = record_event(&pool, &req, &token, CLICK).await;
+    }
+
+    HttpResponse::Found()
+        .insert_header((LOCATION, url))
+        .insert_header((CACHE_CONTROL, "no-store"))
+        .finish()
+}
+
+/// Whether `url` is one of the links the issue of `token` was sent with.
+async fn is_in_issue(pool: &PgPool, token: &TrackingToken, url: &str) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM issue_links WHERE newsletter_issue_id = $1 AND url_hash = $2
+        ) AS "exists!"
+        "#,
+        token.newsletter_issue_id,
+        link_hash(url)
+    )
+    .fetch_one(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })
+}
+
+#[tracing::instrument(name = "Record an issue event", skip(pool, req, token))]
+async fn record_event(
+    pool: &PgPool,
+    req: &HttpRequest,
+    token: &TrackingToken,
+    kind: &str,
+) -> Result<(), sqlx::Error> {
+    let user_agent = req
+        .headers()
+        .get(USER_AGENT)
+        .and_then(|ua| ua.to_str().ok());
+    // Nothing is recorded for an issue or a subscriber that is gone.
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_events (
+            event_id, newsletter_issue_id, subscriber_id, kind, url, user_agent, occurred_at
+        )
+        SELECT $1, newsletter_issue_id, id, $4, $5, $6, now()
+        FROM newsletter_issues, subscriptions
+        WHERE newsletter_issue_id = $2 AND id = $3
+        "#,
+        Uuid::new_v4(),
+        token.newsletter_issue_id,
+        token.subscriber_id,
+        kind,
+        token.url,
+        user_agent
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(())
+}
@@ -0,0 +1,210 @@
+use crate::lib::audit::record_action;
+use crate::lib::authentication::Authorized;
+use crate::lib::configurations::{AdminUserSettings, BrandingSettings};
+use crate::lib::roles::ManageOwnAccount;
+use crate::lib::totp::TotpSecret;
+use crate::lib::two_factor::{generate_recovery_codes, store_recovery_codes, verify_second_factor};
+use actix_web::{HttpResponse, web};
+use chrono::Utc;
+use sqlx::PgPool;
+
+#[derive(serde::Deserialize)]
+pub struct CodeBody {
+    code: String,
+}
+
+/// What turning two-factor authentication on or off came to.
+enum TwoFactorChange<T> {
+    Done(T),
+    WrongCode,
+    /// Already on, or not on yet.
+    Conflict(&'static str),
+}
+
+/// Start turning two-factor authentication on: a new secret, to scan as a
+/// QR code of the provisioning URI. Nothing changes at login until a first
+/// code is confirmed.
+#[tracing::instrument(
+    name = "Start enrolling in two-factor authentication",
+    skip(user, pool, branding),
+    fields(user_id = %user.user_id)
+)]
+pub async fn start_two_factor(
+    user: Authorized<ManageOwnAccount>,
+    pool: web::Data<PgPool>,
+    branding: web::Data<BrandingSettings>,
+) -> HttpResponse {
+    let secret = TotpSecret::generate();
+    let username = match sqlx::query_scalar!(
+        r#"
+        UPDATE users SET totp_secret = $1
+        WHERE user_id = $2 AND totp_enabled_at IS NULL
+        RETURNING username
+        "#,
+        secret.to_base32(),
+        user.user_id
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    {
+        Ok(Some(username)) => username,
+        Ok(None) => {
+            return HttpResponse::Conflict().body("Two-factor authentication is already on.");
+        }
+        Err(e) => {
+            tracing::error!("Failed to execute query: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    HttpResponse::Ok().json(serde_json::json!({
+        "secret": secret.to_base32(),
+        "provisioning_uri": secret.provisioning_uri(&branding.site_name, &username),
+    }))
+}
+
+/// Turn two-factor authentication on with a first code from the
+/// authenticator app, and hand out the recovery codes. They are only ever
+/// shown here.
+#[tracing::instrument(
+    name = "Confirm two-factor authentication",
+    skip(user, body, pool),
+    fields(user_id = %user.user_id)
+)]
+pub async fn confirm_two_factor(
+    user: Authorized<ManageOwnAccount>,
+    body: web::Json<CodeBody>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let user_id = user.user_id;
+    let outcome = async {
+        let mut transaction = pool.begin().await?;
+        let row = sqlx::query!(
+            r#"
+            SELECT totp_secret, totp_enabled_at IS NOT NULL AS "enabled!"
+            FROM users WHERE user_id = $1
+            FOR UPDATE
+            "#,
+            user_id
+        )
+        .fetch_one(&mut *transaction)
+        .await?;
+        if row.enabled {
+            return Ok(TwoFactorChange::Conflict(
+                "Two-factor authentication is already on.",
+            ));
+        }
+        let Some(secret) = row.totp_secret.as_deref().and_then(TotpSecret::parse) else {
+            return Ok(TwoFactorChange::Conflict(
+                "Start enrolling at /admin/account/two-factor first.",
+            ));
+        };
+        let Some(step) = secret.verify(&body.code, Utc::now().timestamp()) else {
+            return Ok(TwoFactorChange::WrongCode);
+        };
+        sqlx::query!(
+            r#"
+            UPDATE users SET totp_enabled_at = now(), totp_last_used_step = $1
+            WHERE user_id = $2
+            "#,
+            step,
+            user_id
+        )
+        .execute(&mut *transaction)
+        .await?;
+        let recovery_codes = generate_recovery_codes();
+        store_recovery_codes(&mut transaction, user_id, &recovery_codes).await?;
+        record_action(
+            &mut *transaction,
+            Some(user_id),
+            "user.two_factor_enabled",
+            None,
+            serde_json::json!({}),
+        )
+        .await?;
+        transaction.commit().await?;
+        Ok::<_, sqlx::Error>(TwoFactorChange::Done(recovery_codes))
+    }
+    .await;
+    match outcome {
+        Ok(TwoFactorChange::Done(recovery_codes)) => {
+            HttpResponse::Ok().json(serde_json::json!({ "recovery_codes": recovery_codes }))
+        }
+        outcome => change_response(outcome),
+    }
+}
+
+/// Turn two-factor authentication off, with a last code. Not for users
+/// whose role requires it.
+#[tracing::instrument(
+    name = "Disable two-factor authentication",
+    skip(user, body, pool, settings),
+    fields(user_id = %user.user_id)
+)]
+pub async fn disable_two_factor(
+    user: Authorized<ManageOwnAccount>,
+    body: web::Json<CodeBody>,
+    pool: web::Data<PgPool>,
+    settings: web::Data<AdminUserSettings>,
+) -> HttpResponse {
+    if settings.requires_two_factor(user.role) {
+        return HttpResponse::Conflict()
+            .body("Two-factor authentication is mandatory for your role.");
+    }
+    let user_id = user.user_id;
+    let outcome = async {
+        let mut transaction = pool.begin().await?;
+        if verify_second_factor(&mut transaction, user_id, &body.code)
+            .await?
+            .is_none()
+        {
+            let enabled = sqlx::query_scalar!(
+                r#"SELECT totp_enabled_at IS NOT NULL AS "enabled!" FROM users WHERE user_id = $1"#,
+                user_id
+            )
+            .fetch_one(&mut *transaction)
+            .await?;
+            return Ok(if enabled {
+                TwoFactorChange::WrongCode
+            } else {
+                TwoFactorChange::Conflict("Two-factor authentication is not on.")
+            });
+        }
+        sqlx::query!(
+            r#"
+            UPDATE users
+            SET totp_secret = NULL, totp_enabled_at = NULL, totp_last_used_step = NULL
+            WHERE user_id = $1
+            "#,
+            user_id
+        )
+        .execute(&mut *transaction)
+        .await?;
+        sqlx::query!(r#"DELETE FROM recovery_codes WHERE user_id = $1"#, user_id)
+            .execute(&mut *transaction)
+            .await?;
+        record_action(
+            &mut *transaction,
+            Some(user_id),
+            "user.two_factor_disabled",
+            None,
+            serde_json::json!({}),
+        )
+        .await?;
+        transaction.commit().await?;
+        Ok::<_, sqlx::Error>(TwoFactorChange::Done(()))
+    }
+    .await;
+    change_response(outcome)
+}
+
+fn change_response<T>(outcome: Result<TwoFactorChange<T>, sqlx::Error>) -> HttpResponse {
+    match outcome {
+        Ok(TwoFactorChange::Done(_)) => HttpResponse::NoContent().finish(),
+        Ok(TwoFactorChange::WrongCode) => HttpResponse::BadRequest().body("The code is not valid."),
+        Ok(TwoFactorChange::Conflict(reason)) => HttpResponse::Conflict().body(reason),
+        Err(e) => {
+            tracing::error!("Failed to execute query: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
@@ -0,0 +1,189 @@
+use crate::lib::configurations::BrandingSettings;
+use crate::lib::localization::{Translations, accept_language};
+use crate::lib::pages::{InvalidTokenPage, UnsubscribePage, UnsubscribedPage, render};
+use crate::lib::startup::HmacSecret;
+use crate::lib::webhooks::{SUBSCRIBER_UNSUBSCRIBED, publish_subscriber_event};
+use crate::lib::welcome_series::STOPPED_UNSUBSCRIBED;
+use actix_web::http::StatusCode;
+use actix_web::{HttpRequest, HttpResponse, web};
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, SecretString};
+use sha2::Sha256;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+/// The status of a subscriber who does not want to hear from us anymore.
+pub const UNSUBSCRIBED: &str = "unsubscribed";
+
+#[derive(serde::Deserialize)]
+pub struct UnsubscribeParameters {
+    subscriber_id: Uuid,
+    token: String,
+}
+
+/// Unsubscribe links never expire, so they are derived from the subscriber id
+/// instead of being stored.
+pub fn unsubscribe_link(base_url: &str, hmac_secret: &SecretString, subscriber_id: Uuid) -> String {
+    let token = hex::encode(
+        unsubscribe_mac(hmac_secret, subscriber_id)
+            .finalize()
+            .into_bytes(),
+    );
+    format!(
+        "{}/subscriptions/unsubscribe?subscriber_id={}&token={}",
+        base_url, subscriber_id, token
+    )
+}
+
+fn unsubscribe_mac(hmac_secret: &SecretString, subscriber_id: Uuid) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(hmac_secret.expose_secret().as_bytes())
+        .expect("HMAC can take a key of any size");
+    mac.update(b"unsubscribe:");
+    mac.update(subscriber_id.as_bytes());
+    mac
+}
+
+impl UnsubscribeParameters {
+    fn is_valid(&self, hmac_secret: &SecretString) -> bool {
+        let Ok(token) = hex::decode(&self.token) else {
+            return false;
+        };
+        unsubscribe_mac(hmac_secret, self.subscriber_id)
+            .verify_slice(&token)
+            .is_ok()
+    }
+}
+
+/// Ask for a confirmation first: link scanners and prefetchers follow every
+/// link in an email, and they must not unsubscribe anybody.
+pub async fn unsubscribe_form(
+    req: HttpRequest,
+    parameters: web::Query<UnsubscribeParameters>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+    branding: web::Data<BrandingSettings>,
+    translations: web::Data<Translations>,
+) -> HttpResponse {
+    if !parameters.is_valid(&hmac_secret.0) {
+        let locale = translations.negotiate(None, accept_language(&req));
+        let page = InvalidTokenPage {
+            branding: &branding,
+            t: translations.translator(&locale),
+            expired: false,
+        };
+        return render(&page, StatusCode::UNAUTHORIZED);
+    }
+
+    let locale = subscriber_locale(&pool, &translations, &req, parameters.subscriber_id).await;
+    let page = UnsubscribePage {
+        branding: &branding,
+        t: translations.translator(&locale),
+        subscriber_id: parameters.subscriber_id,
+        token: &parameters.token,
+    };
+    render(&page, StatusCode::OK)
+}
+
+#[tracing::instrument(
+    name = "Unsubscribe a subscriber",
+    skip(req, parameters, pool, hmac_secret, branding, translations),
+    fields(subscriber_id = %parameters.subscriber_id)
+)]
+pub async fn unsubscribe(
+    req: HttpRequest,
+    parameters: web::Form<UnsubscribeParameters>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+    branding: web::Data<BrandingSettings>,
+    translations: web::Data<Translations>,
+) -> HttpResponse {
+    if !parameters.is_valid(&hmac_secret.0) {
+        let locale = translations.negotiate(None, accept_language(&req));
+        let page = InvalidTokenPage {
+            branding: &branding,
+            t: translations.translator(&locale),
+            expired: false,
+        };
+        return render(&page, StatusCode::UNAUTHORIZED);
+    }
+
+    let outcome = async {
+        let mut transaction = pool.begin().await?;
+        mark_as_unsubscribed(&mut transaction, parameters.subscriber_id).await?;
+        transaction.commit().await
+    }
+    .await;
+    if outcome.is_err() {
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    let locale = subscriber_locale(&pool, &translations, &req, parameters.subscriber_id).await;
+    render(
+        &UnsubscribedPage {
+            branding: &branding,
+            t: translations.translator(&locale),
+        },
+        StatusCode::OK,
+    )
+}
+
+/// The locale the subscriber signed up in, or the best match for the request
+/// if we can't find it.
+async fn subscriber_locale(
+    pool: &PgPool,
+    translations: &Translations,
+    req: &HttpRequest,
+    subscriber_id: Uuid,
+) -> String {
+    let stored = sqlx::query_scalar!(
+        r#"SELECT locale FROM subscriptions WHERE id = $1"#,
+        subscriber_id
+    )
+    .fetch_optional(pool)
+    .await
+    .unwrap_or_else(|e| {
+        tracing::warn!("Failed to fetch the subscriber locale: {:?}", e);
+        None
+    });
+    translations.negotiate(stored.as_deref(), accept_language(req))
+}
+
+/// Webhooks only hear about subscribers who were not unsubscribed already.
+#[tracing::instrument(name = "Mark subscriber as unsubscribed", skip(transaction))]
+pub async fn mark_as_unsubscribed(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    // The welcome series stops along, in the same statement.
+    let unsubscribed = sqlx::query!(
+        r#"
+        WITH stopped AS (
+            UPDATE welcome_series_enrollments SET stopped_at = now(), stopped_reason = $3
+            WHERE subscriber_id = $2 AND completed_at IS NULL AND stopped_at IS NULL
+        )
+        UPDATE subscriptions
+        SET status = $1, unsubscribed_at = COALESCE(unsubscribed_at, now())
+        WHERE id = $2 AND status <> $1
+        "#,
+        UNSUBSCRIBED,
+        subscriber_id,
+        STOPPED_UNSUBSCRIBED
+    )
+    .execute(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    if unsubscribed.rows_affected() == 1 {
+        publish_subscriber_event(
+            transaction,
+            SUBSCRIBER_UNSUBSCRIBED,
+            subscriber_id,
+            serde_json::json!({}),
+        )
+        .await?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,224 @@
+use crate::lib::audit::record_action;
+use crate::lib::authentication::Authorized;
+use crate::lib::roles::ManageWebhooks;
+use crate::lib::webhooks::{
+    EVENT_TYPES, generate_webhook_secret, list_deliveries, list_endpoints, redeliver,
+};
+use actix_web::{HttpResponse, web};
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// How many deliveries the log of an endpoint shows.
+const DELIVERY_LOG_LENGTH: i64 = 100;
+
+#[derive(serde::Deserialize)]
+pub struct WebhookBody {
+    url: String,
+    /// Leave it out, or empty, for every event.
+    #[serde(default)]
+    events: Vec<String>,
+}
+
+impl WebhookBody {
+    fn validate(&self) -> Result<(), String> {
+        let url = reqwest::Url::parse(&self.url)
+            .map_err(|e| format!("{} is not a valid URL: {}.", self.url, e))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err("Webhooks can only be sent over HTTP or HTTPS.".into());
+        }
+        if let Some(unknown) = self
+            .events
+            .iter()
+            .find(|event| !EVENT_TYPES.contains(&event.as_str()))
+        {
+            return Err(format!(
+                "{} is not an event. Use any of: {}.",
+                unknown,
+                EVENT_TYPES.join(", ")
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// The secret is only ever shown here: the receiver needs it to check the
+/// signatures.
+#[derive(serde::Serialize)]
+struct CreatedWebhook {
+    endpoint_id: Uuid,
+    url: String,
+    events: Vec<String>,
+    secret: String,
+    created_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(name = "Register a webhook endpoint", skip(user, body, pool), fields(url = %body.url))]
+pub async fn create_webhook(
+    user: Authorized<ManageWebhooks>,
+    body: web::Json<WebhookBody>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let user_id = user.user_id;
+    if let Err(e) = body.validate() {
+        return HttpResponse::BadRequest().body(e);
+    }
+
+    let WebhookBody { url, mut events } = body.into_inner();
+    events.sort();
+    events.dedup();
+    let webhook = CreatedWebhook {
+        endpoint_id: Uuid::new_v4(),
+        url,
+        events,
+        secret: generate_webhook_secret(),
+        created_at: Utc::now(),
+    };
+    let outcome = async {
+        let mut transaction = pool.begin().await?;
+        sqlx::query!(
+            r#"
+            INSERT INTO webhook_endpoints (endpoint_id, url, secret, events, created_at)
+            VALUES ($1, $2, $3, $4, $5)
+            "#,
+            webhook.endpoint_id,
+            webhook.url,
+            webhook.secret,
+            &webhook.events,
+            webhook.created_at
+        )
+        .execute(&mut *transaction)
+        .await?;
+        record_action(
+            &mut *transaction,
+            Some(user_id),
+            "webhook.created",
+            None,
+            serde_json::json!({
+                "endpoint_id": webhook.endpoint_id,
+                "url": webhook.url,
+                "events": webhook.events,
+            }),
+        )
+        .await?;
+        transaction.commit().await
+    }
+    .await;
+    match outcome {
+        Ok(()) => HttpResponse::Created().json(webhook),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[tracing::instrument(name = "List webhook endpoints for an admin", skip(_user, pool))]
+pub async fn admin_list_webhooks(
+    _user: Authorized<ManageWebhooks>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    match list_endpoints(&pool).await {
+        Ok(endpoints) => HttpResponse::Ok().json(endpoints),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// Its pending deliveries are dropped along with it.
+#[tracing::instrument(name = "Delete a webhook endpoint", skip(user, pool))]
+pub async fn delete_webhook(
+    user: Authorized<ManageWebhooks>,
+    path: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let user_id = user.user_id;
+
+    let endpoint_id = path.into_inner();
+    let outcome = async {
+        let mut transaction = pool.begin().await?;
+        let url = sqlx::query_scalar!(
+            r#"DELETE FROM webhook_endpoints WHERE endpoint_id = $1 RETURNING url"#,
+            endpoint_id
+        )
+        .fetch_optional(&mut *transaction)
+        .await?;
+        let Some(url) = url else {
+            return Ok(false);
+        };
+        record_action(
+            &mut *transaction,
+            Some(user_id),
+            "webhook.deleted",
+            None,
+            serde_json::json!({ "endpoint_id": endpoint_id, "url": url }),
+        )
+        .await?;
+        transaction.commit().await?;
+        Ok::<_, sqlx::Error>(true)
+    }
+    .await;
+    match outcome {
+        Ok(true) => HttpResponse::NoContent().finish(),
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// The latest deliveries to an endpoint, newest first.
+#[tracing::instrument(name = "Show the delivery log of a webhook", skip(_user, pool))]
+pub async fn admin_list_webhook_deliveries(
+    _user: Authorized<ManageWebhooks>,
+    path: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let endpoint_id = path.into_inner();
+    let exists = sqlx::query_scalar!(
+        r#"SELECT EXISTS (SELECT 1 FROM webhook_endpoints WHERE endpoint_id = $1) AS "exists!""#,
+        endpoint_id
+    )
+    .fetch_one(pool.get_ref())
+    .await;
+    match exists {
+        Ok(true) => {}
+        Ok(false) => return HttpResponse::NotFound().finish(),
+        Err(e) => {
+            tracing::error!("Failed to execute query: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    }
+    match list_deliveries(&pool, endpoint_id, DELIVERY_LOG_LENGTH).await {
+        Ok(deliveries) => HttpResponse::Ok().json(deliveries),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// Send a delivery again, e.g. once the receiver is fixed.
+#[tracing::instrument(name = "Redeliver a webhook for an admin", skip(user, pool))]
+pub async fn redeliver_webhook(
+    user: Authorized<ManageWebhooks>,
+    path: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let user_id = user.user_id;
+
+    let delivery_id = path.into_inner();
+    let outcome = async {
+        let mut transaction = pool.begin().await?;
+        if !redeliver(&mut transaction, delivery_id).await? {
+            return Ok(false);
+        }
+        record_action(
+            &mut *transaction,
+            Some(user_id),
+            "webhook.redelivered",
+            None,
+            serde_json::json!({ "delivery_id": delivery_id }),
+        )
+        .await?;
+        transaction.commit().await?;
+        Ok::<_, sqlx::Error>(true)
+    }
+    .await;
+    match outcome {
+        Ok(true) => HttpResponse::Accepted().finish(),
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
@@ -0,0 +1,893 @@
+use crate::lib::domain::SubscriberAttributes;
+use crate::lib::routes::CONFIRMED;
+use crate::lib::tracking::{CLICK, OPEN};
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use sqlx::{PgExecutor, PgPool, Postgres, QueryBuilder};
+use uuid::Uuid;
+
+const MAX_EXPRESSION_LENGTH: usize = 2000;
+/// How deeply `not` and parentheses can nest.
+const MAX_DEPTH: usize = 32;
+/// Engagement is looked at over ten years at most.
+const MAX_DAYS: i64 = 3650;
+
+/// A filter over subscribers, e.g.
+/// `status = "confirmed" and attributes.plan in ("pro", "team") and not clicked within 90 days`.
+///
+/// - `status` and `list` (a list slug) are compared with `=`, `!=`, `in (...)`
+///   and `not in (...)` to strings.
+/// - `attributes.<name>` is compared the same way to strings, numbers, `true`,
+///   `false` or `null`, which matches a missing attribute too. It is ordered
+///   with `<`, `<=`, `>` and `>=` against a number or a string, and only
+///   matches values of the same type.
+/// - `opened within <n> days` and `clicked within <n> days` match engaged
+///   subscribers.
+/// - Conditions are combined with `and`, `or`, `not` and parentheses.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expression {
+    And(Box<Expression>, Box<Expression>),
+    Or(Box<Expression>, Box<Expression>),
+    Not(Box<Expression>),
+    Condition(Condition),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    /// Their status is one of these.
+    Status(Vec<String>),
+    /// They are on one of these lists.
+    List(Vec<String>),
+    /// Their attribute is one of these values.
+    AttributeIn { key: String, values: Vec<Value> },
+    /// Their attribute compares to a number or a string.
+    AttributeCompare {
+        key: String,
+        operator: Comparison,
+        value: Value,
+    },
+    /// They opened, or clicked, an issue in the last `days` days.
+    Engaged { kind: Engagement, days: i64 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl Comparison {
+    fn sql(&self) -> &'static str {
+        match self {
+            Comparison::Less => " < ",
+            Comparison::LessOrEqual => " <= ",
+            Comparison::Greater => " > ",
+            Comparison::GreaterOrEqual => " >= ",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Engagement {
+    Opened,
+    Clicked,
+}
+
+/// Why an expression could not be parsed, and where.
+#[derive(Debug, PartialEq)]
+pub struct ExpressionError {
+    /// In characters, from 0.
+    pub position: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for ExpressionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (at character {})", self.message, self.position)
+    }
+}
+
+impl std::error::Error for ExpressionError {}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    String(String),
+    Number(serde_json::Number),
+    Operator(&'static str),
+    LeftParenthesis,
+    RightParenthesis,
+    Comma,
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Word(word) => write!(f, "`{}`", word),
+            Token::String(s) => write!(f, "{:?}", s),
+            Token::Number(n) => write!(f, "{}", n),
+            Token::Operator(operator) => write!(f, "`{}`", operator),
+            Token::LeftParenthesis => f.write_str("`(`"),
+            Token::RightParenthesis => f.write_str("`)`"),
+            Token::Comma => f.write_str("`,`"),
+        }
+    }
+}
+
+fn tokenize(input: &str) -> Result<Vec<(usize, Token)>, ExpressionError> {
+    let error = |position, message: String| ExpressionError { position, message };
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let start = i;
+        let c = chars[i];
+        let token = match c {
+            c if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            '(' => Token::LeftParenthesis,
+            ')' => Token::RightParenthesis,
+            ',' => Token::Comma,
+            '=' => Token::Operator("="),
+            '!' if chars.get(i + 1) == Some(&'=') => {
+                i += 1;
+                Token::Operator("!=")
+            }
+            '<' | '>' => {
+                let or_equal = chars.get(i + 1) == Some(&'=');
+                if or_equal {
+                    i += 1;
+                }
+                Token::Operator(match (c, or_equal) {
+                    ('<', false) => "<",
+                    ('<', true) => "<=",
+                    (_, false) => ">",
+                    (_, true) => ">=",
+                })
+            }
+            '"' => {
+                let mut s = String::new();
+                loop {
+                    i += 1;
+                    match chars.get(i) {
+                        None => return Err(error(start, "Unterminated string.".into())),
+                        Some('"') => break,
+                        Some('\\') => {
+                            i += 1;
+                            match chars.get(i) {
+                                Some(c @ ('"' | '\\')) => s.push(*c),
+                                _ => {
+                                    return Err(error(
+                                        i - 1,
+                                        r#"Only `\"` and `\\` can be escaped."#.into(),
+                                    ));
+                                }
+                            }
+                        }
+                        Some(c) => s.push(*c),
+                    }
+                }
+                Token::String(s)
+            }
+            c if c.is_ascii_digit() || c == '-' => {
+                while chars
+                    .get(i + 1)
+                    .is_some_and(|c| c.is_ascii_digit() || *c == '.')
+                {
+                    i += 1;
+                }
+                let number: String = chars[start..=i].iter().collect();
+                let number = number
+                    .parse::<i64>()
+                    .map(serde_json::Number::from)
+                    .ok()
+                    .or_else(|| {
+                        number
+                            .parse::<f64>()
+                            .ok()
+                            .and_then(serde_json::Number::from_f64)
+                    })
+                    .ok_or_else(|| error(start, format!("`{}` is not a number.", number)))?;
+                Token::Number(number)
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                while chars
+                    .get(i + 1)
+                    .is_some_and(|c| c.is_ascii_alphanumeric() || *c == '_' || *c == '.')
+                {
+                    i += 1;
+                }
+                Token::Word(chars[start..=i].iter().collect())
+            }
+            c => return Err(error(start, format!("Unexpected `{}`.", c))),
+        };
+        tokens.push((start, token));
+        i += 1;
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    next: usize,
+    /// Where the expression ends, to report a missing token.
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next).map(|(_, token)| token)
+    }
+
+    fn position(&self) -> usize {
+        self.tokens
+            .get(self.next)
+            .map_or(self.end, |(position, _)| *position)
+    }
+
+    fn error<T>(&self, message: impl Into<String>) -> Result<T, ExpressionError> {
+        Err(ExpressionError {
+            position: self.position(),
+            message: message.into(),
+        })
+    }
+
+    fn advance(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.next).map(|(_, token)| token.clone());
+        self.next += 1;
+        token
+    }
+
+    /// Consume the `keyword` if it is next.
+    fn keyword(&mut self, keyword: &str) -> bool {
+        let found =
+            matches!(self.peek(), Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword));
+        if found {
+            self.next += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), ExpressionError> {
+        match self.peek() {
+            Some(token) if *token == expected => {
+                self.next += 1;
+                Ok(())
+            }
+            Some(token) => self.error(format!("Expected {}, found {}.", expected, token)),
+            None => self.error(format!("Expected {}.", expected)),
+        }
+    }
+
+    fn or(&mut self, depth: usize) -> Result<Expression, ExpressionError> {
+        let mut expression = self.and(depth)?;
+        while self.keyword("or") {
+            expression = Expression::Or(Box::new(expression), Box::new(self.and(depth)?));
+        }
+        Ok(expression)
+    }
+
+    fn and(&mut self, depth: usize) -> Result<Expression, ExpressionError> {
+        let mut expression = self.not(depth)?;
+        while self.keyword("and") {
+            expression = Expression::And(Box::new(expression), Box::new(self.not(depth)?));
+        }
+        Ok(expression)
+    }
+
+    fn not(&mut self, depth: usize) -> Result<Expression, ExpressionError> {
+        if depth > MAX_DEPTH {
+            return self.error("The expression is nested too deeply.");
+        }
+        if self.keyword("not") {
+            return Ok(Expression::Not(Box::new(self.not(depth + 1)?)));
+        }
+        if self.peek() == Some(&Token::LeftParenthesis) {
+            self.next += 1;
+            let expression = self.or(depth + 1)?;
+            self.expect(Token::RightParenthesis)?;
+            return Ok(expression);
+        }
+        self.condition()
+    }
+
+    fn condition(&mut self) -> Result<Expression, ExpressionError> {
+        let field = match self.advance() {
+            Some(Token::Word(field)) => field,
+            Some(token) => {
+                self.next -= 1;
+                return self.error(format!("Expected a condition, found {}.", token));
+            }
+            None => return self.error("Expected a condition."),
+        };
+        let condition = match field.as_str() {
+            "status" | "list" => {
+                let (negated, values) = self.membership()?;
+                let mut strings = Vec::new();
+                for value in values {
+                    match value {
+                        Value::String(s) => strings.push(s),
+                        _ => {
+                            self.next -= 1;
+                            return self.error(format!("`{}` is compared to strings.", field));
+                        }
+                    }
+                }
+                let condition = if field == "status" {
+                    Condition::Status(strings)
+                } else {
+                    Condition::List(strings)
+                };
+                return Ok(negate(negated, Expression::Condition(condition)));
+            }
+            "opened" | "clicked" => {
+                if !self.keyword("within") {
+                    return self.error(format!("Expected `{} within <n> days`.", field));
+                }
+                let days = match self.advance() {
+                    Some(Token::Number(n)) => n.as_i64().filter(|n| (1..=MAX_DAYS).contains(n)),
+                    _ => None,
+                };
+                let Some(days) = days else {
+                    self.next -= 1;
+                    return self.error(format!(
+                        "Expected a whole number of days between 1 and {}.",
+                        MAX_DAYS
+                    ));
+                };
+                if !self.keyword("days") && !self.keyword("day") {
+                    return self.error("Expected `days`.");
+                }
+                let kind = if field == "opened" {
+                    Engagement::Opened
+                } else {
+                    Engagement::Clicked
+                };
+                Condition::Engaged { kind, days }
+            }
+            _ => {
+                let Some(key) = field.strip_prefix("attributes.") else {
+                    self.next -= 1;
+                    return self.error(format!(
+                        "Unknown field `{}`: use `status`, `list`, `attributes.<name>`, `opened` \
+                         or `clicked`.",
+                        field
+                    ));
+                };
+                if !SubscriberAttributes::is_valid_key(key) {
+                    self.next -= 1;
+                    return self.error(format!("`{}` is not a valid attribute name.", key));
+                }
+                let key = key.to_owned();
+                let operator = match self.peek() {
+                    Some(Token::Operator("<")) => Some(Comparison::Less),
+                    Some(Token::Operator("<=")) => Some(Comparison::LessOrEqual),
+                    Some(Token::Operator(">")) => Some(Comparison::Greater),
+                    Some(Token::Operator(">=")) => Some(Comparison::GreaterOrEqual),
+                    _ => None,
+                };
+                let Some(operator) = operator else {
+                    let (negated, values) = self.membership()?;
+                    let condition = Condition::AttributeIn { key, values };
+                    return Ok(negate(negated, Expression::Condition(condition)));
+                };
+                self.next += 1;
+                let value = match self.value()? {
+                    value @ (Value::Number(_) | Value::String(_)) => value,
+                    _ => {
+                        self.next -= 1;
+                        return self.error("Only numbers and strings can be ordered.");
+                    }
+                };
+                Condition::AttributeCompare {
+                    key,
+                    operator,
+                    value,
+                }
+            }
+        };
+        Ok(Expression::Condition(condition))
+    }
+
+    /// `= value`, `!= value`, `in (values)` or `not in (values)`: whether it
+    /// is negated, and the values.
+    fn membership(&mut self) -> Result<(bool, Vec<Value>), ExpressionError> {
+        match self.peek() {
+            Some(Token::Operator("=")) => {
+                self.next += 1;
+                return Ok((false, vec![self.value()?]));
+            }
+            Some(Token::Operator("!=")) => {
+                self.next += 1;
+                return Ok((true, vec![self.value()?]));
+            }
+            _ => {}
+        }
+        let negated = self.keyword("not");
+        if !self.keyword("in") {
+            return self.error("Expected `=`, `!=`, `in` or `not in`.");
+        }
+        self.expect(Token::LeftParenthesis)?;
+        let mut values = vec![self.value()?];
+        while self.peek() == Some(&Token::Comma) {
+            self.next += 1;
+            values.push(self.value()?);
+        }
+        self.expect(Token::RightParenthesis)?;
+        Ok((negated, values))
+    }
+
+    fn value(&mut self) -> Result<Value, ExpressionError> {
+        match self.advance() {
+            Some(Token::String(s)) => Ok(Value::String(s)),
+            Some(Token::Number(n)) => Ok(Value::Number(n)),
+            Some(Token::Word(word)) if word == "true" => Ok(Value::Bool(true)),
+            Some(Token::Word(word)) if word == "false" => Ok(Value::Bool(false)),
+            Some(Token::Word(word)) if word == "null" => Ok(Value::Null),
+            Some(token) => {
+                self.next -= 1;
+                self.error(format!("Expected a value, found {}.", token))
+            }
+            None => self.error("Expected a value."),
+        }
+    }
+}
+
+fn negate(negated: bool, expression: Expression) -> Expression {
+    if negated {
+        Expression::Not(Box::new(expression))
+    } else {
+        expression
+    }
+}
+
+impl Expression {
+    pub fn parse(input: &str) -> Result<Expression, ExpressionError> {
+        let length = input.chars().count();
+        if length > MAX_EXPRESSION_LENGTH {
+            return Err(ExpressionError {
+                position: MAX_EXPRESSION_LENGTH,
+                message: format!(
+                    "The expression is longer than {} characters.",
+                    MAX_EXPRESSION_LENGTH
+                ),
+            });
+        }
+        let mut parser = Parser {
+            tokens: tokenize(input)?,
+            next: 0,
+            end: length,
+        };
+        let expression = parser.or(0)?;
+        match parser.peek() {
+            None => Ok(expression),
+            Some(token) => parser.error(format!("Unexpected {}.", token)),
+        }
+    }
+
+    /// Add the expression as a condition on `s`, a row of `subscriptions`.
+    ///
+    /// Conditions are never `NULL`, so that `not` matches everybody else.
+    pub fn push_sql(&self, query: &mut QueryBuilder<'_, Postgres>) {
+        match self {
+            Expression::And(left, right) | Expression::Or(left, right) => {
+                let operator = match self {
+                    Expression::And(..) => " AND ",
+                    _ => " OR ",
+                };
+                query.push("(");
+                left.push_sql(query);
+                query.push(operator);
+                right.push_sql(query);
+                query.push(")");
+            }
+            Expression::Not(expression) => {
+                query.push("NOT ");
+                expression.push_sql(query);
+            }
+            Expression::Condition(condition) => condition.push_sql(query),
+        }
+    }
+}
+
+impl Condition {
+    fn push_sql(&self, query: &mut QueryBuilder<'_, Postgres>) {
+        match self {
+            Condition::Status(statuses) => {
+                query
+                    .push("(s.status = ANY(")
+                    .push_bind(statuses.clone())
+                    .push("))");
+            }
+            Condition::List(slugs) => {
+                query
+                    .push(
+                        "EXISTS (SELECT 1 FROM list_subscriptions ls \
+                         JOIN lists l ON l.list_id = ls.list_id \
+                         WHERE ls.subscriber_id = s.id AND l.slug = ANY(",
+                    )
+                    .push_bind(slugs.clone())
+                    .push("))");
+            }
+            Condition::AttributeIn { key, values } => {
+                query.push("(");
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        query.push(" OR ");
+                    }
+                    if value.is_null() {
+                        query
+                            .push("COALESCE(s.attributes -> ")
+                            .push_bind(key.clone())
+                            .push(", 'null'::jsonb) = 'null'::jsonb");
+                    } else {
+                        // Containment, to use the index on `attributes`.
+                        query
+                            .push("s.attributes @> ")
+                            .push_bind(serde_json::json!({ key: value }));
+                    }
+                }
+                query.push(")");
+            }
+            Condition::AttributeCompare {
+                key,
+                operator,
+                value,
+            } => {
+                // `CASE`, since `AND` does not stop the cast of a string to
+                // a number.
+                let (json_type, cast, value) = match value {
+                    Value::Number(n) => ("'number'", "(s.attributes -> ", n.to_string()),
+                    Value::String(s) => ("'string'", "(s.attributes ->> ", s.clone()),
+                    _ => unreachable!("Only numbers and strings are parsed as ordered."),
+                };
+                query
+                    .push("(CASE WHEN jsonb_typeof(s.attributes -> ")
+                    .push_bind(key.clone())
+                    .push(format!(") = {} THEN {}", json_type, cast))
+                    .push_bind(key.clone());
+                if json_type == "'number'" {
+                    query
+                        .push(")::numeric")
+                        .push(operator.sql())
+                        .push_bind(value)
+                        .push("::numeric");
+                } else {
+                    query.push(")").push(operator.sql()).push_bind(value);
+                }
+                query.push(" ELSE false END)");
+            }
+            Condition::Engaged { kind, days } => {
+                let kind = match kind {
+                    Engagement::Opened => OPEN,
+                    Engagement::Clicked => CLICK,
+                };
+                query
+                    .push("EXISTS (SELECT 1 FROM issue_events e WHERE e.subscriber_id = s.id AND e.kind = ")
+                    .push_bind(kind)
+                    .push(" AND e.occurred_at >= now() - make_interval(days => ")
+                    .push_bind(*days as i32)
+                    .push("))");
+            }
+        }
+    }
+}
+
+/// Add `FROM subscriptions s WHERE ...`, for the confirmed subscribers of
+/// `segment`, or all of them. Suppressed addresses are left out.
+pub fn push_audience(query: &mut QueryBuilder<'_, Postgres>, segment: Option<&Expression>) {
+    query
+        .push(" FROM subscriptions s WHERE s.status = ")
+        .push_bind(CONFIRMED)
+        .push(
+            " AND NOT EXISTS (SELECT 1 FROM suppressions x \
+            WHERE x.email_canonical = s.email_canonical)",
+        );
+    if let Some(segment) = segment {
+        query.push(" AND ");
+        segment.push_sql(query);
+    }
+}
+
+/// Whether the subscriber matches the expression.
+#[tracing::instrument(
+    name = "Match a subscriber against a segment",
+    skip(executor, expression)
+)]
+pub async fn subscriber_matches<'e>(
+    executor: impl PgExecutor<'e>,
+    subscriber_id: Uuid,
+    expression: &Expression,
+) -> Result<bool, sqlx::Error> {
+    let mut query = QueryBuilder::new("SELECT EXISTS (SELECT 1 FROM subscriptions s WHERE s.id = ");
+    query.push_bind(subscriber_id).push(" AND ");
+    expression.push_sql(&mut query);
+    query
+        .push(")")
+        .build_query_scalar()
+        .fetch_one(executor)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to execute query: {:?}", e);
+            e
+        })
+}
+
+#[derive(serde::Serialize)]
+pub struct Segment {
+    pub segment_id: Uuid,
+    pub name: String,
+    pub expression: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Segment {
+    /// Saved expressions were parsed before they were saved.
+    pub fn parsed(&self) -> Result<Expression, sqlx::Error> {
+        Expression::parse(&self.expression).map_err(|e| sqlx::Error::Decode(Box::new(e)))
+    }
+}
+
+#[tracing::instrument(name = "Get a segment", skip(executor))]
+pub async fn get_segment<'e>(
+    executor: impl PgExecutor<'e>,
+    segment_id: Uuid,
+) -> Result<Option<Segment>, sqlx::Error> {
+    sqlx::query_as!(
+        Segment,
+        r#"
+        SELECT segment_id, name, expression, created_at, updated_at
+        FROM segments
+        WHERE segment_id = $1
+        "#,
+        segment_id
+    )
+    .fetch_optional(executor)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })
+}
+
+#[tracing::instrument(name = "List segments", skip(pool))]
+pub async fn list_segments(pool: &PgPool) -> Result<Vec<Segment>, sqlx::Error> {
+    sqlx::query_as!(
+        Segment,
+        r#"
+        SELECT segment_id, name, expression, created_at, updated_at
+        FROM segments
+        ORDER BY name
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })
+}
+
+/// The segment an issue goes to, if any.
+#[tracing::instrument(name = "Get the segment of an issue", skip(executor))]
+pub async fn issue_segment<'e>(
+    executor: impl PgExecutor<'e>,
+    newsletter_issue_id: Uuid,
+) -> Result<Option<Expression>, sqlx::Error> {
+    let expression = sqlx::query_scalar!(
+        r#"
+        SELECT s.expression
+        FROM newsletter_issues i JOIN segments s ON s.segment_id = i.segment_id
+        WHERE i.newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_optional(executor)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    expression
+        .map(|expression| {
+            Expression::parse(&expression).map_err(|e| sqlx::Error::Decode(Box::new(e)))
+        })
+        .transpose()
+}
+
+/// How many confirmed subscribers are in the segment.
+#[tracing::instrument(name = "Count the audience of a segment", skip(pool))]
+pub async fn count_audience(pool: &PgPool, segment: &Expression) -> Result<i64, sqlx::Error> {
+    let mut query = QueryBuilder::new("SELECT count(*)");
+    push_audience(&mut query, Some(segment));
+    query
+        .build_query_scalar()
+        .fetch_one(pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to execute query: {:?}", e);
+            e
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn condition(condition: Condition) -> Expression {
+        Expression::Condition(condition)
+    }
+
+    fn not(expression: Expression) -> Expression {
+        Expression::Not(Box::new(expression))
+    }
+
+    fn sql(input: &str) -> String {
+        let mut query = QueryBuilder::new("");
+        Expression::parse(input).unwrap().push_sql(&mut query);
+        query.sql().to_owned()
+    }
+
+    #[test]
+    fn conditions_are_parsed() {
+        let test_cases = vec![
+            (
+                r#"status = "confirmed""#,
+                condition(Condition::Status(vec!["confirmed".into()])),
+            ),
+            (
+                r#"list != "weekly""#,
+                not(condition(Condition::List(vec!["weekly".into()]))),
+            ),
+            (
+                r#"attributes.plan in ("pro", "team")"#,
+                condition(Condition::AttributeIn {
+                    key: "plan".into(),
+                    values: vec![json!("pro"), json!("team")],
+                }),
+            ),
+            (
+                "attributes.beta not in (true, null)",
+                not(condition(Condition::AttributeIn {
+                    key: "beta".into(),
+                    values: vec![json!(true), json!(null)],
+                })),
+            ),
+            (
+                "attributes.seats >= 2.5",
+                condition(Condition::AttributeCompare {
+                    key: "seats".into(),
+                    operator: Comparison::GreaterOrEqual,
+                    value: json!(2.5),
+                }),
+            ),
+            (
+                r#"attributes.signed_up < "2025-01-01""#,
+                condition(Condition::AttributeCompare {
+                    key: "signed_up".into(),
+                    operator: Comparison::Less,
+                    value: json!("2025-01-01"),
+                }),
+            ),
+            (
+                "clicked within 1 day",
+                condition(Condition::Engaged {
+                    kind: Engagement::Clicked,
+                    days: 1,
+                }),
+            ),
+        ];
+
+        for (input, expected) in test_cases {
+            assert_eq!(Expression::parse(input), Ok(expected), "{}", input);
+        }
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let opened = || {
+            condition(Condition::Engaged {
+                kind: Engagement::Opened,
+                days: 30,
+            })
+        };
+        let pro = || {
+            condition(Condition::AttributeIn {
+                key: "plan".into(),
+                values: vec![json!("pro")],
+            })
+        };
+        let weekly = || condition(Condition::List(vec!["weekly".into()]));
+
+        assert_eq!(
+            Expression::parse(
+                r#"opened within 30 days or attributes.plan = "pro" AND list = "weekly""#
+            ),
+            Ok(Expression::Or(
+                Box::new(opened()),
+                Box::new(Expression::And(Box::new(pro()), Box::new(weekly())))
+            ))
+        );
+        assert_eq!(
+            Expression::parse(
+                r#"not (opened within 30 days or attributes.plan = "pro") and list = "weekly""#
+            ),
+            Ok(Expression::And(
+                Box::new(not(Expression::Or(Box::new(opened()), Box::new(pro())))),
+                Box::new(weekly())
+            ))
+        );
+    }
+
+    #[test]
+    fn strings_can_hold_escaped_quotes() {
+        assert_eq!(
+            Expression::parse(r#"attributes.company = "The \"Best\" \\ co""#),
+            Ok(condition(Condition::AttributeIn {
+                key: "company".into(),
+                values: vec![json!(r#"The "Best" \ co"#)],
+            }))
+        );
+    }
+
+    #[test]
+    fn invalid_expressions_are_rejected_with_their_position() {
+        let test_cases = vec![
+            ("", 0),
+            (r#"plan = "pro""#, 0),
+            (r#"status = 1"#, 9),
+            (r#"attributes.Plan = "pro""#, 0),
+            (r#"attributes.plan = "pro"#, 18),
+            ("attributes.beta > true", 18),
+            ("opened within 0 days", 14),
+            ("opened within 30", 16),
+            (r#"status = "confirmed" and"#, 24),
+            (r#"(status = "confirmed""#, 21),
+            (r#"status = "confirmed" list = "weekly""#, 21),
+            ("attributes.plan ~ 1", 16),
+        ];
+
+        for (input, position) in test_cases {
+            let error = Expression::parse(input).unwrap_err();
+            assert_eq!(error.position, position, "{}: {}", input, error);
+        }
+    }
+
+    #[test]
+    fn deeply_nested_expressions_are_rejected() {
+        let input = format!("{}opened within 1 day", "not ".repeat(100));
+
+        assert!(Expression::parse(&input).is_err());
+    }
+
+    #[test]
+    fn values_are_bound_not_inlined() {
+        assert_eq!(
+            sql(r#"attributes.plan = "pro'; --" or not status in ("confirmed")"#),
+            "((s.attributes @> $1) OR NOT (s.status = ANY($2)))"
+        );
+    }
+
+    #[test]
+    fn ordered_attributes_only_match_values_of_the_same_type() {
+        assert_eq!(
+            sql("attributes.seats > 5"),
+            "(CASE WHEN jsonb_typeof(s.attributes -> $1) = 'number' \
+             THEN (s.attributes -> $2)::numeric > $3::numeric ELSE false END)"
+        );
+    }
+
+    #[test]
+    fn null_matches_missing_attributes() {
+        assert_eq!(
+            sql("attributes.plan = null"),
+            "(COALESCE(s.attributes -> $1, 'null'::jsonb) = 'null'::jsonb)"
+        );
+    }
+}
@@ -0,0 +1,179 @@
+use crate::lib::authentication::AuthenticatedUser;
+use crate::lib::roles::Role;
+use chrono::{DateTime, Duration, Utc};
+use rand::Rng;
+use rand::distr::Alphanumeric;
+use sha2::{Digest, Sha256};
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+/// How long users have to give their second factor once their password
+/// checked out.
+const CHALLENGE_TTL_MINUTES: i64 = 5;
+/// Wrong codes a challenge survives: after that, start again with the
+/// password.
+pub const MAX_CHALLENGE_ATTEMPTS: i16 = 5;
+
+/// A token and when it stops working. The token is only ever shown here.
+#[derive(serde::Serialize)]
+pub struct IssuedToken {
+    pub token: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// A user halfway through logging in.
+pub struct Challenge {
+    pub challenge_id: Uuid,
+    pub user_id: Uuid,
+    pub n_failed_attempts: i16,
+}
+
+fn generate_token() -> String {
+    let mut rng = rand::rng();
+    std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(40)
+        .collect()
+}
+
+/// What is stored instead of the tokens: a leaked table logs nobody in.
+fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// The token of `Authorization: Bearer ...`, if that is the scheme.
+pub fn bearer_token(headers: &actix_web::http::header::HeaderMap) -> Option<&str> {
+    headers
+        .get(actix_web::http::header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+}
+
+#[tracing::instrument(name = "Start a session", skip(executor))]
+pub async fn create_session<'e>(
+    executor: impl PgExecutor<'e>,
+    user_id: Uuid,
+    ttl_hours: i64,
+) -> Result<IssuedToken, sqlx::Error> {
+    let now = Utc::now();
+    let session = IssuedToken {
+        token: generate_token(),
+        expires_at: now + Duration::hours(ttl_hours),
+    };
+    sqlx::query!(
+        r#"
+        INSERT INTO user_sessions (session_id, user_id, token_hash, created_at, expires_at)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        Uuid::new_v4(),
+        user_id,
+        hash_token(&session.token),
+        now,
+        session.expires_at
+    )
+    .execute(executor)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(session)
+}
+
+/// The user logged in with `token`, unless the session is over.
+#[tracing::instrument(name = "Find a session", skip(pool, token))]
+pub async fn find_session(
+    pool: &PgPool,
+    token: &str,
+) -> Result<Option<AuthenticatedUser>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT u.user_id, u.role, u.totp_enabled_at IS NOT NULL AS "two_factor_enabled!"
+        FROM user_sessions s
+        JOIN users u ON u.user_id = s.user_id
+        WHERE s.token_hash = $1 AND s.expires_at > now()
+        "#,
+        hash_token(token)
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(row.and_then(|row| {
+        Some(AuthenticatedUser {
+            user_id: row.user_id,
+            role: Role::parse(&row.role)?,
+            two_factor_enabled: row.two_factor_enabled,
+        })
+    }))
+}
+
+/// Log out. Returns whether there was such a session.
+#[tracing::instrument(name = "End a session", skip(pool, token))]
+pub async fn delete_session(pool: &PgPool, token: &str) -> Result<bool, sqlx::Error> {
+    let deleted = sqlx::query!(
+        r#"DELETE FROM user_sessions WHERE token_hash = $1"#,
+        hash_token(token)
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(deleted.rows_affected() == 1)
+}
+
+#[tracing::instrument(name = "Start a login challenge", skip(pool))]
+pub async fn create_challenge(pool: &PgPool, user_id: Uuid) -> Result<IssuedToken, sqlx::Error> {
+    let challenge = IssuedToken {
+        token: generate_token(),
+        expires_at: Utc::now() + Duration::minutes(CHALLENGE_TTL_MINUTES),
+    };
+    sqlx::query!(
+        r#"
+        INSERT INTO login_challenges (challenge_id, user_id, token_hash, expires_at)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        Uuid::new_v4(),
+        user_id,
+        hash_token(&challenge.token),
+        challenge.expires_at
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(challenge)
+}
+
+/// The challenge behind `token`, locked until the end of the transaction,
+/// unless it expired or ran out of attempts.
+#[tracing::instrument(name = "Find a login challenge", skip(executor, token))]
+pub async fn find_challenge<'e>(
+    executor: impl PgExecutor<'e>,
+    token: &str,
+) -> Result<Option<Challenge>, sqlx::Error> {
+    sqlx::query_as!(
+        Challenge,
+        r#"
+        SELECT challenge_id, user_id, n_failed_attempts
+        FROM login_challenges
+        WHERE token_hash = $1 AND expires_at > now() AND n_failed_attempts < $2
+        FOR UPDATE
+        "#,
+        hash_token(token),
+        MAX_CHALLENGE_ATTEMPTS
+    )
+    .fetch_optional(executor)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })
+}
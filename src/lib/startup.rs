@@ -1,12 +1,35 @@
-﻿use crate::greet;
+use crate::lib::authentication::create_initial_owner;
+use crate::lib::bot_protection::BotProtection;
 use crate::lib::configurations::Setting;
+use crate::lib::cors::{AllowedOrigins, cors};
+use crate::lib::domain::EmailDomainPolicy;
 use crate::lib::email_client::EmailClient;
-use crate::lib::routes::{confirm, health_check, subscribe};
+use crate::lib::email_templates::EmailTemplates;
+use crate::lib::localization::Translations;
+use crate::lib::routes::{
+    accept_invite, admin_confirm_subscriber, admin_delete_subscriber, admin_get_segment,
+    admin_get_subscriber, admin_list_segments, admin_list_subscribers,
+    admin_list_webhook_deliveries, admin_list_webhooks, admin_rename_subscriber,
+    admin_suppress_email, admin_unsubscribe_subscriber, admin_update_subscriber_attributes,
+    archive_page, archived_issue, atom_feed, cancel_issue, change_role, confirm,
+    confirm_two_factor, create_issue, create_segment, create_webhook, delete_issue, delete_segment,
+    delete_user, delete_webhook, disable_two_factor, export_subscribers, forgot_password_form,
+    form_token, get_import, get_newsletter_issue, growth_report, health_check, home,
+    import_subscribers, invite_form, invite_user, issue_report, issues_report, list_imports,
+    list_invites, list_issues, list_users, log_in, log_out, password_reset_form, preview_issue,
+    preview_newsletter, publish_newsletter, redeliver_webhook, reports_page,
+    request_password_reset, reset_password, revoke_invite, rss_feed, schedule_issue,
+    send_test_issue, signup_widget, start_two_factor, subscribe, track_click, track_open,
+    unsubscribe, unsubscribe_form, update_issue, update_segment, verify_login,
+};
 use actix_web::dev::Server;
+use actix_web::middleware::from_fn;
 use actix_web::{App, HttpServer, web};
+use secrecy::SecretString;
 use sqlx::PgPool;
 use sqlx::postgres::PgPoolOptions;
 use std::net::TcpListener;
+use std::sync::Arc;
 use tracing_actix_web::TracingLogger;
 
 /// A new type to hold the newly build server and its port.
@@ -20,8 +43,16 @@ impl Application {
     /// `Application`.
     pub async fn build(configuration: Setting) -> Result<Self, std::io::Error> {
         let connection_pool = get_connection_pool(&configuration);
+        if let Some(owner) = &configuration.admin_users.initial_owner
+            && create_initial_owner(&connection_pool, owner)
+                .await
+                .map_err(|e| std::io::Error::other(e.to_string()))?
+        {
+            tracing::info!(username = %owner.username, "Created the initial owner.");
+        }
         let email_client = configuration
             .email_client
+            .clone()
             .try_into()
             .expect("Invalid sender email address.");
         let address = format!(
@@ -30,12 +61,7 @@ impl Application {
         );
         let listener = TcpListener::bind(&address)?;
         let port = listener.local_addr()?.port();
-        let server = run(
-            listener,
-            connection_pool,
-            email_client,
-            configuration.application.base_url,
-        )?;
+        let server = run(listener, connection_pool, email_client, configuration).await?;
 
         Ok(Self { port, server })
     }
@@ -55,6 +81,9 @@ impl Application {
 // would expose us to conflicts.
 pub struct ApplicationBaseUrl(pub String);
 
+/// The key used to sign the links we send out, e.g. unsubscribe links.
+pub struct HmacSecret(pub SecretString);
+
 pub fn get_connection_pool(configuration: &Setting) -> PgPool {
     PgPoolOptions::new()
         .acquire_timeout(std::time::Duration::from_millis(
@@ -63,27 +92,190 @@ pub fn get_connection_pool(configuration: &Setting) -> PgPool {
         .connect_lazy_with(configuration.database.with_db())
 }
 
-pub fn run(
+pub async fn run(
     listener: TcpListener,
     db_pool: PgPool,
     email_client: EmailClient,
-    base_url: String,
+    configuration: Setting,
 ) -> Result<Server, std::io::Error> {
     let db_pool = web::Data::new(db_pool);
     let email_client = web::Data::new(email_client);
-    let base_url = web::Data::new(ApplicationBaseUrl(base_url));
+    let allowed_origins =
+        AllowedOrigins::new(&configuration.application.base_url, &configuration.embed)
+            .map_err(std::io::Error::other)?;
+    let allowed_origins = web::Data::new(allowed_origins);
+    let base_url = web::Data::new(ApplicationBaseUrl(configuration.application.base_url));
+    let hmac_secret = web::Data::new(HmacSecret(configuration.application.hmac_secret));
+    let bot_protection = web::Data::new(BotProtection::new(configuration.bot_protection));
+    let email_domain_policy = web::Data::new(EmailDomainPolicy::new(
+        &configuration.subscriptions.email_domains,
+    ));
+    let subscription_settings = web::Data::new(configuration.subscriptions);
+    let translations =
+        Translations::load(&configuration.localization).map_err(std::io::Error::other)?;
+    let translations = Arc::new(translations);
+    let email_templates = EmailTemplates::load(
+        &configuration.email_templates,
+        &db_pool,
+        Arc::clone(&translations),
+        &configuration.branding.site_name,
+    )
+    .await
+    .map_err(std::io::Error::other)?;
+    let email_templates = web::Data::new(email_templates);
+    let translations = web::Data::from(translations);
+    let branding = web::Data::new(configuration.branding);
+    let admin_user_settings = web::Data::new(configuration.admin_users);
+    let login_throttling_settings = web::Data::new(configuration.login_throttling);
 
     let server = HttpServer::new(move || {
         App::new()
+            .wrap(from_fn(cors))
             .wrap(TracingLogger::default())
-            .route("/", web::get().to(greet))
-            .route("/{name}", web::get().to(greet))
+            .route("/", web::get().to(home))
             .route("/health_check", web::get().to(health_check))
             .route("/subscriptions", web::post().to(subscribe))
+            .route("/subscriptions/form_token", web::get().to(form_token))
             .route("/subscriptions/confirm", web::get().to(confirm))
+            .route("/embed/signup.js", web::get().to(signup_widget))
+            .route(
+                "/subscriptions/unsubscribe",
+                web::get().to(unsubscribe_form),
+            )
+            .route("/subscriptions/unsubscribe", web::post().to(unsubscribe))
+            .route("/archive", web::get().to(archive_page))
+            // Before `/archive/{slug}`, which would match them too.
+            .route("/archive/feed.rss", web::get().to(rss_feed))
+            .route("/archive/feed.atom", web::get().to(atom_feed))
+            .route("/archive/{slug}", web::get().to(archived_issue))
+            .route("/newsletters", web::post().to(publish_newsletter))
+            .route("/newsletters/preview", web::post().to(preview_newsletter))
+            .route("/newsletters/issues", web::post().to(create_issue))
+            .route("/newsletters/issues", web::get().to(list_issues))
+            .route(
+                "/newsletters/issues/{id}",
+                web::get().to(get_newsletter_issue),
+            )
+            .route("/newsletters/issues/{id}", web::put().to(update_issue))
+            .route("/newsletters/issues/{id}", web::delete().to(delete_issue))
+            .route(
+                "/newsletters/issues/{id}/schedule",
+                web::post().to(schedule_issue),
+            )
+            .route(
+                "/newsletters/issues/{id}/cancel",
+                web::post().to(cancel_issue),
+            )
+            .route(
+                "/newsletters/issues/{id}/preview",
+                web::get().to(preview_issue),
+            )
+            .route(
+                "/newsletters/issues/{id}/test",
+                web::post().to(send_test_issue),
+            )
+            .route("/admin/subscribers", web::get().to(admin_list_subscribers))
+            // Before `/admin/subscribers/{id}`, which would match them too.
+            .route(
+                "/admin/subscribers/export",
+                web::get().to(export_subscribers),
+            )
+            .route(
+                "/admin/subscribers/imports",
+                web::post().to(import_subscribers),
+            )
+            .route("/admin/subscribers/imports", web::get().to(list_imports))
+            .route("/admin/subscribers/imports/{id}", web::get().to(get_import))
+            .route(
+                "/admin/subscribers/{id}",
+                web::get().to(admin_get_subscriber),
+            )
+            .route(
+                "/admin/subscribers/{id}",
+                web::patch().to(admin_rename_subscriber),
+            )
+            .route(
+                "/admin/subscribers/{id}",
+                web::delete().to(admin_delete_subscriber),
+            )
+            .route(
+                "/admin/subscribers/{id}/attributes",
+                web::patch().to(admin_update_subscriber_attributes),
+            )
+            .route(
+                "/admin/subscribers/{id}/confirm",
+                web::post().to(admin_confirm_subscriber),
+            )
+            .route(
+                "/admin/subscribers/{id}/unsubscribe",
+                web::post().to(admin_unsubscribe_subscriber),
+            )
+            .route("/admin/suppressions", web::post().to(admin_suppress_email))
+            .route("/admin/segments", web::post().to(create_segment))
+            .route("/admin/segments", web::get().to(admin_list_segments))
+            .route("/admin/segments/{id}", web::get().to(admin_get_segment))
+            .route("/admin/segments/{id}", web::put().to(update_segment))
+            .route("/admin/segments/{id}", web::delete().to(delete_segment))
+            .route("/admin/reports", web::get().to(reports_page))
+            .route("/admin/reports/issues", web::get().to(issues_report))
+            .route("/admin/reports/issues/{id}", web::get().to(issue_report))
+            .route("/admin/reports/growth", web::get().to(growth_report))
+            .route("/admin/webhooks", web::post().to(create_webhook))
+            .route("/admin/webhooks", web::get().to(admin_list_webhooks))
+            .route("/admin/webhooks/{id}", web::delete().to(delete_webhook))
+            .route(
+                "/admin/webhooks/{id}/deliveries",
+                web::get().to(admin_list_webhook_deliveries),
+            )
+            .route(
+                "/admin/webhooks/deliveries/{id}/redeliver",
+                web::post().to(redeliver_webhook),
+            )
+            .route("/admin/users", web::get().to(list_users))
+            .route("/admin/users/{id}", web::delete().to(delete_user))
+            .route("/admin/users/{id}/role", web::put().to(change_role))
+            .route("/admin/invites", web::post().to(invite_user))
+            .route("/admin/invites", web::get().to(list_invites))
+            .route("/admin/invites/{id}", web::delete().to(revoke_invite))
+            .route("/admin/sessions", web::post().to(log_in))
+            .route("/admin/sessions/two-factor", web::post().to(verify_login))
+            .route("/admin/sessions/current", web::delete().to(log_out))
+            .route(
+                "/admin/account/two-factor",
+                web::post().to(start_two_factor),
+            )
+            .route(
+                "/admin/account/two-factor",
+                web::delete().to(disable_two_factor),
+            )
+            .route(
+                "/admin/account/two-factor/confirm",
+                web::post().to(confirm_two_factor),
+            )
+            .route("/invites/accept", web::get().to(invite_form))
+            .route("/invites/accept", web::post().to(accept_invite))
+            .route("/password-reset", web::get().to(forgot_password_form))
+            .route("/password-reset", web::post().to(request_password_reset))
+            .route(
+                "/password-reset/confirm",
+                web::get().to(password_reset_form),
+            )
+            .route("/password-reset/confirm", web::post().to(reset_password))
+            .route("/t/o/{token}", web::get().to(track_open))
+            .route("/t/c/{token}", web::get().to(track_click))
             .app_data(web::Data::clone(&db_pool))
             .app_data(web::Data::clone(&email_client))
             .app_data(web::Data::clone(&base_url))
+            .app_data(web::Data::clone(&hmac_secret))
+            .app_data(web::Data::clone(&bot_protection))
+            .app_data(web::Data::clone(&subscription_settings))
+            .app_data(web::Data::clone(&email_domain_policy))
+            .app_data(web::Data::clone(&branding))
+            .app_data(web::Data::clone(&translations))
+            .app_data(web::Data::clone(&email_templates))
+            .app_data(web::Data::clone(&allowed_origins))
+            .app_data(web::Data::clone(&admin_user_settings))
+            .app_data(web::Data::clone(&login_throttling_settings))
     })
     .listen(listener)?
     .workers(4)
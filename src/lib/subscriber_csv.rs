@@ -0,0 +1,696 @@
+use crate::lib::domain::{NewSubscriber, SubscriberAttributes, SubscriberEmail, SubscriberName};
+use crate::lib::routes::{CONFIRMED, PENDING_CONFIRMATION};
+use crate::lib::subscribers::SubscriberRecord;
+use chrono::{DateTime, Utc};
+use csv::StringRecord;
+use sqlx::{PgExecutor, PgPool};
+use std::collections::HashSet;
+use uuid::Uuid;
+
+pub const IMPORT_RUNNING: &str = "running";
+pub const IMPORT_COMPLETED: &str = "completed";
+pub const IMPORT_FAILED: &str = "failed";
+
+/// Rows are written to the database, and the progress saved, this many at a
+/// time.
+const BATCH_SIZE: usize = 500;
+/// An import keeps the errors of this many rows. Past that it only counts them.
+const MAX_REPORTED_ERRORS: usize = 1000;
+
+/// What becomes of the imported subscribers.
+#[derive(Clone, Copy, Debug, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportMode {
+    /// They confirmed on the platform they come from.
+    Confirmed,
+    /// They are sent a confirmation email, like a new signup.
+    Reconfirm,
+}
+
+impl ImportMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ImportMode::Confirmed => "confirmed",
+            ImportMode::Reconfirm => "reconfirm",
+        }
+    }
+
+    fn status(&self) -> &'static str {
+        match self {
+            ImportMode::Confirmed => CONFIRMED,
+            ImportMode::Reconfirm => PENDING_CONFIRMATION,
+        }
+    }
+}
+
+/// An import, as saved after every batch.
+#[derive(serde::Serialize)]
+pub struct SubscriberImport {
+    pub import_id: Uuid,
+    pub mode: String,
+    pub status: String,
+    pub processed: i32,
+    pub imported: i32,
+    pub duplicates: i32,
+    pub suppressed: i32,
+    pub invalid: i32,
+    /// `[{"row": 3, "error": "..."}]`
+    pub errors: serde_json::Value,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, PartialEq, serde::Serialize)]
+pub struct RowError {
+    /// The header is row 1.
+    pub row: u64,
+    pub error: String,
+}
+
+/// Splits a CSV file into records as its bytes come in.
+///
+/// A quoted field can hold line breaks, so a record ends at the first line
+/// break outside of quotes.
+#[derive(Default)]
+pub struct CsvRecords {
+    buffer: Vec<u8>,
+    /// How much of `buffer` was looked at already.
+    scanned: usize,
+    in_quotes: bool,
+}
+
+impl CsvRecords {
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// The next complete record, with its line break.
+    pub fn next_record(&mut self) -> Option<Vec<u8>> {
+        for i in self.scanned..self.buffer.len() {
+            match self.buffer[i] {
+                // `""`, an escaped quote, toggles twice.
+                b'"' => self.in_quotes = !self.in_quotes,
+                b'\n' if !self.in_quotes => {
+                    let rest = self.buffer.split_off(i + 1);
+                    self.scanned = 0;
+                    return Some(std::mem::replace(&mut self.buffer, rest));
+                }
+                _ => {}
+            }
+        }
+        self.scanned = self.buffer.len();
+        None
+    }
+
+    /// The last record, if the file does not end with a line break.
+    pub fn finish(&mut self) -> Option<Vec<u8>> {
+        self.scanned = 0;
+        self.in_quotes = false;
+        Some(std::mem::take(&mut self.buffer)).filter(|record| !record.is_empty())
+    }
+}
+
+/// Where the columns the import needs are. The others are ignored.
+///
+/// Attributes come from an `attributes` column holding a JSON object, and
+/// from `attributes.<name>` columns holding strings.
+#[derive(Debug, PartialEq)]
+struct ImportColumns {
+    email: usize,
+    name: usize,
+    attributes: Option<usize>,
+    attribute_columns: Vec<(usize, String)>,
+}
+
+impl ImportColumns {
+    fn from_header(header: &StringRecord) -> Result<Self, String> {
+        let columns: Vec<&str> = header
+            .iter()
+            .map(|field| field.trim_start_matches('\u{feff}').trim())
+            .collect();
+        let position = |column: &str| {
+            columns
+                .iter()
+                .position(|field| field.eq_ignore_ascii_case(column))
+        };
+        let required = |column: &str| {
+            position(column).ok_or_else(|| format!("The header has no `{}` column.", column))
+        };
+        Ok(Self {
+            email: required("email")?,
+            name: required("name")?,
+            attributes: position("attributes"),
+            attribute_columns: columns
+                .iter()
+                .enumerate()
+                .filter_map(|(i, field)| {
+                    let key = field.strip_prefix("attributes.")?;
+                    Some((i, key.to_owned()))
+                })
+                .collect(),
+        })
+    }
+
+    fn parse(&self, record: &StringRecord) -> Result<NewSubscriber, String> {
+        let field = |index: usize| record.get(index).unwrap_or_default().trim();
+        let mut attributes = match self.attributes.map(field) {
+            None | Some("") => serde_json::Map::new(),
+            Some(json) => match serde_json::from_str(json) {
+                Ok(serde_json::Value::Object(attributes)) => attributes,
+                _ => return Err("The `attributes` column is not a JSON object.".into()),
+            },
+        };
+        for (index, key) in &self.attribute_columns {
+            // An empty cell leaves the attribute out.
+            if !field(*index).is_empty() {
+                attributes.insert(key.clone(), field(*index).into());
+            }
+        }
+        Ok(NewSubscriber {
+            email: SubscriberEmail::parse(field(self.email))?,
+            name: SubscriberName::parse(field(self.name))?,
+            attributes: SubscriberAttributes::parse(attributes.into())?,
+        })
+    }
+}
+
+fn parse_record(bytes: &[u8]) -> Result<Option<StringRecord>, csv::Error> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(bytes);
+    reader.records().next().transpose()
+}
+
+/// Validates rows as they come in and writes them to the database in
+/// batches, saving the progress of the import as it goes.
+pub struct SubscriberImporter {
+    import_id: Uuid,
+    mode: ImportMode,
+    locale: String,
+    provider_aware_canonicalization: bool,
+    records: CsvRecords,
+    columns: Option<ImportColumns>,
+    row: u64,
+    batch: Vec<(u64, NewSubscriber, String)>,
+    processed: i32,
+    imported: i32,
+    duplicates: i32,
+    suppressed: i32,
+    invalid: i32,
+    errors: Vec<RowError>,
+}
+
+impl SubscriberImporter {
+    pub fn new(
+        import_id: Uuid,
+        mode: ImportMode,
+        locale: &str,
+        provider_aware_canonicalization: bool,
+    ) -> Self {
+        Self {
+            import_id,
+            mode,
+            locale: locale.to_owned(),
+            provider_aware_canonicalization,
+            records: CsvRecords::default(),
+            columns: None,
+            row: 0,
+            batch: Vec::new(),
+            processed: 0,
+            imported: 0,
+            duplicates: 0,
+            suppressed: 0,
+            invalid: 0,
+            errors: Vec::new(),
+        }
+    }
+
+    /// Read the next bytes of the file. Fails if the header is unusable.
+    pub fn feed(&mut self, bytes: &[u8]) -> Result<(), String> {
+        self.records.push(bytes);
+        while let Some(record) = self.records.next_record() {
+            self.read_record(&record)?;
+        }
+        Ok(())
+    }
+
+    /// Read what is left of the file once it is all in.
+    pub fn finish(&mut self) -> Result<(), String> {
+        if let Some(record) = self.records.finish() {
+            self.read_record(&record)?;
+        }
+        if self.columns.is_none() {
+            return Err("The file is empty.".into());
+        }
+        Ok(())
+    }
+
+    /// Whether it is time to `flush`.
+    pub fn batch_is_full(&self) -> bool {
+        self.batch.len() >= BATCH_SIZE
+    }
+
+    fn report_error(&mut self, row: u64, error: String) {
+        if self.errors.len() < MAX_REPORTED_ERRORS {
+            self.errors.push(RowError { row, error });
+        }
+    }
+
+    fn reject(&mut self, row: u64, error: String) {
+        self.invalid += 1;
+        self.report_error(row, error);
+    }
+
+    fn read_record(&mut self, bytes: &[u8]) -> Result<(), String> {
+        self.row += 1;
+        let record = match parse_record(bytes) {
+            // A blank line.
+            Ok(None) => return Ok(()),
+            Ok(Some(record)) => record,
+            Err(e) if self.columns.is_none() => return Err(format!("Invalid header: {}", e)),
+            Err(e) => {
+                self.processed += 1;
+                self.reject(self.row, e.to_string());
+                return Ok(());
+            }
+        };
+        let Some(columns) = &self.columns else {
+            self.columns = Some(ImportColumns::from_header(&record)?);
+            return Ok(());
+        };
+
+        self.processed += 1;
+        match columns.parse(&record) {
+            Ok(subscriber) => {
+                let canonical_email = subscriber
+                    .email
+                    .canonical(self.provider_aware_canonicalization);
+                self.batch.push((self.row, subscriber, canonical_email));
+            }
+            Err(e) => self.reject(self.row, e),
+        }
+        Ok(())
+    }
+
+    /// Write the rows read so far, skipping suppressed addresses and
+    /// subscribers who are already on the list, and save the progress.
+    ///
+    /// In the `reconfirm` mode, the new subscribers are queued for their
+    /// confirmation email: it is sent in the background.
+    #[tracing::instrument(name = "Import a batch of subscribers", skip(self, pool), fields(import_id = %self.import_id))]
+    pub async fn flush(&mut self, pool: &PgPool) -> Result<(), sqlx::Error> {
+        let batch = std::mem::take(&mut self.batch);
+        let mut transaction = pool.begin().await?;
+
+        let canonical_emails: Vec<String> = batch
+            .iter()
+            .map(|(_, _, canonical)| canonical.clone())
+            .collect();
+        let suppressed: HashSet<String> = sqlx::query_scalar!(
+            r#"SELECT email_canonical FROM suppressions WHERE email_canonical = ANY($1)"#,
+            &canonical_emails
+        )
+        .fetch_all(&mut *transaction)
+        .await?
+        .into_iter()
+        .collect();
+        let (skipped, batch): (Vec<_>, Vec<_>) = batch
+            .into_iter()
+            .partition(|(_, _, canonical)| suppressed.contains(canonical));
+        self.suppressed += skipped.len() as i32;
+
+        let ids: Vec<Uuid> = batch.iter().map(|_| Uuid::new_v4()).collect();
+        let emails: Vec<String> = batch
+            .iter()
+            .map(|(_, subscriber, _)| subscriber.email.as_ref().to_owned())
+            .collect();
+        let canonical_emails: Vec<String> = batch
+            .iter()
+            .map(|(_, _, canonical)| canonical.clone())
+            .collect();
+        let names: Vec<String> = batch
+            .iter()
+            .map(|(_, subscriber, _)| subscriber.name.as_ref().to_owned())
+            .collect();
+        let attributes: Vec<serde_json::Value> = batch
+            .iter()
+            .map(|(_, subscriber, _)| subscriber.attributes.clone().into_json())
+            .collect();
+        // Conflicts on the email, or on its canonical form, are duplicates:
+        // of a subscriber, or of an earlier row.
+        let inserted: HashSet<Uuid> = sqlx::query_scalar!(
+            r#"
+            INSERT INTO subscriptions
+                (id, email, email_canonical, name, attributes, subscribed_at, status, locale,
+                confirmed_at)
+            SELECT id, email, email_canonical, name, attributes, now(), $6, $7, $8
+            FROM UNNEST($1::uuid[], $2::text[], $3::text[], $4::text[], $5::jsonb[])
+                AS t(id, email, email_canonical, name, attributes)
+            ON CONFLICT DO NOTHING
+            RETURNING id
+            "#,
+            &ids,
+            &emails,
+            &canonical_emails,
+            &names,
+            &attributes,
+            self.mode.status(),
+            self.locale,
+            (self.mode == ImportMode::Confirmed).then(Utc::now)
+        )
+        .fetch_all(&mut *transaction)
+        .await?
+        .into_iter()
+        .collect();
+        self.imported += inserted.len() as i32;
+        self.duplicates += (batch.len() - inserted.len()) as i32;
+
+        if self.mode == ImportMode::Reconfirm {
+            let inserted: Vec<Uuid> = inserted.into_iter().collect();
+            sqlx::query!(
+                r#"
+                INSERT INTO confirmation_email_queue (subscriber_id, enqueued_at)
+                SELECT *, now() FROM UNNEST($1::uuid[])
+                "#,
+                &inserted
+            )
+            .execute(&mut *transaction)
+            .await?;
+        }
+
+        self.save(&mut *transaction, IMPORT_RUNNING).await?;
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    /// Save how the import went, with its final `status`.
+    pub async fn complete(&self, pool: &PgPool, status: &str) -> Result<(), sqlx::Error> {
+        self.save(pool, status).await
+    }
+
+    #[tracing::instrument(name = "Save the progress of an import", skip(self, executor), fields(import_id = %self.import_id))]
+    async fn save<'e>(
+        &self,
+        executor: impl PgExecutor<'e>,
+        status: &str,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            UPDATE subscriber_imports
+            SET status = $2, processed = $3, imported = $4, duplicates = $5, suppressed = $6,
+                invalid = $7, errors = $8,
+                finished_at = CASE WHEN $2 = $9 THEN NULL ELSE now() END
+            WHERE import_id = $1
+            "#,
+            self.import_id,
+            status,
+            self.processed,
+            self.imported,
+            self.duplicates,
+            self.suppressed,
+            self.invalid,
+            serde_json::to_value(&self.errors).expect("Errors serialize to JSON."),
+            IMPORT_RUNNING
+        )
+        .execute(executor)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to execute query: {:?}", e);
+            e
+        })?;
+        Ok(())
+    }
+}
+
+#[tracing::instrument(name = "Start an import", skip(pool))]
+pub async fn start_import(
+    pool: &PgPool,
+    user_id: Uuid,
+    mode: ImportMode,
+) -> Result<Uuid, sqlx::Error> {
+    let import_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriber_imports (import_id, user_id, mode, status, started_at)
+        VALUES ($1, $2, $3, $4, now())
+        "#,
+        import_id,
+        user_id,
+        mode.as_str(),
+        IMPORT_RUNNING
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(import_id)
+}
+
+/// Marks an import as failed when dropped, unless it was completed or failed
+/// already: the request importing it may end halfway, e.g. when the upload is
+/// cut short.
+pub struct UnfinishedImport {
+    pool: PgPool,
+    import_id: Uuid,
+}
+
+impl UnfinishedImport {
+    pub fn new(pool: PgPool, import_id: Uuid) -> Self {
+        Self { pool, import_id }
+    }
+}
+
+impl Drop for UnfinishedImport {
+    fn drop(&mut self) {
+        let pool = self.pool.clone();
+        let import_id = self.import_id;
+        tokio::spawn(async move {
+            let failed = sqlx::query!(
+                r#"
+                UPDATE subscriber_imports SET status = $2, finished_at = now()
+                WHERE import_id = $1 AND status = $3
+                "#,
+                import_id,
+                IMPORT_FAILED,
+                IMPORT_RUNNING
+            )
+            .execute(&pool)
+            .await;
+            match failed {
+                Ok(done) if done.rows_affected() > 0 => {
+                    tracing::warn!(%import_id, "An import stopped before it was done.");
+                }
+                Ok(_) => {}
+                Err(e) => tracing::error!("Failed to execute query: {:?}", e),
+            }
+        });
+    }
+}
+
+/// The imports, the latest first, or only `import_id`.
+#[tracing::instrument(name = "Get imports", skip(pool))]
+pub async fn get_imports(
+    pool: &PgPool,
+    import_id: Option<Uuid>,
+) -> Result<Vec<SubscriberImport>, sqlx::Error> {
+    sqlx::query_as!(
+        SubscriberImport,
+        r#"
+        SELECT
+            import_id, mode, status, processed, imported, duplicates, suppressed, invalid,
+            errors, started_at, finished_at
+        FROM subscriber_imports
+        WHERE $1::uuid IS NULL OR import_id = $1
+        ORDER BY started_at DESC
+        "#,
+        import_id
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })
+}
+
+/// A subscriber as a row of an export. It can be imported back.
+#[derive(serde::Serialize)]
+pub struct ExportRow {
+    pub id: Uuid,
+    pub email: String,
+    pub name: String,
+    pub status: String,
+    pub locale: String,
+    pub subscribed_at: DateTime<Utc>,
+    pub confirmed_at: Option<DateTime<Utc>>,
+    pub unsubscribed_at: Option<DateTime<Utc>>,
+    /// Their list slugs, separated by `;`.
+    pub lists: String,
+    /// As JSON.
+    pub attributes: String,
+}
+
+impl From<SubscriberRecord> for ExportRow {
+    fn from(subscriber: SubscriberRecord) -> Self {
+        Self {
+            id: subscriber.id,
+            email: subscriber.email,
+            name: subscriber.name,
+            status: subscriber.status,
+            locale: subscriber.locale,
+            subscribed_at: subscriber.subscribed_at,
+            confirmed_at: subscriber.confirmed_at,
+            unsubscribed_at: subscriber.unsubscribed_at,
+            lists: subscriber.lists.join(";"),
+            attributes: subscriber.attributes.to_string(),
+        }
+    }
+}
+
+/// A chunk of an export: its rows, after the header if it is the first one.
+pub fn export_chunk(rows: Vec<ExportRow>, with_header: bool) -> Result<Vec<u8>, csv::Error> {
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(with_header)
+        .from_writer(vec![]);
+    for row in rows {
+        writer.serialize(row)?;
+    }
+    writer.into_inner().map_err(|e| e.into_error().into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records(chunks: &[&str]) -> Vec<String> {
+        let mut records = CsvRecords::default();
+        let mut all = Vec::new();
+        for chunk in chunks {
+            records.push(chunk.as_bytes());
+            while let Some(record) = records.next_record() {
+                all.push(String::from_utf8(record).unwrap());
+            }
+        }
+        all.extend(
+            records
+                .finish()
+                .map(|record| String::from_utf8(record).unwrap()),
+        );
+        all
+    }
+
+    #[test]
+    fn records_are_split_across_chunks() {
+        assert_eq!(
+            records(&[
+                "email,na",
+                "me\nursula@example.com,Ursula\r\nged@",
+                "example.com,Ged"
+            ]),
+            vec![
+                "email,name\n",
+                "ursula@example.com,Ursula\r\n",
+                "ged@example.com,Ged"
+            ]
+        );
+    }
+
+    #[test]
+    fn quoted_line_breaks_do_not_end_a_record() {
+        assert_eq!(
+            records(&[
+                "email,name\na@example.com,\"Le \"\"Gu",
+                "in\"\"\nUrsula\"\nb@example.com,B\n"
+            ]),
+            vec![
+                "email,name\n",
+                "a@example.com,\"Le \"\"Guin\"\"\nUrsula\"\n",
+                "b@example.com,B\n"
+            ]
+        );
+    }
+
+    #[test]
+    fn columns_are_found_in_any_order_and_case() {
+        let header = StringRecord::from(vec!["\u{feff}Name", "id", " EMAIL "]);
+
+        assert_eq!(
+            ImportColumns::from_header(&header),
+            Ok(ImportColumns {
+                email: 2,
+                name: 0,
+                attributes: None,
+                attribute_columns: vec![],
+            })
+        );
+    }
+
+    #[test]
+    fn attributes_come_from_json_and_from_their_own_columns() {
+        let header = StringRecord::from(vec!["email", "name", "attributes", "attributes.plan"]);
+        let columns = ImportColumns::from_header(&header).unwrap();
+        let record = StringRecord::from(vec![
+            "ged@example.com",
+            "Ged",
+            r#"{"seats": 3, "plan": "free"}"#,
+            "pro",
+        ]);
+
+        let subscriber = columns.parse(&record).unwrap();
+
+        assert_eq!(
+            subscriber.attributes.into_json(),
+            serde_json::json!({"seats": 3, "plan": "pro"})
+        );
+    }
+
+    #[test]
+    fn invalid_attributes_reject_the_row() {
+        let header = StringRecord::from(vec!["email", "name", "attributes"]);
+        let columns = ImportColumns::from_header(&header).unwrap();
+
+        for attributes in ["[1, 2]", "{not json}", r#"{"Plan": "pro"}"#] {
+            let record = StringRecord::from(vec!["ged@example.com", "Ged", attributes]);
+            assert!(columns.parse(&record).is_err(), "{} is valid", attributes);
+        }
+    }
+
+    #[test]
+    fn a_header_without_email_is_rejected() {
+        let header = StringRecord::from(vec!["name", "mail"]);
+
+        assert!(ImportColumns::from_header(&header).is_err());
+    }
+
+    #[test]
+    fn invalid_rows_are_reported_with_their_number() {
+        let mut importer =
+            SubscriberImporter::new(Uuid::new_v4(), ImportMode::Confirmed, "en", false);
+
+        importer
+            .feed(b"email,name\nursula@example.com,Ursula\nnot-an-email,Ged\n\nb@example.com,\n")
+            .unwrap();
+        importer.finish().unwrap();
+
+        assert_eq!(importer.processed, 3);
+        assert_eq!(importer.batch.len(), 1);
+        assert_eq!(importer.invalid, 2);
+        assert_eq!(
+            importer.errors.iter().map(|e| e.row).collect::<Vec<_>>(),
+            vec![3, 5]
+        );
+    }
+
+    #[test]
+    fn an_empty_file_is_rejected() {
+        let mut importer =
+            SubscriberImporter::new(Uuid::new_v4(), ImportMode::Confirmed, "en", false);
+
+        importer.feed(b"").unwrap();
+
+        assert!(importer.finish().is_err());
+    }
+}
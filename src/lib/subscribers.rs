@@ -0,0 +1,191 @@
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use chrono::{DateTime, Days, NaiveDate, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// A subscriber, as the people running the newsletter see them.
+#[derive(serde::Serialize)]
+pub struct SubscriberRecord {
+    pub id: Uuid,
+    pub email: String,
+    pub name: String,
+    pub status: String,
+    pub locale: String,
+    pub subscribed_at: DateTime<Utc>,
+    pub confirmed_at: Option<DateTime<Utc>>,
+    pub unsubscribed_at: Option<DateTime<Utc>>,
+    pub attributes: serde_json::Value,
+    /// The slugs of their lists.
+    pub lists: Vec<String>,
+}
+
+/// Which subscribers to list. Every filter is optional.
+#[derive(Debug, Default, serde::Deserialize, serde::Serialize)]
+pub struct SubscriberFilters {
+    pub status: Option<String>,
+    /// The slug of a list they are on.
+    pub list: Option<String>,
+    /// Signed up on this day, in UTC, or later.
+    pub subscribed_from: Option<NaiveDate>,
+    /// Signed up on this day, in UTC, or earlier.
+    pub subscribed_to: Option<NaiveDate>,
+    /// Part of their email or of their name, in any case.
+    pub q: Option<String>,
+}
+
+/// Where a page of subscribers ends: the next page starts after it.
+#[derive(Debug, PartialEq)]
+pub struct Cursor {
+    pub subscribed_at: DateTime<Utc>,
+    pub id: Uuid,
+}
+
+impl Cursor {
+    pub fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(format!(
+            "{}_{}",
+            self.subscribed_at.timestamp_micros(),
+            self.id
+        ))
+    }
+
+    pub fn decode(cursor: &str) -> Option<Self> {
+        let cursor = String::from_utf8(URL_SAFE_NO_PAD.decode(cursor).ok()?).ok()?;
+        let (micros, id) = cursor.split_once('_')?;
+        Some(Self {
+            subscribed_at: DateTime::from_timestamp_micros(micros.parse().ok()?)?,
+            id: id.parse().ok()?,
+        })
+    }
+}
+
+/// Up to `limit` subscribers matching `filters`, the latest signups first,
+/// starting after `after`.
+///
+/// Pages are keyed on `(subscribed_at, id)`: they stay consistent while
+/// people sign up, and the last page is as fast as the first.
+#[tracing::instrument(name = "List subscribers", skip(pool))]
+pub async fn list_subscribers(
+    pool: &PgPool,
+    filters: &SubscriberFilters,
+    after: Option<&Cursor>,
+    limit: i64,
+) -> Result<Vec<SubscriberRecord>, sqlx::Error> {
+    let start_of_day = |day: NaiveDate| day.and_hms_opt(0, 0, 0).expect("Midnight.").and_utc();
+    let subscribed_from = filters.subscribed_from.map(start_of_day);
+    let subscribed_before = filters
+        .subscribed_to
+        .and_then(|day| day.checked_add_days(Days::new(1)))
+        .map(start_of_day);
+    let pattern = filters
+        .q
+        .as_deref()
+        .map(|q| format!("%{}%", escape_like(q)));
+
+    sqlx::query_as!(
+        SubscriberRecord,
+        r#"
+        SELECT
+            s.id, s.email, s.name, s.status, s.locale, s.subscribed_at, s.confirmed_at,
+            s.unsubscribed_at, s.attributes,
+            ARRAY(
+                SELECT l.slug
+                FROM list_subscriptions ls JOIN lists l ON l.list_id = ls.list_id
+                WHERE ls.subscriber_id = s.id
+                ORDER BY l.slug
+            ) AS "lists!"
+        FROM subscriptions s
+        WHERE ($1::text IS NULL OR s.status = $1)
+            AND ($2::text IS NULL OR EXISTS (
+                SELECT 1
+                FROM list_subscriptions ls JOIN lists l ON l.list_id = ls.list_id
+                WHERE ls.subscriber_id = s.id AND l.slug = $2
+            ))
+            AND ($3::timestamptz IS NULL OR s.subscribed_at >= $3)
+            AND ($4::timestamptz IS NULL OR s.subscribed_at < $4)
+            AND ($5::text IS NULL OR s.email ILIKE $5 OR s.name ILIKE $5)
+            AND ($6::timestamptz IS NULL OR (s.subscribed_at, s.id) < ($6, $7))
+        ORDER BY s.subscribed_at DESC, s.id DESC
+        LIMIT $8
+        "#,
+        filters.status,
+        filters.list,
+        subscribed_from,
+        subscribed_before,
+        pattern,
+        after.map(|cursor| cursor.subscribed_at),
+        after.map(|cursor| cursor.id),
+        limit
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })
+}
+
+#[tracing::instrument(name = "Get a subscriber record", skip(executor))]
+pub async fn get_subscriber_record<'e>(
+    executor: impl sqlx::PgExecutor<'e>,
+    subscriber_id: Uuid,
+) -> Result<Option<SubscriberRecord>, sqlx::Error> {
+    sqlx::query_as!(
+        SubscriberRecord,
+        r#"
+        SELECT
+            s.id, s.email, s.name, s.status, s.locale, s.subscribed_at, s.confirmed_at,
+            s.unsubscribed_at, s.attributes,
+            ARRAY(
+                SELECT l.slug
+                FROM list_subscriptions ls JOIN lists l ON l.list_id = ls.list_id
+                WHERE ls.subscriber_id = s.id
+                ORDER BY l.slug
+            ) AS "lists!"
+        FROM subscriptions s
+        WHERE s.id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(executor)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })
+}
+
+/// Match `text` literally in a `LIKE` pattern.
+fn escape_like(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn cursors_round_trip() {
+        let cursor = Cursor {
+            subscribed_at: Utc.timestamp_micros(1_760_875_200_123_456).unwrap(),
+            id: Uuid::new_v4(),
+        };
+
+        assert_eq!(Cursor::decode(&cursor.encode()), Some(cursor));
+    }
+
+    #[test]
+    fn invalid_cursors_are_rejected() {
+        assert!(Cursor::decode("not a cursor").is_none());
+        assert!(Cursor::decode(&URL_SAFE_NO_PAD.encode("12_not-a-uuid")).is_none());
+    }
+
+    #[test]
+    fn like_wildcards_are_matched_literally() {
+        assert_eq!(escape_like(r"100%_\"), r"100\%\_\\");
+    }
+}
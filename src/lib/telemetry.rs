@@ -24,11 +24,10 @@ where
 
     // The `with` method is provided by `SubscriberExt` and extension
     // that for `Subscriber` exposed by `tracing_subscriber`.
-    let subscriber = Registry::default()
+    Registry::default()
         .with(env_filter)
         .with(JsonStorageLayer)
-        .with(formatting_layer);
-    subscriber
+        .with(formatting_layer)
 }
 
 /// Register a subscriber as global default to process span data.
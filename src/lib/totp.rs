@@ -0,0 +1,190 @@
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+
+/// The digits of the codes authenticator apps show.
+const DIGITS: u32 = 6;
+/// How long each code is valid for, in seconds.
+const STEP_SECONDS: i64 = 30;
+/// Codes from one step before or after now are accepted too: phones and
+/// servers rarely agree on the time to the second.
+const ALLOWED_DRIFT_STEPS: i64 = 1;
+/// The length of the secrets we generate: what RFC 4226 recommends for SHA-1.
+const SECRET_BYTES: usize = 20;
+
+/// The hash function behind the codes. Authenticator apps all support SHA-1:
+/// it is the one we use, the others are here for completeness.
+#[derive(Clone, Copy, Debug)]
+pub enum Algorithm {
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+/// An HMAC-based one-time password, as in RFC 4226: `digits` digits derived
+/// from `counter`.
+pub fn hotp(key: &[u8], counter: u64, digits: u32, algorithm: Algorithm) -> String {
+    fn mac<M: Mac + hmac::digest::KeyInit>(key: &[u8], message: &[u8]) -> Vec<u8> {
+        let mut mac = <M as Mac>::new_from_slice(key).expect("HMAC takes keys of any size.");
+        mac.update(message);
+        mac.finalize().into_bytes().to_vec()
+    }
+    let message = counter.to_be_bytes();
+    let hash = match algorithm {
+        Algorithm::Sha1 => mac::<Hmac<sha1::Sha1>>(key, &message),
+        Algorithm::Sha256 => mac::<Hmac<sha2::Sha256>>(key, &message),
+        Algorithm::Sha512 => mac::<Hmac<sha2::Sha512>>(key, &message),
+    };
+    // Dynamic truncation: the last nibble picks where to read 31 bits from.
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+    format!(
+        "{:0width$}",
+        binary % 10u32.pow(digits),
+        width = digits as usize
+    )
+}
+
+/// A time-based one-time password, as in RFC 6238: the HOTP of the number of
+/// `step_seconds` steps since the Unix epoch.
+pub fn totp(
+    key: &[u8],
+    unix_time: i64,
+    step_seconds: i64,
+    digits: u32,
+    algorithm: Algorithm,
+) -> String {
+    hotp(key, (unix_time / step_seconds) as u64, digits, algorithm)
+}
+
+/// The secret shared with the authenticator app of a user.
+pub struct TotpSecret(Vec<u8>);
+
+impl TotpSecret {
+    pub fn generate() -> Self {
+        let mut secret = vec![0; SECRET_BYTES];
+        rand::rng().fill_bytes(&mut secret);
+        Self(secret)
+    }
+
+    /// As typed into authenticator apps, and as stored.
+    pub fn parse(base32: &str) -> Option<Self> {
+        BASE32_NOPAD.decode(base32.as_bytes()).ok().map(Self)
+    }
+
+    pub fn to_base32(&self) -> String {
+        BASE32_NOPAD.encode(&self.0)
+    }
+
+    /// The `otpauth://` URI authenticator apps read from a QR code.
+    pub fn provisioning_uri(&self, issuer: &str, account: &str) -> String {
+        let mut uri = reqwest::Url::parse("otpauth://totp/").expect("A valid URI.");
+        uri.set_path(&format!("{}:{}", issuer, account));
+        uri.query_pairs_mut()
+            .append_pair("secret", &self.to_base32())
+            .append_pair("issuer", issuer)
+            .append_pair("algorithm", "SHA1")
+            .append_pair("digits", &DIGITS.to_string())
+            .append_pair("period", &STEP_SECONDS.to_string());
+        uri.to_string()
+    }
+
+    /// The step `code` was generated for, if it is valid at `unix_time`.
+    /// Callers refuse steps already used, so that a code works only once.
+    pub fn verify(&self, code: &str, unix_time: i64) -> Option<i64> {
+        let code = code.trim();
+        if code.len() != DIGITS as usize || !code.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let now = unix_time / STEP_SECONDS;
+        (now - ALLOWED_DRIFT_STEPS..=now + ALLOWED_DRIFT_STEPS).find(|step| {
+            let expected = hotp(&self.0, *step as u64, DIGITS, Algorithm::Sha1);
+            // Compare in constant time: the code is a secret until used.
+            expected
+                .bytes()
+                .zip(code.bytes())
+                .fold(0, |difference, (a, b)| difference | (a ^ b))
+                == 0
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The seeds of RFC 6238, Appendix B.
+    const SHA1_SEED: &[u8] = b"12345678901234567890";
+    const SHA256_SEED: &[u8] = b"12345678901234567890123456789012";
+    const SHA512_SEED: &[u8] = b"1234567890123456789012345678901234567890123456789012345678901234";
+
+    #[test]
+    fn hotp_matches_the_rfc_4226_test_values() {
+        let expected = [
+            "755224", "287082", "359152", "969429", "338314", "254676", "287922", "162583",
+            "399871", "520489",
+        ];
+        for (counter, code) in expected.iter().enumerate() {
+            assert_eq!(
+                hotp(SHA1_SEED, counter as u64, 6, Algorithm::Sha1),
+                *code,
+                "counter {}",
+                counter
+            );
+        }
+    }
+
+    #[test]
+    fn totp_matches_the_rfc_6238_test_vectors() {
+        let vectors: [(i64, &str, &str, &str); 6] = [
+            (59, "94287082", "46119246", "90693936"),
+            (1111111109, "07081804", "68084774", "25091201"),
+            (1111111111, "14050471", "67062674", "99943326"),
+            (1234567890, "89005924", "91819424", "93441116"),
+            (2000000000, "69279037", "90698825", "38618901"),
+            (20000000000, "65353130", "77737706", "47863826"),
+        ];
+        for (time, sha1, sha256, sha512) in vectors {
+            assert_eq!(totp(SHA1_SEED, time, 30, 8, Algorithm::Sha1), sha1);
+            assert_eq!(totp(SHA256_SEED, time, 30, 8, Algorithm::Sha256), sha256);
+            assert_eq!(totp(SHA512_SEED, time, 30, 8, Algorithm::Sha512), sha512);
+        }
+    }
+
+    #[test]
+    fn codes_from_the_previous_and_next_steps_are_accepted() {
+        let secret = TotpSecret(SHA1_SEED.to_vec());
+        let now = 1111111111;
+        let code_at = |time| totp(SHA1_SEED, time, 30, 6, Algorithm::Sha1);
+
+        assert_eq!(secret.verify(&code_at(now), now), Some(now / 30));
+        assert_eq!(secret.verify(&code_at(now - 30), now), Some(now / 30 - 1));
+        assert_eq!(secret.verify(&code_at(now + 30), now), Some(now / 30 + 1));
+        assert_eq!(secret.verify(&code_at(now - 90), now), None);
+        assert_eq!(secret.verify("12345", now), None);
+        assert_eq!(secret.verify("abcdef", now), None);
+    }
+
+    #[test]
+    fn secrets_round_trip_through_base32() {
+        let secret = TotpSecret(SHA1_SEED.to_vec());
+        assert_eq!(secret.to_base32(), "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ");
+        assert_eq!(TotpSecret::parse(&secret.to_base32()).unwrap().0, SHA1_SEED);
+    }
+
+    #[test]
+    fn the_provisioning_uri_names_the_issuer_and_the_account() {
+        let secret = TotpSecret(SHA1_SEED.to_vec());
+        assert_eq!(
+            secret.provisioning_uri("Zero To Production", "admin"),
+            "otpauth://totp/Zero%20To%20Production:admin\
+             ?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&issuer=Zero+To+Production\
+             &algorithm=SHA1&digits=6&period=30"
+        );
+    }
+}
@@ -0,0 +1,346 @@
+use actix_web::HttpRequest;
+use actix_web::http::header::USER_AGENT;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, SecretString};
+use sha2::{Digest, Sha256};
+use std::ops::Range;
+use uuid::Uuid;
+
+/// The kinds of issue events: tracked by the links of the issue...
+pub const OPEN: &str = "open";
+pub const CLICK: &str = "click";
+/// ...or recorded when the email is handed to the email API, or given up on.
+pub const DELIVERED: &str = "delivered";
+pub const BOUNCED: &str = "bounced";
+
+/// A transparent 1x1 GIF.
+pub const PIXEL: &[u8] = &[
+    0x47, 0x49, 0x46, 0x38, 0x39, 0x61, 0x01, 0x00, 0x01, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0xff, 0xff, 0xff, 0x21, 0xf9, 0x04, 0x01, 0x00, 0x00, 0x00, 0x00, 0x2c, 0x00, 0x00, 0x00, 0x00,
+    0x01, 0x00, 0x01, 0x00, 0x00, 0x02, 0x02, 0x44, 0x01, 0x00, 0x3b,
+];
+
+/// Parts of the user agent of link scanners, crawlers and scripts.
+const BOT_MARKERS: &[&str] = &[
+    "bot",
+    "crawler",
+    "spider",
+    "slurp",
+    "scanner",
+    "preview",
+    "headless",
+    "curl",
+    "wget",
+    "python-requests",
+    "go-http-client",
+    "barracuda",
+    "mimecast",
+    "proofpoint",
+];
+
+const MAC_LENGTH: usize = 32;
+
+/// What a tracking link stands for: who got which issue, and the link they
+/// clicked, if any.
+#[derive(Debug, PartialEq)]
+pub struct TrackingToken {
+    pub newsletter_issue_id: Uuid,
+    pub subscriber_id: Uuid,
+    pub url: Option<String>,
+}
+
+impl TrackingToken {
+    /// Tokens are self-contained: nothing is stored when an issue is sent.
+    pub fn encode(&self, hmac_secret: &SecretString) -> String {
+        let mut payload = Vec::with_capacity(32 + self.url.as_ref().map_or(0, |url| url.len()));
+        payload.extend_from_slice(self.newsletter_issue_id.as_bytes());
+        payload.extend_from_slice(self.subscriber_id.as_bytes());
+        if let Some(url) = &self.url {
+            payload.extend_from_slice(url.as_bytes());
+        }
+        let mac = mac(hmac_secret, self.kind(), &payload);
+        let mut token = mac.finalize().into_bytes().to_vec();
+        token.extend_from_slice(&payload);
+        URL_SAFE_NO_PAD.encode(token)
+    }
+
+    /// `None` unless `token` was signed by us, for the same `kind` of event.
+    pub fn decode(token: &str, kind: &str, hmac_secret: &SecretString) -> Option<Self> {
+        let token = URL_SAFE_NO_PAD.decode(token).ok()?;
+        if token.len() < MAC_LENGTH + 32 {
+            return None;
+        }
+        let (signature, payload) = token.split_at(MAC_LENGTH);
+        mac(hmac_secret, kind, payload)
+            .verify_slice(signature)
+            .ok()?;
+
+        let newsletter_issue_id = Uuid::from_slice(&payload[..16]).ok()?;
+        let subscriber_id = Uuid::from_slice(&payload[16..32]).ok()?;
+        let url = match kind {
+            CLICK => Some(String::from_utf8(payload[32..].to_vec()).ok()?),
+            _ => None,
+        };
+        Some(Self {
+            newsletter_issue_id,
+            subscriber_id,
+            url,
+        })
+    }
+
+    fn kind(&self) -> &'static str {
+        match self.url {
+            Some(_) => CLICK,
+            None => OPEN,
+        }
+    }
+}
+
+fn mac(hmac_secret: &SecretString, kind: &str, payload: &[u8]) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(hmac_secret.expose_secret().as_bytes())
+        .expect("HMAC can take a key of any size");
+    mac.update(b"tracking:");
+    mac.update(kind.as_bytes());
+    mac.update(b":");
+    mac.update(payload);
+    mac
+}
+
+/// Adds open and click tracking to the content of one issue.
+pub struct IssueTracker<'a> {
+    base_url: &'a str,
+    hmac_secret: &'a SecretString,
+    newsletter_issue_id: Uuid,
+}
+
+impl<'a> IssueTracker<'a> {
+    pub fn new(
+        base_url: &'a str,
+        hmac_secret: &'a SecretString,
+        newsletter_issue_id: Uuid,
+    ) -> Self {
+        Self {
+            base_url,
+            hmac_secret,
+            newsletter_issue_id,
+        }
+    }
+
+    /// Send the web links of `html` through the click redirect and add the
+    /// open pixel at the end.
+    pub fn track(&self, html: &str, subscriber_id: Uuid) -> String {
+        let token = |url: Option<String>| {
+            TrackingToken {
+                newsletter_issue_id: self.newsletter_issue_id,
+                subscriber_id,
+                url,
+            }
+            .encode(self.hmac_secret)
+        };
+
+        let mut tracked = String::with_capacity(html.len());
+        let mut last = 0;
+        for (range, url) in hrefs(html) {
+            if !is_web_link(&url) {
+                continue;
+            }
+            tracked.push_str(&html[last..range.start]);
+            tracked.push_str(&format!("{}/t/c/{}", self.base_url, token(Some(url))));
+            last = range.end;
+        }
+        tracked.push_str(&html[last..]);
+        tracked.push_str(&format!(
+            r#"<img src="{}/t/o/{}" width="1" height="1" alt="" style="display: block; border: 0;">"#,
+            self.base_url,
+            token(None)
+        ));
+        tracked
+    }
+
+    /// The links `track` sent through the click redirect, found in the email
+    /// they ended up in.
+    pub fn tracked_links(&self, html: &str) -> Vec<String> {
+        let redirect = format!("{}/t/c/", self.base_url);
+        hrefs(html)
+            .into_iter()
+            .filter_map(|(_, href)| {
+                let token = href.strip_prefix(&redirect)?;
+                TrackingToken::decode(token, CLICK, self.hmac_secret)?.url
+            })
+            .collect()
+    }
+}
+
+/// What is stored of the links of a sent issue: links can be longer than
+/// what fits in an index.
+pub fn link_hash(url: &str) -> String {
+    hex::encode(Sha256::digest(url.as_bytes()))
+}
+
+/// The links of `html`, as they would be followed.
+pub fn links(html: &str) -> Vec<String> {
+    hrefs(html).into_iter().map(|(_, url)| url).collect()
+}
+
+/// Whether the request was most likely made by a program rather than by
+/// somebody reading the issue: scanners, crawlers and browsers prefetching
+/// links.
+pub fn is_automated(req: &HttpRequest) -> bool {
+    let headers = req.headers();
+    let prefetch = ["purpose", "sec-purpose", "x-purpose", "x-moz"]
+        .iter()
+        .filter_map(|name| headers.get(*name)?.to_str().ok())
+        .any(|value| {
+            let value = value.to_ascii_lowercase();
+            value.contains("prefetch") || value.contains("preview")
+        });
+    if prefetch || req.method() == actix_web::http::Method::HEAD {
+        return true;
+    }
+    match headers.get(USER_AGENT).and_then(|ua| ua.to_str().ok()) {
+        Some(user_agent) => {
+            let user_agent = user_agent.to_ascii_lowercase();
+            BOT_MARKERS.iter().any(|marker| user_agent.contains(marker))
+        }
+        None => true,
+    }
+}
+
+fn is_web_link(url: &str) -> bool {
+    let url = url.trim_start().to_ascii_lowercase();
+    url.starts_with("https://") || url.starts_with("http://")
+}
+
+/// Every `href` attribute of `html`: where its raw value is, and the value
+/// unescaped.
+fn hrefs(html: &str) -> Vec<(Range<usize>, String)> {
+    // Same byte offsets as `html`: only ASCII letters change.
+    let lowercase = html.to_ascii_lowercase();
+    let mut hrefs = vec![];
+    let mut from = 0;
+    while let Some(found) = lowercase[from..].find("href=") {
+        let attribute = from + found;
+        from = attribute + "href=".len();
+        // Not `data-href=`, nor text.
+        if !html[..attribute].ends_with(|c: char| c.is_ascii_whitespace()) {
+            continue;
+        }
+        let Some(quote) = html[from..]
+            .chars()
+            .next()
+            .filter(|c| *c == '"' || *c == '\'')
+        else {
+            continue;
+        };
+        let start = from + 1;
+        let Some(length) = html[start..].find(quote) else {
+            break;
+        };
+        let range = start..start + length;
+        from = range.end;
+        hrefs.push((range.clone(), unescape(&html[range])));
+    }
+    hrefs
+}
+
+fn unescape(value: &str) -> String {
+    value
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&#x27;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secret() -> SecretString {
+        SecretString::from("secret")
+    }
+
+    fn tracker(hmac_secret: &SecretString) -> IssueTracker<'_> {
+        IssueTracker::new("https://example.com", hmac_secret, Uuid::new_v4())
+    }
+
+    #[test]
+    fn tokens_round_trip() {
+        let token = TrackingToken {
+            newsletter_issue_id: Uuid::new_v4(),
+            subscriber_id: Uuid::new_v4(),
+            url: Some("https://www.zero2prod.com/?a=1&b=2".into()),
+        };
+
+        let encoded = token.encode(&secret());
+
+        assert_eq!(
+            TrackingToken::decode(&encoded, CLICK, &secret()),
+            Some(token)
+        );
+    }
+
+    #[test]
+    fn tokens_are_only_valid_for_their_kind_and_secret() {
+        let open = TrackingToken {
+            newsletter_issue_id: Uuid::new_v4(),
+            subscriber_id: Uuid::new_v4(),
+            url: None,
+        }
+        .encode(&secret());
+
+        assert!(TrackingToken::decode(&open, CLICK, &secret()).is_none());
+        assert!(TrackingToken::decode(&open, OPEN, &SecretString::from("other")).is_none());
+        assert!(TrackingToken::decode("not-a-token", OPEN, &secret()).is_none());
+    }
+
+    #[test]
+    fn web_links_are_tracked() {
+        let hmac_secret = secret();
+        let html = r#"<p><a href="https://www.zero2prod.com/?a=1&amp;b=2">Book</a> <a href="mailto:me@example.com">Mail</a></p>"#;
+
+        let tracked = tracker(&hmac_secret).track(html, Uuid::new_v4());
+
+        let links = links(&tracked);
+        assert_eq!(links.len(), 2);
+        let token = links[0].strip_prefix("https://example.com/t/c/").unwrap();
+        let token = TrackingToken::decode(token, CLICK, &hmac_secret).unwrap();
+        assert_eq!(token.url.unwrap(), "https://www.zero2prod.com/?a=1&b=2");
+        assert_eq!(links[1], "mailto:me@example.com");
+    }
+
+    #[test]
+    fn the_tracked_links_are_found_in_the_email() {
+        let hmac_secret = secret();
+        let tracker = tracker(&hmac_secret);
+        let html = r#"<a href="https://a.com/?x=1&amp;y=2">A</a> <a href="mailto:me@example.com">Mail</a>"#;
+
+        let email = format!(
+            r#"<body>{}<a href="https://example.com/unsubscribe">Bye</a></body>"#,
+            tracker.track(html, Uuid::new_v4())
+        );
+
+        assert_eq!(
+            tracker.tracked_links(&email),
+            vec!["https://a.com/?x=1&y=2"]
+        );
+    }
+
+    #[test]
+    fn the_pixel_is_added() {
+        let hmac_secret = secret();
+
+        let tracked = tracker(&hmac_secret).track("<p>Hello</p>", Uuid::new_v4());
+
+        assert!(tracked.starts_with("<p>Hello</p><img src=\"https://example.com/t/o/"));
+    }
+
+    #[test]
+    fn only_href_attributes_are_links() {
+        let html = r#"<a data-href="https://a.com" href='https://b.com'>href="https://c.com"</a>"#;
+        assert_eq!(links(html), vec!["https://b.com".to_owned()]);
+    }
+}
@@ -0,0 +1,165 @@
+use crate::lib::totp::TotpSecret;
+use chrono::Utc;
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use sqlx::{Postgres, Transaction};
+use uuid::Uuid;
+
+/// How many recovery codes users get when they turn two-factor
+/// authentication on.
+const RECOVERY_CODE_COUNT: usize = 10;
+/// Letters and digits nobody mistakes for one another when typing them back.
+const RECOVERY_CODE_ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+
+/// How a user gave their second factor.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SecondFactor {
+    Totp,
+    RecoveryCode,
+}
+
+/// Fresh recovery codes, like `k3m9-x2qa-7hpf-cw4n`.
+pub fn generate_recovery_codes() -> Vec<String> {
+    let mut rng = rand::rng();
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            (0..4)
+                .map(|_| {
+                    (0..4)
+                        .map(|_| {
+                            char::from(
+                                RECOVERY_CODE_ALPHABET
+                                    [rng.random_range(0..RECOVERY_CODE_ALPHABET.len())],
+                            )
+                        })
+                        .collect::<String>()
+                })
+                .collect::<Vec<_>>()
+                .join("-")
+        })
+        .collect()
+}
+
+/// Recovery codes are stored as hashes, ignoring case, dashes and spaces
+/// users may add or leave out when typing them.
+fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    hex::encode(Sha256::digest(normalized.as_bytes()))
+}
+
+/// Replace the recovery codes of a user.
+#[tracing::instrument(name = "Store recovery codes", skip(transaction, codes))]
+pub async fn store_recovery_codes(
+    transaction: &mut Transaction<'_, Postgres>,
+    user_id: Uuid,
+    codes: &[String],
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(r#"DELETE FROM recovery_codes WHERE user_id = $1"#, user_id)
+        .execute(&mut **transaction)
+        .await?;
+    let hashes: Vec<String> = codes.iter().map(|code| hash_recovery_code(code)).collect();
+    sqlx::query!(
+        r#"
+        INSERT INTO recovery_codes (user_id, code_hash)
+        SELECT $1, * FROM UNNEST($2::text[])
+        ON CONFLICT DO NOTHING
+        "#,
+        user_id,
+        &hashes
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+/// Check the second factor of a user with two-factor authentication on: a
+/// code from their authenticator app, or one of their recovery codes.
+///
+/// Either works only once: the step of the code is remembered, the recovery
+/// code marked as used. The user is locked until the end of the transaction,
+/// so that the same code sent twice at once is only accepted once.
+#[tracing::instrument(name = "Verify a second factor", skip(transaction, code))]
+pub async fn verify_second_factor(
+    transaction: &mut Transaction<'_, Postgres>,
+    user_id: Uuid,
+    code: &str,
+) -> Result<Option<SecondFactor>, sqlx::Error> {
+    let Some(user) = sqlx::query!(
+        r#"
+        SELECT totp_secret, totp_last_used_step FROM users
+        WHERE user_id = $1 AND totp_enabled_at IS NOT NULL
+        FOR UPDATE
+        "#,
+        user_id
+    )
+    .fetch_optional(&mut **transaction)
+    .await?
+    else {
+        return Ok(None);
+    };
+
+    let step = user
+        .totp_secret
+        .as_deref()
+        .and_then(TotpSecret::parse)
+        .and_then(|secret| secret.verify(code, Utc::now().timestamp()))
+        .filter(|step| user.totp_last_used_step.is_none_or(|last| *step > last));
+    if let Some(step) = step {
+        sqlx::query!(
+            r#"UPDATE users SET totp_last_used_step = $1 WHERE user_id = $2"#,
+            step,
+            user_id
+        )
+        .execute(&mut **transaction)
+        .await?;
+        return Ok(Some(SecondFactor::Totp));
+    }
+
+    let used = sqlx::query!(
+        r#"
+        UPDATE recovery_codes SET used_at = now()
+        WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL
+        "#,
+        user_id,
+        hash_recovery_code(code)
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok((used.rows_affected() == 1).then_some(SecondFactor::RecoveryCode))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recovery_codes_are_distinct_and_readable() {
+        let codes = generate_recovery_codes();
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+        for code in &codes {
+            assert_eq!(code.len(), 19, "{}", code);
+            assert!(
+                code.bytes()
+                    .all(|b| b == b'-' || RECOVERY_CODE_ALPHABET.contains(&b))
+            );
+        }
+        let distinct: std::collections::HashSet<_> = codes.iter().collect();
+        assert_eq!(distinct.len(), codes.len());
+    }
+
+    #[test]
+    fn recovery_codes_match_however_they_are_typed() {
+        assert_eq!(
+            hash_recovery_code("k3m9-x2qa-7hpf-cw4n"),
+            hash_recovery_code(" K3M9 X2QA 7HPF CW4N ")
+        );
+        assert_ne!(
+            hash_recovery_code("k3m9-x2qa-7hpf-cw4n"),
+            hash_recovery_code("k3m9-x2qa-7hpf-cw4m")
+        );
+    }
+}
@@ -0,0 +1,375 @@
+use crate::lib::configurations::{Setting, WebhookSettings};
+use crate::lib::issue_delivery_worker::ExecutionOutcome;
+use crate::lib::startup::get_connection_pool;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use rand::distr::Alphanumeric;
+use reqwest::header::CONTENT_TYPE;
+use sha2::Sha256;
+use sqlx::{PgPool, Postgres, Transaction};
+use std::time::Duration;
+use tracing::{Span, field::display};
+use uuid::Uuid;
+
+pub const SUBSCRIBER_SUBSCRIBED: &str = "subscriber.subscribed";
+pub const SUBSCRIBER_CONFIRMED: &str = "subscriber.confirmed";
+pub const SUBSCRIBER_UNSUBSCRIBED: &str = "subscriber.unsubscribed";
+pub const EMAIL_BOUNCED: &str = "email.bounced";
+/// The events endpoints can ask for.
+pub const EVENT_TYPES: &[&str] = &[
+    SUBSCRIBER_SUBSCRIBED,
+    SUBSCRIBER_CONFIRMED,
+    SUBSCRIBER_UNSUBSCRIBED,
+    EMAIL_BOUNCED,
+];
+
+pub const DELIVERY_PENDING: &str = "pending";
+pub const DELIVERY_SUCCEEDED: &str = "delivered";
+/// Out of attempts: only a manual redelivery sends it again.
+pub const DELIVERY_FAILED: &str = "failed";
+
+pub const EVENT_ID_HEADER: &str = "X-Webhook-Id";
+pub const TIMESTAMP_HEADER: &str = "X-Webhook-Timestamp";
+pub const SIGNATURE_HEADER: &str = "X-Webhook-Signature";
+
+#[derive(serde::Serialize)]
+pub struct WebhookEndpoint {
+    pub endpoint_id: Uuid,
+    pub url: String,
+    /// Empty for every event.
+    pub events: Vec<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// An entry of the delivery log of an endpoint.
+#[derive(serde::Serialize)]
+pub struct WebhookDelivery {
+    pub delivery_id: Uuid,
+    pub event_id: Uuid,
+    pub event_type: String,
+    pub status: String,
+    pub n_attempts: i16,
+    pub next_attempt_at: DateTime<Utc>,
+    pub last_status_code: Option<i16>,
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub delivered_at: Option<DateTime<Utc>>,
+}
+
+struct DueDelivery {
+    delivery_id: Uuid,
+    event_id: Uuid,
+    n_attempts: i16,
+    url: String,
+    secret: String,
+    payload: serde_json::Value,
+}
+
+/// The signature of a request: the hex encoded HMAC-SHA256 of
+/// `<timestamp>.<body>`, keyed with the secret of the endpoint.
+///
+/// Receivers compute it the same way, compare it to the one in the
+/// `X-Webhook-Signature` header and refuse old timestamps, so that a
+/// request can't be forged nor replayed.
+pub fn sign(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC can take key of any size");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body.as_bytes());
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// A new random secret to sign the requests to an endpoint with.
+pub fn generate_webhook_secret() -> String {
+    let mut rng = rand::rng();
+    let secret: String = std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(32)
+        .collect();
+    format!("whsec_{}", secret)
+}
+
+/// Record an event about a subscriber in the outbox, and queue it for every
+/// endpoint that wants it. `details` are added to the subscriber fields.
+///
+/// Runs in the transaction that caused the event, so that an event is sent
+/// if and only if what it tells about happened.
+#[tracing::instrument(name = "Publish a webhook event", skip(transaction, details))]
+pub async fn publish_subscriber_event(
+    transaction: &mut Transaction<'_, Postgres>,
+    event_type: &str,
+    subscriber_id: Uuid,
+    details: serde_json::Value,
+) -> Result<(), sqlx::Error> {
+    // Nothing is stored while there are no endpoints: they only get the
+    // events that happen after they are registered.
+    sqlx::query!(
+        r#"
+        WITH event AS (
+            INSERT INTO webhook_events (event_id, event_type, payload, occurred_at)
+            SELECT $1, $2,
+                jsonb_build_object(
+                    'id', $1::uuid,
+                    'type', $2::text,
+                    'occurred_at', now(),
+                    'data', jsonb_build_object(
+                        'subscriber_id', s.id,
+                        'email', s.email,
+                        'name', s.name,
+                        'status', s.status
+                    ) || $4::jsonb
+                ),
+                now()
+            FROM subscriptions s
+            WHERE s.id = $3 AND EXISTS (SELECT 1 FROM webhook_endpoints)
+            RETURNING event_id, event_type
+        )
+        INSERT INTO webhook_deliveries
+            (delivery_id, event_id, endpoint_id, status, next_attempt_at, created_at)
+        SELECT gen_random_uuid(), event.event_id, e.endpoint_id, $5, now(), now()
+        FROM event
+        JOIN webhook_endpoints e
+            ON cardinality(e.events) = 0 OR event.event_type = ANY(e.events)
+        "#,
+        Uuid::new_v4(),
+        event_type,
+        subscriber_id,
+        details,
+        DELIVERY_PENDING
+    )
+    .execute(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "List webhook endpoints", skip(pool))]
+pub async fn list_endpoints(pool: &PgPool) -> Result<Vec<WebhookEndpoint>, sqlx::Error> {
+    sqlx::query_as!(
+        WebhookEndpoint,
+        r#"
+        SELECT endpoint_id, url, events, created_at
+        FROM webhook_endpoints
+        ORDER BY created_at, endpoint_id
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })
+}
+
+/// The latest deliveries to an endpoint, newest first.
+#[tracing::instrument(name = "List webhook deliveries", skip(pool))]
+pub async fn list_deliveries(
+    pool: &PgPool,
+    endpoint_id: Uuid,
+    limit: i64,
+) -> Result<Vec<WebhookDelivery>, sqlx::Error> {
+    sqlx::query_as!(
+        WebhookDelivery,
+        r#"
+        SELECT d.delivery_id, d.event_id, ev.event_type, d.status, d.n_attempts,
+            d.next_attempt_at, d.last_status_code, d.last_error, d.created_at, d.delivered_at
+        FROM webhook_deliveries d
+        JOIN webhook_events ev ON ev.event_id = d.event_id
+        WHERE d.endpoint_id = $1
+        ORDER BY d.created_at DESC, d.delivery_id
+        LIMIT $2
+        "#,
+        endpoint_id,
+        limit
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })
+}
+
+/// Queue a delivery again, with a fresh set of attempts. Returns `false` if
+/// there is no such delivery.
+#[tracing::instrument(name = "Redeliver a webhook", skip(transaction))]
+pub async fn redeliver(
+    transaction: &mut Transaction<'_, Postgres>,
+    delivery_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let updated = sqlx::query!(
+        r#"
+        UPDATE webhook_deliveries
+        SET status = $1, n_attempts = 0, next_attempt_at = now()
+        WHERE delivery_id = $2
+        "#,
+        DELIVERY_PENDING,
+        delivery_id
+    )
+    .execute(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(updated.rows_affected() == 1)
+}
+
+/// Sends the queued events to the endpoints, outside of any request.
+///
+/// Deliveries are picked with `FOR UPDATE SKIP LOCKED`, so that each one is
+/// attempted by a single instance at a time. Failed attempts are retried
+/// with an exponential backoff, until `max_attempts`.
+pub struct WebhookDispatcher {
+    pool: PgPool,
+    http_client: reqwest::Client,
+    settings: WebhookSettings,
+}
+
+impl WebhookDispatcher {
+    pub fn build(configuration: &Setting) -> Result<Self, String> {
+        let http_client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(
+                configuration.webhooks.timeout_milliseconds,
+            ))
+            .build()
+            .map_err(|e| format!("Failed to build the webhook client: {}", e))?;
+        Ok(Self {
+            pool: get_connection_pool(configuration),
+            http_client,
+            settings: configuration.webhooks.clone(),
+        })
+    }
+
+    /// Attempt one due delivery, if any.
+    #[tracing::instrument(
+        name = "Deliver a webhook",
+        skip_all,
+        fields(delivery_id = tracing::field::Empty, event_id = tracing::field::Empty),
+        err
+    )]
+    pub async fn try_deliver(
+        &self,
+    ) -> Result<ExecutionOutcome, Box<dyn std::error::Error + Send + Sync>> {
+        let mut transaction = self.pool.begin().await?;
+        let delivery = sqlx::query_as!(
+            DueDelivery,
+            r#"
+            SELECT d.delivery_id, d.event_id, d.n_attempts, e.url, e.secret, ev.payload
+            FROM webhook_deliveries d
+            JOIN webhook_endpoints e ON e.endpoint_id = d.endpoint_id
+            JOIN webhook_events ev ON ev.event_id = d.event_id
+            WHERE d.status = $1 AND d.next_attempt_at <= now()
+            ORDER BY d.next_attempt_at
+            FOR UPDATE OF d SKIP LOCKED
+            LIMIT 1
+            "#,
+            DELIVERY_PENDING
+        )
+        .fetch_optional(&mut *transaction)
+        .await?;
+        let Some(delivery) = delivery else {
+            return Ok(ExecutionOutcome::EmptyQueue);
+        };
+        Span::current()
+            .record("delivery_id", display(delivery.delivery_id))
+            .record("event_id", display(delivery.event_id));
+
+        let body = delivery.payload.to_string();
+        let timestamp = Utc::now().timestamp();
+        let response = self
+            .http_client
+            .post(&delivery.url)
+            .header(CONTENT_TYPE, "application/json")
+            .header(EVENT_ID_HEADER, delivery.event_id.to_string())
+            .header(TIMESTAMP_HEADER, timestamp.to_string())
+            .header(SIGNATURE_HEADER, sign(&delivery.secret, timestamp, &body))
+            .body(body)
+            .send()
+            .await;
+        let (status_code, error) = match response {
+            Ok(response) if response.status().is_success() => {
+                (Some(response.status().as_u16() as i16), None)
+            }
+            Ok(response) => (
+                Some(response.status().as_u16() as i16),
+                Some(format!("The endpoint answered {}.", response.status())),
+            ),
+            Err(e) => (None, Some(e.to_string())),
+        };
+
+        let n_attempts = delivery.n_attempts + 1;
+        let status = match &error {
+            None => DELIVERY_SUCCEEDED,
+            Some(_) if n_attempts < self.settings.max_attempts => DELIVERY_PENDING,
+            Some(_) => DELIVERY_FAILED,
+        };
+        if let Some(e) = &error {
+            tracing::warn!(error = %e, n_attempts, status, "Failed to deliver a webhook.");
+        }
+        // Back off exponentially: 2s, 4s, 8s...
+        sqlx::query!(
+            r#"
+            UPDATE webhook_deliveries
+            SET status = $1, n_attempts = $2, last_status_code = $3, last_error = $4,
+                next_attempt_at = now() + make_interval(secs => power(2, $2::smallint)),
+                delivered_at = CASE WHEN $1 = $5 THEN now() END
+            WHERE delivery_id = $6
+            "#,
+            status,
+            n_attempts,
+            status_code,
+            error,
+            DELIVERY_SUCCEEDED,
+            delivery.delivery_id
+        )
+        .execute(&mut *transaction)
+        .await?;
+        transaction.commit().await?;
+        Ok(ExecutionOutcome::TaskCompleted)
+    }
+}
+
+/// The in-process dispatcher of webhooks, until the application stops.
+pub async fn run_webhook_dispatcher_until_stopped(
+    configuration: Setting,
+) -> Result<(), std::io::Error> {
+    let dispatcher = WebhookDispatcher::build(&configuration).map_err(std::io::Error::other)?;
+    let poll_interval = Duration::from_millis(configuration.webhooks.poll_interval_milliseconds);
+
+    loop {
+        match dispatcher.try_deliver().await {
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+            Ok(ExecutionOutcome::EmptyQueue) | Err(_) => tokio::time::sleep(poll_interval).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_signature_covers_the_timestamp_and_the_body() {
+        let signature = sign("secret", 1_700_000_000, r#"{"a":1}"#);
+        assert!(signature.starts_with("sha256="));
+        assert_eq!(signature.len(), "sha256=".len() + 64);
+        assert_ne!(signature, sign("secret", 1_700_000_001, r#"{"a":1}"#));
+        assert_ne!(signature, sign("secret", 1_700_000_000, r#"{"a":2}"#));
+        assert_ne!(signature, sign("other", 1_700_000_000, r#"{"a":1}"#));
+    }
+
+    #[test]
+    fn the_signature_matches_a_known_value() {
+        // echo -n '1.{}' | openssl dgst -sha256 -hmac key
+        assert_eq!(
+            sign("key", 1, "{}"),
+            "sha256=1ba6b8171186efc613e8bcc0cbdab2748f24984d7c5a84faa2637afa0e40d224"
+        );
+    }
+}
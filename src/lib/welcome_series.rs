@@ -0,0 +1,374 @@
+use crate::lib::configurations::Setting;
+use crate::lib::email_client::EmailClient;
+use crate::lib::email_templates::EmailTemplates;
+use crate::lib::issue_delivery_worker::ExecutionOutcome;
+use crate::lib::issues::get_subscriber;
+use crate::lib::localization::Translations;
+use crate::lib::routes::{CONFIRMED, unsubscribe_link};
+use crate::lib::segments::{Expression, subscriber_matches};
+use crate::lib::startup::get_connection_pool;
+use chrono::{DateTime, Duration, Utc};
+use fluent_bundle::FluentArgs;
+use minijinja::{Value, context};
+use secrecy::SecretString;
+use sqlx::{PgExecutor, PgPool, Postgres, Transaction};
+use std::sync::Arc;
+use tracing::{Span, field::display};
+use uuid::Uuid;
+
+pub const STEP_SENT: &str = "sent";
+/// The subscriber did not match the condition of the step.
+pub const STEP_SKIPPED: &str = "skipped";
+pub const STEP_FAILED: &str = "failed";
+/// Why an enrollment stopped before the end of the series.
+pub const STOPPED_UNSUBSCRIBED: &str = "unsubscribed";
+pub const STOPPED_SUPPRESSED: &str = "suppressed";
+
+/// The variables the template of a step is rendered with.
+const STEP_VARIABLES: &[&str] = &["name", "email", "unsubscribe_link"];
+
+struct Step {
+    delay: Duration,
+    template: String,
+    subject: String,
+    condition: Option<Expression>,
+}
+
+struct Enrollment {
+    subscriber_id: Uuid,
+    next_step: i32,
+    n_retries: i16,
+    enrolled_at: DateTime<Utc>,
+}
+
+/// Sends the steps of the welcome series as they fall due, outside of any
+/// request.
+///
+/// Enrollments are picked with `FOR UPDATE SKIP LOCKED`, so each step goes out
+/// once even with several instances running.
+pub struct WelcomeSeries {
+    pool: PgPool,
+    email_client: EmailClient,
+    email_templates: EmailTemplates,
+    translations: Arc<Translations>,
+    base_url: String,
+    hmac_secret: SecretString,
+    site_name: String,
+    steps: Vec<Step>,
+    max_retries: i16,
+}
+
+impl WelcomeSeries {
+    /// Fails if a step has an invalid condition, or a template that does not
+    /// render.
+    pub async fn build(configuration: &Setting) -> Result<Self, String> {
+        let pool = get_connection_pool(configuration);
+        let translations = Arc::new(Translations::load(&configuration.localization)?);
+        let email_templates = EmailTemplates::load(
+            &configuration.email_templates,
+            &pool,
+            Arc::clone(&translations),
+            &configuration.branding.site_name,
+        )
+        .await?;
+
+        let mut steps = Vec::new();
+        for (i, step) in configuration.welcome_series.steps.iter().enumerate() {
+            email_templates.check(&step.template, STEP_VARIABLES)?;
+            let condition = step
+                .condition
+                .as_deref()
+                .map(Expression::parse)
+                .transpose()
+                .map_err(|e| {
+                    format!(
+                        "Invalid condition for step {} of the welcome series: {}",
+                        i, e
+                    )
+                })?;
+            steps.push(Step {
+                delay: Duration::days(step.delay_days.into()),
+                template: step.template.clone(),
+                subject: step.subject.clone(),
+                condition,
+            });
+        }
+
+        Ok(Self {
+            email_client: configuration.email_client.clone().try_into()?,
+            pool,
+            email_templates,
+            translations,
+            base_url: configuration.application.base_url.clone(),
+            hmac_secret: configuration.application.hmac_secret.clone(),
+            site_name: configuration.branding.site_name.clone(),
+            steps,
+            max_retries: configuration.welcome_series.max_retries,
+        })
+    }
+
+    /// Move one enrollment whose next step is due forward, if any.
+    #[tracing::instrument(
+        name = "Send a welcome series step",
+        skip_all,
+        fields(subscriber_id = tracing::field::Empty, step = tracing::field::Empty),
+        err
+    )]
+    pub async fn try_send_next_step(
+        &self,
+    ) -> Result<ExecutionOutcome, Box<dyn std::error::Error + Send + Sync>> {
+        let mut transaction = self.pool.begin().await?;
+        let enrollment = sqlx::query_as!(
+            Enrollment,
+            r#"
+            SELECT subscriber_id, next_step, n_retries, enrolled_at
+            FROM welcome_series_enrollments
+            WHERE completed_at IS NULL AND stopped_at IS NULL AND next_send_at <= now()
+            ORDER BY next_send_at
+            FOR UPDATE SKIP LOCKED
+            LIMIT 1
+            "#
+        )
+        .fetch_optional(&mut *transaction)
+        .await?;
+        let Some(enrollment) = enrollment else {
+            return Ok(ExecutionOutcome::EmptyQueue);
+        };
+        Span::current()
+            .record("subscriber_id", display(enrollment.subscriber_id))
+            .record("step", enrollment.next_step);
+
+        let Some(step) = self.steps.get(enrollment.next_step as usize) else {
+            // The series was shortened since.
+            self.advance(&mut transaction, &enrollment).await?;
+            transaction.commit().await?;
+            return Ok(ExecutionOutcome::TaskCompleted);
+        };
+        let due = enrollment.enrolled_at + step.delay;
+        if due > Utc::now() {
+            // The series was changed since the step was scheduled.
+            reschedule(&mut transaction, enrollment.subscriber_id, due, 0).await?;
+            transaction.commit().await?;
+            return Ok(ExecutionOutcome::TaskCompleted);
+        }
+
+        let state = sqlx::query!(
+            r#"
+            SELECT s.status,
+                EXISTS (
+                    SELECT 1 FROM suppressions
+                    WHERE email_canonical = s.email_canonical
+                ) AS "suppressed!"
+            FROM subscriptions s
+            WHERE s.id = $1
+            "#,
+            enrollment.subscriber_id
+        )
+        .fetch_one(&mut *transaction)
+        .await?;
+        if state.suppressed || state.status != CONFIRMED {
+            let reason = if state.suppressed {
+                STOPPED_SUPPRESSED
+            } else {
+                STOPPED_UNSUBSCRIBED
+            };
+            stop_welcome_series(&mut *transaction, enrollment.subscriber_id, reason).await?;
+            transaction.commit().await?;
+            return Ok(ExecutionOutcome::TaskCompleted);
+        }
+
+        let matches = match &step.condition {
+            Some(condition) => {
+                subscriber_matches(&mut *transaction, enrollment.subscriber_id, condition).await?
+            }
+            None => true,
+        };
+        let outcome = if !matches {
+            STEP_SKIPPED
+        } else {
+            match self.send(enrollment.subscriber_id, step).await {
+                Ok(()) => STEP_SENT,
+                Err(e) if enrollment.n_retries + 1 < self.max_retries => {
+                    tracing::warn!(error = %e, n_retries = enrollment.n_retries, "Failed to send a welcome email, will retry.");
+                    // Back off exponentially: 2s, 4s, 8s...
+                    let retry_at =
+                        Utc::now() + Duration::seconds(2_i64.pow(enrollment.n_retries as u32 + 1));
+                    reschedule(
+                        &mut transaction,
+                        enrollment.subscriber_id,
+                        retry_at,
+                        enrollment.n_retries + 1,
+                    )
+                    .await?;
+                    transaction.commit().await?;
+                    return Ok(ExecutionOutcome::TaskCompleted);
+                }
+                Err(e) => {
+                    tracing::error!(error = %e, "Failed to send a welcome email, moving on.");
+                    STEP_FAILED
+                }
+            }
+        };
+        sqlx::query!(
+            r#"
+            INSERT INTO welcome_series_deliveries (subscriber_id, step, template, outcome, occurred_at)
+            VALUES ($1, $2, $3, $4, now())
+            "#,
+            enrollment.subscriber_id,
+            enrollment.next_step,
+            step.template,
+            outcome
+        )
+        .execute(&mut *transaction)
+        .await?;
+        self.advance(&mut transaction, &enrollment).await?;
+        transaction.commit().await?;
+        Ok(ExecutionOutcome::TaskCompleted)
+    }
+
+    async fn send(
+        &self,
+        subscriber_id: Uuid,
+        step: &Step,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let subscriber = get_subscriber(&self.pool, subscriber_id)
+            .await?
+            .ok_or("The subscriber is gone.")??;
+        let email = self.email_templates.render(
+            &step.template,
+            &subscriber.locale,
+            context! {
+                name => subscriber.recipient.name,
+                email => subscriber.email.as_ref(),
+                // Built by us out of URL-safe characters: no need to escape it.
+                unsubscribe_link => Value::from_safe_string(
+                    unsubscribe_link(&self.base_url, &self.hmac_secret, subscriber.id)
+                ),
+            },
+        )?;
+        let mut args = FluentArgs::new();
+        args.set("site", self.site_name.clone());
+        let subject = self
+            .translations
+            .translate(&subscriber.locale, &step.subject, Some(&args));
+
+        self.email_client
+            .send_email(&subscriber.email, &subject, &email.html, &email.text)
+            .await?;
+        Ok(())
+    }
+
+    /// Schedule the step after the current one, or complete the series.
+    async fn advance(
+        &self,
+        transaction: &mut Transaction<'_, Postgres>,
+        enrollment: &Enrollment,
+    ) -> Result<(), sqlx::Error> {
+        let next_step = enrollment.next_step + 1;
+        let next_send_at = self
+            .steps
+            .get(next_step as usize)
+            .map(|step| enrollment.enrolled_at + step.delay);
+        sqlx::query!(
+            r#"
+            UPDATE welcome_series_enrollments
+            SET next_step = $1, next_send_at = COALESCE($2, next_send_at), n_retries = 0,
+                completed_at = CASE WHEN $2::timestamptz IS NULL THEN now() END
+            WHERE subscriber_id = $3
+            "#,
+            next_step,
+            next_send_at,
+            enrollment.subscriber_id
+        )
+        .execute(&mut **transaction)
+        .await?;
+        Ok(())
+    }
+}
+
+async fn reschedule(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    next_send_at: DateTime<Utc>,
+    n_retries: i16,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE welcome_series_enrollments SET next_send_at = $1, n_retries = $2
+        WHERE subscriber_id = $3
+        "#,
+        next_send_at,
+        n_retries,
+        subscriber_id
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+/// Start the welcome series of a subscriber who just confirmed. Its first
+/// step is picked up by the scheduler.
+#[tracing::instrument(name = "Enroll a subscriber in the welcome series", skip(executor))]
+pub async fn enroll<'e>(
+    executor: impl PgExecutor<'e>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO welcome_series_enrollments (subscriber_id, next_send_at, enrolled_at)
+        VALUES ($1, now(), now())
+        ON CONFLICT (subscriber_id) DO NOTHING
+        "#,
+        subscriber_id
+    )
+    .execute(executor)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(())
+}
+
+/// Send no more steps to the subscriber.
+#[tracing::instrument(name = "Stop the welcome series of a subscriber", skip(executor))]
+pub async fn stop_welcome_series<'e>(
+    executor: impl PgExecutor<'e>,
+    subscriber_id: Uuid,
+    reason: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE welcome_series_enrollments SET stopped_at = now(), stopped_reason = $2
+        WHERE subscriber_id = $1 AND completed_at IS NULL AND stopped_at IS NULL
+        "#,
+        subscriber_id,
+        reason
+    )
+    .execute(executor)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(())
+}
+
+/// The in-process scheduler of the welcome series, until the application
+/// stops.
+pub async fn run_welcome_series_until_stopped(
+    configuration: Setting,
+) -> Result<(), std::io::Error> {
+    let series = WelcomeSeries::build(&configuration)
+        .await
+        .map_err(std::io::Error::other)?;
+    let poll_interval =
+        std::time::Duration::from_millis(configuration.welcome_series.poll_interval_milliseconds);
+
+    loop {
+        match series.try_send_next_step().await {
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+            Ok(ExecutionOutcome::EmptyQueue) | Err(_) => tokio::time::sleep(poll_interval).await,
+        }
+    }
+}
@@ -1,6 +1,13 @@
+use std::fmt::{Debug, Display};
+use tokio::task::JoinError;
+use zero2prod::lib::canonical_emails::canonicalize_subscriber_emails;
 use zero2prod::lib::configurations::get_configuration;
-use zero2prod::lib::startup::Application;
+use zero2prod::lib::confirmation_reminders::run_confirmation_reminders_until_stopped;
+use zero2prod::lib::issue_delivery_worker::run_worker_until_stopped;
+use zero2prod::lib::startup::{Application, get_connection_pool};
 use zero2prod::lib::telemetry::{get_subscriber, init_subscriber};
+use zero2prod::lib::webhooks::run_webhook_dispatcher_until_stopped;
+use zero2prod::lib::welcome_series::run_welcome_series_until_stopped;
 
 #[tokio::main]
 async fn main() -> std::io::Result<()> {
@@ -10,7 +17,65 @@ async fn main() -> std::io::Result<()> {
     // Panic if we can't read configuration.
     let configuration = get_configuration().expect("Failed to read configuration.");
 
-    let application = Application::build(configuration).await?;
-    application.run_until_stopped().await?;
+    // One-off jobs run instead of the application.
+    match std::env::args().nth(1).as_deref() {
+        None => {}
+        Some("canonicalize-emails") => {
+            let report = canonicalize_subscriber_emails(
+                &get_connection_pool(&configuration),
+                configuration.subscriptions.provider_aware_canonicalization,
+            )
+            .await
+            .map_err(std::io::Error::other)?;
+            tracing::info!(
+                updated = report.updated,
+                removed = report.removed,
+                "Canonicalized subscriber emails."
+            );
+            return Ok(());
+        }
+        Some(job) => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Unknown job: {}", job),
+            ));
+        }
+    }
+
+    let application = Application::build(configuration.clone()).await?;
+    let application_task = tokio::spawn(application.run_until_stopped());
+    let worker_task = tokio::spawn(run_worker_until_stopped(configuration.clone()));
+    let reminders_task = tokio::spawn(run_confirmation_reminders_until_stopped(
+        configuration.clone(),
+    ));
+    let welcome_series_task = tokio::spawn(run_welcome_series_until_stopped(configuration.clone()));
+    let webhooks_task = tokio::spawn(run_webhook_dispatcher_until_stopped(configuration));
+
+    // Whichever stops first takes the whole process down with it.
+    tokio::select! {
+        outcome = application_task => report_exit("API", outcome),
+        outcome = worker_task => report_exit("Issue delivery worker", outcome),
+        outcome = reminders_task => report_exit("Confirmation reminders scheduler", outcome),
+        outcome = welcome_series_task => report_exit("Welcome series scheduler", outcome),
+        outcome = webhooks_task => report_exit("Webhook dispatcher", outcome),
+    };
     Ok(())
 }
+
+fn report_exit(task_name: &str, outcome: Result<Result<(), impl Debug + Display>, JoinError>) {
+    match outcome {
+        Ok(Ok(())) => tracing::info!("{} has exited", task_name),
+        Ok(Err(e)) => tracing::error!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            "{} failed",
+            task_name
+        ),
+        Err(e) => tracing::error!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            "{} task failed to complete",
+            task_name
+        ),
+    }
+}
@@ -0,0 +1,136 @@
+// The signup form, for other sites to embed where they include this script:
+//
+//     <script src="https://newsletter.example.com/embed/signup.js" async></script>
+//
+// `data-list="<slug>"` signs up to a list, and `data-target="<selector>"` puts
+// the form in that element instead of after the script. Sites have to be on
+// the allowed origins of the newsletter.
+(() => {
+    const config = {{ config|safe }};
+    const script = document.currentScript;
+
+    const field = (name, type, label) => {
+        const wrapper = document.createElement("label");
+        wrapper.style.display = "block";
+        wrapper.textContent = label;
+        const input = document.createElement("input");
+        input.type = type;
+        input.name = name;
+        input.required = true;
+        input.style.cssText = "display: block; width: 100%; box-sizing: border-box;";
+        wrapper.append(input);
+        return wrapper;
+    };
+    const form = document.createElement("form");
+    form.className = "newsletter-signup";
+    // Honeypot: hidden from humans, so only bots fill it in.
+    const trap = field("website", "text", config.strings.trap);
+    trap.setAttribute("aria-hidden", "true");
+    trap.style.cssText = "position: absolute; left: -10000px;";
+    Object.assign(trap.querySelector("input"), { required: false, tabIndex: -1, autocomplete: "off" });
+    const button = document.createElement("button");
+    button.type = "submit";
+    button.textContent = config.strings.submit;
+    const message = document.createElement("p");
+    message.setAttribute("role", "status");
+    // Shown when the server suggests a fix for the email domain, so the
+    // subscriber can keep the address as typed.
+    const keepDomain = document.createElement("label");
+    keepDomain.style.display = "block";
+    const keepDomainInput = document.createElement("input");
+    keepDomainInput.type = "checkbox";
+    keepDomainInput.name = "keep_email_domain";
+    const keepDomainText = document.createElement("span");
+    keepDomain.append(keepDomainInput, " ", keepDomainText);
+    keepDomain.hidden = true;
+    form.append(
+        field("name", "text", config.strings.name),
+        field("email", "email", config.strings.email),
+        keepDomain,
+        trap,
+        button,
+        message,
+    );
+    const target = script.dataset.target && document.querySelector(script.dataset.target);
+    if (target) {
+        target.append(form);
+    } else {
+        script.after(form);
+    }
+
+    // Each submission spends its form token, so the next one is asked for
+    // as soon as the previous one is taken: right away for the first,
+    // since submitting too soon after it is handed out is what bots do.
+    let challenge;
+    const nextChallenge = () => {
+        challenge = fetch(`${config.base_url}/subscriptions/form_token`)
+            .then((response) => response.json());
+    };
+    nextChallenge();
+    // Find a nonce such that SHA-256("<form_token>:<nonce>") starts with
+    // enough zero bits.
+    const leadingZeroBits = (bytes) => {
+        let bits = 0;
+        for (const byte of bytes) {
+            if (byte === 0) { bits += 8; continue; }
+            return bits + Math.clz32(byte) - 24;
+        }
+        return bits;
+    };
+    const solve = async (formToken, difficulty) => {
+        const encoder = new TextEncoder();
+        for (let nonce = 0; ; nonce++) {
+            const input = encoder.encode(`${formToken}:${nonce}`);
+            const digest = new Uint8Array(await crypto.subtle.digest("SHA-256", input));
+            if (leadingZeroBits(digest) >= difficulty) return String(nonce);
+        }
+    };
+
+    form.addEventListener("submit", async (event) => {
+        event.preventDefault();
+        button.disabled = true;
+        message.textContent = "";
+        try {
+            const { form_token, pow_difficulty } = await challenge;
+            nextChallenge();
+            const body = {
+                name: form.elements.namedItem("name").value,
+                email: form.elements.namedItem("email").value,
+                website: form.elements.namedItem("website").value,
+                form_token,
+                locale: config.locale,
+                list: script.dataset.list,
+                keep_email_domain: !keepDomain.hidden && keepDomainInput.checked,
+            };
+            if (pow_difficulty) body.pow_nonce = await solve(form_token, pow_difficulty);
+            const response = await fetch(`${config.base_url}/subscriptions`, {
+                method: "POST",
+                headers: { "Content-Type": "application/json" },
+                body: JSON.stringify(body),
+            });
+            if (response.ok) {
+                const { status } = await response.json();
+                const done = document.createElement("p");
+                done.textContent = status === "confirmed" ? config.strings.confirmed : config.strings.check_inbox;
+                form.replaceChildren(done);
+                return;
+            }
+            if (response.status === 400) {
+                const { errors } = await response.json();
+                message.textContent = errors.map((error) => error.message).join(" ");
+                const typo = errors.find((error) => error.code === "possible_typo");
+                keepDomain.hidden = !typo;
+                if (typo) {
+                    keepDomainText.textContent = config.strings.keep_domain
+                        .replace("{suggestion}", typo.suggestion);
+                    keepDomainInput.checked = false;
+                }
+            } else {
+                message.textContent = config.strings.error;
+            }
+        } catch {
+            message.textContent = config.strings.error;
+        }
+        button.disabled = false;
+    });
+})();
@@ -0,0 +1,468 @@
+use crate::helpers::{TestApp, spawn_app};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use uuid::Uuid;
+
+async fn insert_subscriber(
+    app: &TestApp,
+    email: &str,
+    name: &str,
+    status: &str,
+    subscribed_at: DateTime<Utc>,
+) -> Uuid {
+    let subscriber_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, email_canonical, name, subscribed_at, status)
+        VALUES ($1, $2, lower($2), $3, $4, $5)
+        "#,
+        subscriber_id,
+        email,
+        name,
+        subscribed_at,
+        status
+    )
+    .execute(&app.db_pool)
+    .await
+    .expect("Failed to insert a subscriber.");
+    subscriber_id
+}
+
+async fn add_to_list(app: &TestApp, slug: &str, subscriber_id: Uuid) {
+    sqlx::query!(
+        r#"
+        INSERT INTO lists (list_id, slug, name, created_at)
+        VALUES ($1, $2, $2, now())
+        ON CONFLICT (slug) DO NOTHING
+        "#,
+        Uuid::new_v4(),
+        slug
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    sqlx::query!(
+        r#"
+        INSERT INTO list_subscriptions (list_id, subscriber_id, added_at)
+        SELECT list_id, $2, now() FROM lists WHERE slug = $1
+        "#,
+        slug,
+        subscriber_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+fn admin_request(app: &TestApp, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+    reqwest::Client::new()
+        .request(
+            method,
+            format!("{}/admin/subscribers{}", &app.address, path),
+        )
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+}
+
+async fn get_subscribers(app: &TestApp, path: &str) -> reqwest::Response {
+    admin_request(app, reqwest::Method::GET, path)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+fn emails(page: &serde_json::Value) -> Vec<&str> {
+    page["subscribers"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|subscriber| subscriber["email"].as_str().unwrap())
+        .collect()
+}
+
+async fn audited_actions(app: &TestApp, subscriber_id: Uuid) -> Vec<(String, serde_json::Value)> {
+    sqlx::query!(
+        r#"
+        SELECT action, details FROM audit_log
+        WHERE subscriber_id = $1 AND user_id = $2
+        ORDER BY occurred_at
+        "#,
+        subscriber_id,
+        app.test_user.user_id
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap()
+    .into_iter()
+    .map(|row| (row.action, row.details))
+    .collect()
+}
+
+#[tokio::test]
+async fn requests_without_credentials_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::get(format!("{}/admin/subscribers", &app.address))
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn subscribers_can_be_filtered() {
+    // Arrange
+    let app = spawn_app().await;
+    let day = |d| Utc.with_ymd_and_hms(2025, 3, d, 12, 0, 0).unwrap();
+    insert_subscriber(&app, "ged@earthsea.org", "Sparrowhawk", "confirmed", day(1)).await;
+    let tenar = insert_subscriber(&app, "tenar@atuan.org", "Tenar", "confirmed", day(2)).await;
+    insert_subscriber(
+        &app,
+        "therru@gont.org",
+        "Therru",
+        "pending_confirmation",
+        day(3),
+    )
+    .await;
+    insert_subscriber(&app, "100%_real@gont.org", "Ogion", "unsubscribed", day(4)).await;
+    add_to_list(&app, "archipelago", tenar).await;
+    let test_cases = vec![
+        (
+            "?status=confirmed",
+            vec!["tenar@atuan.org", "ged@earthsea.org"],
+        ),
+        ("?list=archipelago", vec!["tenar@atuan.org"]),
+        ("?q=GONT", vec!["100%_real@gont.org", "therru@gont.org"]),
+        ("?q=sparrow", vec!["ged@earthsea.org"]),
+        ("?q=100%25_", vec!["100%_real@gont.org"]),
+        (
+            "?subscribed_from=2025-03-02&subscribed_to=2025-03-03",
+            vec!["therru@gont.org", "tenar@atuan.org"],
+        ),
+        ("?status=confirmed&q=gont", vec![]),
+    ];
+
+    for (query, expected) in test_cases {
+        // Act
+        let response = get_subscribers(&app, query).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 200);
+        let page = response.json::<serde_json::Value>().await.unwrap();
+        assert_eq!(
+            emails(&page),
+            expected,
+            "Unexpected subscribers for {}.",
+            query
+        );
+        assert!(page["next_cursor"].is_null());
+    }
+}
+
+#[tokio::test]
+async fn subscribers_are_listed_one_page_at_a_time() {
+    // Arrange
+    let app = spawn_app().await;
+    let start = Utc::now() - Duration::days(1);
+    for i in 0..5 {
+        insert_subscriber(
+            &app,
+            &format!("reader{}@example.com", i),
+            "Reader",
+            "confirmed",
+            // Two of them at the very same time: the id breaks the tie.
+            start + Duration::minutes(i.min(3)),
+        )
+        .await;
+    }
+
+    // Act
+    let mut seen = Vec::new();
+    let mut path = "?limit=2".to_owned();
+    let mut pages = 0;
+    loop {
+        let response = get_subscribers(&app, &path).await;
+        assert_eq!(response.status().as_u16(), 200);
+        let page = response.json::<serde_json::Value>().await.unwrap();
+        pages += 1;
+        seen.extend(emails(&page).into_iter().map(str::to_owned));
+        match page["next_cursor"].as_str() {
+            Some(cursor) => path = format!("?limit=2&cursor={}", cursor),
+            None => break,
+        }
+    }
+
+    // Assert
+    assert_eq!(pages, 3);
+    let mut latest = seen[..2].to_vec();
+    latest.sort();
+    assert_eq!(latest, ["reader3@example.com", "reader4@example.com"]);
+    assert_eq!(
+        seen[2..],
+        [
+            "reader2@example.com",
+            "reader1@example.com",
+            "reader0@example.com"
+        ]
+    );
+}
+
+#[tokio::test]
+async fn invalid_page_parameters_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    let test_cases = vec![
+        ("?cursor=not-a-cursor", "an invalid cursor"),
+        ("?limit=0", "an empty page"),
+        ("?limit=501", "a page too large"),
+        ("?subscribed_from=yesterday", "a date that is not a date"),
+    ];
+
+    for (query, description) in test_cases {
+        // Act
+        let response = get_subscribers(&app, query).await;
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not fail with 400 Bad Request for {}.",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn viewing_a_subscriber_is_audited() {
+    // Arrange
+    let app = spawn_app().await;
+    let subscriber_id =
+        insert_subscriber(&app, "ged@earthsea.org", "Ged", "confirmed", Utc::now()).await;
+    add_to_list(&app, "wizards", subscriber_id).await;
+
+    // Act
+    let response = get_subscribers(&app, &format!("/{}", subscriber_id)).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let subscriber = response.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(subscriber["email"], "ged@earthsea.org");
+    assert_eq!(subscriber["lists"], serde_json::json!(["wizards"]));
+    assert_eq!(
+        audited_actions(&app, subscriber_id).await,
+        vec![("subscriber.viewed".to_owned(), serde_json::json!({}))]
+    );
+}
+
+#[tokio::test]
+async fn unknown_subscribers_are_not_found() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = get_subscribers(&app, &format!("/{}", Uuid::new_v4())).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn subscribers_can_be_renamed() {
+    // Arrange
+    let app = spawn_app().await;
+    let subscriber_id =
+        insert_subscriber(&app, "ged@earthsea.org", "Duny", "confirmed", Utc::now()).await;
+    let rename = |name: &str| {
+        let app = &app;
+        let body = serde_json::json!({ "name": name });
+        async move {
+            admin_request(app, reqwest::Method::PATCH, &format!("/{}", subscriber_id))
+                .json(&body)
+                .send()
+                .await
+                .unwrap()
+        }
+    };
+
+    // Act
+    let invalid = rename("").await;
+    let renamed = rename("Sparrowhawk").await;
+
+    // Assert
+    assert_eq!(invalid.status().as_u16(), 400);
+    assert_eq!(renamed.status().as_u16(), 200);
+    let subscriber = renamed.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(subscriber["name"], "Sparrowhawk");
+    assert_eq!(
+        audited_actions(&app, subscriber_id).await,
+        vec![(
+            "subscriber.renamed".to_owned(),
+            serde_json::json!({ "from": "Duny", "to": "Sparrowhawk" })
+        )]
+    );
+}
+
+#[tokio::test]
+async fn attributes_can_be_set_and_removed() {
+    // Arrange
+    let app = spawn_app().await;
+    let subscriber_id =
+        insert_subscriber(&app, "ged@earthsea.org", "Ged", "confirmed", Utc::now()).await;
+    let update = |attributes: serde_json::Value| {
+        let app = &app;
+        async move {
+            admin_request(
+                app,
+                reqwest::Method::PATCH,
+                &format!("/{}/attributes", subscriber_id),
+            )
+            .json(&attributes)
+            .send()
+            .await
+            .unwrap()
+        }
+    };
+
+    // Act
+    let first = update(serde_json::json!({"plan": "free", "seats": 3})).await;
+    let second = update(serde_json::json!({"plan": "pro", "seats": null})).await;
+    let invalid = update(serde_json::json!({"Plan": "team"})).await;
+
+    // Assert
+    assert_eq!(first.status().as_u16(), 200);
+    assert_eq!(second.status().as_u16(), 200);
+    let subscriber = second.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(subscriber["attributes"], serde_json::json!({"plan": "pro"}));
+    assert_eq!(invalid.status().as_u16(), 400);
+    assert_eq!(
+        audited_actions(&app, subscriber_id).await,
+        vec![
+            (
+                "subscriber.attributes_updated".to_owned(),
+                serde_json::json!({"plan": "free", "seats": 3})
+            ),
+            (
+                "subscriber.attributes_updated".to_owned(),
+                serde_json::json!({"plan": "pro", "seats": null})
+            ),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn pending_subscribers_can_be_confirmed() {
+    // Arrange
+    let app = spawn_app().await;
+    let pending = insert_subscriber(
+        &app,
+        "therru@gont.org",
+        "Therru",
+        "pending_confirmation",
+        Utc::now(),
+    )
+    .await;
+    let gone = insert_subscriber(&app, "ogion@gont.org", "Ogion", "unsubscribed", Utc::now()).await;
+    let confirm = |subscriber_id: Uuid| {
+        let app = &app;
+        async move {
+            admin_request(
+                app,
+                reqwest::Method::POST,
+                &format!("/{}/confirm", subscriber_id),
+            )
+            .send()
+            .await
+            .unwrap()
+        }
+    };
+
+    // Act
+    let confirmed = confirm(pending).await;
+    let conflict = confirm(gone).await;
+
+    // Assert
+    assert_eq!(confirmed.status().as_u16(), 200);
+    let subscriber = confirmed.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(subscriber["status"], "confirmed");
+    assert!(subscriber["confirmed_at"].is_string());
+    assert_eq!(conflict.status().as_u16(), 409);
+    assert_eq!(
+        audited_actions(&app, pending).await,
+        vec![("subscriber.confirmed".to_owned(), serde_json::json!({}))]
+    );
+    assert!(audited_actions(&app, gone).await.is_empty());
+}
+
+#[tokio::test]
+async fn subscribers_can_be_unsubscribed() {
+    // Arrange
+    let app = spawn_app().await;
+    let subscriber_id =
+        insert_subscriber(&app, "ged@earthsea.org", "Ged", "confirmed", Utc::now()).await;
+
+    // Act
+    let response = admin_request(
+        &app,
+        reqwest::Method::POST,
+        &format!("/{}/unsubscribe", subscriber_id),
+    )
+    .send()
+    .await
+    .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let subscriber = response.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(subscriber["status"], "unsubscribed");
+    assert!(subscriber["unsubscribed_at"].is_string());
+    assert_eq!(
+        audited_actions(&app, subscriber_id).await,
+        vec![(
+            "subscriber.unsubscribed".to_owned(),
+            serde_json::json!({ "from": "confirmed" })
+        )]
+    );
+}
+
+#[tokio::test]
+async fn deleted_subscribers_are_gone_but_remembered_in_the_audit_log() {
+    // Arrange
+    let app = spawn_app().await;
+    let subscriber_id =
+        insert_subscriber(&app, "ged@earthsea.org", "Ged", "confirmed", Utc::now()).await;
+    let delete = || async {
+        admin_request(
+            &app,
+            reqwest::Method::DELETE,
+            &format!("/{}", subscriber_id),
+        )
+        .send()
+        .await
+        .unwrap()
+    };
+
+    // Act
+    let deleted = delete().await;
+    let again = delete().await;
+
+    // Assert
+    assert_eq!(deleted.status().as_u16(), 204);
+    assert_eq!(again.status().as_u16(), 404);
+    let remaining = sqlx::query_scalar!("SELECT count(*) FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(remaining, Some(0));
+    assert_eq!(
+        audited_actions(&app, subscriber_id).await,
+        vec![(
+            "subscriber.deleted".to_owned(),
+            serde_json::json!({
+                "email": "ged@earthsea.org",
+                "name": "Ged",
+                "status": "confirmed"
+            })
+        )]
+    );
+}
@@ -0,0 +1,406 @@
+use crate::helpers::{TestApp, TestUser, spawn_app, spawn_app_with};
+use secrecy::SecretString;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::lib::authentication::create_initial_owner;
+use zero2prod::lib::configurations::InitialOwnerSettings;
+
+/// A user with `role`, alongside the owner every test app has.
+async fn user_with_role(app: &TestApp, role: &'static str) -> TestUser {
+    let user = TestUser::with_role(role);
+    user.store(&app.db_pool).await;
+    user
+}
+
+fn request_as(
+    app: &TestApp,
+    user: &TestUser,
+    method: reqwest::Method,
+    path: &str,
+) -> reqwest::RequestBuilder {
+    reqwest::Client::new()
+        .request(method, format!("{}{}", &app.address, path))
+        .basic_auth(&user.username, Some(&user.password))
+}
+
+/// Invite `email` as `role` on behalf of the owner, and return the signup
+/// link sent to them.
+async fn invite(app: &TestApp, email: &str, role: &str) -> reqwest::Url {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    let response = request_as(app, &app.test_user, reqwest::Method::POST, "/admin/invites")
+        .json(&serde_json::json!({ "email": email, "role": role }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 201);
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    app.get_confirmation_links(email_request).html
+}
+
+async fn sign_up(
+    app: &TestApp,
+    link: &reqwest::Url,
+    username: &str,
+    password: &str,
+) -> reqwest::Response {
+    let token = link
+        .query_pairs()
+        .find(|(name, _)| name == "token")
+        .unwrap()
+        .1
+        .into_owned();
+    reqwest::Client::new()
+        .post(format!("{}/invites/accept", &app.address))
+        .form(&[
+            ("token", token.as_str()),
+            ("username", username),
+            ("password", password),
+        ])
+        .send()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn each_role_only_reaches_its_routes() {
+    // Arrange
+    let app = spawn_app().await;
+    let viewer = user_with_role(&app, "viewer").await;
+    let editor = user_with_role(&app, "editor").await;
+    let publisher = user_with_role(&app, "publisher").await;
+
+    for (user, path, expected) in [
+        (&viewer, "/admin/reports/issues", 200),
+        (&viewer, "/newsletters/issues", 403),
+        (&viewer, "/admin/subscribers", 403),
+        (&editor, "/admin/reports/issues", 200),
+        (&editor, "/newsletters/issues", 200),
+        (&editor, "/admin/segments", 200),
+        (&editor, "/admin/subscribers", 403),
+        (&editor, "/admin/webhooks", 403),
+        (&publisher, "/newsletters/issues", 200),
+        (&publisher, "/admin/users", 403),
+        (&app.test_user, "/admin/users", 200),
+    ] {
+        // Act
+        let response = request_as(&app, user, reqwest::Method::GET, path)
+            .send()
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            expected,
+            "{} on {}",
+            user.role,
+            path
+        );
+    }
+}
+
+#[tokio::test]
+async fn editors_draft_issues_but_only_publishers_schedule_and_send_them() {
+    // Arrange
+    let app = spawn_app().await;
+    let editor = user_with_role(&app, "editor").await;
+    let publisher = user_with_role(&app, "publisher").await;
+    let issue: serde_json::Value =
+        request_as(&app, &editor, reqwest::Method::POST, "/newsletters/issues")
+            .json(&serde_json::json!({
+                "title": "Newsletter title",
+                "content": { "markdown": "Hello" }
+            }))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+    let issue_path = format!(
+        "/newsletters/issues/{}",
+        issue["newsletter_issue_id"].as_str().unwrap()
+    );
+    let schedule = format!("{}/schedule", issue_path);
+
+    // Act
+    let later_by_editor = request_as(&app, &editor, reqwest::Method::POST, &schedule)
+        .json(&serde_json::json!({ "send_at": "2999-01-01T00:00:00Z" }))
+        .send()
+        .await
+        .unwrap();
+    let later_by_publisher = request_as(&app, &publisher, reqwest::Method::POST, &schedule)
+        .json(&serde_json::json!({ "send_at": "2999-01-01T00:00:00Z" }))
+        .send()
+        .await
+        .unwrap();
+    let edited_by_editor = request_as(&app, &editor, reqwest::Method::PUT, &issue_path)
+        .json(&serde_json::json!({
+            "title": "Another title",
+            "content": { "markdown": "Hello" }
+        }))
+        .send()
+        .await
+        .unwrap();
+    let now_by_editor = request_as(&app, &editor, reqwest::Method::POST, &schedule)
+        .json(&serde_json::json!({ "send_at": "2020-01-01T00:00:00Z" }))
+        .send()
+        .await
+        .unwrap();
+    let now_by_publisher = request_as(&app, &publisher, reqwest::Method::POST, &schedule)
+        .json(&serde_json::json!({ "send_at": "2020-01-01T00:00:00Z" }))
+        .send()
+        .await
+        .unwrap();
+    let published_by_editor = request_as(&app, &editor, reqwest::Method::POST, "/newsletters")
+        .json(&serde_json::json!({
+            "title": "Newsletter title",
+            "content": { "markdown": "Hello" }
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(later_by_editor.status().as_u16(), 403);
+    assert_eq!(later_by_publisher.status().as_u16(), 200);
+    assert_eq!(edited_by_editor.status().as_u16(), 409);
+    assert_eq!(now_by_editor.status().as_u16(), 403);
+    assert_eq!(now_by_publisher.status().as_u16(), 200);
+    assert_eq!(published_by_editor.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn invited_people_sign_up_with_the_role_of_their_invite() {
+    // Arrange
+    let app = spawn_app().await;
+    let link = invite(&app, "new.editor@example.com", "editor").await;
+
+    // Act - Part 1 - Open the link
+    let form = reqwest::get(link.clone()).await.unwrap();
+
+    // Assert
+    assert_eq!(form.status().as_u16(), 200);
+    let form = form.text().await.unwrap();
+    assert!(form.contains("Your role: editor"));
+    assert!(form.contains(r#"action="/invites/accept""#));
+
+    // Act - Part 2 - Sign up
+    let response = sign_up(&app, &link, "new-editor", "a very long password").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let new_user = TestUser {
+        username: "new-editor".into(),
+        password: "a very long password".into(),
+        ..TestUser::with_role("editor")
+    };
+    let issues = request_as(&app, &new_user, reqwest::Method::GET, "/newsletters/issues")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(issues.status().as_u16(), 200);
+    let subscribers = request_as(&app, &new_user, reqwest::Method::GET, "/admin/subscribers")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(subscribers.status().as_u16(), 403);
+    let email = sqlx::query_scalar!("SELECT email FROM users WHERE username = 'new-editor'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(email.as_deref(), Some("new.editor@example.com"));
+
+    // Act - Part 3 - Use the link again
+    let again = sign_up(&app, &link, "somebody-else", "a very long password").await;
+
+    // Assert
+    assert_eq!(again.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn expired_invites_cannot_be_used() {
+    // Arrange
+    let app = spawn_app().await;
+    let link = invite(&app, "late@example.com", "viewer").await;
+    sqlx::query!("UPDATE user_invites SET expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let form = reqwest::get(link.clone()).await.unwrap();
+    let response = sign_up(&app, &link, "late", "a very long password").await;
+
+    // Assert
+    assert_eq!(form.status().as_u16(), 410);
+    assert_eq!(response.status().as_u16(), 410);
+    let users =
+        sqlx::query_scalar!(r#"SELECT count(*) AS "count!" FROM users WHERE username = 'late'"#)
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(users, 0);
+}
+
+#[tokio::test]
+async fn short_passwords_and_taken_usernames_are_refused() {
+    // Arrange
+    let app = spawn_app().await;
+    let link = invite(&app, "someone@example.com", "viewer").await;
+
+    // Act
+    let short_password = sign_up(&app, &link, "someone", "too short").await;
+    let taken_username =
+        sign_up(&app, &link, &app.test_user.username, "a very long password").await;
+
+    // Assert
+    assert_eq!(short_password.status().as_u16(), 400);
+    assert_eq!(taken_username.status().as_u16(), 400);
+    assert!(
+        taken_username
+            .text()
+            .await
+            .unwrap()
+            .contains("This username is taken.")
+    );
+    // The invite can still be used.
+    let response = sign_up(&app, &link, "someone", "a very long password").await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn only_owners_invite_and_manage_users() {
+    // Arrange
+    let app = spawn_app().await;
+    let publisher = user_with_role(&app, "publisher").await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let invite = request_as(&app, &publisher, reqwest::Method::POST, "/admin/invites")
+        .json(&serde_json::json!({ "email": "friend@example.com", "role": "owner" }))
+        .send()
+        .await
+        .unwrap();
+    let promotion = request_as(
+        &app,
+        &publisher,
+        reqwest::Method::PUT,
+        &format!("/admin/users/{}/role", publisher.user_id),
+    )
+    .json(&serde_json::json!({ "role": "owner" }))
+    .send()
+    .await
+    .unwrap();
+    let anonymous = reqwest::Client::new()
+        .get(format!("{}/admin/users", &app.address))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(invite.status().as_u16(), 403);
+    assert_eq!(promotion.status().as_u16(), 403);
+    assert_eq!(anonymous.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn there_is_always_an_owner_left() {
+    // Arrange
+    let app = spawn_app().await;
+    let demote_self = format!("/admin/users/{}/role", app.test_user.user_id);
+
+    // Act - Part 1 - The only owner
+    let demotion = request_as(&app, &app.test_user, reqwest::Method::PUT, &demote_self)
+        .json(&serde_json::json!({ "role": "viewer" }))
+        .send()
+        .await
+        .unwrap();
+    let removal = request_as(
+        &app,
+        &app.test_user,
+        reqwest::Method::DELETE,
+        &format!("/admin/users/{}", app.test_user.user_id),
+    )
+    .send()
+    .await
+    .unwrap();
+
+    // Assert
+    assert_eq!(demotion.status().as_u16(), 409);
+    assert_eq!(removal.status().as_u16(), 409);
+
+    // Act - Part 2 - With another owner
+    user_with_role(&app, "owner").await;
+    let demotion = request_as(&app, &app.test_user, reqwest::Method::PUT, &demote_self)
+        .json(&serde_json::json!({ "role": "viewer" }))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(demotion.status().as_u16(), 204);
+    let actions = sqlx::query_scalar!("SELECT action FROM audit_log ORDER BY occurred_at")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(actions, vec!["user.role_changed"]);
+}
+
+fn initial_owner() -> InitialOwnerSettings {
+    InitialOwnerSettings {
+        username: "first-owner".into(),
+        password: SecretString::from("a long enough password"),
+        email: None,
+    }
+}
+
+#[tokio::test]
+async fn the_initial_owner_is_created_when_there_are_no_users() {
+    // Arrange
+    let app = spawn_app_with(|c| c.admin_users.initial_owner = Some(initial_owner())).await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .get(format!("{}/admin/users", &app.address))
+        .basic_auth("first-owner", Some("a long enough password"))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let role = sqlx::query_scalar!("SELECT role FROM users WHERE username = 'first-owner'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(role, "owner");
+}
+
+#[tokio::test]
+async fn no_initial_owner_is_created_once_there_are_users() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let created = create_initial_owner(&app.db_pool, &initial_owner())
+        .await
+        .unwrap();
+
+    // Assert
+    assert!(!created);
+    let usernames = sqlx::query_scalar!("SELECT username FROM users")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(usernames, vec![app.test_user.username.clone()]);
+}
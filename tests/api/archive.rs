@@ -0,0 +1,254 @@
+use crate::helpers::{TestApp, spawn_app};
+use crate::newsletters::create_confirmed_subscriber;
+use reqwest::header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+/// Create an issue and, unless it is a `draft`, send it to every confirmed
+/// subscriber. Returns its slug.
+async fn issue(app: &TestApp, title: &str, markdown: &str, archived: bool, draft: bool) -> String {
+    let issue: serde_json::Value = app
+        .post_issues(&serde_json::json!({
+            "title": title,
+            "content": { "markdown": markdown },
+            "archived": archived
+        }))
+        .await
+        .json()
+        .await
+        .unwrap();
+    if !draft {
+        app.post_issue_action(
+            &format!(
+                "{}/schedule",
+                issue["newsletter_issue_id"].as_str().unwrap()
+            ),
+            &serde_json::json!({ "send_at": "2020-01-01T00:00:00Z" }),
+        )
+        .await;
+        let _mock_guard = Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount_as_scoped(&app.email_server)
+            .await;
+        app.dispatch_all_pending_emails().await;
+    }
+    issue["slug"].as_str().unwrap().to_owned()
+}
+
+async fn get(app: &TestApp, path: &str) -> reqwest::Response {
+    reqwest::get(format!("{}{}", &app.address, path))
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn only_sent_issues_that_opted_in_are_published() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let public = issue(&app, "Public issue", "Hello", true, false).await;
+    let private = issue(&app, "Private issue", "Hello", false, false).await;
+    let draft = issue(&app, "Draft issue", "Hello", true, true).await;
+
+    // Act
+    let archive = get(&app, "/archive").await.text().await.unwrap();
+
+    // Assert
+    assert!(archive.contains(&format!(
+        r#"<a href="/archive/{}">Public issue</a>"#,
+        public
+    )));
+    assert!(!archive.contains("Private issue"));
+    assert!(!archive.contains("Draft issue"));
+    assert_eq!(
+        get(&app, &format!("/archive/{}", public))
+            .await
+            .status()
+            .as_u16(),
+        200
+    );
+    assert_eq!(
+        get(&app, &format!("/archive/{}", private))
+            .await
+            .status()
+            .as_u16(),
+        404
+    );
+    assert_eq!(
+        get(&app, &format!("/archive/{}", draft))
+            .await
+            .status()
+            .as_u16(),
+        404
+    );
+}
+
+#[tokio::test]
+async fn archived_issues_have_no_tracking_nor_personal_links() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let slug = issue(
+        &app,
+        "Hello, world!",
+        "Hi {{ name }}, read [the book](https://www.zero2prod.com/).",
+        true,
+        false,
+    )
+    .await;
+
+    // Act
+    let page = get(&app, &format!("/archive/{}", slug))
+        .await
+        .text()
+        .await
+        .unwrap();
+
+    // Assert
+    assert!(slug.starts_with("hello-world-"));
+    assert!(page.contains(r#"href="https://www.zero2prod.com/""#));
+    assert!(page.contains("Hi , read"));
+    assert!(!page.contains("/t/c/"));
+    assert!(!page.contains("/t/o/"));
+    assert!(!page.contains("/subscriptions/unsubscribe"));
+    assert!(!page.contains("le guin"));
+}
+
+#[tokio::test]
+async fn archived_issues_cannot_run_scripts() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let slug = issue(
+        &app,
+        "Hello",
+        "Hi {{ \"<script>alert(1)</script>\" | safe }}<img src=x onerror=alert(1)>",
+        true,
+        false,
+    )
+    .await;
+
+    // Act
+    let response = get(&app, &format!("/archive/{}", slug)).await;
+
+    // Assert
+    let policy = response.headers()["Content-Security-Policy"]
+        .to_str()
+        .unwrap()
+        .to_owned();
+    assert!(policy.contains("default-src 'none'"));
+    assert!(!policy.contains("script-src"));
+    let page = response.text().await.unwrap();
+    assert!(!page.contains("<script"));
+    assert!(!page.contains("onerror"));
+}
+
+#[tokio::test]
+async fn the_archive_is_available_as_rss_and_atom() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let slug = issue(&app, "Tips & tricks", "Use *tests*.", true, false).await;
+
+    for (feed, content_type, entry) in [
+        ("rss", "application/rss+xml; charset=utf-8", "<item>"),
+        ("atom", "application/atom+xml; charset=utf-8", "<entry>"),
+    ] {
+        // Act
+        let response = get(&app, &format!("/archive/feed.{}", feed)).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 200);
+        assert_eq!(response.headers()["Content-Type"], content_type);
+        let body = response.text().await.unwrap();
+        assert_eq!(body.matches(entry).count(), 1, "{} feed", feed);
+        assert!(body.contains("<title>Tips &#38; tricks</title>"));
+        assert!(body.contains(&format!("/archive/{}", slug)));
+        // The HTML of the issue, escaped.
+        assert!(body.contains("Use &#60;em&#62;tests&#60;/em&#62;."));
+    }
+}
+
+#[tokio::test]
+async fn unchanged_pages_are_not_sent_again() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    issue(&app, "First issue", "Hello", true, false).await;
+    let client = reqwest::Client::new();
+    let url = format!("{}/archive/feed.atom", &app.address);
+    let response = client.get(&url).send().await.unwrap();
+    let etag = response.headers()[ETAG].clone();
+    let last_modified = response.headers()[LAST_MODIFIED].clone();
+
+    // Act - Part 1 - Nothing changed
+    let by_etag = client
+        .get(&url)
+        .header(IF_NONE_MATCH, etag.clone())
+        .send()
+        .await
+        .unwrap();
+    let by_date = client
+        .get(&url)
+        .header(IF_MODIFIED_SINCE, last_modified)
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(by_etag.status().as_u16(), 304);
+    assert_eq!(by_etag.headers()[ETAG], etag);
+    assert!(by_etag.text().await.unwrap().is_empty());
+    assert_eq!(by_date.status().as_u16(), 304);
+
+    // Act - Part 2 - A new issue
+    issue(&app, "Second issue", "Hello again", true, false).await;
+    let response = client
+        .get(&url)
+        .header(IF_NONE_MATCH, etag.clone())
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_ne!(response.headers()[ETAG], etag);
+    assert!(response.text().await.unwrap().contains("Second issue"));
+}
+
+#[tokio::test]
+async fn the_archive_can_be_searched() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    issue(
+        &app,
+        "Error handling",
+        "Use the question mark operator.",
+        true,
+        false,
+    )
+    .await;
+    issue(&app, "Gardening", "Tomatoes love the sun.", true, false).await;
+
+    // Act
+    let tomatoes = get(&app, "/archive?q=tomato").await.text().await.unwrap();
+    let operators = get(&app, "/archive?q=operators+-tomatoes")
+        .await
+        .text()
+        .await
+        .unwrap();
+    let nothing = get(&app, "/archive?q=kubernetes")
+        .await
+        .text()
+        .await
+        .unwrap();
+
+    // Assert
+    assert!(tomatoes.contains("Gardening"));
+    assert!(!tomatoes.contains("Error handling"));
+    assert!(operators.contains("Error handling"));
+    assert!(!operators.contains("Gardening"));
+    assert!(nothing.contains("No issue here yet."));
+}
@@ -0,0 +1,100 @@
+use crate::helpers::{TestApp, spawn_app};
+use chrono::{Duration, Utc};
+use uuid::Uuid;
+use zero2prod::lib::canonical_emails::{CanonicalizationReport, canonicalize_subscriber_emails};
+
+/// A subscriber saved before canonical emails were computed in Rust: only
+/// lowercased.
+async fn insert_legacy_subscriber(app: &TestApp, email: &str, status: &str, days_ago: i64) -> Uuid {
+    let subscriber_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, email_canonical, name, subscribed_at, status)
+        VALUES ($1, $2, lower($2), 'Ursula', $3, $4)
+        "#,
+        subscriber_id,
+        email,
+        Utc::now() - Duration::days(days_ago),
+        status
+    )
+    .execute(&app.db_pool)
+    .await
+    .expect("Failed to insert a subscriber.");
+    subscriber_id
+}
+
+#[tokio::test]
+async fn legacy_subscribers_get_the_canonical_email_of_new_signups() {
+    // Arrange
+    let app = spawn_app().await;
+    let idn = insert_legacy_subscriber(&app, "Ursula@bücher.de", "confirmed", 1).await;
+
+    // Act
+    let report = canonicalize_subscriber_emails(&app.db_pool, true)
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(
+        report,
+        CanonicalizationReport {
+            updated: 1,
+            removed: 0
+        }
+    );
+    let saved = sqlx::query_scalar!(
+        "SELECT email_canonical FROM subscriptions WHERE id = $1",
+        idn
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(saved, "ursula@xn--bcher-kva.de");
+}
+
+#[tokio::test]
+async fn duplicates_are_merged_into_the_oldest_confirmed_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    insert_legacy_subscriber(&app, "u.rsula@gmail.com", "pending_confirmation", 3).await;
+    let confirmed = insert_legacy_subscriber(&app, "ursula+news@gmail.com", "confirmed", 2).await;
+    insert_legacy_subscriber(&app, "Ursula@googlemail.com", "confirmed", 1).await;
+
+    // Act
+    let report = canonicalize_subscriber_emails(&app.db_pool, true)
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(report.removed, 2);
+    let saved = sqlx::query!("SELECT id, email_canonical FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.len(), 1);
+    assert_eq!(saved[0].id, confirmed);
+    assert_eq!(saved[0].email_canonical, "ursula@gmail.com");
+    let actions = sqlx::query_scalar!("SELECT action FROM audit_log")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(actions, vec!["subscriber.deleted_duplicate"; 2]);
+}
+
+#[tokio::test]
+async fn running_it_again_changes_nothing() {
+    // Arrange
+    let app = spawn_app().await;
+    insert_legacy_subscriber(&app, "U.rsula@gmail.com", "confirmed", 1).await;
+    canonicalize_subscriber_emails(&app.db_pool, true)
+        .await
+        .unwrap();
+
+    // Act
+    let report = canonicalize_subscriber_emails(&app.db_pool, true)
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(report, CanonicalizationReport::default());
+}
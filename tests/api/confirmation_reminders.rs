@@ -0,0 +1,145 @@
+use crate::helpers::{TestApp, spawn_app};
+use crate::newsletters::{create_confirmed_subscriber, create_unconfirmed_subscriber};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+/// Pretend every subscriber signed up `hours` hours earlier.
+async fn signed_up_earlier(app: &TestApp, hours: i32) {
+    sqlx::query!(
+        "UPDATE subscriptions SET subscribed_at = subscribed_at - make_interval(hours => $1)",
+        hours
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+/// Pretend every reminder went out `hours` hours earlier.
+async fn reminded_earlier(app: &TestApp, hours: i32) {
+    sqlx::query!(
+        "UPDATE confirmation_reminders SET sent_at = sent_at - make_interval(hours => $1)",
+        hours
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+async fn subscriber_count(app: &TestApp) -> i64 {
+    sqlx::query_scalar!(r#"SELECT count(*) AS "count!" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn pending_subscribers_are_reminded_once_with_a_fresh_link() {
+    // Arrange
+    let app = spawn_app().await;
+    let first_link = create_unconfirmed_subscriber(&app).await;
+    signed_up_earlier(&app, 25).await;
+    app.email_server.reset().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.send_due_confirmation_reminders().await;
+    app.send_due_confirmation_reminders().await;
+
+    // Assert
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let body = serde_json::from_slice::<serde_json::Value>(&email_request.body).unwrap();
+    assert_eq!(body["Subject"], "Please confirm your subscription");
+    let reminder_link = app.get_confirmation_links(email_request);
+    assert_ne!(reminder_link.html, first_link.html);
+
+    let response = reqwest::get(reminder_link.html).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    let status = sqlx::query_scalar!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(status, "confirmed");
+}
+
+#[tokio::test]
+async fn subscribers_are_not_reminded_before_the_delay() {
+    // Arrange
+    let app = spawn_app().await;
+    create_unconfirmed_subscriber(&app).await;
+    signed_up_earlier(&app, 23).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.send_due_confirmation_reminders().await;
+
+    // Assert
+    let reminders =
+        sqlx::query_scalar!(r#"SELECT count(*) AS "count!" FROM confirmation_reminders"#)
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(reminders, 0);
+}
+
+#[tokio::test]
+async fn confirmed_subscribers_are_not_reminded() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    signed_up_earlier(&app, 25).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.send_due_confirmation_reminders().await;
+
+    // Assert
+    assert_eq!(subscriber_count(&app).await, 1);
+}
+
+#[tokio::test]
+async fn subscribers_who_never_confirm_are_deleted_after_the_second_delay() {
+    // Arrange
+    let app = spawn_app().await;
+    create_unconfirmed_subscriber(&app).await;
+    signed_up_earlier(&app, 25).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.send_due_confirmation_reminders().await;
+
+    // Act - Part 1 - Not yet
+    reminded_earlier(&app, 71).await;
+    app.send_due_confirmation_reminders().await;
+
+    // Assert
+    assert_eq!(subscriber_count(&app).await, 1);
+
+    // Act - Part 2 - Past the delay
+    reminded_earlier(&app, 2).await;
+    app.send_due_confirmation_reminders().await;
+
+    // Assert
+    assert_eq!(subscriber_count(&app).await, 0);
+    let tokens = sqlx::query_scalar!(r#"SELECT count(*) AS "count!" FROM subscription_tokens"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(tokens, 0);
+}
@@ -0,0 +1,200 @@
+use crate::helpers::{TestApp, spawn_app_with};
+use reqwest::header::{ACCESS_CONTROL_ALLOW_ORIGIN, ORIGIN};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+const BLOG: &str = "https://blog.example.com";
+
+async fn spawn_app() -> TestApp {
+    spawn_app_with(|conf| conf.embed.allowed_origins = vec![BLOG.into()]).await
+}
+
+/// What the embedded form sends, from a page on `origin`.
+async fn subscribe_from(app: &TestApp, origin: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/subscriptions", &app.address))
+        .header(ORIGIN, origin)
+        .json(&serde_json::json!({
+            "name": "le guin",
+            "email": "ursula_le_guin@gmail.com"
+        }))
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn consent_sources(app: &TestApp) -> Vec<Option<String>> {
+    sqlx::query_scalar!("SELECT source FROM subscriber_consents")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+}
+
+async fn preflight_from(app: &TestApp, origin: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .request(
+            reqwest::Method::OPTIONS,
+            format!("{}/subscriptions", &app.address),
+        )
+        .header(ORIGIN, origin)
+        .header("Access-Control-Request-Method", "POST")
+        .header("Access-Control-Request-Headers", "content-type")
+        .send()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn the_widget_is_served_in_the_language_of_the_visitor() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .get(format!("{}/embed/signup.js", &app.address))
+        .header("Accept-Language", "uk")
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "application/javascript; charset=utf-8"
+    );
+    let script = response.text().await.unwrap();
+    assert!(script.contains(r#""base_url":"http://localhost""#));
+    assert!(script.contains(r#""locale":"uk""#));
+    assert!(script.contains("Підписатися"));
+    assert!(script.contains("Можливо, ви мали на увазі {suggestion}?"));
+    assert!(script.contains("keep_email_domain"));
+}
+
+#[tokio::test]
+async fn allowed_origins_pass_the_preflight() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = preflight_from(&app, BLOG).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 204);
+    assert_eq!(response.headers()[ACCESS_CONTROL_ALLOW_ORIGIN], BLOG);
+    assert_eq!(
+        response.headers()["Access-Control-Allow-Methods"],
+        "GET, POST"
+    );
+    assert_eq!(
+        response.headers()["Access-Control-Allow-Headers"],
+        "content-type"
+    );
+}
+
+#[tokio::test]
+async fn other_origins_fail_the_preflight() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = preflight_from(&app, "https://evil.example.com").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 403);
+    assert!(
+        response
+            .headers()
+            .get(ACCESS_CONTROL_ALLOW_ORIGIN)
+            .is_none()
+    );
+}
+
+#[tokio::test]
+async fn signups_from_an_allowed_origin_are_tagged_with_it() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = subscribe_from(&app, BLOG).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.headers()[ACCESS_CONTROL_ALLOW_ORIGIN], BLOG);
+    assert_eq!(consent_sources(&app).await, vec![Some(BLOG.to_owned())]);
+}
+
+#[tokio::test]
+async fn signups_from_our_own_pages_have_no_source() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = subscribe_from(&app, "http://localhost").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(consent_sources(&app).await, vec![None]);
+}
+
+#[tokio::test]
+async fn signups_from_other_origins_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    for origin in [
+        "https://evil.example.com",
+        "http://blog.example.com",
+        "null",
+    ] {
+        // Act
+        let response = subscribe_from(&app, origin).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 403, "{}", origin);
+    }
+    let subscribers = sqlx::query_scalar!(r#"SELECT count(*) AS "count!" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(subscribers, 0);
+}
+
+#[tokio::test]
+async fn only_allowed_origins_can_read_the_form_token() {
+    // Arrange
+    let app = spawn_app().await;
+    let form_token_from = |origin: &'static str| {
+        reqwest::Client::new()
+            .get(format!("{}/subscriptions/form_token", &app.address))
+            .header(ORIGIN, origin)
+            .send()
+    };
+
+    // Act
+    let allowed = form_token_from(BLOG).await.unwrap();
+    let other = form_token_from("https://evil.example.com").await.unwrap();
+
+    // Assert
+    assert_eq!(allowed.headers()[ACCESS_CONTROL_ALLOW_ORIGIN], BLOG);
+    // Served, but without the header the browser would not let the page
+    // read it.
+    assert_eq!(other.status().as_u16(), 200);
+    assert!(other.headers().get(ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+}
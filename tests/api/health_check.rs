@@ -20,9 +20,5 @@ async fn health_check_works() {
 
     // Assert
     assert!(response.status().is_success());
-    assert_eq!(response.content_length(), Some(19));
-    assert_eq!(
-        response.text().await.unwrap(),
-        "Hello health_check!".to_string()
-    );
+    assert_eq!(response.content_length(), Some(0));
 }
@@ -1,11 +1,18 @@
-﻿use once_cell::sync::Lazy;
+use argon2::password_hash::SaltString;
+use argon2::password_hash::rand_core::OsRng;
+use argon2::{Algorithm, Argon2, Params, PasswordHasher, Version};
+use once_cell::sync::Lazy;
 use sqlx::{Connection, Executor, PgConnection, PgPool};
 use uuid::Uuid;
 use wiremock::MockServer;
-use zero2prod::lib::configurations::{DatabaseSettings, get_configuration};
+use zero2prod::lib::configurations::{DatabaseSettings, Setting, get_configuration};
+use zero2prod::lib::confirmation_reminders::ConfirmationReminders;
+use zero2prod::lib::issue_delivery_worker::{ExecutionOutcome, IssueDelivery};
 use zero2prod::lib::startup::Application;
 use zero2prod::lib::startup::get_connection_pool;
 use zero2prod::lib::telemetry::{get_subscriber, init_subscriber};
+use zero2prod::lib::webhooks::WebhookDispatcher;
+use zero2prod::lib::welcome_series::WelcomeSeries;
 
 // Ensure that the `tracing` stack is only initialised once using `once_cell`.
 static TRACING: Lazy<()> = Lazy::new(|| {
@@ -24,17 +31,75 @@ pub struct ConfirmationLinks {
     pub plain_text: reqwest::Url,
 }
 
+pub struct TestUser {
+    pub user_id: Uuid,
+    pub username: String,
+    pub password: String,
+    pub email: String,
+    pub role: &'static str,
+}
+
+impl TestUser {
+    /// An owner: allowed to do anything.
+    pub fn generate() -> Self {
+        Self::with_role("owner")
+    }
+
+    pub fn with_role(role: &'static str) -> Self {
+        Self {
+            user_id: Uuid::new_v4(),
+            username: Uuid::new_v4().to_string(),
+            password: Uuid::new_v4().to_string(),
+            email: format!("{}@example.com", Uuid::new_v4()),
+            role,
+        }
+    }
+
+    pub async fn store(&self, pool: &PgPool) {
+        let salt = SaltString::generate(&mut OsRng);
+        // Same parameters as new passwords get, but not the library defaults:
+        // verification must read them from the hash.
+        let password_hash = Argon2::new(
+            Algorithm::Argon2id,
+            Version::V0x13,
+            Params::new(15000, 2, 1, None).unwrap(),
+        )
+        .hash_password(self.password.as_bytes(), &salt)
+        .unwrap()
+        .to_string();
+        sqlx::query!(
+            r#"
+            INSERT INTO users (user_id, username, password_hash, email, role)
+            VALUES ($1, $2, $3, $4, $5)
+            "#,
+            self.user_id,
+            self.username,
+            password_hash,
+            self.email,
+            self.role,
+        )
+        .execute(pool)
+        .await
+        .expect("Failed to store test user.");
+    }
+}
+
 pub struct TestApp {
     pub address: String,
     pub port: u16,
     pub db_pool: PgPool,
     pub email_server: MockServer,
+    pub test_user: TestUser,
+    pub issue_delivery: IssueDelivery,
+    pub welcome_series: WelcomeSeries,
+    pub confirmation_reminders: ConfirmationReminders,
+    pub webhook_dispatcher: WebhookDispatcher,
 }
 
 impl TestApp {
     pub async fn post_subscriptions(&self, body: String) -> reqwest::Response {
         reqwest::Client::new()
-            .post(&format!("{}/subscriptions", &self.address))
+            .post(format!("{}/subscriptions", &self.address))
             .header("Content-Type", "application/x-www-form-urlencoded")
             .body(body)
             .send()
@@ -42,6 +107,196 @@ impl TestApp {
             .expect("Failed to execute request.")
     }
 
+    pub async fn post_subscriptions_json(&self, body: &serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/subscriptions", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_form_token(&self) -> serde_json::Value {
+        reqwest::Client::new()
+            .get(format!("{}/subscriptions/form_token", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .json()
+            .await
+            .expect("Failed to parse the form token.")
+    }
+
+    /// Extract the unsubscribe link from an HTML page, pointed at the test
+    /// application.
+    pub fn get_unsubscribe_link(&self, html: &str) -> reqwest::Url {
+        let raw_link = linkify::LinkFinder::new()
+            .links(html)
+            .map(|l| l.as_str().replace("&#38;", "&"))
+            .find(|l| l.contains("/subscriptions/unsubscribe"))
+            .expect("No unsubscribe link found.");
+        let mut unsubscribe_link = reqwest::Url::parse(&raw_link).unwrap();
+        assert_eq!(unsubscribe_link.host_str().unwrap(), "localhost");
+        unsubscribe_link.set_port(Some(self.port)).unwrap();
+        unsubscribe_link
+    }
+
+    /// Submit the unsubscribe confirmation form for `unsubscribe_link`.
+    pub async fn post_unsubscribe(&self, unsubscribe_link: &reqwest::Url) -> reqwest::Response {
+        let body = unsubscribe_link.query().unwrap().to_owned();
+        reqwest::Client::new()
+            .post(format!("{}/subscriptions/unsubscribe", &self.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_newsletters(&self, body: &serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/newsletters", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_newsletters_preview(&self, body: &serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/newsletters/preview", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_issues(&self, body: &serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/newsletters/issues", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// Get `/newsletters/issues/{path}`, e.g. an issue or its preview.
+    pub async fn get_issues(&self, path: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/newsletters/issues/{}", &self.address, path))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// Post to `/newsletters/issues/{path}`, e.g. to schedule an issue.
+    pub async fn post_issue_action(
+        &self,
+        path: &str,
+        body: &serde_json::Value,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/newsletters/issues/{}", &self.address, path))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn put_issue(&self, issue_id: &str, body: &serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .put(format!("{}/newsletters/issues/{}", &self.address, issue_id))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn delete_issue(&self, issue_id: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .delete(format!("{}/newsletters/issues/{}", &self.address, issue_id))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// Do what the background worker would: start the due issues and send
+    /// every email that can go out now.
+    pub async fn dispatch_all_pending_emails(&self) {
+        self.issue_delivery.start_due_issues().await.unwrap();
+        loop {
+            if let ExecutionOutcome::EmptyQueue =
+                self.issue_delivery.try_execute_task().await.unwrap()
+            {
+                break;
+            }
+        }
+    }
+
+    /// Do what the welcome series scheduler would: send every step that is
+    /// due now.
+    pub async fn send_due_welcome_emails(&self) {
+        loop {
+            if let ExecutionOutcome::EmptyQueue =
+                self.welcome_series.try_send_next_step().await.unwrap()
+            {
+                break;
+            }
+        }
+    }
+
+    /// Do what the confirmation reminders scheduler would: forget the
+    /// subscribers who never confirmed, then send every reminder due now.
+    pub async fn send_due_confirmation_reminders(&self) {
+        self.confirmation_reminders
+            .delete_unconfirmed()
+            .await
+            .unwrap();
+        loop {
+            if let ExecutionOutcome::EmptyQueue = self
+                .confirmation_reminders
+                .try_send_reminder()
+                .await
+                .unwrap()
+            {
+                break;
+            }
+        }
+    }
+
+    /// Do what the confirmation reminders scheduler would: send every
+    /// confirmation email queued, e.g. by an import.
+    pub async fn send_queued_confirmation_emails(&self) {
+        loop {
+            if let ExecutionOutcome::EmptyQueue = self
+                .confirmation_reminders
+                .try_send_queued_confirmation()
+                .await
+                .unwrap()
+            {
+                break;
+            }
+        }
+    }
+
+    /// Do what the webhook dispatcher would: attempt every delivery due now.
+    pub async fn dispatch_due_webhooks(&self) {
+        loop {
+            if let ExecutionOutcome::EmptyQueue =
+                self.webhook_dispatcher.try_deliver().await.unwrap()
+            {
+                break;
+            }
+        }
+    }
+
     /// Extract the confirmation links embedded in the request to the email API.
     pub fn get_confirmation_links(&self, email_request: &wiremock::Request) -> ConfirmationLinks {
         // Parse the body as JSON, starting from raw bytes.
@@ -61,8 +316,8 @@ impl TestApp {
             confirmation_link
         };
 
-        let html = get_link(&body["HtmlBody"].as_str().unwrap());
-        let plain_text = get_link(&body["TextBody"].as_str().unwrap());
+        let html = get_link(body["HtmlBody"].as_str().unwrap());
+        let plain_text = get_link(body["TextBody"].as_str().unwrap());
         // The two links should be identical.
         assert_eq!(html, plain_text);
 
@@ -71,6 +326,11 @@ impl TestApp {
 }
 
 pub async fn spawn_app() -> TestApp {
+    spawn_app_with(|_| {}).await
+}
+
+/// Spawn the application with `customize` applied to its configuration.
+pub async fn spawn_app_with(customize: impl FnOnce(&mut Setting)) -> TestApp {
     // The first time `initialize` is invoked the code in `Tracing` is executed.
     // Al other invocations will
     Lazy::force(&TRACING);
@@ -86,6 +346,7 @@ pub async fn spawn_app() -> TestApp {
         // Use random OS port.
         conf.application.port = 0;
         conf.email_client.base_url = email_server.uri();
+        customize(&mut conf);
         conf
     };
     // Create and migrate the database.
@@ -97,14 +358,28 @@ pub async fn spawn_app() -> TestApp {
     // Get the port before spawning the application.
     let application_port = application.port();
     let address = format!("http://localhost:{}", application_port);
-    let _ = tokio::spawn(application.run_until_stopped());
+    tokio::spawn(application.run_until_stopped());
 
-    TestApp {
+    let test_app = TestApp {
         address,
         port: application_port,
         db_pool: get_connection_pool(&configuration),
         email_server,
-    }
+        test_user: TestUser::generate(),
+        issue_delivery: IssueDelivery::build(&configuration)
+            .await
+            .expect("Failed to build the issue delivery."),
+        welcome_series: WelcomeSeries::build(&configuration)
+            .await
+            .expect("Failed to build the welcome series."),
+        confirmation_reminders: ConfirmationReminders::build(&configuration)
+            .await
+            .expect("Failed to build the confirmation reminders."),
+        webhook_dispatcher: WebhookDispatcher::build(&configuration)
+            .expect("Failed to build the webhook dispatcher."),
+    };
+    test_app.test_user.store(&test_app.db_pool).await;
+    test_app
 }
 
 pub async fn configure_database(config: &DatabaseSettings) -> PgPool {
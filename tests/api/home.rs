@@ -0,0 +1,60 @@
+use crate::helpers::spawn_app;
+
+#[tokio::test]
+async fn the_home_page_renders_the_signup_form() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::get(&app.address).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let html = response.text().await.unwrap();
+    assert!(html.contains(r#"<form id="signup" method="post" action="/subscriptions""#));
+    assert!(html.contains(r#"name="form_token""#));
+    assert!(html.contains("Zero To Production"));
+}
+
+#[tokio::test]
+async fn the_home_page_is_translated_into_the_preferred_language() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .get(&app.address)
+        .header("Accept-Language", "uk-UA, en;q=0.5")
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    let html = response.text().await.unwrap();
+    assert!(html.contains(r#"<html lang="uk">"#));
+    assert!(html.contains("Підписатися"));
+    assert!(html.contains(r#"name="locale" value="uk""#));
+}
+
+#[tokio::test]
+async fn the_lang_parameter_picks_the_language_of_the_home_page() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .get(format!("{}/?lang=uk", &app.address))
+        .header("Accept-Language", "en")
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert!(
+        response
+            .text()
+            .await
+            .unwrap()
+            .contains(r#"<html lang="uk">"#)
+    );
+}
@@ -0,0 +1,135 @@
+use crate::helpers::{TestApp, spawn_app, spawn_app_with};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn log_in(app: &TestApp, username: &str, password: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .get(format!("{}/admin/users", &app.address))
+        .basic_auth(username, Some(password))
+        .send()
+        .await
+        .unwrap()
+}
+
+/// Without progressive delays, to reach the lockout right away.
+async fn spawn_app_without_delays() -> TestApp {
+    spawn_app_with(|c| c.login_throttling.base_delay_milliseconds = 0).await
+}
+
+#[tokio::test]
+async fn logging_in_again_right_after_a_failure_has_to_wait() {
+    // Arrange
+    let app = spawn_app().await;
+    let username = app.test_user.username.clone();
+
+    // Act
+    let wrong = log_in(&app, &username, "a wrong password").await;
+    let right = log_in(&app, &username, &app.test_user.password).await;
+    let other_user = log_in(&app, "someone-else", "another wrong password").await;
+
+    // Assert
+    assert_eq!(wrong.status().as_u16(), 401);
+    assert_eq!(right.status().as_u16(), 429);
+    assert_eq!(right.headers()["Retry-After"], "1");
+    // The same IP address has to wait too, whatever the username.
+    assert_eq!(other_user.status().as_u16(), 429);
+}
+
+#[tokio::test]
+async fn a_forged_forwarded_address_does_not_start_the_ip_address_over() {
+    // Arrange
+    let app = spawn_app().await;
+    let log_in_from = |username: &'static str, forwarded_for: &'static str| {
+        reqwest::Client::new()
+            .get(format!("{}/admin/users", &app.address))
+            .basic_auth(username, Some("a wrong password"))
+            .header("X-Forwarded-For", forwarded_for)
+            .send()
+    };
+
+    // Act
+    let first = log_in_from("first-user", "203.0.113.1").await.unwrap();
+    let second = log_in_from("second-user", "203.0.113.2").await.unwrap();
+    let third = log_in_from("third-user", "203.0.113.3").await.unwrap();
+
+    // Assert
+    assert_eq!(first.status().as_u16(), 401);
+    assert_eq!(second.status().as_u16(), 429);
+    assert_eq!(third.status().as_u16(), 429);
+}
+
+#[tokio::test]
+async fn accounts_are_locked_after_too_many_failures_and_their_user_is_told() {
+    // Arrange
+    let app = spawn_app_without_delays().await;
+    let username = app.test_user.username.clone();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    for _ in 0..5 {
+        let response = log_in(&app, &username, "a wrong password").await;
+        assert_eq!(response.status().as_u16(), 401);
+    }
+
+    // Act
+    let response = log_in(&app, &username, &app.test_user.password).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 429);
+    assert_eq!(response.headers()["Retry-After"], "900");
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["To"], app.test_user.email);
+    assert!(body["TextBody"].as_str().unwrap().contains("15 minutes"));
+    let actions = sqlx::query_scalar!("SELECT action FROM audit_log")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(actions, vec!["user.locked_out"]);
+}
+
+#[tokio::test]
+async fn unknown_usernames_are_locked_like_known_ones() {
+    // Arrange
+    let app = spawn_app_without_delays().await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    for _ in 0..5 {
+        let response = log_in(&app, "nobody", "a wrong password").await;
+        assert_eq!(response.status().as_u16(), 401);
+    }
+
+    // Act
+    let response = log_in(&app, "nobody", "a wrong password").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 429);
+    assert_eq!(response.headers()["Retry-After"], "900");
+}
+
+#[tokio::test]
+async fn a_successful_login_starts_the_account_over() {
+    // Arrange
+    let app = spawn_app_without_delays().await;
+    let username = app.test_user.username.clone();
+    for _ in 0..4 {
+        log_in(&app, &username, "a wrong password").await;
+    }
+
+    // Act
+    let right = log_in(&app, &username, &app.test_user.password).await;
+    for _ in 0..4 {
+        log_in(&app, &username, "a wrong password").await;
+    }
+    let right_again = log_in(&app, &username, &app.test_user.password).await;
+
+    // Assert
+    assert_eq!(right.status().as_u16(), 200);
+    assert_eq!(right_again.status().as_u16(), 200);
+}
@@ -1,4 +1,23 @@
-﻿mod health_check;
+﻿mod admin_subscribers;
+mod admin_users;
+mod archive;
+mod canonical_emails;
+mod confirmation_reminders;
+mod embed;
+mod health_check;
 mod helpers;
+mod home;
+mod login_throttling;
+mod newsletter_issues;
+mod newsletters;
+mod password_reset;
+mod reports;
+mod segments;
+mod subscriber_csv;
 mod subscriptions;
 mod subscriptions_confirm;
+mod tracking;
+mod two_factor;
+mod unsubscribe;
+mod webhooks;
+mod welcome_series;
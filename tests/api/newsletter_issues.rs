@@ -0,0 +1,434 @@
+use crate::helpers::{TestApp, spawn_app};
+use crate::newsletters::create_confirmed_subscriber;
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+fn markdown_issue() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "markdown": "# Hello {{ name }}\n\nRead [the book](https://www.zero2prod.com)."
+        }
+    })
+}
+
+/// Create a draft and return its id.
+async fn create_draft(app: &TestApp) -> String {
+    let response = app.post_issues(&markdown_issue()).await;
+    assert_eq!(response.status().as_u16(), 201);
+    let body = response.json::<serde_json::Value>().await.unwrap();
+    body["newsletter_issue_id"].as_str().unwrap().to_owned()
+}
+
+async fn get_status(app: &TestApp, issue_id: &str) -> String {
+    let issue = app
+        .get_issues(issue_id)
+        .await
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    issue["status"].as_str().unwrap().to_owned()
+}
+
+fn in_the_past() -> serde_json::Value {
+    serde_json::json!({ "send_at": "2020-01-01T00:00:00Z" })
+}
+
+fn in_the_future() -> serde_json::Value {
+    serde_json::json!({ "send_at": "2999-01-01T00:00:00Z" })
+}
+
+#[tokio::test]
+async fn new_issues_are_drafts() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let issue_id = create_draft(&app).await;
+
+    // Assert
+    let issue = app
+        .get_issues(&issue_id)
+        .await
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    assert_eq!(issue["title"], "Newsletter title");
+    assert_eq!(issue["status"], "draft");
+    assert_eq!(issue["content"], markdown_issue()["content"]);
+    assert!(issue["scheduled_for"].is_null());
+
+    let issues = reqwest::Client::new()
+        .get(format!("{}/newsletters/issues", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .unwrap()
+        .json::<Vec<serde_json::Value>>()
+        .await
+        .unwrap();
+    assert_eq!(issues.len(), 1);
+    assert_eq!(issues[0]["newsletter_issue_id"], issue_id.as_str());
+}
+
+#[tokio::test]
+async fn issues_require_authentication() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters/issues", &app.address))
+        .json(&markdown_issue())
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn invalid_issues_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    let test_cases = vec![
+        (
+            serde_json::json!({"title": " ", "content": {"markdown": "Hello"}}),
+            "empty title",
+        ),
+        (
+            serde_json::json!({"title": "Title", "content": {"markdown": "Hi {{ nmae }}"}}),
+            "unknown merge field",
+        ),
+    ];
+
+    for (body, error_message) in test_cases {
+        // Act
+        let response = app.post_issues(&body).await;
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not fail with 400 Bad Request when the payload had an {}.",
+            error_message
+        );
+    }
+}
+
+#[tokio::test]
+async fn drafts_can_be_edited() {
+    // Arrange
+    let app = spawn_app().await;
+    let issue_id = create_draft(&app).await;
+    let mut edited = markdown_issue();
+    edited["title"] = "Edited title".into();
+
+    // Act
+    let response = app.put_issue(&issue_id, &edited).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let issue = response.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(issue["title"], "Edited title");
+}
+
+#[tokio::test]
+async fn only_drafts_can_be_deleted() {
+    // Arrange
+    let app = spawn_app().await;
+    let draft_id = create_draft(&app).await;
+    let scheduled_id = create_draft(&app).await;
+    app.post_issue_action(&format!("{}/schedule", scheduled_id), &in_the_future())
+        .await;
+
+    // Act
+    let deleted = app.delete_issue(&draft_id).await;
+    let not_deleted = app.delete_issue(&scheduled_id).await;
+
+    // Assert
+    assert_eq!(deleted.status().as_u16(), 204);
+    assert_eq!(app.get_issues(&draft_id).await.status().as_u16(), 404);
+    assert_eq!(not_deleted.status().as_u16(), 409);
+}
+
+#[tokio::test]
+async fn issues_scheduled_in_the_past_are_delivered_right_away() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let issue_id = create_draft(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_issue_action(&format!("{}/schedule", issue_id), &in_the_past())
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body = serde_json::from_slice::<serde_json::Value>(&email_request.body).unwrap();
+    assert_eq!(body["Subject"], "Newsletter title");
+    assert!(body["HtmlBody"].as_str().unwrap().contains("Hello le guin"));
+
+    let issue = app
+        .get_issues(&issue_id)
+        .await
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    assert_eq!(issue["status"], "sent");
+    assert!(!issue["sent_at"].is_null());
+    // A sent issue cannot be changed anymore.
+    assert_eq!(
+        app.put_issue(&issue_id, &markdown_issue())
+            .await
+            .status()
+            .as_u16(),
+        409
+    );
+}
+
+#[tokio::test]
+async fn issues_are_not_sent_before_their_time() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let issue_id = create_draft(&app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_issue_action(&format!("{}/schedule", issue_id), &in_the_future())
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(get_status(&app, &issue_id).await, "scheduled");
+}
+
+#[tokio::test]
+async fn cancelled_issues_are_not_sent() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let issue_id = create_draft(&app).await;
+    app.post_issue_action(&format!("{}/schedule", issue_id), &in_the_past())
+        .await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_issue_action(&format!("{}/cancel", issue_id), &serde_json::json!({}))
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(get_status(&app, &issue_id).await, "cancelled");
+}
+
+#[tokio::test]
+async fn a_due_issue_is_started_only_once() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let issue_id = create_draft(&app).await;
+    app.post_issue_action(&format!("{}/schedule", issue_id), &in_the_past())
+        .await;
+
+    // Act - two instances looking for due issues at the same time
+    let (first, second) = tokio::join!(
+        app.issue_delivery.start_due_issues(),
+        app.issue_delivery.start_due_issues()
+    );
+
+    // Assert
+    assert_eq!(first.unwrap().len() + second.unwrap().len(), 1);
+    let queued = sqlx::query_scalar!("SELECT count(*) FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(queued, Some(1));
+}
+
+#[tokio::test]
+async fn failed_deliveries_are_retried_later() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let issue_id = create_draft(&app).await;
+    app.post_issue_action(&format!("{}/schedule", issue_id), &in_the_past())
+        .await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let n_retries = sqlx::query_scalar!("SELECT n_retries FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(n_retries, 1);
+    assert_eq!(get_status(&app, &issue_id).await, "sending");
+}
+
+#[tokio::test]
+async fn issues_can_be_previewed_in_the_browser() {
+    // Arrange
+    let app = spawn_app().await;
+    let issue_id = create_draft(&app).await;
+
+    // Act
+    let response = app.get_issues(&format!("{}/preview", issue_id)).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers().get("Content-Type").unwrap(),
+        "text/html; charset=utf-8"
+    );
+    let html = response.text().await.unwrap();
+    assert!(html.contains("Hello Ursula Le Guin"));
+}
+
+#[tokio::test]
+async fn scripts_do_not_make_it_into_the_preview() {
+    // Arrange
+    let app = spawn_app().await;
+    let test_cases = vec![
+        (
+            serde_json::json!({
+                "markdown": "Hi {{ \"<script>alert(1)</script>\" | safe }}"
+            }),
+            "a merge field",
+        ),
+        (
+            serde_json::json!({
+                "html": "<p>Hi</p><script>alert(1)</script>",
+                "text": "Hi"
+            }),
+            "ready-made HTML",
+        ),
+    ];
+
+    for (content, description) in test_cases {
+        let response = app
+            .post_issues(&serde_json::json!({ "title": "Hi", "content": content }))
+            .await;
+        assert_eq!(response.status().as_u16(), 201);
+        let issue = response.json::<serde_json::Value>().await.unwrap();
+        let issue_id = issue["newsletter_issue_id"].as_str().unwrap();
+
+        // Act
+        let response = app.get_issues(&format!("{}/preview", issue_id)).await;
+
+        // Assert
+        assert!(
+            response.headers()["Content-Security-Policy"]
+                .to_str()
+                .unwrap()
+                .contains("default-src 'none'")
+        );
+        let html = response.text().await.unwrap();
+        assert!(
+            !html.contains("<script"),
+            "A script made it into the preview through {}.",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_issues_are_sent_to_the_user() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let issue_id = create_draft(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_issue_action(&format!("{}/test", issue_id), &serde_json::json!({}))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 202);
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body = serde_json::from_slice::<serde_json::Value>(&email_request.body).unwrap();
+    assert_eq!(body["To"], app.test_user.email.as_str());
+    assert_eq!(body["Subject"], "[Test] Newsletter title");
+    // A test does not start sending the issue.
+    assert_eq!(get_status(&app, &issue_id).await, "draft");
+}
+
+#[tokio::test]
+async fn test_issues_cannot_be_sent_to_other_addresses() {
+    // Arrange
+    let app = spawn_app().await;
+    let issue_id = create_draft(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_issue_action(
+            &format!("{}/test", issue_id),
+            &serde_json::json!({ "email": "someone-else@example.com" }),
+        )
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 202);
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let body = serde_json::from_slice::<serde_json::Value>(&email_request.body).unwrap();
+    assert_eq!(body["To"], app.test_user.email.as_str());
+}
@@ -0,0 +1,235 @@
+use crate::helpers::{TestApp, spawn_app};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn forgot_password(app: &TestApp, email: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/password-reset", &app.address))
+        .form(&[("email", email)])
+        .send()
+        .await
+        .unwrap()
+}
+
+/// Ask for a reset link for the test user, and return the link sent to them.
+async fn reset_link(app: &TestApp) -> reqwest::Url {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    let response = forgot_password(app, &app.test_user.email).await;
+    assert_eq!(response.status().as_u16(), 200);
+    // It is sent after answering.
+    let mut email_requests = vec![];
+    for _ in 0..50 {
+        email_requests = app.email_server.received_requests().await.unwrap();
+        if !email_requests.is_empty() {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    app.get_confirmation_links(&email_requests[0]).html
+}
+
+async fn reset_password(app: &TestApp, link: &reqwest::Url, password: &str) -> reqwest::Response {
+    let token = link
+        .query_pairs()
+        .find(|(name, _)| name == "token")
+        .unwrap()
+        .1
+        .into_owned();
+    reqwest::Client::new()
+        .post(format!("{}/password-reset/confirm", &app.address))
+        .form(&[("token", token.as_str()), ("password", password)])
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn list_users(app: &TestApp, password: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .get(format!("{}/admin/users", &app.address))
+        .basic_auth(&app.test_user.username, Some(password))
+        .send()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn a_reset_link_sets_a_new_password_once() {
+    // Arrange
+    let app = spawn_app().await;
+    let link = reset_link(&app).await;
+
+    // Act - Part 1 - Open the link
+    let form = reqwest::get(link.clone()).await.unwrap();
+
+    // Assert
+    assert_eq!(form.status().as_u16(), 200);
+    assert!(
+        form.text()
+            .await
+            .unwrap()
+            .contains(r#"action="/password-reset/confirm""#)
+    );
+
+    // Act - Part 2 - Pick a new password
+    let response = reset_password(&app, &link, "a brand new password").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        list_users(&app, "a brand new password")
+            .await
+            .status()
+            .as_u16(),
+        200
+    );
+    assert_eq!(
+        list_users(&app, &app.test_user.password)
+            .await
+            .status()
+            .as_u16(),
+        401
+    );
+
+    // Act - Part 3 - Use the link again
+    let again = reset_password(&app, &link, "yet another new password").await;
+
+    // Assert
+    assert_eq!(again.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn unknown_emails_get_the_same_page_and_no_email() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = forgot_password(&app, "nobody@example.com").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.text().await.unwrap().contains("Check your inbox"));
+}
+
+#[tokio::test]
+async fn the_page_does_not_wait_for_the_email() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200).set_delay(std::time::Duration::from_secs(5)))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let started = std::time::Instant::now();
+    let response = forgot_password(&app, &app.test_user.email).await;
+
+    // Assert - as fast as for an unknown email, which sends nothing.
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(started.elapsed() < std::time::Duration::from_secs(5));
+}
+
+#[tokio::test]
+async fn asking_again_right_away_sends_no_other_email() {
+    // Arrange
+    let app = spawn_app().await;
+    reset_link(&app).await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = forgot_password(&app, &app.test_user.email.to_uppercase()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn short_passwords_are_refused_and_the_link_still_works() {
+    // Arrange
+    let app = spawn_app().await;
+    let link = reset_link(&app).await;
+
+    // Act
+    let short = reset_password(&app, &link, "too short").await;
+    let long_enough = reset_password(&app, &link, "a brand new password").await;
+
+    // Assert
+    assert_eq!(short.status().as_u16(), 400);
+    assert_eq!(long_enough.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn expired_links_cannot_be_used() {
+    // Arrange
+    let app = spawn_app().await;
+    let link = reset_link(&app).await;
+    sqlx::query!("UPDATE password_reset_tokens SET expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let form = reqwest::get(link.clone()).await.unwrap();
+    let response = reset_password(&app, &link, "a brand new password").await;
+
+    // Assert
+    assert_eq!(form.status().as_u16(), 410);
+    assert_eq!(response.status().as_u16(), 410);
+    assert_eq!(
+        list_users(&app, &app.test_user.password)
+            .await
+            .status()
+            .as_u16(),
+        200
+    );
+}
+
+#[tokio::test]
+async fn resetting_a_password_ends_every_session_of_the_user() {
+    // Arrange
+    let app = spawn_app().await;
+    let session: serde_json::Value = reqwest::Client::new()
+        .post(format!("{}/admin/sessions", &app.address))
+        .json(&serde_json::json!({
+            "username": app.test_user.username,
+            "password": app.test_user.password,
+        }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let token = session["token"].as_str().unwrap();
+    let link = reset_link(&app).await;
+
+    // Act
+    reset_password(&app, &link, "a brand new password").await;
+
+    // Assert
+    let response = reqwest::Client::new()
+        .get(format!("{}/admin/users", &app.address))
+        .bearer_auth(token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+    let actions = sqlx::query_scalar!("SELECT action FROM audit_log")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(actions, vec!["user.password_reset"]);
+}
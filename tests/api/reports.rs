@@ -0,0 +1,197 @@
+use crate::helpers::{TestApp, spawn_app};
+use crate::newsletters::create_confirmed_subscriber;
+use crate::tracking::{BROWSER, client, send_issue};
+use chrono::Utc;
+
+async fn get_report(app: &TestApp, path: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .get(format!("{}/admin/reports{}", &app.address, path))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+#[tokio::test]
+async fn issue_reports_count_what_happened_to_the_issue() {
+    // Arrange
+    let app = spawn_app().await;
+    let issue = send_issue(&app, true).await;
+    for link in [
+        &issue.clicks[0],
+        &issue.clicks[0],
+        issue.open.as_ref().unwrap(),
+    ] {
+        client()
+            .get(link.clone())
+            .header("User-Agent", BROWSER)
+            .send()
+            .await
+            .unwrap();
+    }
+    let unsubscribe_link = app.get_unsubscribe_link(&issue.html);
+    app.post_unsubscribe(&unsubscribe_link).await;
+
+    // Act
+    let response = get_report(&app, &format!("/issues/{}", issue.newsletter_issue_id)).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let report = response.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(report["status"], "sent");
+    assert_eq!(report["delivered"], 1);
+    assert_eq!(report["bounced"], 0);
+    assert_eq!(report["unique_opens"], 1);
+    assert_eq!(report["unique_clicks"], 1);
+    assert_eq!(report["unsubscribes"], 1);
+    assert_eq!(
+        report["top_links"],
+        serde_json::json!([{
+            "url": "https://www.zero2prod.com/?a=1&b=2",
+            "clicks": 2,
+            "unique_clicks": 1
+        }])
+    );
+}
+
+#[tokio::test]
+async fn issue_reports_can_be_exported_as_csv() {
+    // Arrange
+    let app = spawn_app().await;
+    let issue = send_issue(&app, true).await;
+
+    // Act
+    let response = get_report(&app, "/issues?format=csv").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers().get("Content-Type").unwrap(),
+        "text/csv; charset=utf-8"
+    );
+    let csv = response.text().await.unwrap();
+    let mut lines = csv.lines();
+    assert_eq!(
+        lines.next().unwrap(),
+        "newsletter_issue_id,title,status,sent_at,delivered,bounced,unique_opens,unique_clicks,unsubscribes"
+    );
+    let row = lines.next().unwrap();
+    assert!(row.starts_with(&format!(
+        "{},Newsletter title,sent,",
+        issue.newsletter_issue_id
+    )));
+    assert!(row.ends_with(",1,0,0,0,0"));
+}
+
+#[tokio::test]
+async fn drafts_have_no_report() {
+    // Arrange
+    let app = spawn_app().await;
+    let issue = app
+        .post_issues(&serde_json::json!({
+            "title": "Draft",
+            "content": {"markdown": "Not yet."}
+        }))
+        .await
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+
+    // Act
+    let response = get_report(
+        &app,
+        &format!("/issues/{}", issue["newsletter_issue_id"].as_str().unwrap()),
+    )
+    .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn growth_reports_count_subscribers_per_day() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let today = Utc::now().date_naive();
+
+    // Act
+    let response = get_report(&app, "/growth").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let days = response.json::<Vec<serde_json::Value>>().await.unwrap();
+    assert_eq!(days.len(), 30);
+    assert_eq!(
+        days.last().unwrap(),
+        &serde_json::json!({
+            "day": today.to_string(),
+            "new": 1,
+            "confirmed": 1,
+            "unsubscribed": 0
+        })
+    );
+    assert!(days[..29].iter().all(|day| day["new"] == 0));
+}
+
+#[tokio::test]
+async fn growth_reports_can_be_exported_as_csv() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = get_report(&app, "/growth?from=2025-01-01&to=2025-01-02&format=csv").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.text().await.unwrap(),
+        "day,new,confirmed,unsubscribed\n2025-01-01,0,0,0\n2025-01-02,0,0,0\n"
+    );
+}
+
+#[tokio::test]
+async fn growth_reports_reject_invalid_ranges() {
+    // Arrange
+    let app = spawn_app().await;
+    let test_cases = vec![
+        (
+            "?from=2025-02-01&to=2025-01-01",
+            "a range ending before it starts",
+        ),
+        ("?from=2020-01-01&to=2025-01-01", "a range of several years"),
+        ("?from=yesterday", "a date that is not a date"),
+    ];
+
+    for (query, description) in test_cases {
+        // Act
+        let response = get_report(&app, &format!("/growth{}", query)).await;
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not fail with 400 Bad Request for {}.",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn the_reports_page_is_shown_to_authenticated_users_only() {
+    // Arrange
+    let app = spawn_app().await;
+    send_issue(&app, true).await;
+
+    // Act
+    let anonymous = reqwest::get(format!("{}/admin/reports", &app.address))
+        .await
+        .unwrap();
+    let authenticated = get_report(&app, "").await;
+
+    // Assert
+    assert_eq!(anonymous.status().as_u16(), 401);
+    assert_eq!(authenticated.status().as_u16(), 200);
+    let html = authenticated.text().await.unwrap();
+    assert!(html.contains("Newsletter title"));
+}
@@ -0,0 +1,309 @@
+use crate::helpers::{TestApp, spawn_app};
+use crate::subscriber_csv::post_import;
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+/// Ged and Tenar are confirmed, Therru is waiting for her confirmation email.
+async fn import_subscribers(app: &TestApp) {
+    post_import(
+        app,
+        "?mode=confirmed",
+        "\
+email,name,attributes.plan,attributes\n\
+ged@earthsea.org,Ged,pro,\"{\"\"seats\"\": 5}\"\n\
+tenar@atuan.org,Tenar,free,\"{\"\"seats\"\": 1}\"\n",
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount_as_scoped(&app.email_server)
+        .await;
+    post_import(
+        app,
+        "?mode=reconfirm",
+        "email,name,attributes.plan\ntherru@gont.org,Therru,pro\n",
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+}
+
+fn segments_request(app: &TestApp, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+    reqwest::Client::new()
+        .request(method, format!("{}/admin/segments{}", &app.address, path))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+}
+
+async fn create_segment(app: &TestApp, name: &str, expression: &str) -> reqwest::Response {
+    segments_request(app, reqwest::Method::POST, "")
+        .json(&serde_json::json!({ "name": name, "expression": expression }))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn create_segment_id(app: &TestApp, expression: &str) -> String {
+    create_segment(app, "Segment", expression)
+        .await
+        .json::<serde_json::Value>()
+        .await
+        .unwrap()["segment_id"]
+        .as_str()
+        .unwrap()
+        .to_owned()
+}
+
+fn issue(segment_id: &str) -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": { "markdown": "Hello {{ name }}" },
+        "segment_id": segment_id
+    })
+}
+
+async fn recipients(app: &TestApp) -> Vec<String> {
+    let mut recipients: Vec<String> = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .map(|request| {
+            let body = serde_json::from_slice::<serde_json::Value>(&request.body).unwrap();
+            body["To"].as_str().unwrap().to_owned()
+        })
+        .collect();
+    recipients.sort();
+    recipients
+}
+
+#[tokio::test]
+async fn segments_require_credentials() {
+    // Arrange
+    let app = spawn_app().await;
+    let segment_path = format!("/admin/segments/{}", Uuid::new_v4());
+
+    for (method, path) in [
+        (reqwest::Method::GET, "/admin/segments"),
+        (reqwest::Method::GET, segment_path.as_str()),
+        (reqwest::Method::DELETE, segment_path.as_str()),
+    ] {
+        // Act
+        let response = reqwest::Client::new()
+            .request(method, format!("{}{}", &app.address, path))
+            .send()
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            401,
+            "{} is not protected.",
+            path
+        );
+    }
+}
+
+#[tokio::test]
+async fn segments_report_how_many_confirmed_subscribers_they_match() {
+    // Arrange
+    let app = spawn_app().await;
+    import_subscribers(&app).await;
+
+    // Act
+    let response = create_segment(
+        &app,
+        "Big customers",
+        r#"attributes.plan = "pro" and attributes.seats >= 2 or opened within 30 days"#,
+    )
+    .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 201);
+    let segment = response.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(segment["name"], "Big customers");
+    // Therru is on the pro plan too, but not confirmed.
+    assert_eq!(segment["audience"], 1);
+
+    let segment_id = segment["segment_id"].as_str().unwrap();
+    let updated = segments_request(&app, reqwest::Method::PUT, &format!("/{}", segment_id))
+        .json(&serde_json::json!({
+            "name": "Everybody but the pros",
+            "expression": r#"attributes.plan != "pro""#
+        }))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    assert_eq!(updated["audience"], 1);
+    let listed = segments_request(&app, reqwest::Method::GET, "")
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    assert_eq!(listed[0]["name"], "Everybody but the pros");
+}
+
+#[tokio::test]
+async fn invalid_segments_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    let test_cases = vec![
+        (
+            "Pros",
+            r#"attributes.plan = "pro"#,
+            "an unterminated string",
+        ),
+        ("Pros", r#"plan = "pro""#, "an unknown field"),
+        ("Pros", "opened within 30", "a missing unit"),
+        ("", r#"attributes.plan = "pro""#, "an empty name"),
+    ];
+
+    for (name, expression, description) in test_cases {
+        // Act
+        let response = create_segment(&app, name, expression).await;
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not fail with 400 Bad Request for {}.",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn segment_names_are_unique() {
+    // Arrange
+    let app = spawn_app().await;
+    create_segment(&app, "Pros", r#"attributes.plan = "pro""#).await;
+
+    // Act
+    let response = create_segment(&app, "Pros", r#"attributes.plan = "team""#).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 409);
+}
+
+#[tokio::test]
+async fn newsletters_can_be_published_to_a_segment() {
+    // Arrange
+    let app = spawn_app().await;
+    import_subscribers(&app).await;
+    let segment_id = create_segment_id(&app, r#"attributes.plan in ("pro", "team")"#).await;
+
+    app.email_server.reset().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_newsletters(&issue(&segment_id)).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 202);
+    assert_eq!(recipients(&app).await, vec!["ged@earthsea.org"]);
+}
+
+#[tokio::test]
+async fn scheduled_issues_go_to_their_segment_only() {
+    // Arrange
+    let app = spawn_app().await;
+    import_subscribers(&app).await;
+    let segment_id = create_segment_id(&app, "not attributes.seats > 1").await;
+    let issue_id = app
+        .post_issues(&issue(&segment_id))
+        .await
+        .json::<serde_json::Value>()
+        .await
+        .unwrap()["newsletter_issue_id"]
+        .as_str()
+        .unwrap()
+        .to_owned();
+    app.email_server.reset().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_issue_action(
+        &format!("{}/schedule", issue_id),
+        &serde_json::json!({ "send_at": "2020-01-01T00:00:00Z" }),
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(recipients(&app).await, vec!["tenar@atuan.org"]);
+    let issue = app
+        .get_issues(&issue_id)
+        .await
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    assert_eq!(issue["segment_id"], segment_id.as_str());
+    assert_eq!(issue["status"], "sent");
+    // The segment the issue was sent to is kept.
+    let deleted = segments_request(&app, reqwest::Method::DELETE, &format!("/{}", segment_id))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(deleted.status().as_u16(), 409);
+}
+
+#[tokio::test]
+async fn issues_for_an_unknown_segment_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    let segment_id = Uuid::new_v4().to_string();
+
+    // Act
+    let published = app.post_newsletters(&issue(&segment_id)).await;
+    let drafted = app.post_issues(&issue(&segment_id)).await;
+
+    // Assert
+    assert_eq!(published.status().as_u16(), 400);
+    assert_eq!(drafted.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn unused_segments_can_be_deleted() {
+    // Arrange
+    let app = spawn_app().await;
+    let segment_id = create_segment_id(&app, "clicked within 7 days").await;
+
+    // Act
+    let response = segments_request(&app, reqwest::Method::DELETE, &format!("/{}", segment_id))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 204);
+    let response = segments_request(&app, reqwest::Method::GET, &format!("/{}", segment_id))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 404);
+}
@@ -0,0 +1,461 @@
+use crate::helpers::{TestApp, spawn_app};
+use crate::newsletters::create_confirmed_subscriber;
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::lib::subscriber_csv::{ImportMode, UnfinishedImport, start_import};
+
+pub(crate) async fn post_import(
+    app: &TestApp,
+    query: &str,
+    csv: impl Into<String>,
+) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!(
+            "{}/admin/subscribers/imports{}",
+            &app.address, query
+        ))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .header("Content-Type", "text/csv")
+        .body(csv.into())
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn get_admin(app: &TestApp, path: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .get(format!("{}/admin{}", &app.address, path))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn post_suppression(app: &TestApp, body: &serde_json::Value) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/admin/suppressions", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .json(body)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn saved_subscribers(app: &TestApp) -> Vec<(String, String, String)> {
+    sqlx::query!("SELECT email, name, status FROM subscriptions ORDER BY email")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|row| (row.email, row.name, row.status))
+        .collect()
+}
+
+#[tokio::test]
+async fn imports_and_exports_require_credentials() {
+    // Arrange
+    let app = spawn_app().await;
+
+    for (method, path) in [
+        (
+            reqwest::Method::POST,
+            "/admin/subscribers/imports?mode=confirmed",
+        ),
+        (reqwest::Method::GET, "/admin/subscribers/imports"),
+        (reqwest::Method::GET, "/admin/subscribers/export"),
+    ] {
+        // Act
+        let response = reqwest::Client::new()
+            .request(method, format!("{}{}", &app.address, path))
+            .send()
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            401,
+            "{} is not protected.",
+            path
+        );
+    }
+}
+
+#[tokio::test]
+async fn valid_rows_are_imported_and_the_others_reported() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    post_suppression(
+        &app,
+        &serde_json::json!({"email": "Bounced@Example.com", "reason": "bounced"}),
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let csv = "\
+id,Name,Email\n\
+1,Ged,ged@earthsea.org\n\
+2,Tenar,not-an-email\n\
+3,,therru@gont.org\n\
+4,\"Ogion, the Silent\",ogion@gont.org\n\
+5,Ged again,GED@earthsea.org\n\
+6,Ursula,ursula_le_guin@gmail.com\n\
+7,Bounced,bounced@example.com\n";
+
+    // Act
+    let response = post_import(&app, "?mode=confirmed", csv).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let import = response.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(import["status"], "completed");
+    assert_eq!(import["mode"], "confirmed");
+    assert_eq!(import["processed"], 7);
+    assert_eq!(import["imported"], 2);
+    assert_eq!(import["duplicates"], 2);
+    assert_eq!(import["suppressed"], 1);
+    assert_eq!(import["invalid"], 2);
+    let rows: Vec<_> = import["errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| e["row"].as_u64().unwrap())
+        .collect();
+    assert_eq!(rows, vec![3, 4]);
+    assert!(import["finished_at"].is_string());
+    assert_eq!(
+        saved_subscribers(&app).await,
+        vec![
+            ("ged@earthsea.org".into(), "Ged".into(), "confirmed".into()),
+            (
+                "ogion@gont.org".into(),
+                "Ogion, the Silent".into(),
+                "confirmed".into()
+            ),
+            (
+                "ursula_le_guin@gmail.com".into(),
+                "le guin".into(),
+                "confirmed".into()
+            ),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn reconfirmed_imports_send_a_confirmation_email() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = post_import(
+        &app,
+        "?mode=reconfirm",
+        "email,name\nged@earthsea.org,Ged\ntenar@atuan.org,Tenar",
+    )
+    .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let import = response.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(import["imported"], 2);
+    assert!(
+        saved_subscribers(&app)
+            .await
+            .iter()
+            .all(|(_, _, status)| status == "pending_confirmation")
+    );
+    // The emails are sent in the background, after the import.
+    assert!(
+        app.email_server
+            .received_requests()
+            .await
+            .unwrap()
+            .is_empty()
+    );
+    app.send_queued_confirmation_emails().await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let confirmed =
+        sqlx::query_scalar!("SELECT count(*) FROM subscriptions WHERE status = 'confirmed'")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(confirmed, Some(1));
+}
+
+#[tokio::test]
+async fn large_files_are_imported_in_batches() {
+    // Arrange
+    let app = spawn_app().await;
+    let mut csv = "email,name\n".to_owned();
+    for i in 0..1234 {
+        csv.push_str(&format!("reader{}@example.com,Reader {}\n", i, i));
+    }
+
+    // Act
+    let response = post_import(&app, "?mode=confirmed", csv).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let import = response.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(import["processed"], 1234);
+    assert_eq!(import["imported"], 1234);
+    assert_eq!(saved_subscribers(&app).await.len(), 1234);
+}
+
+#[tokio::test]
+async fn attributes_are_imported() {
+    // Arrange
+    let app = spawn_app().await;
+    let csv = "\
+email,name,attributes,attributes.plan\n\
+ged@earthsea.org,Ged,\"{\"\"seats\"\": 3}\",pro\n\
+tenar@atuan.org,Tenar,,\n\
+therru@gont.org,Therru,not json,\n";
+
+    // Act
+    let response = post_import(&app, "?mode=confirmed", csv).await;
+
+    // Assert
+    let import = response.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(import["imported"], 2);
+    assert_eq!(import["errors"][0]["row"], 4);
+    let attributes: Vec<_> =
+        sqlx::query_scalar!("SELECT attributes FROM subscriptions ORDER BY email")
+            .fetch_all(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(
+        attributes,
+        vec![
+            serde_json::json!({"seats": 3, "plan": "pro"}),
+            serde_json::json!({})
+        ]
+    );
+}
+
+#[tokio::test]
+async fn imports_can_be_followed() {
+    // Arrange
+    let app = spawn_app().await;
+    let import = post_import(
+        &app,
+        "?mode=confirmed",
+        "email,name\nged@earthsea.org,Ged\n",
+    )
+    .await
+    .json::<serde_json::Value>()
+    .await
+    .unwrap();
+    let import_id = import["import_id"].as_str().unwrap();
+
+    // Act
+    let imports = get_admin(&app, "/subscribers/imports").await;
+    let one = get_admin(&app, &format!("/subscribers/imports/{}", import_id)).await;
+
+    // Assert
+    assert_eq!(imports.status().as_u16(), 200);
+    assert_eq!(
+        imports.json::<serde_json::Value>().await.unwrap(),
+        serde_json::json!([import])
+    );
+    assert_eq!(one.status().as_u16(), 200);
+    assert_eq!(one.json::<serde_json::Value>().await.unwrap(), import);
+}
+
+#[tokio::test]
+async fn unusable_files_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    let test_cases = vec![
+        (
+            "?mode=confirmed",
+            "name,mail\nGed,ged@earthsea.org\n",
+            "a file without an email column",
+        ),
+        ("?mode=confirmed", "", "an empty file"),
+        ("", "email,name\n", "a missing mode"),
+        ("?mode=maybe", "email,name\n", "an unknown mode"),
+    ];
+
+    for (query, csv, description) in test_cases {
+        // Act
+        let response = post_import(&app, query, csv).await;
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not fail with 400 Bad Request for {}.",
+            description
+        );
+    }
+    let statuses = sqlx::query_scalar!("SELECT status FROM subscriber_imports")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(statuses, vec!["failed", "failed"]);
+}
+
+#[tokio::test]
+async fn imports_that_stop_halfway_are_failed() {
+    // Arrange
+    let app = spawn_app().await;
+    let import_id = start_import(&app.db_pool, app.test_user.user_id, ImportMode::Confirmed)
+        .await
+        .unwrap();
+
+    // Act - the request importing it ends before completing it.
+    drop(UnfinishedImport::new(app.db_pool.clone(), import_id));
+
+    // Assert
+    let mut status = String::new();
+    for _ in 0..50 {
+        status = sqlx::query_scalar!(
+            "SELECT status FROM subscriber_imports WHERE import_id = $1",
+            import_id
+        )
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+        if status != "running" {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    assert_eq!(status, "failed");
+}
+
+#[tokio::test]
+async fn deleted_subscribers_are_not_imported_again() {
+    // Arrange
+    let app = spawn_app().await;
+    post_import(
+        &app,
+        "?mode=confirmed",
+        "email,name\nged@earthsea.org,Ged\n",
+    )
+    .await;
+    let subscriber_id = sqlx::query_scalar!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    reqwest::Client::new()
+        .delete(format!(
+            "{}/admin/subscribers/{}",
+            &app.address, subscriber_id
+        ))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    let response = post_import(
+        &app,
+        "?mode=confirmed",
+        "email,name\nGed@Earthsea.org,Ged\n",
+    )
+    .await;
+
+    // Assert
+    let import = response.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(import["imported"], 0);
+    assert_eq!(import["suppressed"], 1);
+    assert!(saved_subscribers(&app).await.is_empty());
+}
+
+#[tokio::test]
+async fn invalid_suppressions_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = post_suppression(
+        &app,
+        &serde_json::json!({"email": "not-an-email", "reason": "bounced"}),
+    )
+    .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn exports_use_the_filters_of_the_listing() {
+    // Arrange
+    let app = spawn_app().await;
+    post_import(
+        &app,
+        "?mode=confirmed",
+        "email,name\nged@earthsea.org,Ged\ntenar@atuan.org,\"Tenar, of Atuan\"\n",
+    )
+    .await;
+    post_import(
+        &app,
+        "?mode=reconfirm",
+        "email,name\ntherru@gont.org,Therru\n",
+    )
+    .await;
+
+    // Act
+    let response = get_admin(&app, "/subscribers/export?status=confirmed").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers().get("Content-Type").unwrap(),
+        "text/csv; charset=utf-8"
+    );
+    let csv = response.text().await.unwrap();
+    let mut reader = csv::Reader::from_reader(csv.as_bytes());
+    assert_eq!(
+        reader.headers().unwrap(),
+        vec![
+            "id",
+            "email",
+            "name",
+            "status",
+            "locale",
+            "subscribed_at",
+            "confirmed_at",
+            "unsubscribed_at",
+            "lists",
+            "attributes"
+        ]
+    );
+    let mut rows: Vec<(String, String)> = reader
+        .records()
+        .map(|record| {
+            let record = record.unwrap();
+            (record[1].to_owned(), record[2].to_owned())
+        })
+        .collect();
+    rows.sort();
+    assert_eq!(
+        rows,
+        vec![
+            ("ged@earthsea.org".into(), "Ged".into()),
+            ("tenar@atuan.org".into(), "Tenar, of Atuan".into()),
+        ]
+    );
+}
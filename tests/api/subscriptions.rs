@@ -1,7 +1,24 @@
-﻿use crate::helpers::spawn_app;
+use crate::helpers::{TestApp, spawn_app, spawn_app_with};
+use uuid::Uuid;
 use wiremock::matchers::any;
 use wiremock::matchers::{method, path};
 use wiremock::{Mock, ResponseTemplate};
+use zero2prod::lib::configurations::OptIn;
+
+async fn create_list(app: &TestApp, slug: &str, opt_in: Option<&str>) {
+    sqlx::query!(
+        r#"
+        INSERT INTO lists (list_id, slug, name, created_at, opt_in)
+        VALUES ($1, $2, $2, now(), $3)
+        "#,
+        Uuid::new_v4(),
+        slug,
+        opt_in
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
 
 #[tokio::test]
 async fn subscriptions_returns_200_for_valid_form_data() {
@@ -123,3 +140,622 @@ async fn subscribe_sends_a_confirmation_email_for_valid_data() {
 
     assert_eq!(confirmation_links.html, confirmation_links.plain_text);
 }
+
+#[tokio::test]
+async fn subscribe_silently_drops_submissions_with_a_filled_honeypot() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com&website=http%3A%2F%2Fspam.com";
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_subscriptions(body.into()).await;
+
+    // Assert
+    // The bot must not be able to tell it has been caught.
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_optional(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription");
+    assert!(saved.is_none());
+}
+
+#[tokio::test]
+async fn subscribe_silently_drops_forms_submitted_too_fast() {
+    // Arrange
+    let app = spawn_app().await;
+    let form_token = app.get_form_token().await;
+    let form_token = form_token["form_token"].as_str().unwrap();
+    let body = format!(
+        "name=le%20guin&email=ursula_le_guin%40gmail.com&form_token={}",
+        form_token
+    );
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_subscriptions(body).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_optional(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription");
+    assert!(saved.is_none());
+}
+
+#[tokio::test]
+async fn a_form_rejected_for_its_content_can_be_fixed_and_sent_again() {
+    // Arrange
+    let app = spawn_app_with(|c| c.bot_protection.min_submit_seconds = 0).await;
+    let form_token = app.get_form_token().await;
+    let form_token = form_token["form_token"].as_str().unwrap();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let typo = app
+        .post_subscriptions(format!(
+            "name=le%20guin&email=ursula_le_guin%40gmial.com&form_token={}",
+            form_token
+        ))
+        .await;
+    let fixed = app
+        .post_subscriptions(format!(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com&form_token={}",
+            form_token
+        ))
+        .await;
+
+    // Assert
+    assert_eq!(typo.status().as_u16(), 400);
+    assert_eq!(fixed.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription");
+    assert_eq!(saved.email, "ursula_le_guin@gmail.com");
+}
+
+#[tokio::test]
+async fn subscribe_silently_drops_forms_sent_again_after_a_signup() {
+    // Arrange
+    let app = spawn_app_with(|c| c.bot_protection.min_submit_seconds = 0).await;
+    let form_token = app.get_form_token().await;
+    let form_token = form_token["form_token"].as_str().unwrap();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let first = app
+        .post_subscriptions(format!(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com&form_token={}",
+            form_token
+        ))
+        .await;
+    let replayed = app
+        .post_subscriptions(format!(
+            "name=ged&email=ged%40earthsea.org&form_token={}",
+            form_token
+        ))
+        .await;
+
+    // Assert
+    assert_eq!(first.status().as_u16(), 200);
+    assert_eq!(replayed.status().as_u16(), 200);
+    let saved = sqlx::query_scalar!("SELECT email FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscriptions");
+    assert_eq!(saved, vec!["ursula_le_guin@gmail.com"]);
+}
+
+#[tokio::test]
+async fn subscribe_treats_differently_spelled_addresses_as_the_same_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    let first = "name=le%20guin&email=Ursula.Le.Guin%40GMail.com";
+    let second = "name=le%20guin&email=ursulaleguin%2Bnews%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let first_response = app.post_subscriptions(first.into()).await;
+    let second_response = app.post_subscriptions(second.into()).await;
+
+    // Assert
+    assert_eq!(first_response.status().as_u16(), 200);
+    assert_eq!(second_response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT email, email_canonical FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscriptions");
+    assert_eq!(saved.len(), 1);
+    assert_eq!(saved[0].email, "Ursula.Le.Guin@gmail.com");
+    assert_eq!(saved[0].email_canonical, "ursulaleguin@gmail.com");
+}
+
+#[tokio::test]
+async fn subscribe_suggests_a_fix_for_a_mistyped_popular_domain() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmial.com";
+
+    // Act
+    let response = app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    assert!(
+        response
+            .text()
+            .await
+            .unwrap()
+            .contains("Did you mean ursula_le_guin@gmail.com?")
+    );
+}
+
+#[tokio::test]
+async fn subscribe_accepts_a_suggested_domain_the_subscriber_insists_on() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmial.com&keep_email_domain=true";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn subscribe_rejects_disposable_email_domains() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40mailinator.com";
+
+    // Act
+    let response = app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn subscribe_accepts_json_and_returns_the_subscriber_id() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = serde_json::json!({
+        "name": "le guin",
+        "email": "ursula_le_guin@gmail.com"
+    });
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_subscriptions_json(&body).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let response_body: serde_json::Value = response.json().await.unwrap();
+    let saved = sqlx::query!("SELECT id, status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription");
+    assert_eq!(response_body["subscriber_id"], saved.id.to_string());
+    assert_eq!(response_body["status"], saved.status);
+}
+
+#[tokio::test]
+async fn subscribe_reports_every_invalid_json_field() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = serde_json::json!({
+        "name": "",
+        "email": "definitely-not-an-email"
+    });
+
+    // Act
+    let response = app.post_subscriptions_json(&body).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let response_body: serde_json::Value = response.json().await.unwrap();
+    let fields = response_body["errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|error| error["field"].as_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(fields, vec!["name", "email"]);
+}
+
+#[tokio::test]
+async fn subscribe_returns_a_structured_error_for_a_malformed_json_body() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = serde_json::json!({ "name": "le guin" });
+
+    // Act
+    let response = app.post_subscriptions_json(&body).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let response_body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(response_body["errors"][0]["code"], "malformed_body");
+}
+
+#[tokio::test]
+async fn subscribe_returns_the_domain_suggestion_to_json_clients() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = serde_json::json!({
+        "name": "le guin",
+        "email": "ursula_le_guin@gmial.com"
+    });
+
+    // Act
+    let response = app.post_subscriptions_json(&body).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let response_body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(response_body["errors"][0]["code"], "possible_typo");
+    assert_eq!(
+        response_body["errors"][0]["suggestion"],
+        "ursula_le_guin@gmail.com"
+    );
+}
+
+#[tokio::test]
+async fn subscribe_shows_the_check_your_inbox_page_to_form_clients() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let html = response.text().await.unwrap();
+    assert!(html.contains("Check your inbox"));
+    assert!(html.contains("ursula_le_guin@gmail.com"));
+}
+
+#[tokio::test]
+async fn subscribe_escapes_user_provided_values_when_showing_the_form_again() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=%3Cscript%3Ealert(1)%3C%2Fscript%3E";
+
+    // Act
+    let response = app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let html = response.text().await.unwrap();
+    assert!(!html.contains("<script>alert(1)"));
+    assert!(html.contains("&#60;script&#62;alert(1)"));
+}
+
+#[tokio::test]
+async fn subscribe_sends_the_confirmation_email_in_the_requested_language() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions", &app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("Accept-Language", "fr;q=0.9, uk-UA;q=0.8, en;q=0.5")
+        .body(body)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert!(response.text().await.unwrap().contains("Перевірте пошту"));
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let email: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(email["Subject"], "Ласкаво просимо!");
+
+    let saved = sqlx::query!("SELECT locale FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.locale, "uk");
+}
+
+#[tokio::test]
+async fn an_explicit_locale_overrides_the_accept_language_header() {
+    // Arrange
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    reqwest::Client::new()
+        .post(format!("{}/subscriptions", &app.address))
+        .header("Accept-Language", "uk")
+        .json(&serde_json::json!({
+            "name": "le guin",
+            "email": "ursula_le_guin@gmail.com",
+            "locale": "en"
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let email: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(email["Subject"], "Welcome!");
+}
+
+#[tokio::test]
+async fn the_confirmation_email_is_rendered_from_the_email_templates() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_subscriptions(body.into()).await;
+
+    // Assert
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let email: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let html = email["HtmlBody"].as_str().unwrap();
+    let text = email["TextBody"].as_str().unwrap();
+    assert!(html.starts_with("<!DOCTYPE html>"));
+    assert!(html.contains("Hi le guin, welcome to Zero To Production!"));
+    assert!(text.contains("Confirm your subscription: http://"));
+}
+
+#[tokio::test]
+async fn subscribe_stores_the_attributes_the_form_may_set() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    let form = "name=le%20guin&email=ursula_le_guin%40gmail.com&keep_email_domain=false\
+                &attributes.source=footer&attributes.vip=yes";
+    let json = serde_json::json!({
+        "name": "ged",
+        "email": "ged@earthsea.org",
+        "attributes": {"plan": "pro", "country": "EA", "vip": true}
+    });
+
+    // Act
+    let form_response = app.post_subscriptions(form.into()).await;
+    let json_response = app.post_subscriptions_json(&json).await;
+
+    // Assert
+    assert_eq!(form_response.status().as_u16(), 200);
+    assert_eq!(json_response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT email, attributes FROM subscriptions ORDER BY email")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    // `vip` is not one of the `signup_attributes`.
+    assert_eq!(
+        saved[0].attributes,
+        serde_json::json!({"plan": "pro", "country": "EA"})
+    );
+    assert_eq!(saved[1].attributes, serde_json::json!({"source": "footer"}));
+}
+
+#[tokio::test]
+async fn subscribe_rejects_invalid_attributes() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = serde_json::json!({
+        "name": "le guin",
+        "email": "ursula_le_guin@gmail.com",
+        "attributes": {"plan": {"name": "pro"}}
+    });
+
+    // Act
+    let response = app.post_subscriptions_json(&body).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["errors"][0]["field"], "attributes");
+}
+
+#[tokio::test]
+async fn signups_to_a_single_opt_in_list_are_confirmed_right_away() {
+    // Arrange
+    let app = spawn_app().await;
+    create_list(&app, "staff", Some("single")).await;
+    let body = serde_json::json!({
+        "name": "le guin",
+        "email": "ursula_le_guin@gmail.com",
+        "list": "staff"
+    });
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions", &app.address))
+        .header("User-Agent", "signup-test")
+        .json(&body)
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let response_body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(response_body["status"], "confirmed");
+    let saved = sqlx::query!(
+        r#"
+        SELECT s.status, s.confirmed_at IS NOT NULL AS "confirmed!", l.slug
+        FROM subscriptions s
+        JOIN list_subscriptions ls ON ls.subscriber_id = s.id
+        JOIN lists l ON l.list_id = ls.list_id
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.status, "confirmed");
+    assert!(saved.confirmed);
+    assert_eq!(saved.slug, "staff");
+    let consent = sqlx::query!(
+        "SELECT method, user_agent, ip_address, list_id IS NOT NULL AS \"has_list!\" FROM subscriber_consents"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(consent.method, "single_opt_in");
+    assert_eq!(consent.user_agent.as_deref(), Some("signup-test"));
+    assert!(consent.ip_address.is_some());
+    assert!(consent.has_list);
+    // The welcome series stands in for the confirmation email.
+    let enrolled =
+        sqlx::query_scalar!(r#"SELECT count(*) AS "count!" FROM welcome_series_enrollments"#)
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(enrolled, 1);
+}
+
+#[tokio::test]
+async fn lists_can_require_double_opt_in_on_a_single_opt_in_deployment() {
+    // Arrange
+    let app = spawn_app_with(|conf| {
+        conf.subscriptions.opt_in = OptIn::Single;
+        conf.subscriptions.single_opt_in_welcome_email = false;
+    })
+    .await;
+    create_list(&app, "newsletter", Some("double")).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let single = app
+        .post_subscriptions_json(&serde_json::json!({
+            "name": "le guin",
+            "email": "ursula_le_guin@gmail.com"
+        }))
+        .await;
+    let double = app
+        .post_subscriptions_json(&serde_json::json!({
+            "name": "ged",
+            "email": "ged@earthsea.org",
+            "list": "newsletter"
+        }))
+        .await;
+
+    // Assert
+    let single: serde_json::Value = single.json().await.unwrap();
+    let double: serde_json::Value = double.json().await.unwrap();
+    assert_eq!(single["status"], "confirmed");
+    assert_eq!(double["status"], "pending_confirmation");
+    let consents = sqlx::query_scalar!("SELECT method FROM subscriber_consents ORDER BY method")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(consents, vec!["double_opt_in", "single_opt_in"]);
+    // No welcome email when it is turned off.
+    let enrolled =
+        sqlx::query_scalar!(r#"SELECT count(*) AS "count!" FROM welcome_series_enrollments"#)
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(enrolled, 0);
+}
+
+#[tokio::test]
+async fn subscribe_rejects_unknown_lists() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = serde_json::json!({
+        "name": "le guin",
+        "email": "ursula_le_guin@gmail.com",
+        "list": "nope"
+    });
+
+    // Act
+    let response = app.post_subscriptions_json(&body).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["errors"][0]["field"], "list");
+    assert_eq!(body["errors"][0]["code"], "unknown");
+}
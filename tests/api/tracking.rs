@@ -0,0 +1,262 @@
+use crate::helpers::{TestApp, spawn_app};
+use crate::newsletters::create_confirmed_subscriber;
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::lib::configurations::get_configuration;
+use zero2prod::lib::tracking::TrackingToken;
+
+pub(crate) const BROWSER: &str =
+    "Mozilla/5.0 (X11; Linux x86_64; rv:131.0) Gecko/20100101 Firefox/131.0";
+
+/// The issue sent to the confirmed subscriber.
+pub(crate) struct SentIssue {
+    pub newsletter_issue_id: String,
+    pub html: String,
+    /// The click and open links, pointed at the test application.
+    pub clicks: Vec<reqwest::Url>,
+    pub open: Option<reqwest::Url>,
+}
+
+/// Send an issue to a newly confirmed subscriber, through the API.
+pub(crate) async fn send_issue(app: &TestApp, tracking: bool) -> SentIssue {
+    create_confirmed_subscriber(app).await;
+    let body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "markdown": "Read [the book](https://www.zero2prod.com/?a=1&b=2) or [write](mailto:me@example.com)."
+        },
+        "tracking": tracking
+    });
+    let issue = app
+        .post_issues(&body)
+        .await
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    let issue_id = issue["newsletter_issue_id"].as_str().unwrap().to_owned();
+    app.post_issue_action(
+        &format!("{}/schedule", issue_id),
+        &serde_json::json!({ "send_at": "2020-01-01T00:00:00Z" }),
+    )
+    .await;
+
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body = serde_json::from_slice::<serde_json::Value>(&email_request.body).unwrap();
+    let html = body["HtmlBody"].as_str().unwrap().to_owned();
+    let links = linkify::LinkFinder::new()
+        .links(&html)
+        .filter_map(|l| {
+            let mut link = reqwest::Url::parse(l.as_str()).ok()?;
+            // Only ours: not the links of the content, nor email addresses.
+            if link.host_str() != Some("localhost") {
+                return None;
+            }
+            link.set_port(Some(app.port)).unwrap();
+            Some(link)
+        })
+        .collect::<Vec<_>>();
+    SentIssue {
+        newsletter_issue_id: issue_id,
+        html,
+        clicks: links
+            .iter()
+            .filter(|l| l.path().starts_with("/t/c/"))
+            .cloned()
+            .collect(),
+        open: links.into_iter().find(|l| l.path().starts_with("/t/o/")),
+    }
+}
+
+pub(crate) fn client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap()
+}
+
+async fn count_events(app: &TestApp, kind: &str) -> i64 {
+    sqlx::query_scalar!("SELECT count(*) FROM issue_events WHERE kind = $1", kind)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn clicks_are_redirected_to_the_original_link_and_recorded() {
+    // Arrange
+    let app = spawn_app().await;
+    let links = send_issue(&app, true).await;
+    assert_eq!(links.clicks.len(), 1);
+
+    // Act
+    let response = client()
+        .get(links.clicks[0].clone())
+        .header("User-Agent", BROWSER)
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 302);
+    assert_eq!(
+        response.headers().get("Location").unwrap(),
+        "https://www.zero2prod.com/?a=1&b=2"
+    );
+    let url = sqlx::query_scalar!("SELECT url FROM issue_events WHERE kind = 'click'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(url.as_deref(), Some("https://www.zero2prod.com/?a=1&b=2"));
+}
+
+#[tokio::test]
+async fn opens_are_recorded_through_the_pixel() {
+    // Arrange
+    let app = spawn_app().await;
+    let links = send_issue(&app, true).await;
+
+    // Act
+    let response = client()
+        .get(links.open.unwrap())
+        .header("User-Agent", BROWSER)
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.headers().get("Content-Type").unwrap(), "image/gif");
+    assert_eq!(count_events(&app, "open").await, 1);
+}
+
+#[tokio::test]
+async fn bots_and_prefetches_are_not_recorded() {
+    // Arrange
+    let app = spawn_app().await;
+    let links = send_issue(&app, true).await;
+    let click = links.clicks[0].clone();
+    let requests = vec![
+        (client().get(click.clone()), "no user agent"),
+        (
+            client()
+                .get(click.clone())
+                .header("User-Agent", "Mozilla/5.0 (compatible; Googlebot/2.1)"),
+            "a crawler",
+        ),
+        (
+            client()
+                .get(click.clone())
+                .header("User-Agent", BROWSER)
+                .header("Sec-Purpose", "prefetch"),
+            "a prefetch",
+        ),
+    ];
+
+    for (request, description) in requests {
+        // Act
+        let response = request.send().await.unwrap();
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            302,
+            "The redirect did not work for {}.",
+            description
+        );
+    }
+    assert_eq!(count_events(&app, "click").await, 0);
+}
+
+#[tokio::test]
+async fn links_that_are_not_in_the_issue_are_not_redirected_to() {
+    // Arrange
+    let app = spawn_app().await;
+    let links = send_issue(&app, true).await;
+    let hmac_secret = get_configuration().unwrap().application.hmac_secret;
+    let token = links.clicks[0].path().strip_prefix("/t/c/").unwrap();
+    let token = TrackingToken::decode(token, "click", &hmac_secret).unwrap();
+    // Properly signed, but not a link of the issue.
+    let forged = TrackingToken {
+        url: Some("https://evil.example.com".into()),
+        ..token
+    }
+    .encode(&hmac_secret);
+
+    // Act
+    let forged = client()
+        .get(format!("{}/t/c/{}", app.address, forged))
+        .header("User-Agent", BROWSER)
+        .send()
+        .await
+        .unwrap();
+    let tampered = client()
+        .get(format!("{}/t/c/{}x", app.address, Uuid::new_v4()))
+        .header("User-Agent", BROWSER)
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(forged.status().as_u16(), 404);
+    assert_eq!(tampered.status().as_u16(), 404);
+    assert_eq!(count_events(&app, "click").await, 0);
+}
+
+#[tokio::test]
+async fn links_keep_working_once_the_subscriber_is_gone() {
+    // Arrange
+    let app = spawn_app().await;
+    let links = send_issue(&app, true).await;
+    sqlx::query!("DELETE FROM subscription_tokens")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    sqlx::query!("DELETE FROM subscriptions")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = client()
+        .get(links.clicks[0].clone())
+        .header("User-Agent", BROWSER)
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 302);
+    assert_eq!(
+        response.headers().get("Location").unwrap(),
+        "https://www.zero2prod.com/?a=1&b=2"
+    );
+}
+
+#[tokio::test]
+async fn tracking_can_be_turned_off() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let links = send_issue(&app, false).await;
+
+    // Assert
+    assert!(links.clicks.is_empty());
+    assert!(links.open.is_none());
+}
@@ -0,0 +1,343 @@
+use crate::helpers::{TestApp, TestUser, spawn_app, spawn_app_with};
+use data_encoding::BASE32_NOPAD;
+use zero2prod::lib::totp::{Algorithm, totp};
+
+/// The code an authenticator app shows for `secret`, `offset_seconds` from
+/// now.
+fn code_for(secret: &str, offset_seconds: i64) -> String {
+    let key = BASE32_NOPAD.decode(secret.as_bytes()).unwrap();
+    let now = chrono::Utc::now().timestamp();
+    totp(&key, now + offset_seconds, 30, 6, Algorithm::Sha1)
+}
+
+fn basic_request(
+    app: &TestApp,
+    user: &TestUser,
+    method: reqwest::Method,
+    path: &str,
+) -> reqwest::RequestBuilder {
+    reqwest::Client::new()
+        .request(method, format!("{}{}", &app.address, path))
+        .basic_auth(&user.username, Some(&user.password))
+}
+
+fn session_request(
+    app: &TestApp,
+    token: &str,
+    method: reqwest::Method,
+    path: &str,
+) -> reqwest::RequestBuilder {
+    reqwest::Client::new()
+        .request(method, format!("{}{}", &app.address, path))
+        .bearer_auth(token)
+}
+
+/// Turn two-factor authentication on for `user`, with a code from the
+/// current step. Returns the secret and the recovery codes.
+async fn enroll(app: &TestApp, user: &TestUser) -> (String, Vec<String>) {
+    let enrollment: serde_json::Value = basic_request(
+        app,
+        user,
+        reqwest::Method::POST,
+        "/admin/account/two-factor",
+    )
+    .send()
+    .await
+    .unwrap()
+    .json()
+    .await
+    .unwrap();
+    let secret = enrollment["secret"].as_str().unwrap().to_owned();
+    let response = basic_request(
+        app,
+        user,
+        reqwest::Method::POST,
+        "/admin/account/two-factor/confirm",
+    )
+    .json(&serde_json::json!({ "code": code_for(&secret, 0) }))
+    .send()
+    .await
+    .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    let recovery_codes = body["recovery_codes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|code| code.as_str().unwrap().to_owned())
+        .collect();
+    (secret, recovery_codes)
+}
+
+/// Log in with the password of `user`, and return the two-factor challenge.
+async fn start_login(app: &TestApp, user: &TestUser) -> String {
+    let response = reqwest::Client::new()
+        .post(format!("{}/admin/sessions", &app.address))
+        .json(&serde_json::json!({
+            "username": user.username,
+            "password": user.password,
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    body["two_factor_challenge"].as_str().unwrap().to_owned()
+}
+
+async fn answer_challenge(app: &TestApp, challenge: &str, code: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/admin/sessions/two-factor", &app.address))
+        .json(&serde_json::json!({ "challenge": challenge, "code": code }))
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn session_token(response: reqwest::Response) -> String {
+    assert_eq!(response.status().as_u16(), 201);
+    let body: serde_json::Value = response.json().await.unwrap();
+    body["token"].as_str().unwrap().to_owned()
+}
+
+#[tokio::test]
+async fn enrollment_returns_a_provisioning_uri_for_authenticator_apps() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = basic_request(
+        &app,
+        &app.test_user,
+        reqwest::Method::POST,
+        "/admin/account/two-factor",
+    )
+    .send()
+    .await
+    .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    let secret = body["secret"].as_str().unwrap();
+    let uri = reqwest::Url::parse(body["provisioning_uri"].as_str().unwrap()).unwrap();
+    assert_eq!(uri.scheme(), "otpauth");
+    assert_eq!(uri.host_str(), Some("totp"));
+    assert!(
+        uri.path()
+            .ends_with(&format!(":{}", app.test_user.username))
+    );
+    assert!(uri.query_pairs().any(|(k, v)| k == "secret" && v == secret));
+    // Until a first code is confirmed, the password alone still works.
+    let users = basic_request(&app, &app.test_user, reqwest::Method::GET, "/admin/users")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(users.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn users_with_two_factor_authentication_log_in_with_a_code() {
+    // Arrange
+    let app = spawn_app().await;
+    let (secret, recovery_codes) = enroll(&app, &app.test_user).await;
+    assert_eq!(recovery_codes.len(), 10);
+
+    // Act - Part 1 - The password alone
+    let basic = basic_request(&app, &app.test_user, reqwest::Method::GET, "/admin/users")
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(basic.status().as_u16(), 401);
+
+    // Act - Part 2 - The password, then a wrong code and the right one
+    let challenge = start_login(&app, &app.test_user).await;
+    let wrong = answer_challenge(&app, &challenge, "000000").await;
+    // The code of the current step was used to confirm the enrollment.
+    let code = code_for(&secret, 30);
+    let right = answer_challenge(&app, &challenge, &code).await;
+
+    // Assert
+    assert_eq!(wrong.status().as_u16(), 401);
+    let token = session_token(right).await;
+    let users = session_request(&app, &token, reqwest::Method::GET, "/admin/users")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(users.status().as_u16(), 200);
+
+    // Act - Part 3 - The same code again
+    let challenge = start_login(&app, &app.test_user).await;
+    let replayed = answer_challenge(&app, &challenge, &code).await;
+
+    // Assert
+    assert_eq!(replayed.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn recovery_codes_work_only_once() {
+    // Arrange
+    let app = spawn_app().await;
+    let (_, recovery_codes) = enroll(&app, &app.test_user).await;
+
+    // Act
+    let challenge = start_login(&app, &app.test_user).await;
+    let first = answer_challenge(&app, &challenge, &recovery_codes[3].to_uppercase()).await;
+    let challenge = start_login(&app, &app.test_user).await;
+    let second = answer_challenge(&app, &challenge, &recovery_codes[3]).await;
+
+    // Assert
+    assert_eq!(first.status().as_u16(), 201);
+    assert_eq!(second.status().as_u16(), 401);
+    let actions = sqlx::query_scalar!("SELECT action FROM audit_log ORDER BY occurred_at")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(
+        actions,
+        vec!["user.two_factor_enabled", "user.recovery_code_used"]
+    );
+}
+
+#[tokio::test]
+async fn a_challenge_ends_after_five_wrong_codes() {
+    // Arrange
+    let app = spawn_app().await;
+    let (secret, _) = enroll(&app, &app.test_user).await;
+    let challenge = start_login(&app, &app.test_user).await;
+    for _ in 0..5 {
+        let response = answer_challenge(&app, &challenge, "000000").await;
+        assert_eq!(response.status().as_u16(), 401);
+    }
+
+    // Act
+    let response = answer_challenge(&app, &challenge, &code_for(&secret, 30)).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn logging_out_ends_the_session() {
+    // Arrange
+    let app = spawn_app().await;
+    let response = reqwest::Client::new()
+        .post(format!("{}/admin/sessions", &app.address))
+        .json(&serde_json::json!({
+            "username": app.test_user.username,
+            "password": app.test_user.password,
+        }))
+        .send()
+        .await
+        .unwrap();
+    let token = session_token(response).await;
+
+    // Act
+    let log_out = session_request(
+        &app,
+        &token,
+        reqwest::Method::DELETE,
+        "/admin/sessions/current",
+    )
+    .send()
+    .await
+    .unwrap();
+
+    // Assert
+    assert_eq!(log_out.status().as_u16(), 204);
+    let users = session_request(&app, &token, reqwest::Method::GET, "/admin/users")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(users.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn two_factor_authentication_can_be_turned_off_with_a_code() {
+    // Arrange
+    let app = spawn_app().await;
+    let (secret, recovery_codes) = enroll(&app, &app.test_user).await;
+    let challenge = start_login(&app, &app.test_user).await;
+    let token =
+        session_token(answer_challenge(&app, &challenge, &code_for(&secret, 30)).await).await;
+    let disable = |code: &str| {
+        session_request(
+            &app,
+            &token,
+            reqwest::Method::DELETE,
+            "/admin/account/two-factor",
+        )
+        .json(&serde_json::json!({ "code": code }))
+        .send()
+    };
+
+    // Act
+    let wrong = disable("000000").await.unwrap();
+    let right = disable(&recovery_codes[0]).await.unwrap();
+
+    // Assert
+    assert_eq!(wrong.status().as_u16(), 400);
+    assert_eq!(right.status().as_u16(), 204);
+    let users = basic_request(&app, &app.test_user, reqwest::Method::GET, "/admin/users")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(users.status().as_u16(), 200);
+    let again = disable(&recovery_codes[1]).await.unwrap();
+    assert_eq!(again.status().as_u16(), 409);
+}
+
+#[tokio::test]
+async fn two_factor_authentication_can_be_mandatory_for_publishers() {
+    // Arrange
+    let app = spawn_app_with(|c| c.admin_users.two_factor_mandatory_for_publishers = true).await;
+    let publisher = TestUser::with_role("publisher");
+    publisher.store(&app.db_pool).await;
+    let editor = TestUser::with_role("editor");
+    editor.store(&app.db_pool).await;
+
+    // Act - Part 1 - Before enrolling
+    let by_publisher = basic_request(
+        &app,
+        &publisher,
+        reqwest::Method::GET,
+        "/newsletters/issues",
+    )
+    .send()
+    .await
+    .unwrap();
+    let by_editor = basic_request(&app, &editor, reqwest::Method::GET, "/newsletters/issues")
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(by_publisher.status().as_u16(), 403);
+    assert_eq!(by_editor.status().as_u16(), 200);
+
+    // Act - Part 2 - After enrolling
+    let (secret, _) = enroll(&app, &publisher).await;
+    let challenge = start_login(&app, &publisher).await;
+    let token =
+        session_token(answer_challenge(&app, &challenge, &code_for(&secret, 30)).await).await;
+    let by_publisher = session_request(&app, &token, reqwest::Method::GET, "/newsletters/issues")
+        .send()
+        .await
+        .unwrap();
+    let disable = session_request(
+        &app,
+        &token,
+        reqwest::Method::DELETE,
+        "/admin/account/two-factor",
+    )
+    .json(&serde_json::json!({ "code": code_for(&secret, 60) }))
+    .send()
+    .await
+    .unwrap();
+
+    // Assert
+    assert_eq!(by_publisher.status().as_u16(), 200);
+    assert_eq!(disable.status().as_u16(), 409);
+}
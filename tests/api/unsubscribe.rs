@@ -0,0 +1,64 @@
+use crate::helpers::spawn_app;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+#[tokio::test]
+async fn the_unsubscribe_link_on_the_confirmation_page_unsubscribes() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into()).await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    let confirmed_page = reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    let unsubscribe_link = app.get_unsubscribe_link(&confirmed_page);
+
+    // Act - Part 1 - Following the link only asks for a confirmation
+    let response = reqwest::get(unsubscribe_link.clone()).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+
+    // Act - Part 2 - Submit the confirmation form
+    let response = app.post_unsubscribe(&unsubscribe_link).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "unsubscribed");
+}
+
+#[tokio::test]
+async fn a_tampered_unsubscribe_link_is_rejected_with_a_401() {
+    // Arrange
+    let app = spawn_app().await;
+    let url = format!(
+        "{}/subscriptions/unsubscribe?subscriber_id={}&token=deadbeef",
+        app.address,
+        uuid::Uuid::new_v4()
+    );
+
+    // Act
+    let response = reqwest::get(&url).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}
@@ -0,0 +1,317 @@
+use crate::helpers::{TestApp, spawn_app, spawn_app_with};
+use crate::newsletters::{create_confirmed_subscriber, create_unconfirmed_subscriber};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+use zero2prod::lib::webhooks::sign;
+
+fn admin_request(app: &TestApp, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+    reqwest::Client::new()
+        .request(method, format!("{}/admin/webhooks{}", &app.address, path))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+}
+
+/// Register `receiver` for `events`, and return the endpoint with its secret.
+async fn register(app: &TestApp, receiver: &MockServer, events: &[&str]) -> serde_json::Value {
+    let response = admin_request(app, reqwest::Method::POST, "")
+        .json(&serde_json::json!({
+            "url": format!("{}/hooks", receiver.uri()),
+            "events": events
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 201);
+    response.json().await.unwrap()
+}
+
+async fn deliveries(app: &TestApp, endpoint: &serde_json::Value) -> Vec<serde_json::Value> {
+    let path = format!("/{}/deliveries", endpoint["endpoint_id"].as_str().unwrap());
+    admin_request(app, reqwest::Method::GET, &path)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap()
+}
+
+/// The events the receiver got, in order, after checking their signature.
+async fn received_events(receiver: &MockServer, secret: &str) -> Vec<serde_json::Value> {
+    receiver
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .map(|request| {
+            let header = |name: &str| request.headers.get(name).unwrap().to_str().unwrap();
+            let body = std::str::from_utf8(&request.body).unwrap();
+            let timestamp: i64 = header("X-Webhook-Timestamp").parse().unwrap();
+            assert_eq!(header("X-Webhook-Signature"), sign(secret, timestamp, body));
+            let event: serde_json::Value = serde_json::from_str(body).unwrap();
+            assert_eq!(header("X-Webhook-Id"), event["id"]);
+            event
+        })
+        .collect()
+}
+
+async fn receiver_answering(status: u16) -> MockServer {
+    let receiver = MockServer::start().await;
+    Mock::given(path("/hooks"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(status))
+        .mount(&receiver)
+        .await;
+    receiver
+}
+
+#[tokio::test]
+async fn webhooks_require_credentials() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .get(format!("{}/admin/webhooks", &app.address))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn invalid_endpoints_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    let test_cases = vec![
+        (serde_json::json!({"url": "not a url"}), "an invalid URL"),
+        (
+            serde_json::json!({"url": "ftp://crm.example.com/hooks"}),
+            "a non-HTTP URL",
+        ),
+        (
+            serde_json::json!({"url": "https://crm.example.com/hooks", "events": ["subscriber.deleted"]}),
+            "an unknown event",
+        ),
+    ];
+
+    for (body, description) in test_cases {
+        // Act
+        let response = admin_request(&app, reqwest::Method::POST, "")
+            .json(&body)
+            .send()
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not reject {}.",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn subscriber_events_are_signed_and_delivered_in_order() {
+    // Arrange
+    let app = spawn_app().await;
+    let receiver = receiver_answering(200).await;
+    let endpoint = register(&app, &receiver, &[]).await;
+
+    // Act
+    let confirmation_link = create_unconfirmed_subscriber(&app).await;
+    reqwest::get(confirmation_link.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let subscriber_id = sqlx::query_scalar!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    reqwest::Client::new()
+        .post(format!(
+            "{}/admin/subscribers/{}/unsubscribe",
+            &app.address, subscriber_id
+        ))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    app.dispatch_due_webhooks().await;
+
+    // Assert
+    let events = received_events(&receiver, endpoint["secret"].as_str().unwrap()).await;
+    let types = events
+        .iter()
+        .map(|event| event["type"].as_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        types,
+        vec![
+            "subscriber.subscribed",
+            "subscriber.confirmed",
+            "subscriber.unsubscribed"
+        ]
+    );
+    assert_eq!(events[0]["data"]["email"], "ursula_le_guin@gmail.com");
+    assert_eq!(events[0]["data"]["status"], "pending_confirmation");
+    assert_eq!(
+        events[2]["data"]["subscriber_id"],
+        subscriber_id.to_string()
+    );
+    let log = deliveries(&app, &endpoint).await;
+    assert_eq!(log.len(), 3);
+    assert!(log.iter().all(|delivery| delivery["status"] == "delivered"));
+}
+
+#[tokio::test]
+async fn endpoints_only_get_the_events_they_asked_for() {
+    // Arrange
+    let app = spawn_app().await;
+    let receiver = receiver_answering(200).await;
+    let endpoint = register(&app, &receiver, &["subscriber.confirmed"]).await;
+
+    // Act
+    let confirmation_link = create_unconfirmed_subscriber(&app).await;
+    reqwest::get(confirmation_link.html).await.unwrap();
+    app.dispatch_due_webhooks().await;
+
+    // Assert
+    let events = received_events(&receiver, endpoint["secret"].as_str().unwrap()).await;
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0]["type"], "subscriber.confirmed");
+    assert_eq!(events[0]["data"]["status"], "confirmed");
+}
+
+#[tokio::test]
+async fn failed_deliveries_are_retried_then_can_be_redelivered() {
+    // Arrange
+    let app = spawn_app_with(|conf| conf.webhooks.max_attempts = 2).await;
+    let receiver = receiver_answering(503).await;
+    let endpoint = register(&app, &receiver, &["subscriber.subscribed"]).await;
+    create_unconfirmed_subscriber(&app).await;
+
+    // Act - Part 1 - First attempt
+    app.dispatch_due_webhooks().await;
+
+    // Assert
+    let log = deliveries(&app, &endpoint).await;
+    assert_eq!(log[0]["status"], "pending");
+    assert_eq!(log[0]["n_attempts"], 1);
+    assert_eq!(log[0]["last_status_code"], 503);
+    // Backing off: not due again yet.
+    assert_eq!(receiver.received_requests().await.unwrap().len(), 1);
+
+    // Act - Part 2 - Out of attempts
+    sqlx::query!("UPDATE webhook_deliveries SET next_attempt_at = now()")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    app.dispatch_due_webhooks().await;
+
+    // Assert
+    let log = deliveries(&app, &endpoint).await;
+    assert_eq!(log[0]["status"], "failed");
+    assert_eq!(log[0]["n_attempts"], 2);
+
+    // Act - Part 3 - Manual redelivery once the receiver is fixed
+    receiver.reset().await;
+    Mock::given(path("/hooks"))
+        .respond_with(ResponseTemplate::new(204))
+        .expect(1)
+        .mount(&receiver)
+        .await;
+    let redelivery_path = format!(
+        "/deliveries/{}/redeliver",
+        log[0]["delivery_id"].as_str().unwrap()
+    );
+    let response = admin_request(&app, reqwest::Method::POST, &redelivery_path)
+        .send()
+        .await
+        .unwrap();
+    app.dispatch_due_webhooks().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 202);
+    let log = deliveries(&app, &endpoint).await;
+    assert_eq!(log[0]["status"], "delivered");
+    assert_eq!(log[0]["last_status_code"], 204);
+    assert!(log[0]["delivered_at"].is_string());
+}
+
+#[tokio::test]
+async fn bounced_emails_are_reported() {
+    // Arrange
+    let app = spawn_app_with(|conf| conf.issue_delivery.max_retries = 1).await;
+    let receiver = receiver_answering(200).await;
+    create_confirmed_subscriber(&app).await;
+    let endpoint = register(&app, &receiver, &["email.bounced"]).await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(422))
+        .mount(&app.email_server)
+        .await;
+    let issue: serde_json::Value = app
+        .post_issues(&serde_json::json!({
+            "title": "Newsletter title",
+            "content": {"markdown": "Hello"}
+        }))
+        .await
+        .json()
+        .await
+        .unwrap();
+    let issue_id = issue["newsletter_issue_id"].as_str().unwrap();
+    app.post_issue_action(
+        &format!("{}/schedule", issue_id),
+        &serde_json::json!({ "send_at": "2020-01-01T00:00:00Z" }),
+    )
+    .await;
+
+    // Act
+    app.dispatch_all_pending_emails().await;
+    app.dispatch_due_webhooks().await;
+
+    // Assert
+    let events = received_events(&receiver, endpoint["secret"].as_str().unwrap()).await;
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0]["type"], "email.bounced");
+    assert_eq!(events[0]["data"]["newsletter_issue_id"], issue_id);
+}
+
+#[tokio::test]
+async fn deleted_endpoints_get_nothing_more() {
+    // Arrange
+    let app = spawn_app().await;
+    let receiver = MockServer::start().await;
+    Mock::given(path("/hooks"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&receiver)
+        .await;
+    let endpoint = register(&app, &receiver, &[]).await;
+    let endpoint_path = format!("/{}", endpoint["endpoint_id"].as_str().unwrap());
+
+    // Act
+    let response = admin_request(&app, reqwest::Method::DELETE, &endpoint_path)
+        .send()
+        .await
+        .unwrap();
+    create_unconfirmed_subscriber(&app).await;
+    app.dispatch_due_webhooks().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 204);
+    let endpoints: Vec<serde_json::Value> = admin_request(&app, reqwest::Method::GET, "")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert!(endpoints.is_empty());
+}
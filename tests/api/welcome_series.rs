@@ -0,0 +1,249 @@
+use crate::helpers::{TestApp, spawn_app};
+use crate::newsletters::create_confirmed_subscriber;
+use crate::tracking::{BROWSER, client, send_issue};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+/// Pretend the subscriber enrolled `days` days ago, and that their next step
+/// is due.
+async fn travel_back(app: &TestApp, days: i32) {
+    sqlx::query!(
+        r#"
+        UPDATE welcome_series_enrollments
+        SET enrolled_at = enrolled_at - make_interval(days => $1), next_send_at = now()
+        "#,
+        days
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+async fn enrollment(app: &TestApp) -> (i32, bool, Option<String>) {
+    let enrollment = sqlx::query!(
+        r#"
+        SELECT next_step, completed_at IS NOT NULL AS "completed!", stopped_reason
+        FROM welcome_series_enrollments
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    (
+        enrollment.next_step,
+        enrollment.completed,
+        enrollment.stopped_reason,
+    )
+}
+
+async fn deliveries(app: &TestApp) -> Vec<(i32, String)> {
+    sqlx::query!("SELECT step, outcome FROM welcome_series_deliveries ORDER BY step")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| (r.step, r.outcome))
+        .collect()
+}
+
+async fn subscriber_id(app: &TestApp) -> String {
+    sqlx::query_scalar!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .to_string()
+}
+
+async fn sent_subjects(app: &TestApp) -> Vec<String> {
+    app.email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .map(|r| {
+            let body = serde_json::from_slice::<serde_json::Value>(&r.body).unwrap();
+            body["Subject"].as_str().unwrap().to_owned()
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn confirmed_subscribers_receive_each_step_when_it_is_due() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    // Forget the confirmation email.
+    app.email_server.reset().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Part 1 - Day 0
+    app.send_due_welcome_emails().await;
+    // Nothing else is due yet.
+    app.send_due_welcome_emails().await;
+
+    // Assert
+    assert_eq!(
+        sent_subjects(&app).await,
+        vec!["Welcome to Zero To Production"]
+    );
+    assert_eq!(enrollment(&app).await, (1, false, None));
+
+    // Act - Part 2 - Day 3
+    travel_back(&app, 3).await;
+    app.send_due_welcome_emails().await;
+
+    // Assert
+    assert_eq!(sent_subjects(&app).await.len(), 2);
+
+    // Act - Part 3 - Day 7
+    travel_back(&app, 4).await;
+    app.send_due_welcome_emails().await;
+
+    // Assert
+    assert_eq!(sent_subjects(&app).await.len(), 3);
+    assert_eq!(enrollment(&app).await, (3, true, None));
+    assert_eq!(
+        deliveries(&app).await,
+        vec![
+            (0, "sent".to_owned()),
+            (1, "sent".to_owned()),
+            (2, "sent".to_owned())
+        ]
+    );
+}
+
+#[tokio::test]
+async fn steps_whose_condition_does_not_match_are_skipped() {
+    // Arrange
+    let app = spawn_app().await;
+    let issue = send_issue(&app, true).await;
+    // The catch-up step is for those who did not open anything.
+    client()
+        .get(issue.open.unwrap())
+        .header("User-Agent", BROWSER)
+        .send()
+        .await
+        .unwrap();
+    app.email_server.reset().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.send_due_welcome_emails().await;
+    travel_back(&app, 3).await;
+    app.send_due_welcome_emails().await;
+    travel_back(&app, 4).await;
+    app.send_due_welcome_emails().await;
+
+    // Assert
+    assert_eq!(enrollment(&app).await, (3, true, None));
+    assert_eq!(
+        deliveries(&app).await,
+        vec![
+            (0, "sent".to_owned()),
+            (1, "sent".to_owned()),
+            (2, "skipped".to_owned())
+        ]
+    );
+}
+
+#[tokio::test]
+async fn unsubscribing_stops_the_series() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!(
+            "{}/admin/subscribers/{}/unsubscribe",
+            &app.address,
+            subscriber_id(&app).await
+        ))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .unwrap();
+    app.send_due_welcome_emails().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        enrollment(&app).await,
+        (0, false, Some("unsubscribed".to_owned()))
+    );
+    assert!(deliveries(&app).await.is_empty());
+}
+
+#[tokio::test]
+async fn suppression_stops_the_series() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.send_due_welcome_emails().await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/admin/suppressions", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .json(&serde_json::json!({
+            "email": "Ursula_Le_Guin@gmail.com",
+            "reason": "complained"
+        }))
+        .send()
+        .await
+        .unwrap();
+    travel_back(&app, 3).await;
+    app.send_due_welcome_emails().await;
+
+    // Assert
+    assert!(response.status().is_success());
+    assert_eq!(
+        enrollment(&app).await,
+        (1, false, Some("suppressed".to_owned()))
+    );
+}
+
+#[tokio::test]
+async fn subscribers_who_never_confirm_are_not_enrolled() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    app.send_due_welcome_emails().await;
+
+    // Assert
+    let enrolled = sqlx::query_scalar!("SELECT count(*) FROM welcome_series_enrollments")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(enrolled, Some(0));
+}